@@ -0,0 +1,77 @@
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rust_https_server::buffer_pool::{BufferPool, BUFFER_CAPACITY};
+use rust_https_server::http::Response;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts heap allocations so the benchmark can report how many each
+// serialized response costs with and without the pool.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_per_iteration(iterations: usize, mut f: impl FnMut()) -> f64 {
+    f();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..iterations {
+        f();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / iterations as f64
+}
+
+fn benchmark_connection_buffers(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let pool = BufferPool::default();
+    let response = Response::ok().with_text("Hello, World!");
+
+    let fresh = || {
+        runtime.block_on(async {
+            let mut read = BytesMut::with_capacity(BUFFER_CAPACITY);
+            read.extend_from_slice(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            let mut head = BytesMut::with_capacity(128 + response.headers.len() * 32);
+            response.write_with(&mut tokio::io::sink(), &mut head).await.unwrap();
+            black_box((read, head));
+        })
+    };
+    let pooled = || {
+        runtime.block_on(async {
+            let mut read = pool.get();
+            read.extend_from_slice(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            let mut head = pool.get();
+            response.write_with(&mut tokio::io::sink(), &mut head).await.unwrap();
+            black_box((&read, &head));
+        })
+    };
+
+    println!(
+        "allocations per request: fresh {:.1}, pooled {:.1}",
+        allocations_per_iteration(10_000, fresh),
+        allocations_per_iteration(10_000, pooled)
+    );
+
+    c.bench_function("connection_buffers_fresh", |b| b.iter(fresh));
+    c.bench_function("connection_buffers_pooled", |b| b.iter(pooled));
+}
+
+criterion_group!(benches, benchmark_connection_buffers);
+criterion_main!(benches);
@@ -1,7 +1,14 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use rust_https_server::http::{Request, Response};
+use rust_https_server::config::Config;
 use rust_https_server::router::Router;
+use rust_https_server::parser;
+use rust_https_server::server::Server;
+use rust_https_server::testing::TestClient;
 use http::{Method, Uri, Version};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 fn benchmark_router_creation(c: &mut Criterion) {
     c.bench_function("router_creation", |b| {
@@ -46,11 +53,217 @@ fn benchmark_mime_type_detection(c: &mut Criterion) {
     });
 }
 
+fn benchmark_response_serialization(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let response = Response::ok().with_text("Hello, World!");
+
+    c.bench_function("response_to_bytes_13b", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let mut sink = tokio::io::sink();
+                sink.write_all(&response.to_bytes()).await.unwrap();
+                black_box(sink);
+            })
+        });
+    });
+
+    c.bench_function("response_write_to_13b", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let mut sink = tokio::io::sink();
+                response.write_to(&mut sink).await.unwrap();
+                black_box(sink);
+            })
+        });
+    });
+}
+
+fn benchmark_connection_state(c: &mut Criterion) {
+    let config = Config::default();
+    let server = Server::new(config.clone());
+    let router = server.router().clone();
+
+    c.bench_function("connection_state_deep_clone", |b| {
+        b.iter(|| black_box((config.clone(), router.clone())));
+    });
+
+    let shared_config = Arc::new(config.clone());
+    let shared_router = Arc::new(router.clone());
+    c.bench_function("connection_state_arc_clone", |b| {
+        b.iter(|| black_box((shared_config.clone(), shared_router.clone())));
+    });
+}
+
+fn benchmark_router_dispatch(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let mut router = Router::new();
+    router
+        .get("/", |_| Ok(Response::ok().with_text("Hello")))
+        .get("/user-agent", |_| Ok(Response::ok().with_text("User-Agent")))
+        .get("/echo/{param}", |_| Ok(Response::ok().with_text("Echo")));
+    let request = Request::new(Method::GET, "/echo/abc".parse().unwrap(), Version::HTTP_11);
+
+    c.bench_function("router_dispatch", |b| {
+        b.iter(|| runtime.block_on(async { black_box(router.handle(request.clone()).await.unwrap()) }));
+    });
+}
+
+fn benchmark_route_scaling(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let mut group = c.benchmark_group("router_dispatch_scaling");
+    for count in [1, 50, 1000] {
+        let mut router = Router::new();
+        for i in 0..count {
+            router.get(&format!("/service{}/items/{{id}}", i), |_| Ok(Response::ok().with_text("Item")));
+        }
+        let path = format!("/service{}/items/42", count - 1);
+        let request = Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+
+        group.bench_with_input(BenchmarkId::from_parameter(count), &request, |b, request| {
+            b.iter(|| runtime.block_on(async { black_box(router.handle(request.clone()).await.unwrap()) }));
+        });
+    }
+    group.finish();
+}
+
+fn benchmark_incremental_parsing(c: &mut Criterion) {
+    let body = vec![b'x'; 64 * 1024];
+    let mut request = format!(
+        "POST /echo/upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+
+    c.bench_function("parse_64k_in_1k_chunks_incremental", |b| {
+        b.iter(|| {
+            let mut buffer = BytesMut::new();
+            let mut state = parser::ParseState::default();
+            for chunk in request.chunks(1024) {
+                buffer.extend_from_slice(chunk);
+                if let Some(request) = parser::parse_request(&mut buffer, &mut state).unwrap() {
+                    black_box(request);
+                }
+            }
+        });
+    });
+
+    c.bench_function("parse_64k_in_1k_chunks_rescan", |b| {
+        b.iter(|| {
+            let mut buffer = BytesMut::new();
+            for chunk in request.chunks(1024) {
+                buffer.extend_from_slice(chunk);
+                let mut state = parser::ParseState::default();
+                if let Some(request) = parser::parse_request(&mut buffer, &mut state).unwrap() {
+                    black_box(request);
+                }
+            }
+        });
+    });
+}
+
+fn benchmark_large_file(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let size = 32 * 1024 * 1024;
+    std::fs::write(dir.path().join("large.bin"), vec![7u8; size]).unwrap();
+
+    let mut group = c.benchmark_group("large_file_32m");
+    group.throughput(Throughput::Bytes(size as u64));
+    group.sample_size(20);
+    for use_mmap in [false, true] {
+        let mut config = Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.allowed_extensions.push("bin".to_string());
+        config.files.use_mmap = use_mmap;
+        let client = runtime.block_on(async { TestClient::new(config) });
+
+        // The body is copied out as a socket write would, so mapped pages are
+        // actually faulted in rather than only mapped.
+        let mut sent = Vec::with_capacity(size);
+        let name = if use_mmap { "mmap" } else { "read" };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let response = runtime.block_on(client.get("/files/large.bin"));
+                sent.clear();
+                sent.extend_from_slice(response.bytes());
+                black_box(&sent);
+            });
+        });
+    }
+    group.finish();
+}
+
+// Drives whole connections through the parser, router and response writer
+// over an in-memory pipe. Throughput is in requests, so criterion reports
+// requests per second.
+fn benchmark_end_to_end(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let mut config = Config::default();
+    config.routes.enable_echo = true;
+    let server = Server::new(config);
+    let addr = "127.0.0.1:40000".parse().unwrap();
+
+    let headers: String = (0..20).map(|i| format!("X-Header-{}: value-{}\r\n", i, i)).collect();
+    let body = "a".repeat(64 * 1024);
+    let pipelined: String = (0..100)
+        .map(|i| {
+            let close = if i == 99 { "Connection: close\r\n" } else { "" };
+            format!("GET /echo/{} HTTP/1.1\r\nHost: localhost\r\n{}\r\n", i, close)
+        })
+        .collect();
+    let workloads = [
+        ("minimal_get", 1, "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_string()),
+        ("get_20_headers", 1, format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n", headers)),
+        (
+            "post_64k",
+            1,
+            format!("POST /echo/upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body),
+        ),
+        ("pipelined_100", 100, pipelined),
+    ];
+
+    // Every workload ends with `Connection: close`, so the server ends the
+    // exchange once it has answered.
+    let exchange = |request: &[u8]| {
+        runtime.block_on(async {
+            let (mut client, socket) = tokio::io::duplex(64 * 1024);
+            let connection = server.serve_connection(socket, addr);
+            let exchange = async {
+                client.write_all(request).await.unwrap();
+                let mut response = Vec::new();
+                client.read_to_end(&mut response).await.unwrap();
+                response
+            };
+            let (served, response) = tokio::join!(connection, exchange);
+            served.unwrap();
+            response
+        })
+    };
+
+    let mut group = c.benchmark_group("end_to_end");
+    for (name, requests, request) in &workloads {
+        let response = String::from_utf8(exchange(request.as_bytes())).unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), *requests, "{}", name);
+
+        group.throughput(Throughput::Elements(*requests as u64));
+        group.bench_function(*name, |b| b.iter(|| black_box(exchange(request.as_bytes()))));
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     benchmark_router_creation,
     benchmark_response_creation,
     benchmark_request_creation,
-    benchmark_mime_type_detection
+    benchmark_mime_type_detection,
+    benchmark_response_serialization,
+    benchmark_connection_state,
+    benchmark_router_dispatch,
+    benchmark_route_scaling,
+    benchmark_incremental_parsing,
+    benchmark_large_file,
+    benchmark_end_to_end
 );
 criterion_main!(benches); 
\ No newline at end of file
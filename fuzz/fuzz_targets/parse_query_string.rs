@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_https_server::utils::parse_query_string;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(query) = std::str::from_utf8(data) {
+        let params = parse_query_string(query);
+        assert!(params.len() <= query.split('&').count());
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use rust_https_server::parser::{parse_request_with, ParseOptions, ParseState};
+
+fuzz_target!(|data: &[u8]| {
+    for strict in [true, false] {
+        let options = ParseOptions { strict, ..Default::default() };
+
+        let mut buffer = BytesMut::from(data);
+        let mut state = ParseState::default();
+        loop {
+            let before = buffer.len();
+            match parse_request_with(&mut buffer, &mut state, &options) {
+                Ok(Some(_)) => assert!(buffer.len() < before, "a parsed request must consume input"),
+                _ => break,
+            }
+        }
+
+        let mut buffer = BytesMut::new();
+        let mut state = ParseState::default();
+        for chunk in data.chunks(7) {
+            buffer.extend_from_slice(chunk);
+            while let Ok(Some(_)) = parse_request_with(&mut buffer, &mut state, &options) {}
+        }
+    }
+});
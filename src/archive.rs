@@ -0,0 +1,209 @@
+use crate::{config::FileConfig, http::BodyStream, utils, Error, Result};
+use bytes::{Bytes, BytesMut};
+use chrono::{Datelike, Timelike};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::error;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn from_query(format: &str) -> Option<Self> {
+        match format {
+            "zip" => Some(Self::Zip),
+            "tar.gz" | "tgz" => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::TarGz => "application/gzip",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+// Walks `dir` with the same visibility rules as directory listings. Symlinks
+// are followed only when they resolve inside `root`, and each directory is
+// visited once so a link back to an ancestor cannot loop.
+pub fn collect(dir: &Path, root: &Path, config: &FileConfig) -> Result<Vec<ArchiveEntry>> {
+    let root = root.canonicalize()?;
+    let mut visited = HashSet::new();
+    let mut entries = Vec::new();
+    let mut total = 0;
+    collect_into(dir, "", &root, config, &mut visited, &mut entries, &mut total)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn collect_into(
+    dir: &Path,
+    prefix: &str,
+    root: &Path,
+    config: &FileConfig,
+    visited: &mut HashSet<PathBuf>,
+    entries: &mut Vec<ArchiveEntry>,
+    total: &mut u64,
+) -> Result<()> {
+    if !visited.insert(dir.canonicalize()?) {
+        return Ok(());
+    }
+    for entry in utils::read_directory(dir, config)? {
+        let path = entry.path();
+        let Ok(resolved) = path.canonicalize() else {
+            continue;
+        };
+        if !resolved.starts_with(root) {
+            continue;
+        }
+        let Some(metadata) = utils::entry_metadata(&entry) else {
+            continue;
+        };
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if metadata.is_dir() {
+            collect_into(&path, &format!("{}/", name), root, config, visited, entries, total)?;
+            continue;
+        }
+        if !metadata.is_file() || utils::validate_file_extension(&name, &config.allowed_extensions).is_err() {
+            continue;
+        }
+
+        *total += metadata.len();
+        if entries.len() >= config.archive.max_entries || *total > config.archive.max_bytes {
+            let over_bytes = *total > config.archive.max_bytes;
+            return Err(Error::ContentTooLarge {
+                limit: usize::try_from(config.archive.max_bytes).unwrap_or(usize::MAX),
+                actual: over_bytes.then(|| usize::try_from(*total).unwrap_or(usize::MAX)),
+            });
+        }
+        entries.push(ArchiveEntry {
+            name,
+            path,
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+        });
+    }
+    Ok(())
+}
+
+pub fn stream(format: ArchiveFormat, base: String, entries: Vec<ArchiveEntry>) -> BodyStream {
+    let (sender, stream) = BodyStream::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter::new(sender.clone());
+        let result = match format {
+            ArchiveFormat::Zip => write_zip(&mut writer, &base, &entries),
+            ArchiveFormat::TarGz => write_tar_gz(&mut writer, &base, &entries),
+        }
+        .and_then(|_| writer.flush());
+        if let Err(e) = result {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                error!(archive = %base, "Archive stream aborted: {}", e);
+            }
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+    stream
+}
+
+fn write_zip(writer: &mut ChannelWriter, base: &str, entries: &[ArchiveEntry]) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new_stream(writer);
+    for entry in entries {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .last_modified_time(zip_time(entry.modified))
+            .large_file(entry.size >= u64::from(u32::MAX));
+        zip.start_file(format!("{}/{}", base, entry.name), options).map_err(io::Error::other)?;
+        io::copy(&mut std::fs::File::open(&entry.path)?.take(entry.size), &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+fn write_tar_gz(writer: &mut ChannelWriter, base: &str, entries: &[ArchiveEntry]) -> io::Result<()> {
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.size);
+        header.set_mode(0o644);
+        header.set_mtime(entry.modified.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()));
+        header.set_entry_type(tar::EntryType::Regular);
+        let file = std::fs::File::open(&entry.path)?.take(entry.size);
+        tar.append_data(&mut header, format!("{}/{}", base, entry.name), file)?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn zip_time(time: SystemTime) -> zip::DateTime {
+    let time = chrono::DateTime::<chrono::Utc>::from(time);
+    zip::DateTime::from_date_and_time(
+        time.year().clamp(1980, 2107) as u16,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+pub(crate) struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: BytesMut,
+}
+
+impl ChannelWriter {
+    pub(crate) fn new(sender: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self { sender, buffer: BytesMut::with_capacity(CHUNK_SIZE) }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = self.buffer.split().freeze();
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{
+    auth::Claims,
+    config::AuditConfig,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    Result,
+};
+use futures::future::BoxFuture;
+use http::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tracing::error;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    pub client_ip: Option<String>,
+    pub user: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub bytes: u64,
+    pub status: u16,
+    pub result: String,
+}
+
+enum Message {
+    Entry(AuditEntry),
+    Flush(mpsc::Sender<()>),
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    sender: Option<mpsc::Sender<Message>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AuditLog {
+    pub fn from_config(config: &AuditConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        Self::open(&config.path).map(Some)
+    }
+
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (sender, receiver) = mpsc::channel();
+
+        let writer = std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || Self::run_writer(BufWriter::new(file), receiver))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    fn run_writer(mut out: BufWriter<std::fs::File>, receiver: mpsc::Receiver<Message>) {
+        while let Ok(message) = receiver.recv() {
+            let mut acks = Vec::new();
+            for message in std::iter::once(message).chain(receiver.try_iter()) {
+                match message {
+                    Message::Entry(entry) => {
+                        let written = serde_json::to_writer(&mut out, &entry).map_err(std::io::Error::from);
+                        if let Err(e) = written.and_then(|_| out.write_all(b"\n")) {
+                            error!("Failed to write audit entry: {}", e);
+                        }
+                    }
+                    Message::Flush(ack) => acks.push(ack),
+                }
+            }
+            if let Err(e) = out.flush() {
+                error!("Failed to flush audit log: {}", e);
+            }
+            for ack in acks {
+                let _ = ack.send(());
+            }
+        }
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Message::Entry(entry));
+        }
+    }
+
+    pub fn flush(&self) {
+        let (ack, done) = mpsc::channel();
+        if let Some(sender) = &self.sender {
+            if sender.send(Message::Flush(ack)).is_ok() {
+                let _ = done.recv();
+            }
+        }
+    }
+}
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+pub struct AuditLayer {
+    log: Arc<AuditLog>,
+}
+
+impl AuditLayer {
+    pub fn new(log: Arc<AuditLog>) -> Self {
+        Self { log }
+    }
+
+    fn outcome(status: StatusCode) -> Option<&'static str> {
+        match status {
+            status if status.is_success() => Some("success"),
+            StatusCode::FORBIDDEN | StatusCode::PAYLOAD_TOO_LARGE | StatusCode::INSUFFICIENT_STORAGE => Some("denied"),
+            _ => None,
+        }
+    }
+}
+
+impl Middleware for AuditLayer {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            if !matches!(request.method, Method::POST | Method::PUT | Method::DELETE) {
+                return next.run(request).await;
+            }
+
+            let method = request.method.to_string();
+            let path = request.path().to_string();
+            let client_ip = request.client_ip().map(|ip| ip.to_string());
+            let user = request.extension::<Claims>().and_then(Claims::subject).map(str::to_string);
+            let bytes = request.body.as_ref().map(|body| body.len() as u64).unwrap_or(0);
+
+            let result = next.run(request).await;
+            let status = match &result {
+                Ok(response) => response.status,
+                Err(e) => e.status_code(),
+            };
+
+            if let Some(outcome) = Self::outcome(status) {
+                self.log.record(AuditEntry {
+                    timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    client_ip,
+                    user,
+                    method,
+                    path,
+                    bytes,
+                    status: status.as_u16(),
+                    result: outcome.to_string(),
+                });
+            }
+            result
+        })
+    }
+}
\ No newline at end of file
@@ -0,0 +1,266 @@
+use crate::{
+    config::AuthConfig,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    Error, Result,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use http::StatusCode;
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims(Map<String, Value>);
+
+impl Claims {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub").and_then(|v| v.as_str())
+    }
+
+    pub fn into_inner(self) -> Map<String, Value> {
+        self.0
+    }
+}
+
+enum VerifyingKey {
+    Hs256(Vec<u8>),
+    #[cfg(feature = "rs256")]
+    Rs256(jsonwebtoken::DecodingKey),
+}
+
+impl VerifyingKey {
+    fn algorithm(&self) -> &'static str {
+        match self {
+            VerifyingKey::Hs256(_) => "HS256",
+            #[cfg(feature = "rs256")]
+            VerifyingKey::Rs256(_) => "RS256",
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &str) -> bool {
+        match self {
+            VerifyingKey::Hs256(secret) => {
+                let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+                    return false;
+                };
+                let Ok(mut mac) = HmacSha256::new_from_slice(secret) else {
+                    return false;
+                };
+                mac.update(message);
+                mac.verify_slice(&signature).is_ok()
+            }
+            #[cfg(feature = "rs256")]
+            VerifyingKey::Rs256(key) => {
+                jsonwebtoken::crypto::verify(signature, message, key, jsonwebtoken::Algorithm::RS256)
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenError {
+    Malformed,
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+}
+
+impl TokenError {
+    fn status(self) -> StatusCode {
+        match self {
+            TokenError::InvalidIssuer | TokenError::InvalidAudience => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            TokenError::Malformed => "The token is malformed",
+            TokenError::InvalidSignature => "The token signature is invalid",
+            TokenError::Expired => "The token has expired",
+            TokenError::NotYetValid => "The token is not yet valid",
+            TokenError::InvalidIssuer => "The token issuer is not accepted",
+            TokenError::InvalidAudience => "The token audience is not accepted",
+        }
+    }
+}
+
+pub struct JwtAuth {
+    key: VerifyingKey,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: u64,
+    realm: String,
+}
+
+impl JwtAuth {
+    pub fn from_config(config: &AuthConfig) -> Result<Self> {
+        let key = match config.algorithm.as_str() {
+            "HS256" => {
+                let secret = config
+                    .secret
+                    .as_deref()
+                    .filter(|s| !s.is_empty())
+                    .ok_or_else(|| Error::Config("HS256 auth requires a secret".to_string()))?;
+                VerifyingKey::Hs256(secret.as_bytes().to_vec())
+            }
+            #[cfg(feature = "rs256")]
+            "RS256" => {
+                let path = config
+                    .public_key_path
+                    .as_deref()
+                    .ok_or_else(|| Error::Config("RS256 auth requires a public_key_path".to_string()))?;
+                let pem = std::fs::read(path)?;
+                let key = jsonwebtoken::DecodingKey::from_rsa_pem(&pem)
+                    .map_err(|e| Error::Config(format!("Invalid RS256 public key: {}", e)))?;
+                VerifyingKey::Rs256(key)
+            }
+            other => {
+                return Err(Error::Config(format!("Unsupported auth algorithm: {}", other)));
+            }
+        };
+
+        Ok(Self {
+            key,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            leeway: config.leeway,
+            realm: config.realm.clone(),
+        })
+    }
+
+    fn validate(&self, token: &str) -> std::result::Result<Claims, TokenError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(TokenError::Malformed);
+        };
+
+        let header = decode_segment(header)?;
+        if header.get("alg").and_then(|v| v.as_str()) != Some(self.key.algorithm()) {
+            return Err(TokenError::InvalidSignature);
+        }
+
+        let message_len = token.len() - signature.len() - 1;
+        if !self.key.verify(&token.as_bytes()[..message_len], signature) {
+            return Err(TokenError::InvalidSignature);
+        }
+
+        let claims = decode_segment(payload)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        if let Some(exp) = claims.get("exp") {
+            let exp = exp.as_u64().ok_or(TokenError::Malformed)?;
+            if now > exp.saturating_add(self.leeway) {
+                return Err(TokenError::Expired);
+            }
+        }
+
+        if let Some(nbf) = claims.get("nbf") {
+            let nbf = nbf.as_u64().ok_or(TokenError::Malformed)?;
+            if now.saturating_add(self.leeway) < nbf {
+                return Err(TokenError::NotYetValid);
+            }
+        }
+
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(|v| v.as_str()) != Some(issuer.as_str()) {
+                return Err(TokenError::InvalidIssuer);
+            }
+        }
+
+        if let Some(audience) = &self.audience {
+            let accepted = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience.as_str())),
+                _ => false,
+            };
+            if !accepted {
+                return Err(TokenError::InvalidAudience);
+            }
+        }
+
+        Ok(Claims(claims))
+    }
+
+    fn challenge(&self, error: Option<TokenError>) -> Response {
+        let (status, value) = match error {
+            Some(error) => (
+                error.status(),
+                format!(
+                    "Bearer realm=\"{}\", error=\"invalid_token\", error_description=\"{}\"",
+                    self.realm,
+                    error.description()
+                ),
+            ),
+            None => (StatusCode::UNAUTHORIZED, format!("Bearer realm=\"{}\"", self.realm)),
+        };
+
+        Response::new(status)
+            .with_header("www-authenticate", &value)
+            .with_text(error.map(|e| e.description()).unwrap_or("Authentication required"))
+    }
+}
+
+impl Middleware for JwtAuth {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let token = request
+                .header("authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split_once(' '))
+                .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+                .map(|(_, token)| token.trim().to_string());
+
+            let Some(token) = token else {
+                return Ok(self.challenge(None));
+            };
+
+            match self.validate(&token) {
+                Ok(claims) => {
+                    request.extensions.insert(claims);
+                    next.run(request).await
+                }
+                Err(error) => Ok(self.challenge(Some(error))),
+            }
+        })
+    }
+}
+
+fn decode_segment(segment: &str) -> std::result::Result<Map<String, Value>, TokenError> {
+    let bytes = URL_SAFE_NO_PAD.decode(segment).map_err(|_| TokenError::Malformed)?;
+    match serde_json::from_slice(&bytes) {
+        Ok(Value::Object(map)) => Ok(map),
+        _ => Err(TokenError::Malformed),
+    }
+}
+
+pub fn encode_hs256(claims: &Value, secret: &[u8]) -> Result<String> {
+    let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let message = format!("{}.{}", header, payload);
+
+    let mut mac = HmacSha256::new_from_slice(secret)
+        .map_err(|e| Error::Internal(format!("Invalid HMAC key: {}", e)))?;
+    mac.update(message.as_bytes());
+    let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+    Ok(format!("{}.{}", message, signature))
+}
@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+    released: Notify,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        (self.limit > 0).then_some(self.limit)
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    pub fn reservation(&self) -> Reservation<'_> {
+        Reservation { budget: self, bytes: 0 }
+    }
+
+    fn try_acquire(&self, bytes: usize) -> bool {
+        let acquired = self.used.fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+            let total = used.checked_add(bytes)?;
+            (self.limit == 0 || total <= self.limit).then_some(total)
+        });
+        match acquired {
+            Ok(used) => {
+                self.peak.fetch_max(used + bytes, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn acquire(&self, bytes: usize) {
+        let used = self.used.fetch_add(bytes, Ordering::AcqRel);
+        self.peak.fetch_max(used + bytes, Ordering::Relaxed);
+    }
+
+    fn release(&self, bytes: usize) {
+        if bytes > 0 {
+            self.used.fetch_sub(bytes, Ordering::AcqRel);
+            self.released.notify_waiters();
+        }
+    }
+}
+
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+
+impl Reservation<'_> {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn try_grow_to(&mut self, total: usize) -> bool {
+        if total <= self.bytes {
+            return true;
+        }
+        if !self.budget.try_acquire(total - self.bytes) {
+            return false;
+        }
+        self.bytes = total;
+        true
+    }
+
+    pub async fn grow_to(&mut self, total: usize, wait: Duration) -> bool {
+        if self.budget.limit().is_some_and(|limit| total > limit) {
+            return false;
+        }
+        let deadline = Instant::now() + wait;
+        loop {
+            let released = self.budget.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            if self.try_grow_to(total) {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    pub fn account(&mut self, total: usize) {
+        if total > self.bytes {
+            self.budget.acquire(total - self.bytes);
+            self.bytes = total;
+        }
+    }
+
+    pub fn shrink_to(&mut self, total: usize) {
+        if total < self.bytes {
+            self.budget.release(self.bytes - total);
+            self.bytes = total;
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
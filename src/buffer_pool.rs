@@ -0,0 +1,114 @@
+use bytes::BytesMut;
+use crossbeam_channel::{Receiver, Sender};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const BUFFER_CAPACITY: usize = 4096;
+
+// Recycles the read buffers of connections and the buffers response heads
+// are written into. At most `max_pooled` buffers are kept, and one that grew
+// past `max_capacity` is dropped rather than pooled so a burst of large
+// requests does not pin its memory.
+#[derive(Debug)]
+pub struct BufferPool {
+    free: Sender<BytesMut>,
+    pooled: Receiver<BytesMut>,
+    max_capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(1024, 64 * 1024)
+    }
+}
+
+impl BufferPool {
+    pub fn new(max_pooled: usize, max_capacity: usize) -> Self {
+        let (free, pooled) = crossbeam_channel::bounded(max_pooled);
+        Self {
+            free,
+            pooled,
+            max_capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self) -> PooledBuffer<'_> {
+        PooledBuffer {
+            pool: self,
+            buffer: self.take(),
+        }
+    }
+
+    pub fn take(&self) -> BytesMut {
+        match self.pooled.try_recv() {
+            Ok(buffer) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            Err(_) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                BytesMut::with_capacity(BUFFER_CAPACITY)
+            }
+        }
+    }
+
+    // A buffer whose storage is still shared with a request body cannot be
+    // reclaimed without allocating, so it is dropped instead.
+    pub fn put(&self, mut buffer: BytesMut) {
+        buffer.clear();
+        if !buffer.try_reclaim(BUFFER_CAPACITY) || buffer.capacity() > self.max_capacity {
+            return;
+        }
+        let _ = self.free.try_send(buffer);
+    }
+
+    pub fn pooled(&self) -> usize {
+        self.pooled.len()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn counters(&self) -> [(&'static str, &'static str, &AtomicU64); 2] {
+        [
+            ("buffer_pool_hits_total", "Buffers taken from the pool.", &self.hits),
+            ("buffer_pool_misses_total", "Buffers allocated because the pool was empty.", &self.misses),
+        ]
+    }
+}
+
+// Goes back to its pool when dropped.
+#[derive(Debug)]
+pub struct PooledBuffer<'a> {
+    pool: &'a BufferPool,
+    buffer: BytesMut,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buffer));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{config::HttpClientConfig, http::Response, tls, Error, Result};
+use bytes::{Bytes, BytesMut};
+use http::header::{HeaderName, HeaderValue};
+use http::{HeaderMap, Method, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use rustls::crypto::ring;
+use rustls::RootCertStore;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A pooled HTTP/1.1 client for handlers that call upstream services. Clones
+// share one connection pool, so the server builds a single instance and hands
+// it to every request as an extension.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    inner: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    timeout: Duration,
+    max_response_bytes: usize,
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        Self::from_config(&HttpClientConfig::default()).expect("default client configuration is valid")
+    }
+
+    pub fn from_config(config: &HttpClientConfig) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(path) = &config.ca_path {
+            for cert in tls::load_certs(path)? {
+                roots.add(cert)?;
+            }
+        }
+        let tls = rustls::ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(Duration::from_millis(config.connect_timeout_ms)));
+        http.set_nodelay(true);
+        let connector = HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http);
+
+        let inner = Client::builder(TokioExecutor::new())
+            .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs))
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .build(connector);
+        Ok(Self {
+            inner,
+            timeout: Duration::from_millis(config.timeout_ms),
+            max_response_bytes: config.max_response_bytes,
+        })
+    }
+
+    pub fn get(&self, url: &str) -> ClientRequest {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post(&self, url: &str) -> ClientRequest {
+        self.request(Method::POST, url)
+    }
+
+    pub fn put(&self, url: &str) -> ClientRequest {
+        self.request(Method::PUT, url)
+    }
+
+    pub fn delete(&self, url: &str) -> ClientRequest {
+        self.request(Method::DELETE, url)
+    }
+
+    pub fn request(&self, method: Method, url: &str) -> ClientRequest {
+        ClientRequest {
+            client: self.clone(),
+            method,
+            uri: url.parse().map_err(Error::from),
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            timeout: self.timeout,
+        }
+    }
+
+    async fn execute(&self, request: hyper::Request<Full<Bytes>>, timeout: Duration) -> Result<Response> {
+        let exchange = async {
+            let response = self.inner.request(request).await.map_err(|e| Error::Upstream(e.to_string()))?;
+            let (parts, mut body) = response.into_parts();
+            let mut collected = BytesMut::new();
+            while let Some(frame) = body.frame().await {
+                let frame = frame.map_err(|e| Error::Upstream(e.to_string()))?;
+                if let Ok(data) = frame.into_data() {
+                    if collected.len() + data.len() > self.max_response_bytes {
+                        return Err(Error::Upstream(format!("response exceeds {} bytes", self.max_response_bytes)));
+                    }
+                    collected.extend_from_slice(&data);
+                }
+            }
+            let mut response = Response::new(parts.status).with_version(parts.version);
+            response.headers = parts.headers;
+            response.body = Some(collected.freeze());
+            Ok(response)
+        };
+        tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| Error::Timeout(format!("upstream did not respond within {:?}", timeout)))?
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct ClientRequest {
+    client: HttpClient,
+    method: Method,
+    uri: Result<Uri>,
+    headers: HeaderMap,
+    body: Bytes,
+    timeout: Duration,
+}
+
+impl ClientRequest {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::from_str(value)) {
+            self.headers.append(name, value);
+        }
+        self
+    }
+
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn json<T: Serialize>(mut self, value: &T) -> Result<Self> {
+        self.body = Bytes::from(serde_json::to_vec(value)?);
+        self.headers.insert(http::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        Ok(self)
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn send(self) -> Result<Response> {
+        let uri = self.uri?;
+        if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.authority().is_none() {
+            return Err(Error::BadRequest(format!("Client URL must be absolute http:// or https://: {}", uri)));
+        }
+        let mut request = hyper::Request::new(Full::new(self.body));
+        *request.method_mut() = self.method;
+        *request.uri_mut() = uri;
+        *request.headers_mut() = self.headers;
+        self.client.execute(request, self.timeout).await
+    }
+}
@@ -1,12 +1,101 @@
-use serde::{Deserialize, Serialize};
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub type SharedConfig = Arc<ArcSwap<Config>>;
+
+const RELOADABLE_FIELDS: &[&str] = &[
+    "server.method_override",
+    "server.trace",
+    "files.root_dir",
+    "files.max_file_size",
+    "files.allowed_extensions",
+    "files.enable_directory_listing",
+    "files.show_hidden",
+    "files.hide_non_utf8_names",
+    "files.max_ranges",
+    "files.require_delete_preconditions",
+    "files.archive.enabled",
+    "files.archive.max_entries",
+    "files.archive.max_bytes",
+    "files.precompressed",
+    "files.cache_control",
+    "files.cache_control_default",
+    "security.max_request_size",
+    "security.rate_limit_requests",
+    "security.rate_limit_window",
+    "security.trusted_proxies",
+    "security.proxy_header",
+    "security.ip_allow",
+    "security.ip_deny",
+    "security.max_json_size",
+    "security.max_request_line",
+    "security.max_header_line",
+    "security.max_header_bytes",
+    "security.max_headers",
+    "security.drop_denied_connections",
+    "security.header_timeout",
+    "security.min_body_rate",
+    "security.body_rate_window",
+    "performance.buffer_wait_ms",
+    "performance.keep_alive_timeout",
+    "performance.max_requests_per_connection",
+    "performance.allow_half_close",
+    "performance.decompress_requests",
+    "logging.level",
+    "logging.targets",
+    "redirects",
+    "rewrites",
+];
+
+// Sections whose fields all take effect on reload.
+const RELOADABLE_SECTIONS: &[&str] = &["security.headers", "security.csrf", "auth", "mirror"];
+
+const REDACTED_FIELDS: &[&str] = &["auth.secret", "security.rate_limit_store.redis_url"];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub files: FileConfig,
     pub security: SecurityConfig,
     pub performance: PerformanceConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub redirects: Vec<RedirectRule>,
+    #[serde(default)]
+    pub rewrites: Vec<RewriteRule>,
+    #[serde(default)]
+    pub virtual_hosts: VirtualHostsConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+    #[serde(default)]
+    pub otel: OtelConfig,
+    #[serde(default)]
+    pub site_files: SiteFilesConfig,
+    #[serde(default)]
+    pub mirror: MirrorConfig,
+    #[serde(default)]
+    pub client: HttpClientConfig,
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    #[serde(default)]
+    pub routes: RoutesConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +104,69 @@ pub struct ServerConfig {
     pub port: u16,
     pub workers: usize,
     pub backlog: u32,
+    #[serde(default = "default_strict_parsing")]
+    pub strict_parsing: bool,
+    #[serde(default)]
+    pub slash_policy: SlashPolicy,
+    #[serde(default)]
+    pub duplicate_routes: DuplicateRoutePolicy,
+    #[serde(default)]
+    pub redirect_http_port: Option<u16>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub listen: Vec<String>,
+    #[serde(default)]
+    pub method_override: bool,
+    #[serde(default)]
+    pub trace: TracePolicy,
+    #[serde(default = "default_shutdown_grace_seconds")]
+    pub shutdown_grace_seconds: u64,
+}
+
+impl ServerConfig {
+    pub fn listen_addresses(&self) -> Vec<String> {
+        if !self.listen.is_empty() {
+            return self.listen.clone();
+        }
+        vec![socket_address(&self.host, self.port)]
+    }
+}
+
+pub fn socket_address(host: &str, port: u16) -> String {
+    match host.contains(':') && !host.starts_with('[') {
+        true => format!("[{}]:{}", host, port),
+        false => format!("{}:{}", host, port),
+    }
+}
+
+fn same_listen_address(left: &str, right: &str) -> bool {
+    let split = |address: &str| {
+        let (host, port) = address.rsplit_once(':')?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        Some((host.parse::<std::net::IpAddr>().ok(), host.to_ascii_lowercase(), port.parse::<u16>().ok()?))
+    };
+    let (Some((left_ip, left_host, left_port)), Some((right_ip, right_host, right_port))) = (split(left), split(right)) else {
+        return false;
+    };
+    let unspecified = |ip: Option<std::net::IpAddr>| ip.is_some_and(|ip| ip.is_unspecified());
+    left_port == right_port && left_port != 0 && (left_host == right_host || unspecified(left_ip) || unspecified(right_ip))
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(address) => vec![address],
+        OneOrMany::Many(addresses) => addresses,
+    })
+}
+
+fn default_strict_parsing() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +175,118 @@ pub struct FileConfig {
     pub max_file_size: usize,
     pub allowed_extensions: Vec<String>,
     pub enable_directory_listing: bool,
+    #[serde(default)]
+    pub show_hidden: bool,
+    #[serde(default)]
+    pub hide_non_utf8_names: bool,
+    #[serde(default)]
+    pub precompressed: bool,
+    #[serde(default)]
+    pub spa_fallback: Option<String>,
+    #[serde(default)]
+    pub cache_control: HashMap<String, String>,
+    #[serde(default)]
+    pub cache_control_default: Option<String>,
+    #[serde(default)]
+    pub cache: FileCacheConfig,
+    #[serde(default)]
+    pub archive: FileArchiveConfig,
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+    #[serde(default)]
+    pub directory_quotas: HashMap<String, u64>,
+    #[serde(default = "default_quota_refresh_secs")]
+    pub quota_refresh_secs: u64,
+    #[serde(default)]
+    pub mime_types: HashMap<String, String>,
+    #[serde(default = "default_text_charset")]
+    pub text_charset: String,
+    #[serde(default)]
+    pub sniff_content_type: bool,
+    #[serde(default = "default_max_ranges")]
+    pub max_ranges: usize,
+    #[serde(default)]
+    pub require_delete_preconditions: bool,
+    #[serde(default)]
+    pub upload_validation: UploadValidationConfig,
+    #[serde(default)]
+    pub use_mmap: bool,
+    #[serde(default = "default_mmap_min_size")]
+    pub mmap_min_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadValidationConfig {
+    pub enabled: bool,
+    pub types: Vec<String>,
+}
+
+impl Default for UploadValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            types: ["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf", "application/zip"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+fn default_quota_refresh_secs() -> u64 {
+    60
+}
+
+fn default_max_ranges() -> usize {
+    8
+}
+
+fn default_mmap_min_size() -> u64 {
+    4 * 1024 * 1024
+}
+
+fn default_text_charset() -> String {
+    "utf-8".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileCacheConfig {
+    pub enabled: bool,
+    pub max_entries: usize,
+    pub max_file_size: usize,
+    pub max_total_bytes: usize,
+    pub revalidate_ms: u64,
+}
+
+impl Default for FileCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 1024,
+            max_file_size: 1024 * 1024,
+            max_total_bytes: 64 * 1024 * 1024,
+            revalidate_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileArchiveConfig {
+    pub enabled: bool,
+    pub max_entries: usize,
+    pub max_bytes: u64,
+}
+
+impl Default for FileArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_entries: 10_000,
+            max_bytes: 1024 * 1024 * 1024,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,26 +296,163 @@ pub struct SecurityConfig {
     pub enable_cors: bool,
     pub rate_limit_requests: usize,
     pub rate_limit_window: u64,
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    #[serde(default)]
+    pub proxy_header: ProxyHeader,
+    #[serde(default)]
+    pub ip_allow: Vec<String>,
+    #[serde(default)]
+    pub ip_deny: Vec<String>,
+    #[serde(default)]
+    pub drop_denied_connections: bool,
+    #[serde(default)]
+    pub headers: SecurityHeadersConfig,
+    #[serde(default = "default_max_request_line")]
+    pub max_request_line: usize,
+    #[serde(default = "default_max_header_line")]
+    pub max_header_line: usize,
+    #[serde(default = "default_max_header_bytes")]
+    pub max_header_bytes: usize,
+    #[serde(default = "default_max_headers")]
+    pub max_headers: usize,
+    #[serde(default = "default_max_json_size")]
+    pub max_json_size: usize,
+    #[serde(default = "default_header_timeout")]
+    pub header_timeout: u64,
+    #[serde(default)]
+    pub min_body_rate: u64,
+    #[serde(default = "default_body_rate_window")]
+    pub body_rate_window: u64,
+    #[serde(default)]
+    pub rate_limit_store: RateLimitStoreConfig,
+    #[serde(default)]
+    pub csrf: CsrfConfig,
+}
+
+pub(crate) fn default_max_request_line() -> usize {
+    8 * 1024
+}
+
+pub(crate) fn default_max_header_line() -> usize {
+    8 * 1024
+}
+
+pub(crate) fn default_max_header_bytes() -> usize {
+    64 * 1024
+}
+
+pub(crate) fn default_max_headers() -> usize {
+    100
+}
+
+fn default_shutdown_grace_seconds() -> u64 {
+    30
+}
+
+fn default_request_timeout() -> u64 {
+    30
+}
+
+fn default_max_json_size() -> usize {
+    crate::http::DEFAULT_JSON_LIMIT
+}
+
+fn default_header_timeout() -> u64 {
+    10
+}
+
+fn default_body_rate_window() -> u64 {
+    5
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    #[default]
+    Memory,
+    Redis,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitStoreConfig {
+    pub backend: RateLimitBackend,
+    pub redis_url: String,
+    pub key_prefix: String,
+    pub timeout_ms: u64,
+    pub fail_open: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsrfConfig {
+    pub enabled: bool,
+    pub cookie_name: String,
+    pub header_name: String,
+    pub form_field: String,
+    pub exempt_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    pub hsts: bool,
+    pub hsts_max_age: u64,
+    pub hsts_include_subdomains: bool,
+    pub content_type_options: bool,
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub content_security_policy: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PerformanceConfig {
     pub connection_timeout: u64,
     pub keep_alive_timeout: u64,
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
     pub max_connections: usize,
     pub enable_compression: bool,
     pub compression_level: u32,
+    #[serde(default)]
+    pub decompress_requests: bool,
+    pub http2_max_concurrent_streams: u32,
+    pub http2_initial_window_size: u32,
+    pub http2_initial_connection_window_size: u32,
+    pub max_buffered_bytes: usize,
+    pub buffer_wait_ms: u64,
+    pub max_requests_per_connection: usize,
+    pub allow_half_close: bool,
+    pub slow_request_threshold_ms: u64,
+    pub buffer_pool_size: usize,
+    pub max_pooled_buffer_size: usize,
+    pub tcp: TcpConfig,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            server: ServerConfig::default(),
-            files: FileConfig::default(),
-            security: SecurityConfig::default(),
-            performance: PerformanceConfig::default(),
-        }
-    }
+// Applied to every accepted connection. `keepalive_secs = 0` leaves
+// SO_KEEPALIVE off and unset buffer sizes keep the OS defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcpConfig {
+    pub nodelay: bool,
+    pub keepalive_secs: u64,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub algorithm: String,
+    pub secret: Option<String>,
+    pub public_key_path: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub leeway: u64,
+    pub realm: String,
 }
 
 impl Default for ServerConfig {
@@ -61,6 +462,14 @@ impl Default for ServerConfig {
             port: 4221,
             workers: num_cpus::get(),
             backlog: 1024,
+            strict_parsing: true,
+            slash_policy: SlashPolicy::default(),
+            duplicate_routes: DuplicateRoutePolicy::default(),
+            redirect_http_port: None,
+            listen: Vec::new(),
+            method_override: false,
+            trace: TracePolicy::default(),
+            shutdown_grace_seconds: default_shutdown_grace_seconds(),
         }
     }
 }
@@ -76,6 +485,25 @@ impl Default for FileConfig {
                 "jpeg".to_string(), "png".to_string(), "gif".to_string()
             ],
             enable_directory_listing: false,
+            show_hidden: false,
+            hide_non_utf8_names: false,
+            precompressed: false,
+            spa_fallback: None,
+            cache_control: HashMap::new(),
+            cache_control_default: None,
+            cache: FileCacheConfig::default(),
+            archive: FileArchiveConfig::default(),
+            max_total_bytes: None,
+            directory_quotas: HashMap::new(),
+            quota_refresh_secs: default_quota_refresh_secs(),
+            mime_types: HashMap::new(),
+            text_charset: default_text_charset(),
+            sniff_content_type: false,
+            max_ranges: default_max_ranges(),
+            require_delete_preconditions: false,
+            upload_validation: UploadValidationConfig::default(),
+            use_mmap: false,
+            mmap_min_size: default_mmap_min_size(),
         }
     }
 }
@@ -86,8 +514,62 @@ impl Default for SecurityConfig {
             max_request_size: 10 * 1024 * 1024,
             allowed_origins: vec!["*".to_string()],
             enable_cors: true,
-            rate_limit_requests: 1000,
+            rate_limit_requests: 0,
             rate_limit_window: 60,
+            trusted_proxies: Vec::new(),
+            proxy_header: ProxyHeader::default(),
+            ip_allow: Vec::new(),
+            ip_deny: Vec::new(),
+            drop_denied_connections: false,
+            headers: SecurityHeadersConfig::default(),
+            max_request_line: default_max_request_line(),
+            max_header_line: default_max_header_line(),
+            max_header_bytes: default_max_header_bytes(),
+            max_headers: default_max_headers(),
+            max_json_size: default_max_json_size(),
+            header_timeout: default_header_timeout(),
+            min_body_rate: 0,
+            body_rate_window: default_body_rate_window(),
+            rate_limit_store: RateLimitStoreConfig::default(),
+            csrf: CsrfConfig::default(),
+        }
+    }
+}
+
+impl Default for RateLimitStoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: RateLimitBackend::Memory,
+            redis_url: "redis://127.0.0.1:6379".to_string(),
+            key_prefix: "ratelimit:".to_string(),
+            timeout_ms: 200,
+            fail_open: true,
+        }
+    }
+}
+
+impl Default for CsrfConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cookie_name: "csrf_token".to_string(),
+            header_name: "x-csrf-token".to_string(),
+            form_field: "csrf_token".to_string(),
+            exempt_paths: Vec::new(),
+        }
+    }
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            hsts: true,
+            hsts_max_age: 31536000,
+            hsts_include_subdomains: false,
+            content_type_options: true,
+            frame_options: Some("SAMEORIGIN".to_string()),
+            referrer_policy: Some("strict-origin-when-cross-origin".to_string()),
+            content_security_policy: None,
         }
     }
 }
@@ -97,27 +579,519 @@ impl Default for PerformanceConfig {
         Self {
             connection_timeout: 30,
             keep_alive_timeout: 5,
+            request_timeout: default_request_timeout(),
             max_connections: 10000,
             enable_compression: true,
             compression_level: 6,
+            decompress_requests: false,
+            http2_max_concurrent_streams: 100,
+            http2_initial_window_size: 1024 * 1024,
+            http2_initial_connection_window_size: 2 * 1024 * 1024,
+            max_buffered_bytes: 0,
+            buffer_wait_ms: 1000,
+            max_requests_per_connection: 0,
+            allow_half_close: true,
+            slow_request_threshold_ms: 1000,
+            buffer_pool_size: 1024,
+            max_pooled_buffer_size: 64 * 1024,
+            tcp: TcpConfig::default(),
+        }
+    }
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive_secs: 0,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlashPolicy {
+    Redirect,
+    #[default]
+    Merge,
+    Strict,
+}
+
+// The header the trusted proxies write the client address to. Only that one
+// is read, since a proxy passes any other through as the client sent it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyHeader {
+    #[default]
+    XForwardedFor,
+    Forwarded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TracePolicy {
+    #[default]
+    Reject,
+    Echo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateRoutePolicy {
+    Error,
+    #[default]
+    Warn,
+    Replace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Never,
+    Minutely,
+    Hourly,
+    Daily,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: LogFormat,
+    pub output: String,
+    pub rotation: LogRotation,
+    pub targets: HashMap<String, String>,
+    pub redact_headers: Vec<String>,
+    pub redact_query_params: Vec<String>,
+    pub dump_wire: bool,
+    pub dump_wire_limit: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            output: "stderr".to_string(),
+            rotation: LogRotation::Never,
+            targets: HashMap::new(),
+            redact_headers: Vec::new(),
+            redact_query_params: Vec::new(),
+            dump_wire: false,
+            dump_wire_limit: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub path: String,
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    pub enabled: bool,
+    pub retry_after: u64,
+    pub body: Option<String>,
+    pub content_type: String,
+    pub allow_paths: Vec<String>,
+    pub allow_ips: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+    pub enabled: bool,
+    pub paths: Vec<String>,
+    pub ttl_secs: u64,
+    pub max_entries: usize,
+    pub max_entry_bytes: usize,
+    pub max_total_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SiteFilesConfig {
+    pub robots_txt: Option<String>,
+    pub robots_path: Option<String>,
+    pub favicon_path: Option<String>,
+    pub favicon_max_age: u64,
+    pub quiet: bool,
+}
+
+impl SiteFilesConfig {
+    pub fn serves(&self, path: &str) -> bool {
+        match path {
+            "/robots.txt" => self.robots_txt.is_some() || self.robots_path.is_some(),
+            "/favicon.ico" => self.favicon_path.is_some(),
+            _ => false,
+        }
+    }
+
+    pub fn is_quiet(&self, path: &str) -> bool {
+        self.quiet && self.serves(path)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MirrorConfig {
+    pub enabled: bool,
+    pub upstream: String,
+    pub percentage: f64,
+    pub paths: Vec<String>,
+    pub max_body_bytes: usize,
+    pub max_in_flight: usize,
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    pub connect_timeout_ms: u64,
+    pub timeout_ms: u64,
+    pub pool_idle_timeout_secs: u64,
+    pub pool_max_idle_per_host: usize,
+    pub max_response_bytes: usize,
+    pub ca_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemplatesConfig {
+    pub dir: Option<String>,
+    pub reload: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutesConfig {
+    pub enable_echo: bool,
+    pub enable_user_agent: bool,
+    pub enable_files: bool,
+    pub files_read_only: bool,
+}
+
+impl RoutesConfig {
+    pub fn active(&self) -> Vec<&'static str> {
+        let mut active = Vec::new();
+        if self.enable_echo {
+            active.push("echo");
+        }
+        if self.enable_user_agent {
+            active.push("user-agent");
+        }
+        match (self.enable_files, self.files_read_only) {
+            (true, true) => active.push("files (read-only)"),
+            (true, false) => active.push("files (read-write)"),
+            (false, _) => {}
+        }
+        active
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtelConfig {
+    pub enabled: bool,
+    pub endpoint: String,
+    pub service_name: String,
+    pub export_metrics: bool,
+    pub metrics_interval: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VirtualHostsConfig {
+    pub strict: bool,
+    pub hosts: HashMap<String, VirtualHostConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VirtualHostConfig {
+    pub root_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+fn default_redirect_status() -> u16 {
+    308
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteRule {
+    pub pattern: String,
+    pub replacement: String,
+    #[serde(default)]
+    pub last: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    #[default]
+    None,
+    Optional,
+    Required,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    pub min_version: TlsVersion,
+    pub cipher_suites: Vec<String>,
+    pub client_auth: ClientAuth,
+    pub client_ca_path: Option<String>,
+    pub client_rules: Vec<ClientCertRule>,
+    pub certificates: Vec<TlsCertificate>,
+    pub reload_interval: u64,
+    pub self_signed: bool,
+    pub self_signed_dir: Option<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert_path: None,
+            key_path: None,
+            min_version: TlsVersion::default(),
+            cipher_suites: Vec::new(),
+            client_auth: ClientAuth::default(),
+            client_ca_path: None,
+            client_rules: Vec::new(),
+            certificates: Vec::new(),
+            reload_interval: 30,
+            self_signed: false,
+            self_signed_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsCertificate {
+    pub hostnames: Vec<String>,
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientCertRule {
+    pub prefix: String,
+    pub allow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "audit.log".to_string(),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "/metrics".to_string(),
+            address: None,
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            port: 4222,
+        }
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retry_after: 120,
+            body: None,
+            content_type: "text/html; charset=utf-8".to_string(),
+            allow_paths: vec!["/health".to_string()],
+            allow_ips: Vec::new(),
+        }
+    }
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            paths: vec!["/".to_string()],
+            ttl_secs: 60,
+            max_entries: 1024,
+            max_entry_bytes: 1024 * 1024,
+            max_total_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for SiteFilesConfig {
+    fn default() -> Self {
+        Self {
+            robots_txt: None,
+            robots_path: None,
+            favicon_path: None,
+            favicon_max_age: 86400,
+            quiet: true,
+        }
+    }
+}
+
+impl Default for RoutesConfig {
+    fn default() -> Self {
+        Self {
+            enable_echo: false,
+            enable_user_agent: false,
+            enable_files: true,
+            files_read_only: true,
+        }
+    }
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            upstream: String::new(),
+            percentage: 100.0,
+            paths: vec!["/".to_string()],
+            max_body_bytes: 1024 * 1024,
+            max_in_flight: 64,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 5000,
+            timeout_ms: 30000,
+            pool_idle_timeout_secs: 90,
+            pool_max_idle_per_host: 32,
+            max_response_bytes: 10 * 1024 * 1024,
+            ca_path: None,
+        }
+    }
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://localhost:4318".to_string(),
+            service_name: "rust-https-server".to_string(),
+            export_metrics: false,
+            metrics_interval: 60,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            algorithm: "HS256".to_string(),
+            secret: None,
+            public_key_path: None,
+            issuer: None,
+            audience: None,
+            leeway: 60,
+            realm: "api".to_string(),
         }
     }
 }
 
 impl Config {
     pub fn load(config_path: Option<&str>) -> crate::Result<Self> {
-        let mut config = config::Config::default();
+        let config: Config = Self::sources(config_path)?.try_deserialize().unwrap_or_else(|_| Config::default());
+        Ok(config)
+    }
+
+    pub fn serve_directory(mut self, root_dir: &str) -> Self {
+        self.files.root_dir = root_dir.to_string();
+        self.files.enable_directory_listing = true;
+        self.files.allowed_extensions.clear();
+        self.files.spa_fallback = None;
+        self
+    }
+
+    pub fn try_load(config_path: Option<&str>) -> crate::Result<Self> {
+        if let Some(path) = config_path.filter(|path| !Path::new(path).exists()) {
+            return Err(crate::Error::Config(format!("Configuration file not found: {}", path)));
+        }
+        Ok(Self::sources(config_path)?.try_deserialize()?)
+    }
+
+    fn sources(config_path: Option<&str>) -> crate::Result<config::Config> {
+        let mut builder = config::Config::builder();
 
         if let Some(path) = config_path {
             if Path::new(path).exists() {
-                config.merge(config::File::with_name(path))?;
+                builder = builder.add_source(config::File::with_name(path));
             }
         }
 
-        config.merge(config::Environment::with_prefix("RUST_HTTP_SERVER"))?;
-
-        let config: Config = config.try_deserialize().unwrap_or_else(|_| Config::default());
-        Ok(config)
+        builder = builder.add_source(config::Environment::with_prefix("RUST_HTTP_SERVER"));
+        Ok(builder.build()?)
     }
 
     pub fn validate(&self) -> crate::Result<()> {
@@ -125,6 +1099,24 @@ impl Config {
             return Err(crate::Error::Config("Port cannot be 0".to_string()));
         }
 
+        let missing_port = |address: &&String| address.rsplit_once(':').is_none_or(|(_, port)| port.parse::<u16>().is_err());
+        if let Some(address) = self.server.listen.iter().find(missing_port) {
+            return Err(crate::Error::Config(format!("Listen address must include a port: {}", address)));
+        }
+
+        if self.admin.enabled {
+            let admin = socket_address(&self.admin.host, self.admin.port);
+            if self.server.listen_addresses().iter().any(|address| same_listen_address(address, &admin)) {
+                return Err(crate::Error::Config(format!("Admin listener {} overlaps the main listener", admin)));
+            }
+        }
+
+        if let Some(port) = self.server.redirect_http_port {
+            if port == 0 || port == self.server.port {
+                return Err(crate::Error::Config(format!("Invalid redirect_http_port: {}", port)));
+            }
+        }
+
         if self.server.workers == 0 {
             return Err(crate::Error::Config("Workers cannot be 0".to_string()));
         }
@@ -137,6 +1129,174 @@ impl Config {
             return Err(crate::Error::Config("Max file size cannot be 0".to_string()));
         }
 
+        let security = &self.security;
+        for cidr in security.trusted_proxies.iter().chain(&security.ip_allow).chain(&security.ip_deny) {
+            crate::utils::Cidr::parse(cidr)?;
+        }
+
+        for value in self.files.cache_control.values().chain(&self.files.cache_control_default) {
+            if http::HeaderValue::from_str(value).is_err() {
+                return Err(crate::Error::Config(format!("Invalid Cache-Control value: {:?}", value)));
+            }
+        }
+
+        for (extension, value) in &self.files.mime_types {
+            if value.parse::<mime::Mime>().is_err() || http::HeaderValue::from_str(value).is_err() {
+                return Err(crate::Error::Config(format!("Invalid MIME type for .{}: {:?}", extension, value)));
+            }
+        }
+        if !self.files.text_charset.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:+".contains(&b)) {
+            return Err(crate::Error::Config(format!("Invalid text_charset: {:?}", self.files.text_charset)));
+        }
+        if self.files.max_ranges == 0 {
+            return Err(crate::Error::Config("files.max_ranges must be at least 1".to_string()));
+        }
+        if let Some(unknown) = self.files.upload_validation.types.iter().find(|mime| !crate::utils::mime::has_signature(&mime.trim().to_ascii_lowercase())) {
+            return Err(crate::Error::Config(format!("files.upload_validation.types has no known signature for {:?}", unknown)));
+        }
+
+        if self.site_files.robots_txt.is_some() && self.site_files.robots_path.is_some() {
+            return Err(crate::Error::Config("site_files.robots_txt and site_files.robots_path are mutually exclusive".to_string()));
+        }
+
+        if self.mirror.enabled {
+            let upstream = self.mirror.upstream.parse::<http::Uri>().ok();
+            if upstream.as_ref().is_none_or(|uri| !matches!(uri.scheme_str(), Some("http" | "https")) || uri.authority().is_none()) {
+                return Err(crate::Error::Config(format!("mirror.upstream must be an http:// or https:// URL: {:?}", self.mirror.upstream)));
+            }
+            if !(0.0..=100.0).contains(&self.mirror.percentage) {
+                return Err(crate::Error::Config(format!("mirror.percentage must be between 0 and 100: {}", self.mirror.percentage)));
+            }
+        }
+
+        if let Some(path) = self.response_cache.paths.iter().find(|path| !path.starts_with('/')) {
+            return Err(crate::Error::Config(format!("Response cache path must start with '/': {}", path)));
+        }
+
+        if self.tls.enabled {
+            crate::tls::validate(&self.tls)?;
+        }
+
+        if self.security.rate_limit_requests > 0 && self.security.rate_limit_window == 0 {
+            return Err(crate::Error::Config("security.rate_limit_window must be at least 1 second".to_string()));
+        }
+        let store = &self.security.rate_limit_store;
+        if store.backend == RateLimitBackend::Redis {
+            if cfg!(not(feature = "redis")) {
+                return Err(crate::Error::Config("The redis rate limit backend requires the `redis` feature".to_string()));
+            }
+            if !store.redis_url.starts_with("redis://") && !store.redis_url.starts_with("rediss://") {
+                return Err(crate::Error::Config("security.rate_limit_store.redis_url must be a redis:// or rediss:// URL".to_string()));
+            }
+            if store.timeout_ms == 0 {
+                return Err(crate::Error::Config("security.rate_limit_store.timeout_ms must be at least 1".to_string()));
+            }
+        }
+
+        let csrf = &self.security.csrf;
+        if csrf.enabled {
+            let is_token = |name: &str| !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+            if !is_token(&csrf.cookie_name) {
+                return Err(crate::Error::Config(format!("security.csrf.cookie_name is not a valid cookie name: {:?}", csrf.cookie_name)));
+            }
+            if http::header::HeaderName::from_bytes(csrf.header_name.as_bytes()).is_err() {
+                return Err(crate::Error::Config(format!("security.csrf.header_name is not a valid header name: {:?}", csrf.header_name)));
+            }
+            if csrf.form_field.is_empty() {
+                return Err(crate::Error::Config("security.csrf.form_field must not be empty".to_string()));
+            }
+        }
+
+        if self.templates.dir.is_some() && cfg!(not(feature = "templates")) {
+            return Err(crate::Error::Config("templates.dir requires the `templates` feature".to_string()));
+        }
+
+        if self.otel.enabled {
+            if cfg!(not(feature = "otel")) {
+                return Err(crate::Error::Config("OpenTelemetry export requires the `otel` feature".to_string()));
+            }
+            if self.otel.export_metrics && self.otel.metrics_interval == 0 {
+                return Err(crate::Error::Config("otel.metrics_interval cannot be 0".to_string()));
+            }
+        }
+
+        if self.auth.enabled {
+            match self.auth.algorithm.as_str() {
+                "HS256" => {
+                    if self.auth.secret.as_deref().unwrap_or("").is_empty() {
+                        return Err(crate::Error::Config("HS256 auth requires a secret".to_string()));
+                    }
+                }
+                "RS256" => {
+                    if cfg!(not(feature = "rs256")) {
+                        return Err(crate::Error::Config("RS256 auth requires the `rs256` feature".to_string()));
+                    }
+                    if self.auth.public_key_path.is_none() {
+                        return Err(crate::Error::Config("RS256 auth requires a public_key_path".to_string()));
+                    }
+                }
+                other => {
+                    return Err(crate::Error::Config(format!("Unsupported auth algorithm: {}", other)));
+                }
+            }
+        }
+
         Ok(())
     }
-} 
\ No newline at end of file
+} 
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTED_FIELDS.contains(&self.field.as_str()) {
+            true => write!(f, "{} (redacted)", self.field),
+            false => write!(f, "{}: {} -> {}", self.field, self.old, self.new),
+        }
+    }
+}
+
+pub fn changed_fields(old: &Config, new: &Config) -> Vec<ConfigChange> {
+    let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+        return Vec::new();
+    };
+    let mut changes = Vec::new();
+    collect_changes(String::new(), &old, &new, &mut changes);
+    changes
+}
+
+fn collect_changes(field: String, old: &serde_json::Value, new: &serde_json::Value, changes: &mut Vec<ConfigChange>) {
+    match (old, new) {
+        (serde_json::Value::Object(old_fields), serde_json::Value::Object(new_fields)) if !is_reloadable_map(&field) => {
+            let mut keys: Vec<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let nested = match field.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", field, key),
+                };
+                let missing = serde_json::Value::Null;
+                collect_changes(nested, old_fields.get(key).unwrap_or(&missing), new_fields.get(key).unwrap_or(&missing), changes);
+            }
+        }
+        _ if old != new => changes.push(ConfigChange { field, old: old.clone(), new: new.clone() }),
+        _ => {}
+    }
+}
+
+fn is_reloadable_map(field: &str) -> bool {
+    matches!(field, "files.cache_control" | "logging.targets")
+}
+
+pub fn is_reloadable(field: &str) -> bool {
+    RELOADABLE_FIELDS.contains(&field)
+        || RELOADABLE_SECTIONS
+            .iter()
+            .any(|section| field.strip_prefix(section).is_some_and(|rest| rest.starts_with('.')))
+}
\ No newline at end of file
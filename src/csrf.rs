@@ -0,0 +1,143 @@
+use crate::{
+    config::CsrfConfig,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    utils, Error, Result,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::future::BoxFuture;
+use http::header::{self, HeaderName, HeaderValue};
+use http::Method;
+
+const TOKEN_BYTES: usize = 32;
+
+// The token issued to the current client, inserted as a request extension so
+// handlers can embed it in the forms they render.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrfToken {
+    value: String,
+    field: String,
+}
+
+impl CsrfToken {
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn field_name(&self) -> &str {
+        &self.field
+    }
+
+    pub fn hidden_input(&self) -> String {
+        format!(
+            "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+            utils::escape_html(&self.field),
+            utils::escape_html(&self.value)
+        )
+    }
+}
+
+// Double-submit cookie: safe requests get a random token cookie, and
+// POST/PUT/DELETE/PATCH must echo it back in a header or form field. A
+// cross-site page can make the browser send the cookie but cannot read it.
+#[derive(Debug, Clone)]
+pub struct Csrf {
+    cookie_name: String,
+    header_name: HeaderName,
+    form_field: String,
+    exempt_paths: Vec<String>,
+}
+
+impl Csrf {
+    pub fn from_config(config: &CsrfConfig) -> Result<Self> {
+        let header_name = HeaderName::from_bytes(config.header_name.as_bytes())
+            .map_err(|_| Error::Config(format!("Invalid CSRF header name: {:?}", config.header_name)))?;
+        Ok(Self {
+            cookie_name: config.cookie_name.clone(),
+            header_name,
+            form_field: config.form_field.clone(),
+            exempt_paths: config.exempt_paths.clone(),
+        })
+    }
+
+    fn is_exempt(&self, request: &Request) -> bool {
+        let path = request.path();
+        self.exempt_paths.iter().any(|prefix| {
+            path.strip_prefix(prefix.trim_end_matches('/'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    fn cookie_token(&self, request: &Request) -> Option<String> {
+        request
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == self.cookie_name)
+            .map(|(_, value)| value.to_string())
+            .filter(|value| URL_SAFE_NO_PAD.decode(value).is_ok_and(|bytes| bytes.len() == TOKEN_BYTES))
+    }
+
+    fn submitted_token(&self, request: &Request) -> Option<String> {
+        if let Some(value) = request.headers.get(&self.header_name) {
+            return value.to_str().ok().map(|value| value.trim().to_string());
+        }
+        request.form().ok()?.remove(&self.form_field)
+    }
+
+    fn set_cookie(&self, token: &str, secure: bool) -> Option<HeaderValue> {
+        let secure = if secure { "; Secure" } else { "" };
+        HeaderValue::from_str(&format!("{}={}; Path=/; SameSite=Lax{}", self.cookie_name, token, secure)).ok()
+    }
+}
+
+impl Middleware for Csrf {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        if self.is_exempt(&request) {
+            return next.run(request);
+        }
+        Box::pin(async move {
+            let cookie = self.cookie_token(&request);
+            if matches!(request.method, Method::POST | Method::PUT | Method::DELETE | Method::PATCH) {
+                let submitted = self.submitted_token(&request);
+                let valid = match (&cookie, &submitted) {
+                    (Some(expected), Some(submitted)) => constant_time_eq(expected.as_bytes(), submitted.as_bytes()),
+                    _ => false,
+                };
+                if !valid {
+                    return Err(Error::PermissionDenied("CSRF token missing or invalid".to_string()));
+                }
+            }
+
+            let secure = request.is_secure();
+            let (value, issued) = match cookie {
+                Some(value) => (value, false),
+                None => (generate_token(), true),
+            };
+            request.extensions.insert(CsrfToken {
+                value: value.clone(),
+                field: self.form_field.clone(),
+            });
+            let mut response = next.run(request).await?;
+            if let Some(cookie) = issued.then(|| self.set_cookie(&value, secure)).flatten() {
+                response.headers.append(header::SET_COOKIE, cookie);
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    bytes[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    bytes[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
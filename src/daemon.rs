@@ -0,0 +1,60 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+pub struct Daemon {
+    ready: File,
+}
+
+pub fn daemonize() -> io::Result<Daemon> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (mut waiting, ready) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    if fork()? {
+        drop(ready);
+        let mut status = [0u8; 1];
+        let code = match waiting.read(&mut status) {
+            Ok(1) => 0,
+            _ => 1,
+        };
+        unsafe { libc::_exit(code) };
+    }
+    drop(waiting);
+
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if fork()? {
+        unsafe { libc::_exit(0) };
+    }
+
+    Ok(Daemon { ready })
+}
+
+fn fork() -> io::Result<bool> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+impl Daemon {
+    pub fn ready(mut self, log_path: Option<&Path>) -> io::Result<()> {
+        let null = File::open("/dev/null")?;
+        let output = match log_path {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+            None => OpenOptions::new().write(true).open("/dev/null")?,
+        };
+        for (source, target) in [(&null, libc::STDIN_FILENO), (&output, libc::STDOUT_FILENO), (&output, libc::STDERR_FILENO)] {
+            if unsafe { libc::dup2(source.as_raw_fd(), target) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        self.ready.write_all(&[1])
+    }
+}
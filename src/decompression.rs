@@ -0,0 +1,68 @@
+use crate::{
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    Error, Result,
+};
+use bytes::Bytes;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::future::BoxFuture;
+use http::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use http::HeaderValue;
+use std::io::Read;
+
+#[derive(Debug, Clone)]
+pub struct RequestDecompression {
+    max_size: usize,
+}
+
+impl RequestDecompression {
+    pub fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+
+    pub fn decode(&self, request: &mut Request) -> Result<()> {
+        let Some(value) = request.headers.get(CONTENT_ENCODING) else {
+            return Ok(());
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| Error::UnsupportedEncoding("invalid Content-Encoding header".to_string()))?
+            .to_string();
+
+        let mut body = request.body.take().unwrap_or_default();
+        for coding in value.rsplit(',').map(str::trim).filter(|coding| !coding.is_empty()) {
+            body = match coding.to_ascii_lowercase().as_str() {
+                "identity" => body,
+                "gzip" | "x-gzip" => self.inflate(GzDecoder::new(&body[..]))?,
+                "deflate" => self.inflate(ZlibDecoder::new(&body[..]))?,
+                _ => return Err(Error::UnsupportedEncoding(coding.to_string())),
+            };
+        }
+
+        request.headers.remove(CONTENT_ENCODING);
+        request.headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+        request.body = Some(body);
+        Ok(())
+    }
+
+    fn inflate(&self, decoder: impl Read) -> Result<Bytes> {
+        let mut decoded = Vec::new();
+        decoder
+            .take(self.max_size as u64 + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|e| Error::BadRequest(format!("Invalid compressed body: {}", e)))?;
+        if decoded.len() > self.max_size {
+            return Err(Error::ContentTooLarge { limit: self.max_size, actual: None });
+        }
+        Ok(decoded.into())
+    }
+}
+
+impl Middleware for RequestDecompression {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        match self.decode(&mut request) {
+            Ok(()) => next.run(request),
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Running,
+    Draining,
+    Aborting,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DrainReport {
+    pub drained: usize,
+    pub aborted: usize,
+}
+
+// Tracks open connections so a shutdown can let them finish. Once draining
+// starts, idle keep-alive connections close and busy ones close after their
+// current response; whatever is left when the grace period ends is aborted.
+#[derive(Debug)]
+pub struct Drain {
+    phase: watch::Sender<Phase>,
+    open: AtomicUsize,
+    drained: AtomicUsize,
+    aborted: AtomicUsize,
+    closed: Notify,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drain {
+    pub fn new() -> Self {
+        Self {
+            phase: watch::Sender::new(Phase::Running),
+            open: AtomicUsize::new(0),
+            drained: AtomicUsize::new(0),
+            aborted: AtomicUsize::new(0),
+            closed: Notify::new(),
+        }
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.open.load(Ordering::Acquire)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.phase.borrow() != Phase::Running
+    }
+
+    pub fn signal(&self) -> DrainSignal {
+        DrainSignal(self.phase.subscribe())
+    }
+
+    pub fn track(self: &Arc<Self>) -> DrainGuard {
+        self.open.fetch_add(1, Ordering::AcqRel);
+        DrainGuard {
+            drain: self.clone(),
+            aborted: false,
+        }
+    }
+
+    pub async fn drain(&self, grace: Duration) -> DrainReport {
+        self.phase.send_replace(Phase::Draining);
+        if tokio::time::timeout(grace, self.all_closed()).await.is_err() {
+            self.phase.send_replace(Phase::Aborting);
+            self.all_closed().await;
+        }
+        DrainReport {
+            drained: self.drained.load(Ordering::Acquire),
+            aborted: self.aborted.load(Ordering::Acquire),
+        }
+    }
+
+    async fn all_closed(&self) {
+        loop {
+            let closed = self.closed.notified();
+            if self.open_connections() == 0 {
+                return;
+            }
+            closed.await;
+        }
+    }
+}
+
+// Held by a connection for as long as it is open.
+#[derive(Debug)]
+pub struct DrainGuard {
+    drain: Arc<Drain>,
+    aborted: bool,
+}
+
+impl DrainGuard {
+    pub fn abort(&mut self) {
+        self.aborted = true;
+    }
+}
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        if self.aborted {
+            self.drain.aborted.fetch_add(1, Ordering::AcqRel);
+        } else if self.drain.is_draining() {
+            self.drain.drained.fetch_add(1, Ordering::AcqRel);
+        }
+        self.drain.open.fetch_sub(1, Ordering::AcqRel);
+        self.drain.closed.notify_waiters();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DrainSignal(watch::Receiver<Phase>);
+
+impl DrainSignal {
+    pub fn is_draining(&self) -> bool {
+        *self.0.borrow() != Phase::Running
+    }
+
+    pub async fn draining(&mut self) {
+        if self.0.wait_for(|phase| *phase != Phase::Running).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    pub async fn aborting(&mut self) {
+        if self.0.wait_for(|phase| *phase == Phase::Aborting).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    config::FileConfig,
+    file_cache::CachedFile,
+    http::{Request, Response},
+    server::Server,
+    utils::{self, etag},
+    Error, Result,
+};
+use bytes::Bytes;
+use http::Method;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+pub use include_dir::Dir;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+// Files baked into the binary with `include_dir!`. ETags hash the embedded
+// bytes, so they only change when a rebuild changes the content; that is what
+// makes the immutable Cache-Control default safe.
+#[derive(Debug, Clone)]
+pub struct EmbeddedAssets {
+    files: HashMap<String, Arc<CachedFile>>,
+    cache_control: Option<String>,
+    max_ranges: usize,
+}
+
+impl EmbeddedAssets {
+    pub fn new(dir: &'static Dir<'static>) -> Self {
+        let mut files = HashMap::new();
+        collect(dir, &mut files);
+        Self {
+            files,
+            cache_control: Some(IMMUTABLE.to_string()),
+            max_ranges: FileConfig::default().max_ranges,
+        }
+    }
+
+    pub fn cache_control(mut self, value: Option<&str>) -> Self {
+        self.cache_control = value.map(str::to_string);
+        self
+    }
+
+    pub fn max_ranges(mut self, max_ranges: usize) -> Self {
+        self.max_ranges = max_ranges;
+        self
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Arc<CachedFile>> {
+        let path = path.trim_matches('/');
+        self.files.get(path).or_else(|| match path.is_empty() {
+            true => self.files.get("index.html"),
+            false => self.files.get(&format!("{}/index.html", path)),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub(crate) fn respond(&self, request: &Request) -> Result<Response> {
+        let filename = request.params.get("path").map(String::as_str).unwrap_or("");
+        let decoded = urlencoding::decode(filename).map_err(|_| Error::InvalidPath("Path is not valid UTF-8".to_string()))?;
+        let Some(file) = self.get(&decoded) else {
+            return Err(Error::RouteNotFound(request.path().to_string()));
+        };
+
+        let mut response = Response::ok()
+            .with_content_type(&file.mime_type)
+            .with_etag(&file.etag)
+            .with_header("accept-ranges", "bytes")
+            .with_body(file.body.clone());
+        if file.modified != UNIX_EPOCH {
+            response = response.with_last_modified(file.modified);
+        }
+        if let Some(cache_control) = &self.cache_control {
+            response = response.with_header("cache-control", cache_control);
+        }
+        let mut response = Server::with_range(request, response, file, self.max_ranges);
+        if request.method == Method::HEAD {
+            response.body = None;
+        }
+        Ok(response)
+    }
+}
+
+fn collect(dir: &'static Dir<'static>, files: &mut HashMap<String, Arc<CachedFile>>) {
+    for file in dir.files() {
+        let path = file.path().to_string_lossy().replace('\\', "/");
+        let contents = file.contents();
+        let embedded = CachedFile {
+            body: Bytes::from_static(contents),
+            mime_type: utils::get_mime_type(&path).to_string(),
+            etag: etag::from_bytes(contents),
+            modified: file.metadata().map_or(UNIX_EPOCH, |metadata| metadata.modified()),
+        };
+        files.insert(path, Arc::new(embedded));
+    }
+    for dir in dir.dirs() {
+        collect(dir, files);
+    }
+}
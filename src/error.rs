@@ -1,3 +1,5 @@
+use crate::http::Response;
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,6 +16,9 @@ pub enum Error {
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("Failed to bind {0}: {1}")]
+    Bind(String, std::io::Error),
+
     #[error("Route not found: {0}")]
     RouteNotFound(String),
 
@@ -35,12 +40,54 @@ pub enum Error {
     #[error("Invalid path: {0}")]
     InvalidPath(String),
 
-    #[error("Content too large: {0}")]
-    ContentTooLarge(usize),
+    #[error("Content too large: the limit is {limit} bytes")]
+    ContentTooLarge { limit: usize, actual: Option<usize> },
+
+    #[error("Too many requests: retry after {0}s")]
+    TooManyRequests(u64),
+
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    #[error("URI too long: request line exceeds {0} bytes")]
+    UriTooLong(usize),
+
+    #[error("Request header fields too large: {0}")]
+    HeaderFieldsTooLarge(String),
+
+    #[error("Not implemented: {0}")]
+    NotImplemented(String),
+
+    #[error("HTTP version not supported: {0}")]
+    VersionNotSupported(String),
+
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    #[error("Precondition required: {0}")]
+    PreconditionRequired(String),
+
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+
+    #[error("Upstream error: {0}")]
+    Upstream(String),
+
+    #[error("Loop detected: {0}")]
+    LoopDetected(String),
 
     #[error("Unsupported encoding: {0}")]
     UnsupportedEncoding(String),
 
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
@@ -58,22 +105,162 @@ pub enum Error {
 
     #[error("Invalid URI: {0}")]
     InvalidUri(#[from] http::uri::InvalidUri),
+
+    #[error("TLS error: {0}")]
+    Tls(#[from] rustls::Error),
+
+    #[cfg(feature = "http2")]
+    #[error("HTTP/2 error: {0}")]
+    Http2(#[from] h2::Error),
+
+    #[cfg(feature = "templates")]
+    #[error("Template error: {0}")]
+    Template(String),
+
+    #[error("{0}")]
+    Custom(Box<dyn ResponseError>),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-impl From<Error> for http::StatusCode {
-    fn from(err: Error) -> Self {
-        match err {
+impl Error {
+    pub fn status_code(&self) -> http::StatusCode {
+        match self {
+            Error::Parse(_) => http::StatusCode::BAD_REQUEST,
             Error::RouteNotFound(_) => http::StatusCode::NOT_FOUND,
             Error::MethodNotAllowed(_) => http::StatusCode::METHOD_NOT_ALLOWED,
             Error::BadRequest(_) => http::StatusCode::BAD_REQUEST,
             Error::FileNotFound(_) => http::StatusCode::NOT_FOUND,
             Error::PermissionDenied(_) => http::StatusCode::FORBIDDEN,
             Error::InvalidPath(_) => http::StatusCode::BAD_REQUEST,
-            Error::ContentTooLarge(_) => http::StatusCode::PAYLOAD_TOO_LARGE,
+            Error::ContentTooLarge { .. } => http::StatusCode::PAYLOAD_TOO_LARGE,
+            Error::TooManyRequests(_) => http::StatusCode::TOO_MANY_REQUESTS,
+            Error::ServiceUnavailable(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+            Error::UriTooLong(_) => http::StatusCode::URI_TOO_LONG,
+            Error::HeaderFieldsTooLarge(_) => http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            Error::NotImplemented(_) => http::StatusCode::NOT_IMPLEMENTED,
+            Error::VersionNotSupported(_) => http::StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            Error::Timeout(_) => http::StatusCode::GATEWAY_TIMEOUT,
+            Error::Conflict(_) => http::StatusCode::CONFLICT,
+            Error::PreconditionFailed(_) => http::StatusCode::PRECONDITION_FAILED,
+            Error::PreconditionRequired(_) => http::StatusCode::PRECONDITION_REQUIRED,
+            Error::InsufficientStorage(_) => http::StatusCode::INSUFFICIENT_STORAGE,
+            Error::Upstream(_) => http::StatusCode::BAD_GATEWAY,
+            Error::LoopDetected(_) => http::StatusCode::LOOP_DETECTED,
             Error::UnsupportedEncoding(_) => http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::UnsupportedMediaType(_) => http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::Custom(error) => error.status_code(),
             _ => http::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
-} 
\ No newline at end of file
+}
+
+impl Error {
+    // A stable, machine-readable name for the error, sent as `error.code` in
+    // structured error bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Parse(_) => "parse_error",
+            Error::RouteNotFound(_) => "route_not_found",
+            Error::MethodNotAllowed(_) => "method_not_allowed",
+            Error::BadRequest(_) => "bad_request",
+            Error::FileNotFound(_) => "file_not_found",
+            Error::PermissionDenied(_) => "permission_denied",
+            Error::InvalidPath(_) => "invalid_path",
+            Error::ContentTooLarge { .. } => "content_too_large",
+            Error::TooManyRequests(_) => "too_many_requests",
+            Error::ServiceUnavailable(_) => "service_unavailable",
+            Error::UriTooLong(_) => "uri_too_long",
+            Error::HeaderFieldsTooLarge(_) => "header_fields_too_large",
+            Error::NotImplemented(_) => "not_implemented",
+            Error::VersionNotSupported(_) => "version_not_supported",
+            Error::Timeout(_) => "timeout",
+            Error::Conflict(_) => "conflict",
+            Error::PreconditionFailed(_) => "precondition_failed",
+            Error::PreconditionRequired(_) => "precondition_required",
+            Error::InsufficientStorage(_) => "insufficient_storage",
+            Error::Upstream(_) => "upstream_error",
+            Error::LoopDetected(_) => "loop_detected",
+            Error::UnsupportedEncoding(_) => "unsupported_encoding",
+            Error::UnsupportedMediaType(_) => "unsupported_media_type",
+            Error::Custom(_) => "application_error",
+            _ => "internal_error",
+        }
+    }
+
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Error::ContentTooLarge { limit, actual } => Some(serde_json::json!({ "limit": limit, "actual": actual })),
+            Error::TooManyRequests(retry_after) => Some(serde_json::json!({ "retry_after": retry_after })),
+            Error::UriTooLong(limit) => Some(serde_json::json!({ "limit": limit })),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for http::StatusCode {
+    fn from(err: Error) -> Self {
+        err.status_code()
+    }
+}
+
+// Lets application error types decide their own status and body. Handlers
+// registered with `Router::add_handler` may return any `ResponseError`; the
+// error still travels through middleware as an `Err` and is rendered with
+// `error_response` at the end.
+pub trait ResponseError: fmt::Debug + fmt::Display + Send + Sync + 'static {
+    fn status_code(&self) -> http::StatusCode {
+        http::StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> Response {
+        let status = self.status_code();
+        match status.is_server_error() {
+            true => Response::new(status).with_text(status.canonical_reason().unwrap_or("Error")),
+            false => Response::new(status).with_text(&self.to_string()),
+        }
+    }
+
+    // The crate's own `Error` passes through unchanged so its responses keep
+    // content negotiation; everything else is wrapped in `Error::Custom`.
+    fn into_error(self) -> Error
+    where
+        Self: Sized,
+    {
+        Error::Custom(Box::new(self))
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> http::StatusCode {
+        Error::status_code(self)
+    }
+
+    fn error_response(&self) -> Response {
+        match self {
+            Error::Custom(error) => error.error_response(),
+            _ => {
+                let status = Error::status_code(self);
+                match status.is_server_error() {
+                    true => Response::new(status).with_text(status.canonical_reason().unwrap_or("Error")),
+                    false => Response::new(status).with_text(&self.to_string()),
+                }
+            }
+        }
+    }
+
+    fn into_error(self) -> Error {
+        self
+    }
+}
+
+// Any `anyhow::Error` becomes a 500; the full cause chain goes to the log and
+// only the reason phrase to the client.
+#[cfg(feature = "anyhow")]
+impl ResponseError for anyhow::Error {
+    fn error_response(&self) -> Response {
+        tracing::error!("Request failed: {:#}", self);
+        let status = http::StatusCode::INTERNAL_SERVER_ERROR;
+        Response::new(status).with_text(status.canonical_reason().unwrap_or("Error"))
+    }
+}
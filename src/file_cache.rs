@@ -0,0 +1,163 @@
+use crate::config::FileCacheConfig;
+use crate::utils::etag::{self, EntityTag};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub body: Bytes,
+    pub mime_type: String,
+    pub etag: EntityTag,
+    pub modified: SystemTime,
+}
+
+impl CachedFile {
+    pub fn new(body: Bytes, mime_type: &str, modified: SystemTime) -> Self {
+        let etag = etag::from_metadata(body.len() as u64, modified);
+        Self {
+            body,
+            mime_type: mime_type.to_string(),
+            etag,
+            modified,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    file: Arc<CachedFile>,
+    last_used: u64,
+    validated_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<PathBuf, Entry>,
+    total_bytes: usize,
+    clock: u64,
+}
+
+impl CacheState {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.total_bytes -= entry.file.body.len();
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(path, _)| path.clone());
+        if let Some(path) = oldest {
+            self.remove(&path);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileCache {
+    config: FileCacheConfig,
+    state: Mutex<CacheState>,
+}
+
+impl FileCache {
+    pub fn new(config: FileCacheConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn accepts(&self, size: usize) -> bool {
+        self.config.enabled && size <= self.config.max_file_size && size <= self.config.max_total_bytes
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Arc<CachedFile>> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let file = {
+            let mut state = self.state.lock();
+            state.clock += 1;
+            let clock = state.clock;
+            let entry = state.entries.get_mut(path)?;
+            entry.last_used = clock;
+            if entry.validated_at.elapsed() < Duration::from_millis(self.config.revalidate_ms) {
+                return Some(entry.file.clone());
+            }
+            entry.file.clone()
+        };
+
+        let fresh = std::fs::metadata(path)
+            .and_then(|metadata| Ok(metadata.is_file() && metadata.len() == file.body.len() as u64 && metadata.modified()? == file.modified))
+            .unwrap_or(false);
+
+        let mut state = self.state.lock();
+        if !fresh {
+            if state.entries.get(path).is_some_and(|entry| Arc::ptr_eq(&entry.file, &file)) {
+                state.remove(path);
+            }
+            return None;
+        }
+        if let Some(entry) = state.entries.get_mut(path) {
+            entry.validated_at = Instant::now();
+        }
+        Some(file)
+    }
+
+    pub fn insert(&self, path: PathBuf, file: CachedFile) -> Arc<CachedFile> {
+        let file = Arc::new(file);
+        let size = file.body.len();
+        if !self.accepts(size) {
+            return file;
+        }
+
+        let mut state = self.state.lock();
+        state.remove(&path);
+        while !state.entries.is_empty()
+            && (state.entries.len() >= self.config.max_entries || state.total_bytes + size > self.config.max_total_bytes)
+        {
+            state.evict_lru();
+        }
+        if self.config.max_entries == 0 {
+            return file;
+        }
+
+        state.clock += 1;
+        let entry = Entry {
+            file: file.clone(),
+            last_used: state.clock,
+            validated_at: Instant::now(),
+        };
+        state.total_bytes += size;
+        state.entries.insert(path, entry);
+        file
+    }
+
+    pub fn invalidate(&self, path: &Path) {
+        self.state.lock().remove(path);
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.state.lock().total_bytes
+    }
+}
\ No newline at end of file
@@ -1,7 +1,31 @@
-use bytes::Bytes;
-use http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, Version};
+use crate::utils::etag::{self, EntityTag};
+use crate::utils::http_date;
+use bytes::{BufMut, Bytes, BytesMut};
+use http::{Extensions, HeaderMap, HeaderValue, Method, StatusCode, Uri, Version};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io::IoSlice;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+pub const DEFAULT_JSON_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonLimit(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimit(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+// Inserted by the connection while the handler runs; flips to `true` when
+// the client closes or resets the connection before the response is sent.
+#[derive(Debug, Clone)]
+pub struct ClientGone(pub tokio::sync::watch::Receiver<bool>);
 
 #[derive(Debug, Clone)]
 pub struct Request {
@@ -11,13 +35,45 @@ pub struct Request {
     pub headers: HeaderMap,
     pub body: Option<Bytes>,
     pub params: HashMap<String, String>,
+    pub extensions: Extensions,
+    pub remote_addr: Option<SocketAddr>,
+    pub client_ip: Option<IpAddr>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Response {
     pub status: StatusCode,
+    pub version: Version,
     pub headers: HeaderMap,
     pub body: Option<Bytes>,
+    pub stream: Option<BodyStream>,
+}
+
+// A response body produced while it is being sent. Chunks arrive over a
+// bounded channel, so a slow client holds back the producer; an `Err` chunk
+// aborts the connection because the status line is already on the wire.
+#[derive(Debug, Clone)]
+pub struct BodyStream(Arc<parking_lot::Mutex<Option<mpsc::Receiver<std::io::Result<Bytes>>>>>);
+
+impl BodyStream {
+    pub fn channel(capacity: usize) -> (mpsc::Sender<std::io::Result<Bytes>>, Self) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (sender, Self(Arc::new(parking_lot::Mutex::new(Some(receiver)))))
+    }
+
+    pub fn take(&self) -> Option<mpsc::Receiver<std::io::Result<Bytes>>> {
+        self.0.lock().take()
+    }
+
+    pub async fn collect(&self) -> std::io::Result<Bytes> {
+        let mut body = BytesMut::new();
+        if let Some(mut receiver) = self.take() {
+            while let Some(chunk) = receiver.recv().await {
+                body.extend_from_slice(&chunk?);
+            }
+        }
+        Ok(body.freeze())
+    }
 }
 
 impl Request {
@@ -29,6 +85,9 @@ impl Request {
             headers: HeaderMap::new(),
             body: None,
             params: HashMap::new(),
+            extensions: Extensions::new(),
+            remote_addr: None,
+            client_ip: None,
         }
     }
 
@@ -36,14 +95,91 @@ impl Request {
         self.uri.path()
     }
 
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip.or_else(|| self.remote_addr.map(|addr| addr.ip()))
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.uri.scheme() == Some(&http::uri::Scheme::HTTPS) || self.extensions.get::<crate::tls::TlsInfo>().is_some()
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.extensions.get::<RequestId>().map(|id| id.0.as_str())
+    }
+
+    // Resolves once the client has gone away. The handler's own future is
+    // dropped at that point; this is for work it spawned elsewhere. Never
+    // resolves for requests that did not come from a connection.
+    pub fn cancelled(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let gone = self.extensions.get::<ClientGone>().map(|gone| gone.0.clone());
+        async move {
+            if let Some(mut gone) = gone {
+                if gone.wait_for(|gone| *gone).await.is_ok() {
+                    return;
+                }
+            }
+            std::future::pending().await
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.extensions.get::<ClientGone>().is_some_and(|gone| *gone.0.borrow())
+    }
+
+    pub fn client_identity(&self) -> Option<&crate::tls::ClientIdentity> {
+        self.extensions.get::<crate::tls::ClientIdentity>()
+    }
+
+    pub fn host(&self) -> Option<String> {
+        let authority = self
+            .headers
+            .get("host")
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| self.uri.authority().map(|authority| authority.as_str()))?
+            .trim();
+
+        let host = if authority.starts_with('[') {
+            authority.split_inclusive(']').next().unwrap_or(authority)
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+                _ => authority,
+            }
+        };
+        let host = host.trim_end_matches('.');
+        (!host.is_empty()).then(|| host.to_ascii_lowercase())
+    }
+
     pub fn query(&self) -> Option<&str> {
         self.uri.query()
     }
 
+    pub fn query_params(&self) -> crate::utils::QueryParams {
+        crate::utils::QueryParams::parse(self.query().unwrap_or_default())
+    }
+
+    pub fn query_as<T>(&self) -> crate::Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        self.query_params().deserialize()
+    }
+
     pub fn header(&self, name: &str) -> Option<&HeaderValue> {
         self.headers.get(name)
     }
 
+    pub fn extension<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.extensions.get::<T>()
+    }
+
     pub fn content_length(&self) -> Option<usize> {
         self.headers
             .get("content-length")
@@ -69,6 +205,55 @@ impl Request {
             .and_then(|v| v.to_str().ok())
     }
 
+    pub fn accepts_encoding(&self, coding: &str) -> bool {
+        let Some(header) = self.accept_encoding() else {
+            return false;
+        };
+
+        let mut wildcard = None;
+        for item in header.split(',') {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap_or("").trim();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if name.eq_ignore_ascii_case(coding) {
+                return quality > 0.0;
+            }
+            if name == "*" {
+                wildcard = Some(quality > 0.0);
+            }
+        }
+        wildcard.unwrap_or(false)
+    }
+
+    pub fn accepts(&self, mime: &str) -> bool {
+        let Some(accept) = self.header("accept").and_then(|value| value.to_str().ok()) else {
+            return true;
+        };
+        crate::utils::accept_quality(&crate::utils::parse_accept(accept), mime).is_some_and(|(quality, _)| quality > 0.0)
+    }
+
+    pub fn preferred<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        let Some(accept) = self.header("accept").and_then(|value| value.to_str().ok()) else {
+            return offers.first().copied();
+        };
+        let ranges = crate::utils::parse_accept(accept);
+
+        let mut best: Option<(&'a str, (f32, u8))> = None;
+        for &offer in offers {
+            let Some(score) = crate::utils::accept_quality(&ranges, offer).filter(|(quality, _)| *quality > 0.0) else {
+                continue;
+            };
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((offer, score));
+            }
+        }
+        best.map(|(offer, _)| offer)
+    }
+
     pub fn supports_gzip(&self) -> bool {
         self.accept_encoding()
             .map(|encoding| encoding.contains("gzip"))
@@ -79,6 +264,62 @@ impl Request {
         self.body.as_ref().map(|b| String::from_utf8_lossy(b).to_string())
     }
 
+    pub fn multipart(&self) -> crate::Result<crate::multipart::Multipart> {
+        let content_type = self
+            .content_type()
+            .ok_or_else(|| crate::Error::BadRequest("Missing Content-Type".to_string()))?;
+        crate::multipart::Multipart::from_content_type(self.body.clone().unwrap_or_default(), content_type)
+    }
+
+    pub fn form(&self) -> crate::Result<HashMap<String, String>> {
+        let body = self.form_body()?;
+        Ok(url::form_urlencoded::parse(body).into_owned().collect())
+    }
+
+    pub fn form_as<T>(&self) -> crate::Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let body = self.form_body()?;
+        serde_html_form::from_bytes(body).map_err(|e| crate::Error::BadRequest(format!("Invalid form body: {}", e)))
+    }
+
+    fn form_body(&self) -> crate::Result<&[u8]> {
+        let content_type = self.content_type().unwrap_or_default();
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        if !media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Err(crate::Error::UnsupportedEncoding(format!(
+                "expected application/x-www-form-urlencoded, got {:?}",
+                content_type
+            )));
+        }
+        Ok(self.body.as_deref().unwrap_or_default())
+    }
+
+    pub fn json<T>(&self) -> crate::Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let content_type = self.content_type().unwrap_or_default();
+        let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        if media_type != "application/json" && !(media_type.starts_with("application/") && media_type.ends_with("+json")) {
+            return Err(crate::Error::UnsupportedEncoding(format!("expected application/json, got {:?}", content_type)));
+        }
+
+        let limit = self.extension::<JsonLimit>().map_or(DEFAULT_JSON_LIMIT, |limit| limit.0);
+        let body = self.body.as_deref().unwrap_or_default();
+        if body.len() > limit {
+            return Err(crate::Error::ContentTooLarge { limit, actual: Some(body.len()) });
+        }
+        if body.is_empty() {
+            return Err(crate::Error::BadRequest("Request body is required for JSON parsing".to_string()));
+        }
+
+        serde_json::from_slice(body).map_err(|e| {
+            crate::Error::BadRequest(format!("Invalid JSON: {} (near `{}`)", e, json_snippet(body, e.line(), e.column())))
+        })
+    }
+
     pub fn body_as_json<T>(&self) -> crate::Result<T>
     where
         T: for<'de> Deserialize<'de>,
@@ -91,12 +332,31 @@ impl Request {
     }
 }
 
+fn json_snippet(body: &[u8], line: usize, column: usize) -> String {
+    const CONTEXT: usize = 20;
+    let line_start: usize = body
+        .split(|&b| b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum();
+    let position = (line_start + column.saturating_sub(1)).min(body.len());
+    let start = position.saturating_sub(CONTEXT);
+    let end = (position + CONTEXT).min(body.len());
+    let snippet: String = String::from_utf8_lossy(&body[start..end])
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    format!("{}{}{}", if start > 0 { "..." } else { "" }, snippet, if end < body.len() { "..." } else { "" })
+}
+
 impl Response {
     pub fn new(status: StatusCode) -> Self {
         Self {
             status,
+            version: Version::HTTP_11,
             headers: HeaderMap::new(),
             body: None,
+            stream: None,
         }
     }
 
@@ -120,10 +380,40 @@ impl Response {
         Self::new(StatusCode::CREATED)
     }
 
+    pub fn redirect(location: &str) -> Self {
+        Self::redirect_with(StatusCode::FOUND, location)
+    }
+
+    pub fn see_other(location: &str) -> Self {
+        Self::redirect_with(StatusCode::SEE_OTHER, location)
+    }
+
+    pub fn temporary_redirect(location: &str) -> Self {
+        Self::redirect_with(StatusCode::TEMPORARY_REDIRECT, location)
+    }
+
+    pub fn permanent_redirect(location: &str) -> Self {
+        Self::redirect_with(StatusCode::PERMANENT_REDIRECT, location)
+    }
+
+    pub fn redirect_with(status: StatusCode, location: &str) -> Self {
+        let mut response = Self::new(status);
+        let location = crate::utils::encode_location(location);
+        if let Ok(value) = HeaderValue::from_str(&location) {
+            response.headers.insert("location", value);
+        }
+        response.with_content_length(0)
+    }
+
     pub fn method_not_allowed() -> Self {
         Self::new(StatusCode::METHOD_NOT_ALLOWED)
     }
 
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
     pub fn with_header(mut self, name: &str, value: &str) -> Self {
         if let Ok(header_value) = HeaderValue::from_str(value) {
             if let Ok(header_name) = http::header::HeaderName::from_lowercase(name.as_bytes()) {
@@ -133,6 +423,21 @@ impl Response {
         self
     }
 
+    pub fn with_attachment(self, filename: &str) -> Self {
+        let name: String = filename.chars().filter(|c| !c.is_control()).collect();
+        let fallback: String = name
+            .chars()
+            .map(|c| if c.is_ascii() && c != '"' && c != '\\' { c } else { '_' })
+            .collect();
+
+        let mut value = format!("attachment; filename=\"{}\"", fallback);
+        if fallback != name {
+            value.push_str("; filename*=UTF-8''");
+            value.push_str(&urlencoding::encode(&name));
+        }
+        self.with_header("content-disposition", &value)
+    }
+
     pub fn with_content_type(mut self, content_type: &str) -> Self {
         self.headers.insert("content-type", HeaderValue::from_str(content_type).unwrap_or_else(|_| HeaderValue::from_static("text/plain")));
         self
@@ -150,6 +455,14 @@ impl Response {
         self
     }
 
+    pub fn with_stream(mut self, stream: BodyStream) -> Self {
+        self.headers.remove("content-length");
+        self.headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+        self.body = None;
+        self.stream = Some(stream);
+        self
+    }
+
     pub fn with_json<T>(mut self, data: &T) -> crate::Result<Self>
     where
         T: Serialize,
@@ -178,6 +491,14 @@ impl Response {
         self
     }
 
+    #[cfg(feature = "templates")]
+    pub fn with_template<S>(self, templates: &crate::templates::Templates, name: &str, context: S) -> crate::Result<Self>
+    where
+        S: Serialize,
+    {
+        Ok(self.with_html(&templates.render(name, context)?))
+    }
+
     pub fn with_cors(mut self, origin: &str) -> Self {
         self.headers.insert("access-control-allow-origin", HeaderValue::from_str(origin).unwrap_or_else(|_| HeaderValue::from_static("*")));
         self.headers.insert("access-control-allow-methods", HeaderValue::from_static("GET, POST, PUT, DELETE, OPTIONS"));
@@ -185,23 +506,121 @@ impl Response {
         self
     }
 
+    pub fn with_etag(mut self, etag: &EntityTag) -> Self {
+        if let Ok(value) = HeaderValue::from_str(&etag.to_string()) {
+            self.headers.insert("etag", value);
+        }
+        self
+    }
+
+    pub fn with_last_modified(self, time: SystemTime) -> Self {
+        self.with_header("last-modified", &http_date::format(time))
+    }
+
+    pub fn etag(&self) -> Option<EntityTag> {
+        self.headers.get("etag")?.to_str().ok().and_then(EntityTag::parse)
+    }
+
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        http_date::parse(self.headers.get("last-modified")?.to_str().ok()?)
+    }
+
+    pub fn into_not_modified(mut self) -> Self {
+        self.status = StatusCode::NOT_MODIFIED;
+        self.body = None;
+        self.stream = None;
+        for name in ["content-length", "content-type", "content-range", "transfer-encoding"] {
+            self.headers.remove(name);
+        }
+        self
+    }
+
     pub fn with_compression(mut self, encoding: &str) -> Self {
         self.headers.insert("content-encoding", HeaderValue::from_str(encoding).unwrap_or_else(|_| HeaderValue::from_static("identity")));
         self
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut response = Vec::new();
-        
-        let status_line = format!("HTTP/1.1 {} {}\r\n", self.status.as_u16(), self.status.canonical_reason().unwrap_or("Unknown"));
-        response.extend_from_slice(status_line.as_bytes());
-        
+    pub fn write_head<B: BufMut>(&self, buf: &mut B) {
+        let mut status = itoa::Buffer::new();
+        buf.put_slice(match self.version {
+            Version::HTTP_10 => b"HTTP/1.0 ",
+            _ => b"HTTP/1.1 ",
+        });
+        buf.put_slice(status.format(self.status.as_u16()).as_bytes());
+        buf.put_u8(b' ');
+        buf.put_slice(self.status.canonical_reason().unwrap_or("Unknown").as_bytes());
+        buf.put_slice(b"\r\n");
+
         for (name, value) in &self.headers {
-            let header_line = format!("{}: {}\r\n", name.as_str(), value.to_str().unwrap_or(""));
-            response.extend_from_slice(header_line.as_bytes());
+            buf.put_slice(name.as_str().as_bytes());
+            buf.put_slice(b": ");
+            buf.put_slice(value.as_bytes());
+            buf.put_slice(b"\r\n");
         }
-        
-        response.extend_from_slice(b"\r\n");
+
+        buf.put_slice(b"\r\n");
+    }
+
+    pub async fn write_to<W>(&self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut head = BytesMut::with_capacity(128 + self.headers.len() * 32);
+        self.write_with(writer, &mut head).await
+    }
+
+    // Like `write_to`, but the head is serialized into `head`, which is
+    // cleared first, so a pooled buffer can be reused across responses.
+    pub async fn write_with<W>(&self, writer: &mut W, head: &mut BytesMut) -> std::io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        head.clear();
+        self.write_head(head);
+
+        let mut head = &head[..];
+        let mut body = self.body.as_deref().unwrap_or(&[]);
+
+        while !head.is_empty() {
+            let n = writer.write_vectored(&[IoSlice::new(head), IoSlice::new(body)]).await?;
+            if n == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            if n >= head.len() {
+                body = &body[n - head.len()..];
+                head = &[];
+            } else {
+                head = &head[n..];
+            }
+        }
+
+        writer.write_all(body).await?;
+
+        if let Some(mut receiver) = self.stream.as_ref().and_then(BodyStream::take) {
+            let chunked = self.version != Version::HTTP_10;
+            while let Some(chunk) = receiver.recv().await {
+                let chunk = chunk?;
+                if chunk.is_empty() {
+                    continue;
+                }
+                if chunked {
+                    writer.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await?;
+                    writer.write_all(&chunk).await?;
+                    writer.write_all(b"\r\n").await?;
+                } else {
+                    writer.write_all(&chunk).await?;
+                }
+            }
+            if chunked {
+                writer.write_all(b"0\r\n\r\n").await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut response = Vec::new();
+        self.write_head(&mut response);
         
         if let Some(body) = &self.body {
             response.extend_from_slice(body);
@@ -211,6 +630,42 @@ impl Response {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConditionalGet {
+    if_none_match: Option<String>,
+    if_modified_since: Option<SystemTime>,
+}
+
+impl ConditionalGet {
+    pub(crate) fn from_request(request: &Request) -> Self {
+        if request.method != Method::GET && request.method != Method::HEAD {
+            return Self::default();
+        }
+        let header = |name| request.headers.get(name).and_then(|value| value.to_str().ok());
+        Self {
+            if_none_match: header("if-none-match").map(str::to_string),
+            if_modified_since: header("if-modified-since").and_then(http_date::parse),
+        }
+    }
+
+    // RFC 7232 section 6: If-Modified-Since is only evaluated when the
+    // request has no If-None-Match.
+    pub(crate) fn apply(&self, response: Response) -> Response {
+        if !matches!(response.status, StatusCode::OK | StatusCode::PARTIAL_CONTENT) {
+            return response;
+        }
+        let not_modified = match (&self.if_none_match, self.if_modified_since) {
+            (Some(if_none_match), _) => response.etag().is_some_and(|etag| etag::if_none_match_matches(if_none_match, &etag)),
+            (None, Some(since)) => response.last_modified().is_some_and(|modified| modified <= since),
+            (None, None) => false,
+        };
+        match not_modified {
+            true => response.into_not_modified(),
+            false => response,
+        }
+    }
+}
+
 impl Default for Response {
     fn default() -> Self {
         Self::ok()
@@ -0,0 +1,228 @@
+use crate::{
+    config::Config,
+    http::{BodyStream, Request, Response},
+    metrics::Metrics,
+    redact::Redactor,
+    router::Router,
+    server::Server,
+    tls::TlsInfo,
+    utils,
+    Error, Result,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use http::{StatusCode, Version};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{debug, Instrument};
+
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const CONNECTION_HEADERS: &[&str] = &["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+pub(crate) struct Rewind<T> {
+    prefix: Bytes,
+    inner: T,
+}
+
+impl<T> Rewind<T> {
+    pub(crate) fn new(prefix: Bytes, inner: T) -> Self {
+        Self { prefix, inner }
+    }
+}
+
+impl<T> AsyncRead for Rewind<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        if !self.prefix.is_empty() {
+            let n = self.prefix.len().min(buf.remaining());
+            buf.put_slice(&self.prefix[..n]);
+            self.prefix.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for Rewind<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+pub(crate) async fn serve_connection<T>(
+    io: T,
+    addr: SocketAddr,
+    tls_info: Option<TlsInfo>,
+    config: Arc<Config>,
+    router: Arc<Router>,
+    metrics: Arc<Metrics>,
+) -> Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut connection = h2::server::Builder::new()
+        .max_concurrent_streams(config.performance.http2_max_concurrent_streams)
+        .initial_window_size(config.performance.http2_initial_window_size)
+        .initial_connection_window_size(config.performance.http2_initial_connection_window_size)
+        .handshake::<_, Bytes>(io)
+        .await?;
+
+    debug!("HTTP/2 connection established with {}", addr);
+
+    while let Some(stream) = connection.accept().await {
+        let (request, respond) = stream?;
+        let config = config.clone();
+        let router = router.clone();
+        let metrics = metrics.clone();
+        let tls_info = tls_info.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = serve_stream(request, respond, addr, tls_info, config, router, metrics).await {
+                debug!("HTTP/2 stream error: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn serve_stream(
+    request: http::Request<h2::RecvStream>,
+    mut respond: h2::server::SendResponse<Bytes>,
+    addr: SocketAddr,
+    tls_info: Option<TlsInfo>,
+    config: Arc<Config>,
+    router: Arc<Router>,
+    metrics: Arc<Metrics>,
+) -> Result<()> {
+    let (parts, mut body) = request.into_parts();
+    let mut request = Request::new(parts.method, parts.uri, Version::HTTP_2);
+    request.headers = parts.headers;
+    request.remote_addr = Some(addr);
+    if let Some(info) = tls_info {
+        if let Some(identity) = &info.client_identity {
+            request.extensions.insert(identity.clone());
+        }
+        request.extensions.insert(info);
+    }
+
+    // The body is held to the same limit as on HTTP/1 and counted against the
+    // buffer budget while it is read, so a client cannot stream an unbounded
+    // body into memory. Flow-control capacity is only released for accepted data.
+    let limit = Server::body_limit(&request, &config, &router);
+    if let Some(length) = request.content_length().filter(|&length| length > limit) {
+        let response = Server::error_response_for(&request, Error::ContentTooLarge { limit, actual: Some(length) });
+        return refuse(&mut respond, response);
+    }
+    let buffer_wait = Duration::from_millis(config.performance.buffer_wait_ms);
+    let mut buffered = metrics.buffer_budget().reservation();
+    let mut data = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let total = data.len() + chunk.len();
+        if total > limit {
+            let response = Server::error_response_for(&request, Error::ContentTooLarge { limit, actual: None });
+            return refuse(&mut respond, response);
+        }
+        if !buffered.grow_to(total, buffer_wait).await {
+            debug!("Refusing HTTP/2 stream from {}: buffer budget exhausted", addr);
+            let response = Response::new(StatusCode::SERVICE_UNAVAILABLE)
+                .with_header("retry-after", "1")
+                .with_text("Service Unavailable");
+            return refuse(&mut respond, response);
+        }
+        let _ = body.flow_control().release_capacity(chunk.len());
+        data.extend_from_slice(&chunk);
+    }
+    if !data.is_empty() {
+        request.body = Some(data.freeze());
+    }
+
+    let started = Instant::now();
+    let span = Server::request_span(&mut request, addr, &Redactor::from_config(&config.logging));
+    let quiet = config.site_files.is_quiet(request.path());
+    let response = Server::respond(request, &config, &router, &metrics).instrument(span.clone()).await;
+    let status = response.status;
+    let receiver = response.stream.as_ref().and_then(BodyStream::take);
+    let stream = span.in_scope(|| send_response(&mut respond, response, receiver.is_some()))?;
+    if let (Some(mut stream), Some(mut receiver)) = (stream, receiver) {
+        while let Some(chunk) = receiver.recv().await {
+            match chunk {
+                Ok(chunk) => send_data(&mut stream, chunk).await?,
+                Err(_) => {
+                    stream.send_reset(h2::Reason::INTERNAL_ERROR);
+                    return Ok(());
+                }
+            }
+        }
+        stream.send_data(Bytes::new(), true)?;
+    }
+    Server::finish_request_span(&span, status, started, quiet);
+    Ok(())
+}
+
+// Sends a streamed chunk only as fast as the client's flow-control window
+// allows, so a slow reader holds back the producer instead of having the whole
+// body queued in h2's send buffer.
+async fn send_data(stream: &mut h2::SendStream<Bytes>, mut chunk: Bytes) -> Result<()> {
+    while !chunk.is_empty() {
+        stream.reserve_capacity(chunk.len());
+        let granted = match std::future::poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(granted) => granted?,
+            None => return Err(h2::Error::from(h2::Reason::STREAM_CLOSED).into()),
+        };
+        if granted > 0 {
+            stream.send_data(chunk.split_to(granted.min(chunk.len())), false)?;
+        }
+    }
+    Ok(())
+}
+
+// Answers without reading the rest of the body. Dropping the body afterwards
+// makes h2 reset the stream once the response is flushed, which tells the
+// client to stop sending. An explicit reset here would discard the queued
+// response.
+fn refuse(respond: &mut h2::server::SendResponse<Bytes>, response: Response) -> Result<()> {
+    send_response(respond, response, false)?;
+    Ok(())
+}
+
+fn send_response(
+    respond: &mut h2::server::SendResponse<Bytes>,
+    response: Response,
+    streaming: bool,
+) -> Result<Option<h2::SendStream<Bytes>>> {
+    let mut head = http::Response::new(());
+    *head.status_mut() = response.status;
+    *head.version_mut() = Version::HTTP_2;
+    for (name, value) in &response.headers {
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            head.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    head.headers_mut().entry("date").or_insert_with(utils::http_date::current_header);
+
+    let body = response.body.filter(|body| !body.is_empty());
+    let mut stream = respond.send_response(head, body.is_none() && !streaming)?;
+    if let Some(body) = body {
+        stream.send_data(body, !streaming)?;
+    }
+    Ok(streaming.then_some(stream))
+}
@@ -1,22 +1,66 @@
+pub mod archive;
+pub mod audit;
+pub mod auth;
+pub mod budget;
+pub mod buffer_pool;
+pub mod client;
 pub mod config;
+pub mod csrf;
+#[cfg(all(unix, feature = "daemon"))]
+pub mod daemon;
+pub mod decompression;
+pub mod drain;
+#[cfg(feature = "embed")]
+pub mod embed;
 pub mod error;
+pub mod file_cache;
 pub mod http;
+#[cfg(feature = "http2")]
+pub mod http2;
+pub mod logging;
+pub mod maintenance;
+pub mod methods;
+pub mod metrics;
+pub mod middleware;
+pub mod mirror;
+pub mod multipart;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod parser;
+pub mod pidfile;
+pub mod rate_limit;
+pub mod redact;
+pub mod redirect;
+pub mod response_cache;
+pub mod rewrite;
+mod route_tree;
 pub mod router;
+pub mod security;
 pub mod server;
+pub mod site_files;
+pub mod storage;
+#[cfg(unix)]
+pub mod systemd;
+#[cfg(feature = "templates")]
+pub mod templates;
+pub mod testing;
+pub mod tls;
+pub mod upload;
 pub mod utils;
+pub mod webdav;
 
-pub use error::{Error, Result};
+pub use error::{Error, ResponseError, Result};
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::http::{Request, Response};
-    use http::{Method, Uri, Version};
+    use ::http::{Method, Uri, Version};
 
     #[test]
     fn test_response_creation() {
         let response = Response::ok().with_text("Hello, World!");
-        assert_eq!(response.status, http::StatusCode::OK);
+        assert_eq!(response.status, ::http::StatusCode::OK);
         assert!(response.body.is_some());
     }
 
@@ -24,7 +68,7 @@ mod tests {
     fn test_request_creation() {
         let uri = "http://localhost:4221/test".parse::<Uri>().unwrap();
         let request = Request::new(Method::GET, uri, Version::HTTP_11);
-        assert_eq!(request.method(), Method::GET);
+        assert_eq!(request.method, Method::GET);
         assert_eq!(request.path(), "/test");
     }
 
@@ -40,6 +84,228 @@ mod tests {
         assert!(utils::sanitize_path("test.txt").is_ok());
         assert!(utils::sanitize_path("../test.txt").is_err());
         assert!(utils::sanitize_path("test\\file.txt").is_err());
+
+        for (input, expected) in [
+            ("", ""),
+            ("/", ""),
+            ("/docs/readme.txt", "docs/readme.txt"),
+            ("docs//./guide/", "docs/guide"),
+            ("a..b.txt", "a..b.txt"),
+            ("%2e%2e/secret.txt", "%2e%2e/secret.txt"),
+            ("nul.txt", "nul.txt"),
+        ] {
+            assert_eq!(utils::sanitize_path(input).unwrap(), expected, "{:?}", input);
+        }
+
+        let deep = vec!["d"; utils::MAX_PATH_DEPTH + 1].join("/");
+        let long = "x".repeat(utils::MAX_PATH_COMPONENT + 1);
+        for attack in [
+            "..",
+            "/../etc/passwd",
+            "docs/../../etc/passwd",
+            "docs/./../..",
+            "..\\..\\windows\\win.ini",
+            "C:\\Windows\\system.ini",
+            "file.txt\0.png",
+            "line\nbreak.txt",
+            deep.as_str(),
+            long.as_str(),
+        ] {
+            assert!(utils::sanitize_path(attack).is_err(), "{:?}", attack);
+        }
+
+        for reserved in ["CON", "nul.txt", "com1.log", "Lpt9", "aux .txt", "C:", "file.", "name ", "a|b"] {
+            assert!(utils::is_windows_unsafe_component(reserved), "{:?}", reserved);
+        }
+        for allowed in ["console.txt", "nullable", "com10", ".hidden", "report.final.pdf"] {
+            assert!(!utils::is_windows_unsafe_component(allowed), "{:?}", allowed);
+        }
+    }
+
+    fn path_segment() -> impl proptest::strategy::Strategy<Value = String> {
+        use proptest::prelude::*;
+        prop_oneof![
+            Just("..".to_string()),
+            Just(".".to_string()),
+            Just(String::new()),
+            Just("%2e%2e".to_string()),
+            Just("C:".to_string()),
+            Just("\\".to_string()),
+            Just("\0".to_string()),
+            "[a-zA-Z0-9._ -]{1,12}",
+            any::<String>(),
+        ]
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_sanitized_path_never_escapes_root(segments in proptest::collection::vec(path_segment(), 0..40), absolute in proptest::bool::ANY) {
+            let path = format!("{}{}", if absolute { "/" } else { "" }, segments.join("/"));
+            if let Ok(sanitized) = utils::sanitize_path(&path) {
+                let root = std::path::Path::new("/srv/files");
+                let joined = root.join(&sanitized);
+                proptest::prop_assert!(joined.starts_with(root), "{:?} -> {:?}", path, sanitized);
+                proptest::prop_assert!(
+                    std::path::Path::new(&sanitized).components().all(|component| matches!(component, std::path::Component::Normal(_))),
+                    "{:?} -> {:?}", path, sanitized
+                );
+                proptest::prop_assert!(sanitized.split('/').count() <= utils::MAX_PATH_DEPTH);
+                proptest::prop_assert_eq!(utils::sanitize_path(&sanitized).unwrap(), sanitized);
+            }
+        }
+    }
+
+    #[test]
+    fn test_utils_query_params() {
+        use utils::{QueryOptions, QueryParams};
+
+        let params = QueryParams::parse("tag=a&tag=b&name=first&name=second");
+        assert_eq!(params.all("tag").collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(params.first("name"), Some("first"));
+        assert_eq!(params.first("missing"), None);
+        assert_eq!(params.all("missing").count(), 0);
+        assert_eq!(params.len(), 4);
+
+        let params = QueryParams::parse("?debug&verbose=&x=1");
+        assert!(params.contains("debug"));
+        assert_eq!(params.first("debug"), Some(""));
+        assert_eq!(params.first("verbose"), Some(""));
+        assert_eq!(params.iter().collect::<Vec<_>>(), [("debug", ""), ("verbose", ""), ("x", "1")]);
+
+        let params = QueryParams::parse("q=hello+world&path=%2Fa%20b&plus=%2B&caf%C3%A9=cr%C3%A8me");
+        assert_eq!(params.first("q"), Some("hello world"));
+        assert_eq!(params.first("path"), Some("/a b"));
+        assert_eq!(params.first("plus"), Some("+"));
+        assert_eq!(params.first("café"), Some("crème"));
+
+        let params = QueryParams::parse("bad=%zz&trunc=%4&bytes=%ff");
+        assert_eq!(params.first("bad"), Some("%zz"));
+        assert_eq!(params.first("trunc"), Some("%4"));
+        assert_eq!(params.first("bytes"), Some("\u{fffd}"));
+
+        let params = QueryParams::parse("&&a=1&&&b=2&");
+        assert_eq!(params.iter().collect::<Vec<_>>(), [("a", "1"), ("b", "2")]);
+        assert!(QueryParams::parse("").is_empty());
+        assert!(QueryParams::parse("?").is_empty());
+        assert!(QueryParams::parse("&&&").is_empty());
+        assert_eq!(QueryParams::parse("=x").iter().collect::<Vec<_>>(), [("", "x")]);
+        assert_eq!(QueryParams::parse("a=b=c").first("a"), Some("b=c"));
+
+        assert_eq!(QueryParams::parse("a=1;b=2").first("a"), Some("1;b=2"));
+        let options = QueryOptions { semicolon_separator: true };
+        let params = QueryParams::parse_with("a=1;b=2&c=3;;", options);
+        assert_eq!(params.iter().collect::<Vec<_>>(), [("a", "1"), ("b", "2"), ("c", "3")]);
+        assert_eq!(QueryParams::parse_with("a=%3B", options).first("a"), Some(";"));
+
+        let params = QueryParams::parse("ids[]=1&ids[]=2&ids%5B%5D=3&plain=x");
+        assert_eq!(params.all("ids").collect::<Vec<_>>(), ["1", "2", "3"]);
+        assert!(!params.contains("ids[]"));
+
+        let map = utils::parse_query_string("a=1&a=2&debug&q=x+y");
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["a"], "2");
+        assert_eq!(map["debug"], "");
+        assert_eq!(map["q"], "x y");
+    }
+
+    #[test]
+    fn test_request_query_as() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Search {
+            q: String,
+            #[serde(default)]
+            tag: Vec<String>,
+            page: Option<u32>,
+        }
+
+        let request = Request::new(Method::GET, "/search?q=rust+http&tag[]=a&tag[]=b&page=2".parse().unwrap(), Version::HTTP_11);
+        let search: Search = request.query_as().unwrap();
+        assert_eq!(search.q, "rust http");
+        assert_eq!(search.tag, ["a", "b"]);
+        assert_eq!(search.page, Some(2));
+
+        let request = Request::new(Method::GET, "/search?q=x&tag=only".parse().unwrap(), Version::HTTP_11);
+        let search: Search = request.query_as().unwrap();
+        assert_eq!(search.tag, ["only"]);
+        assert_eq!(search.page, None);
+
+        let request = Request::new(Method::GET, "/search?q=x&page=two".parse().unwrap(), Version::HTTP_11);
+        assert!(matches!(request.query_as::<Search>(), Err(Error::BadRequest(_))));
+        let request = Request::new(Method::GET, "/search".parse().unwrap(), Version::HTTP_11);
+        assert!(matches!(request.query_as::<Search>(), Err(Error::BadRequest(_))));
+        assert!(request.query_params().is_empty());
+    }
+
+    #[test]
+    fn test_utils_http_date() {
+        use std::time::{Duration, UNIX_EPOCH};
+        use utils::http_date;
+
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(http_date::format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date::format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date::parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(http_date::parse("Sunday, 06-Nov-94 08:49:37 GMT"), Some(time));
+        assert_eq!(http_date::parse("Sun Nov  6 08:49:37 1994"), Some(time));
+        assert_eq!(http_date::parse("sun, 06 nov 1994 08:49:37 gmt"), Some(time));
+        assert_eq!(http_date::parse("SUNDAY, 06-NOV-94 08:49:37 GMT"), Some(time));
+        assert_eq!(http_date::parse("  Sun Nov 06 08:49:37 1994 "), Some(time));
+
+        for seconds in [0, 951782400, 1709164800, 1709251199, 4107542400, 253402300799] {
+            let time = UNIX_EPOCH + Duration::from_secs(seconds);
+            assert_eq!(http_date::parse(&http_date::format(time)), Some(time), "{}", seconds);
+        }
+
+        let leap_day = http_date::parse("Thu, 29 Feb 2024 12:00:00 GMT").unwrap();
+        assert_eq!(leap_day, UNIX_EPOCH + Duration::from_secs(1709208000));
+        assert_eq!(http_date::format(leap_day + Duration::from_secs(12 * 3600)), "Fri, 01 Mar 2024 00:00:00 GMT");
+        assert_eq!(http_date::parse("Tue, 29 Feb 2000 00:00:00 GMT"), Some(UNIX_EPOCH + Duration::from_secs(951782400)));
+        assert_eq!(http_date::parse("Thu Feb 29 12:00:00 2024"), Some(leap_day));
+        assert_eq!(http_date::parse("Sun, 29 Feb 2023 00:00:00 GMT"), None);
+        assert_eq!(http_date::parse("Thu, 29 Feb 1900 00:00:00 GMT"), None);
+
+        for garbage in [
+            "",
+            "GMT",
+            "yesterday",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49:37 +0000",
+            "Mon, 06 Nov 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:60:00 GMT",
+            "Sun, 06 Nov 99999999 08:49:37 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            "Sunday, 06-Nov-+9 08:49:37 GMT",
+            "Sun Nov  6 08:49:37",
+            "784111777",
+            "Sun, 06 Nov 1994 08:49:37 GMT trailing",
+            "\u{e9}\u{e9}\u{e9}",
+        ] {
+            assert_eq!(http_date::parse(garbage), None, "{:?}", garbage);
+        }
+
+        let current = http_date::current_header();
+        let parsed = http_date::parse(current.to_str().unwrap()).unwrap();
+        let drift = std::time::SystemTime::now().duration_since(parsed).unwrap();
+        assert!(drift < Duration::from_secs(2), "{:?}", drift);
+    }
+
+    #[tokio::test]
+    async fn test_responses_carry_date_header() {
+        let (_server, addr) = spawn_server(config::Config::default()).await;
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let date = response
+            .lines()
+            .find_map(|line| line.strip_prefix("date: "))
+            .unwrap_or_else(|| panic!("{}", response));
+        assert!(utils::http_date::parse(date).is_some(), "{}", date);
+
+        let response = send_raw(addr, b"BROKEN\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        assert!(response.contains("\r\ndate: "), "{}", response);
     }
 
     #[test]
@@ -53,4 +319,5415 @@ mod tests {
         assert_eq!(utils::get_mime_type("test.png"), "image/png");
         assert_eq!(utils::get_mime_type("test.unknown"), "application/octet-stream");
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_utils_mime_registry() {
+        use std::collections::HashMap;
+        use utils::mime::{self, MimeRegistry};
+
+        assert_eq!(utils::get_mime_type("APP.WASM"), "application/wasm");
+        assert_eq!(utils::get_mime_type("noextension"), "application/octet-stream");
+
+        let registry = MimeRegistry::default();
+        assert_eq!(registry.lookup("index.html"), Some("text/html; charset=utf-8"));
+        assert_eq!(registry.lookup("INDEX.HTM"), Some("text/html; charset=utf-8"));
+        assert_eq!(registry.lookup("app.js"), Some("application/javascript; charset=utf-8"));
+        assert_eq!(registry.lookup("logo.svg"), Some("image/svg+xml; charset=utf-8"));
+        assert_eq!(registry.lookup("data.json"), Some("application/json"));
+        assert_eq!(registry.lookup("photo.webp"), Some("image/webp"));
+        assert_eq!(registry.lookup("photo.avif"), Some("image/avif"));
+        assert_eq!(registry.lookup("module.wasm"), Some("application/wasm"));
+        assert_eq!(registry.lookup("README.md"), Some("text/markdown; charset=utf-8"));
+        assert_eq!(registry.lookup("dir.d/file"), None);
+        assert_eq!(registry.lookup("archive.unknown"), None);
+        assert_eq!(registry.content_type("archive.unknown", b"PK\x03\x04"), mime::OCTET_STREAM);
+
+        let overrides: HashMap<String, String> = [
+            ("md", "text/x-markdown"),
+            (".LOG", "text/plain; charset=latin1"),
+            ("js", "text/javascript"),
+            ("glb", "model/gltf-binary"),
+        ]
+        .into_iter()
+        .map(|(extension, mime)| (extension.to_string(), mime.to_string()))
+        .collect();
+        let registry = MimeRegistry::new(&overrides, "iso-8859-1", true);
+        assert_eq!(registry.lookup("notes.md"), Some("text/x-markdown; charset=iso-8859-1"));
+        assert_eq!(registry.lookup("server.log"), Some("text/plain; charset=latin1"));
+        assert_eq!(registry.lookup("app.js"), Some("text/javascript; charset=iso-8859-1"));
+        assert_eq!(registry.lookup("scene.glb"), Some("model/gltf-binary"));
+        assert_eq!(registry.lookup("page.html"), Some("text/html; charset=iso-8859-1"));
+
+        let registry = MimeRegistry::new(&HashMap::new(), "", true);
+        assert_eq!(registry.lookup("page.html"), Some("text/html"));
+        for (body, expected) in [
+            (&b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"[..], "image/png"),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", "image/jpeg"),
+            (b"GIF89a\x01\0", "image/gif"),
+            (b"GIF87a", "image/gif"),
+            (b"%PDF-1.7\n", "application/pdf"),
+            (b"PK\x03\x04\x14\0", "application/zip"),
+            (b"PK\x05\x06", "application/zip"),
+            (b"plain text", mime::OCTET_STREAM),
+            (b"\x89PN", mime::OCTET_STREAM),
+            (b"", mime::OCTET_STREAM),
+        ] {
+            assert_eq!(registry.content_type("upload", body), expected, "{:?}", body);
+        }
+        assert_eq!(registry.content_type("report.txt", b"%PDF-1.7"), "text/plain");
+    }
+
+    fn jwt_router(config: &config::AuthConfig) -> router::Router {
+        let mut router = router::Router::new();
+        router
+            .layer(auth::JwtAuth::from_config(config).unwrap())
+            .get("/me", |request| {
+                let subject = request
+                    .extension::<auth::Claims>()
+                    .and_then(|claims| claims.subject())
+                    .unwrap_or("")
+                    .to_string();
+                Ok(Response::ok().with_text(&subject))
+            });
+        router
+    }
+
+    fn jwt_request(token: &str) -> Request {
+        let uri = "/me".parse::<Uri>().unwrap();
+        let mut request = Request::new(Method::GET, uri, Version::HTTP_11);
+        request.headers.insert(
+            "authorization",
+            ::http::HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+        );
+        request
+    }
+
+    fn jwt_config() -> config::AuthConfig {
+        config::AuthConfig {
+            enabled: true,
+            secret: Some("test-secret".to_string()),
+            issuer: Some("test-issuer".to_string()),
+            audience: Some("test-api".to_string()),
+            leeway: 5,
+            ..Default::default()
+        }
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[tokio::test]
+    async fn test_jwt_valid_token_injects_claims() {
+        let router = jwt_router(&jwt_config());
+        let claims = serde_json::json!({
+            "sub": "alice", "iss": "test-issuer", "aud": "test-api", "exp": now() + 300
+        });
+        let token = auth::encode_hs256(&claims, b"test-secret").unwrap();
+
+        let response = router.handle(jwt_request(&token)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+        assert_eq!(response.body.unwrap(), "alice");
+    }
+
+    #[tokio::test]
+    async fn test_jwt_missing_token_is_challenged() {
+        let router = jwt_router(&jwt_config());
+        let uri = "/me".parse::<Uri>().unwrap();
+        let request = Request::new(Method::GET, uri, Version::HTTP_11);
+
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers["www-authenticate"], "Bearer realm=\"api\"");
+    }
+
+    #[tokio::test]
+    async fn test_jwt_expired_token_rejected() {
+        let router = jwt_router(&jwt_config());
+        let claims = serde_json::json!({
+            "sub": "alice", "iss": "test-issuer", "aud": "test-api", "exp": now() - 60
+        });
+        let token = auth::encode_hs256(&claims, b"test-secret").unwrap();
+
+        let response = router.handle(jwt_request(&token)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::UNAUTHORIZED);
+        let challenge = response.headers["www-authenticate"].to_str().unwrap();
+        assert!(challenge.contains("error=\"invalid_token\""));
+        assert!(challenge.contains("expired"));
+    }
+
+    #[tokio::test]
+    async fn test_jwt_leeway_accepts_recently_expired_token() {
+        let router = jwt_router(&jwt_config());
+        let claims = serde_json::json!({
+            "sub": "alice", "iss": "test-issuer", "aud": "test-api", "exp": now() - 2
+        });
+        let token = auth::encode_hs256(&claims, b"test-secret").unwrap();
+
+        let response = router.handle(jwt_request(&token)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_jwt_wrong_audience_forbidden() {
+        let router = jwt_router(&jwt_config());
+        let claims = serde_json::json!({
+            "sub": "alice", "iss": "test-issuer", "aud": ["other-api"], "exp": now() + 300
+        });
+        let token = auth::encode_hs256(&claims, b"test-secret").unwrap();
+
+        let response = router.handle(jwt_request(&token)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_jwt_tampered_token_rejected() {
+        let router = jwt_router(&jwt_config());
+        let claims = serde_json::json!({
+            "sub": "alice", "iss": "test-issuer", "aud": "test-api", "exp": now() + 300
+        });
+        let token = auth::encode_hs256(&claims, b"test-secret").unwrap();
+        let forged = serde_json::json!({
+            "sub": "admin", "iss": "test-issuer", "aud": "test-api", "exp": now() + 300
+        });
+        let forged_payload = base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            serde_json::to_vec(&forged).unwrap(),
+        );
+        let parts: Vec<&str> = token.split('.').collect();
+        let tampered = format!("{}.{}.{}", parts[0], forged_payload, parts[2]);
+
+        let response = router.handle(jwt_request(&tampered)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::UNAUTHORIZED);
+
+        let wrong_key = auth::encode_hs256(&claims, b"other-secret").unwrap();
+        let response = router.handle(jwt_request(&wrong_key)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_defaults_on_root() {
+        let server = server::Server::new(config::Config::default());
+        let response = server.handle_request(testing::TestRequest::get("/").into_request()).await;
+
+        assert_eq!(response.headers["x-content-type-options"], "nosniff");
+        assert_eq!(response.headers["x-frame-options"], "SAMEORIGIN");
+        assert_eq!(response.headers["referrer-policy"], "strict-origin-when-cross-origin");
+        assert!(!response.headers.contains_key("strict-transport-security"));
+        assert!(!response.headers.contains_key("content-security-policy"));
+
+        let uri = "https://localhost/".parse::<Uri>().unwrap();
+        let response = server
+            .router()
+            .handle(Request::new(Method::GET, uri, Version::HTTP_11))
+            .await
+            .unwrap();
+        assert_eq!(response.headers["strict-transport-security"], "max-age=31536000");
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_on_error_responses() {
+        let mut config = config::Config::default();
+        config.security.rate_limit_requests = 1;
+        let server = server::Server::new(config);
+        let missing = || testing::TestRequest::get("/no/such/route").header("accept", "text/html").into_request();
+
+        let response = server.handle_request(missing()).await;
+        assert_eq!(response.status, ::http::StatusCode::NOT_FOUND);
+        assert!(response.headers["content-type"].to_str().unwrap().starts_with("text/html"));
+        assert_eq!(response.headers["x-content-type-options"], "nosniff");
+        assert_eq!(response.headers["x-frame-options"], "SAMEORIGIN");
+
+        let response = server.handle_request(missing()).await;
+        assert_eq!(response.status, ::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers["x-content-type-options"], "nosniff");
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_preserve_handler_values() {
+        let headers = config::SecurityHeadersConfig {
+            frame_options: None,
+            content_security_policy: Some("default-src 'self'".to_string()),
+            ..Default::default()
+        };
+        let mut router = router::Router::new();
+        router
+            .layer(security::SecurityHeaders::from_config(&headers))
+            .get("/", |_| Ok(Response::ok().with_header("content-security-policy", "default-src 'none'")))
+            .get("/plain", |_| Ok(Response::ok()));
+
+        let uri = "/".parse::<Uri>().unwrap();
+        let response = router.handle(Request::new(Method::GET, uri, Version::HTTP_11)).await.unwrap();
+        assert_eq!(response.headers["content-security-policy"], "default-src 'none'");
+        assert!(!response.headers.contains_key("x-frame-options"));
+
+        let uri = "/plain".parse::<Uri>().unwrap();
+        let response = router.handle(Request::new(Method::GET, uri, Version::HTTP_11)).await.unwrap();
+        assert_eq!(response.headers["content-security-policy"], "default-src 'self'");
+    }
+
+    fn client_ip_router() -> router::Router {
+        client_ip_router_with(config::ProxyHeader::XForwardedFor)
+    }
+
+    fn client_ip_router_with(header: config::ProxyHeader) -> router::Router {
+        let proxies = vec!["10.0.0.0/8".to_string(), "::1".to_string()];
+        let mut router = router::Router::new();
+        router
+            .layer(security::TrustedProxies::from_config(&proxies).unwrap().with_header(header))
+            .get("/ip", |request| {
+                let ip = request.client_ip().map(|ip| ip.to_string()).unwrap_or_default();
+                Ok(Response::ok().with_text(&ip))
+            });
+        router
+    }
+
+    fn client_ip_request(peer: &str, headers: &[(&'static str, &str)]) -> Request {
+        let uri = "/ip".parse::<Uri>().unwrap();
+        let mut request = Request::new(Method::GET, uri, Version::HTTP_11);
+        request.remote_addr = Some(peer.parse().unwrap());
+        for (name, value) in headers {
+            request.headers.append(*name, ::http::HeaderValue::from_str(value).unwrap());
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_direct_connection() {
+        let router = client_ip_router();
+        let request = client_ip_request("203.0.113.7:5000", &[]);
+        assert_eq!(request.remote_addr().unwrap().port(), 5000);
+
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.body.unwrap(), "203.0.113.7");
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_through_trusted_proxy() {
+        let router = client_ip_router();
+
+        let request = client_ip_request(
+            "10.1.2.3:40000",
+            &[("x-forwarded-for", "198.51.100.1, 198.51.100.9"), ("x-forwarded-for", "10.9.9.9")],
+        );
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.body.unwrap(), "198.51.100.9");
+
+        let router = client_ip_router_with(config::ProxyHeader::Forwarded);
+        let request = client_ip_request(
+            "[::1]:40000",
+            &[("forwarded", "for=192.0.2.60;proto=http, for=\"[2001:db8:cafe::17]:4711\"")],
+        );
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.body.unwrap(), "2001:db8:cafe::17");
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_ignores_forged_forwarded_through_proxy() {
+        let router = client_ip_router();
+        let request = client_ip_request(
+            "10.1.2.3:40000",
+            &[("forwarded", "for=1.2.3.4"), ("x-forwarded-for", "198.51.100.7")],
+        );
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.body.unwrap(), "198.51.100.7");
+
+        let router = client_ip_router_with(config::ProxyHeader::Forwarded);
+        let request = client_ip_request(
+            "10.1.2.3:40000",
+            &[("x-forwarded-for", "1.2.3.4"), ("forwarded", "for=198.51.100.7")],
+        );
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.body.unwrap(), "198.51.100.7");
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_ignores_forged_header_from_untrusted_peer() {
+        let router = client_ip_router();
+        let request = client_ip_request(
+            "203.0.113.7:5000",
+            &[("x-forwarded-for", "1.2.3.4"), ("forwarded", "for=5.6.7.8")],
+        );
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.body.unwrap(), "203.0.113.7");
+    }
+
+    #[test]
+    fn test_utils_cidr_parsing() {
+        assert!(utils::Cidr::parse("192.168.0.0/16").is_ok());
+        assert!(utils::Cidr::parse("10.0.0.1").is_ok());
+        assert!(utils::Cidr::parse("2001:db8::/32").is_ok());
+        assert!(utils::Cidr::parse("::1").is_ok());
+        assert!(utils::Cidr::parse("0.0.0.0/0").is_ok());
+        assert!(utils::Cidr::parse("10.0.0.0/33").is_err());
+        assert!(utils::Cidr::parse("2001:db8::/129").is_err());
+        assert!(utils::Cidr::parse("10.0.0.0/").is_err());
+        assert!(utils::Cidr::parse("not-an-ip").is_err());
+        assert!(utils::Cidr::parse("300.1.1.1").is_err());
+    }
+
+    #[test]
+    fn test_utils_cidr_matching() {
+        let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+
+        let net = utils::Cidr::parse("192.168.1.0/24").unwrap();
+        assert!(net.contains(ip("192.168.1.0")));
+        assert!(net.contains(ip("192.168.1.255")));
+        assert!(!net.contains(ip("192.168.2.1")));
+        assert!(!net.contains(ip("2001:db8::1")));
+        assert!(net.contains(ip("::ffff:192.168.1.20")));
+
+        let single = utils::Cidr::parse("10.1.2.3").unwrap();
+        assert!(single.contains(ip("10.1.2.3")));
+        assert!(!single.contains(ip("10.1.2.4")));
+
+        let everything = utils::Cidr::parse("0.0.0.0/0").unwrap();
+        assert!(everything.contains(ip("8.8.8.8")));
+
+        let odd = utils::Cidr::parse("172.16.0.0/12").unwrap();
+        assert!(odd.contains(ip("172.31.255.255")));
+        assert!(!odd.contains(ip("172.32.0.0")));
+
+        let v6 = utils::Cidr::parse("2001:db8:abcd::/48").unwrap();
+        assert!(v6.contains(ip("2001:db8:abcd:12::1")));
+        assert!(!v6.contains(ip("2001:db8:abce::1")));
+        assert!(!v6.contains(ip("10.0.0.1")));
+
+        let all_v6 = utils::Cidr::parse("::/0").unwrap();
+        assert!(all_v6.contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn test_ip_filter_deny_wins_over_allow() {
+        let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+        let filter = security::IpFilter::from_config(
+            &["10.0.0.0/8".to_string()],
+            &["10.0.0.66".to_string()],
+        )
+        .unwrap();
+
+        assert!(filter.is_allowed(ip("10.4.5.6")));
+        assert!(!filter.is_allowed(ip("10.0.0.66")));
+        assert!(!filter.is_allowed(ip("192.168.1.1")));
+        assert!(security::IpFilter::default().is_allowed(ip("192.168.1.1")));
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter_scoped_to_route_prefix() {
+        let filter = security::IpFilter::from_config(&["10.0.0.0/8".to_string()], &[]).unwrap();
+        let mut router = router::Router::new();
+        router
+            .layer_at("/admin", filter)
+            .get("/admin/stats", |_| Ok(Response::ok().with_text("stats")))
+            .get("/administrator", |_| Ok(Response::ok().with_text("public")));
+
+        let request = |path: &str| {
+            let mut request = Request::new(Method::GET, path.parse::<Uri>().unwrap(), Version::HTTP_11);
+            request.remote_addr = Some("203.0.113.5:1234".parse().unwrap());
+            request
+        };
+
+        let response = router.handle(request("/admin/stats")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::FORBIDDEN);
+        let response = router.handle(request("/administrator")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_denied_loopback_alias_is_rejected() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = match tokio::net::TcpListener::bind("[::]:0").await {
+            Ok(listener) => listener,
+            Err(_) => tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        };
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = config::Config::default();
+        config.security.ip_deny = vec!["127.0.0.0/8".to_string()];
+        let filter = std::sync::Arc::new(
+            security::IpFilter::from_config(&config.security.ip_allow, &config.security.ip_deny).unwrap(),
+        );
+        let server = server::Server::new(config.clone());
+        let router = std::sync::Arc::new(server.router().clone());
+        let config = std::sync::Arc::new(config);
+
+        let mut client = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (socket, addr) = listener.accept().await.unwrap();
+        let metrics = std::sync::Arc::new(metrics::Metrics::new());
+        let deadline = server::Server::header_deadline(&config);
+        server::Server::handle_connection(socket, addr, config, router, filter, metrics, None, deadline, drain::Drain::new().signal()).await.unwrap();
+
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.ok();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 403"));
+    }
+
+    fn demo_config() -> config::Config {
+        config::Config {
+            routes: config::RoutesConfig { enable_echo: true, enable_user_agent: true, enable_files: true, files_read_only: false },
+            ..Default::default()
+        }
+    }
+
+    async fn spawn_server(config: config::Config) -> (std::sync::Arc<server::Server>, std::net::SocketAddr) {
+        let server = std::sync::Arc::new(server::Server::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let running = server.clone();
+        tokio::spawn(async move { running.serve(listener).await });
+        (server, addr)
+    }
+
+    async fn send_raw(addr: std::net::SocketAddr, request: &[u8]) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_connections() {
+        use std::time::{Duration, Instant};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut config = config::Config::default();
+        config.server.shutdown_grace_seconds = 1;
+        config.performance.keep_alive_timeout = 60;
+        let (server, addr) = spawn_server(config).await;
+        let slow = |_| async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(Response::ok().with_text("done"))
+        };
+        server.router().replace(vec![
+            router::Route::new(Method::GET, "/", |_| Ok(Response::ok().with_text("hi"))),
+            router::Route::new_async(Method::GET, "/slow", slow, router::RouteOptions::default()),
+            router::Route::new_async(Method::GET, "/stuck", |_| futures::future::pending(), router::RouteOptions::default()),
+        ]);
+        let read_response = |mut stream: tokio::net::TcpStream| async move {
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            String::from_utf8_lossy(&response).to_string()
+        };
+
+        let mut idle = tokio::net::TcpStream::connect(addr).await.unwrap();
+        idle.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut head = [0; 15];
+        idle.read_exact(&mut head).await.unwrap();
+        assert_eq!(&head, b"HTTP/1.1 200 OK");
+        let mut busy = tokio::net::TcpStream::connect(addr).await.unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut stuck = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stuck.write_all(b"GET /stuck HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let started = Instant::now();
+        let drain = tokio::spawn({
+            let server = server.clone();
+            async move { server.drain().await }
+        });
+        let idle = tokio::time::timeout(Duration::from_millis(200), read_response(idle)).await.expect("idle connection stayed open");
+        assert!(idle.ends_with("hi"), "{}", idle);
+        let busy = read_response(busy).await;
+        assert!(busy.starts_with("HTTP/1.1 200") && busy.ends_with("done"), "{}", busy);
+        assert!(busy.contains("connection: close\r\n"), "{}", busy);
+        assert!(read_response(stuck).await.is_empty());
+
+        let report = drain.await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(report, drain::DrainReport { drained: 2, aborted: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_cancels_handler() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+
+        struct SetOnDrop(Arc<AtomicBool>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let (server, addr) = spawn_server(config::Config::default()).await;
+        let dropped = Arc::new(AtomicBool::new(false));
+        let finished = Arc::new(AtomicBool::new(false));
+        let (noticed, was_noticed) = tokio::sync::oneshot::channel();
+        let noticed = Arc::new(parking_lot::Mutex::new(Some(noticed)));
+        let handler = {
+            let (dropped, finished) = (dropped.clone(), finished.clone());
+            move |request: Request| {
+                let _guard = SetOnDrop(dropped.clone());
+                let finished = finished.clone();
+                let noticed = noticed.lock().take();
+                let cancelled = request.cancelled();
+                tokio::spawn(async move {
+                    cancelled.await;
+                    if let Some(noticed) = noticed {
+                        let _ = noticed.send(());
+                    }
+                });
+                async move {
+                    let _guard = _guard;
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    finished.store(true, Ordering::SeqCst);
+                    Ok(Response::ok())
+                }
+            }
+        };
+        server.router().replace(vec![router::Route::new_async(Method::GET, "/slow", handler, router::RouteOptions::default())]);
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        socket2::SockRef::from(&client).set_linger(Some(Duration::ZERO)).unwrap();
+        drop(client);
+
+        tokio::time::timeout(Duration::from_secs(1), was_noticed).await.expect("cancellation not signalled").unwrap();
+        for _ in 0..100 {
+            if dropped.load(Ordering::SeqCst) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(!finished.load(Ordering::SeqCst));
+        assert!(server.metrics().render().contains("http_client_disconnects_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_noticed_with_full_buffer() {
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+
+        let mut config = config::Config::default();
+        config.security.max_request_size = 16 * 1024;
+        let (server, addr) = spawn_server(config).await;
+        let slow = |_request: Request| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(Response::ok())
+        };
+        server.router().replace(vec![router::Route::new_async(Method::GET, "/slow", slow, router::RouteOptions::default())]);
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        client.write_all(&vec![b'x'; 64 * 1024]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        socket2::SockRef::from(&client).set_linger(Some(Duration::ZERO)).unwrap();
+        drop(client);
+
+        for _ in 0..100 {
+            if server.metrics().render().contains("http_client_disconnects_total 1\n") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("disconnect not noticed");
+    }
+
+    #[tokio::test]
+    async fn test_half_close_gets_response() {
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        for allow_half_close in [true, false] {
+            let mut config = config::Config::default();
+            config.performance.allow_half_close = allow_half_close;
+            let (server, addr) = spawn_server(config).await;
+            let slow = |_request: Request| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(Response::ok().with_text("done"))
+            };
+            server.router().replace(vec![router::Route::new_async(Method::GET, "/slow", slow, router::RouteOptions::default())]);
+
+            let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+            client.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+            client.shutdown().await.unwrap();
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).await.unwrap();
+            let response = String::from_utf8_lossy(&response);
+            if allow_half_close {
+                assert!(response.starts_with("HTTP/1.1 200 OK\r\n") && response.ends_with("done"), "{}", response);
+                assert!(server.metrics().render().contains("http_client_disconnects_total 0\n"));
+            } else {
+                assert!(response.is_empty(), "{}", response);
+                assert!(server.metrics().render().contains("http_client_disconnects_total 1\n"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_socket_options() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let tcp = config::TcpConfig {
+            nodelay: true,
+            keepalive_secs: 30,
+            recv_buffer_size: Some(256 * 1024),
+            send_buffer_size: Some(128 * 1024),
+        };
+        server::Server::configure_socket(&socket, &tcp);
+
+        let options = socket2::SockRef::from(&socket);
+        assert!(options.tcp_nodelay().unwrap());
+        assert!(options.keepalive().unwrap());
+        if cfg!(target_os = "linux") {
+            // Linux doubles the requested size for bookkeeping and caps it at
+            // net.core.rmem_max / wmem_max, so only check that it moved.
+            let defaults = socket2::SockRef::from(&client);
+            assert_ne!(options.recv_buffer_size().unwrap(), defaults.recv_buffer_size().unwrap());
+            assert_ne!(options.send_buffer_size().unwrap(), defaults.send_buffer_size().unwrap());
+        }
+
+        server::Server::configure_socket(&socket, &config::TcpConfig { nodelay: false, ..config::TcpConfig::default() });
+        assert!(!options.tcp_nodelay().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_counts_requests() {
+        let mut config = demo_config();
+        config.metrics.enabled = true;
+        let (server, addr) = spawn_server(config).await;
+
+        let ok = send_raw(addr, b"GET /echo/hi HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(ok.starts_with("HTTP/1.1 200"));
+        send_raw(addr, b"GET /echo/again HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let missing = send_raw(addr, b"GET /nope HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(missing.starts_with("HTTP/1.1 404"));
+        send_raw(addr, b"NOT A REQUEST\r\n\r\n").await;
+
+        let scrape = send_raw(addr, b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(scrape.contains("http_requests_total{method=\"GET\",status=\"200\"} 2"));
+        assert!(scrape.contains("http_requests_total{method=\"GET\",status=\"404\"} 1"));
+        assert!(scrape.contains("http_parse_errors_total 1"));
+        assert!(scrape.contains("http_request_duration_seconds_count 3"));
+        assert!(scrape.contains("# TYPE http_active_connections gauge"));
+        assert_eq!(server.metrics().requests_total(&Method::GET, ::http::StatusCode::NOT_FOUND), 1);
+    }
+
+    #[derive(Clone, Default)]
+    struct CaptureWriter(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl CaptureWriter {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl std::io::Write for CaptureWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CaptureWriter {
+        type Writer = CaptureWriter;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_logging_json_output() {
+        let config = config::LoggingConfig {
+            format: config::LogFormat::Json,
+            ..Default::default()
+        };
+        let writer = CaptureWriter::default();
+        let subscriber = logging::build_subscriber(&config, None, writer.clone(), false);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "rust_https_server::server", port = 4221, "listening");
+            tracing::debug!(target: "rust_https_server::server", "filtered out");
+        });
+
+        let output = writer.contents();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "rust_https_server::server");
+        assert_eq!(line["fields"]["message"], "listening");
+        assert_eq!(line["fields"]["port"], 4221);
+    }
+
+    #[test]
+    fn test_logging_filter_directives() {
+        let mut config = config::LoggingConfig::default();
+        config.targets.insert("hyper".to_string(), "warn".to_string());
+        assert_eq!(logging::filter_directives(&config, None), "rust_https_server=info,hyper=warn");
+        assert_eq!(logging::filter_directives(&config, Some("trace")), "rust_https_server=trace,hyper=warn");
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn test_http2_prior_knowledge_echo() {
+        let (_server, addr) = spawn_server(demo_config()).await;
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) = h2::client::handshake(tcp).await.unwrap();
+        tokio::spawn(connection);
+
+        let request = ::http::Request::builder()
+            .uri(format!("http://{}/echo/x", addr))
+            .body(())
+            .unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let response = response.await.unwrap();
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+        assert!(!response.headers().contains_key("connection"));
+
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(data, b"x");
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn test_http2_streamed_response_respects_flow_control() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (server, addr) = spawn_server(demo_config()).await;
+        let produced = std::sync::Arc::new(AtomicUsize::new(0));
+        let handler = {
+            let produced = produced.clone();
+            move |_request: Request| {
+                let produced = produced.clone();
+                async move {
+                    let (sender, stream) = http::BodyStream::channel(1);
+                    tokio::spawn(async move {
+                        for _ in 0..32 {
+                            if sender.send(Ok(bytes::Bytes::from(vec![b'a'; 4096]))).await.is_err() {
+                                break;
+                            }
+                            produced.fetch_add(1, Ordering::SeqCst);
+                        }
+                    });
+                    Ok(Response::ok().with_stream(stream))
+                }
+            }
+        };
+        server.router().replace(vec![router::Route::new_async(Method::GET, "/stream", handler, router::RouteOptions::default())]);
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) = h2::client::Builder::new().initial_window_size(4096).handshake::<_, bytes::Bytes>(tcp).await.unwrap();
+        tokio::spawn(connection);
+        let request = ::http::Request::get(format!("http://{}/stream", addr)).body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let response = response.await.unwrap();
+
+        // Without reading, the server may send one window and buffer a chunk or two.
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(produced.load(Ordering::SeqCst) < 8, "{} chunks produced", produced.load(Ordering::SeqCst));
+
+        let mut body = response.into_body();
+        let mut received = 0;
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            received += chunk.len();
+            body.flow_control().release_capacity(chunk.len()).unwrap();
+        }
+        assert_eq!(received, 32 * 4096);
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn test_http2_request_body_limit() {
+        let mut config = demo_config();
+        config.security.max_request_size = 1024;
+        let (server, addr) = spawn_server(config).await;
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) = h2::client::handshake(tcp).await.unwrap();
+        tokio::spawn(connection);
+
+        let declared = ::http::Request::post(format!("http://{}/echo/x", addr)).header("content-length", "4096").body(()).unwrap();
+        let (response, _) = client.send_request(declared, false).unwrap();
+        assert_eq!(response.await.unwrap().status(), ::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let idle = server.metrics().buffer_budget().used();
+        let streamed = ::http::Request::post(format!("http://{}/echo/x", addr)).body(()).unwrap();
+        let (response, mut body) = client.send_request(streamed, false).unwrap();
+        for _ in 0..8 {
+            body.reserve_capacity(512);
+            if body.send_data(bytes::Bytes::from(vec![b'a'; 512]), false).is_err() {
+                break;
+            }
+        }
+        assert_eq!(response.await.unwrap().status(), ::http::StatusCode::PAYLOAD_TOO_LARGE);
+        for _ in 0..100 {
+            if server.metrics().buffer_budget().used() == idle {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.metrics().buffer_budget().used(), idle);
+
+        let small = ::http::Request::post(format!("http://{}/echo/y", addr)).body(()).unwrap();
+        let (response, mut body) = client.send_request(small, false).unwrap();
+        body.send_data(bytes::Bytes::from_static(b"hello"), true).unwrap();
+        assert_eq!(response.await.unwrap().status(), ::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_response_write_to_matches_to_bytes() {
+        let response = Response::ok().with_header("x-test", "1").with_text("Hello, World!");
+        let mut written = Vec::new();
+        response.write_to(&mut written).await.unwrap();
+        assert_eq!(written, response.to_bytes());
+
+        let text = String::from_utf8(written).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("content-length: 13\r\n"));
+        assert!(text.ends_with("\r\n\r\nHello, World!"));
+    }
+
+    #[test]
+    fn test_parse_request_incrementally() {
+        let raw = b"POST /echo/x HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\nhello world";
+        let mut buffer = bytes::BytesMut::new();
+        let mut state = parser::ParseState::default();
+
+        for (i, chunk) in raw.chunks(5).enumerate() {
+            buffer.extend_from_slice(chunk);
+            let parsed = parser::parse_request(&mut buffer, &mut state).unwrap();
+            if (i + 1) * 5 < raw.len() {
+                assert!(parsed.is_none());
+            } else {
+                let request = parsed.unwrap();
+                assert_eq!(request.path(), "/echo/x");
+                assert_eq!(request.body.unwrap(), "hello world");
+                assert!(buffer.is_empty());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_cache_replaces_stale_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.cache.enabled = true;
+        config.files.cache.revalidate_ms = 0;
+        let server = server::Server::new(config);
+
+        let get = || Request::new(Method::GET, "/files/app.css".parse().unwrap(), Version::HTTP_11);
+
+        std::fs::write(dir.path().join("app.css"), "body{}").unwrap();
+        let first = server.router().handle(get()).await.unwrap();
+        let second = server.router().handle(get()).await.unwrap();
+        assert_eq!(first.body, second.body);
+        assert_eq!(second.headers.get("etag"), first.headers.get("etag"));
+        assert_eq!(server.metrics().file_cache_misses(), 1);
+        assert_eq!(server.metrics().file_cache_hits(), 1);
+        assert_eq!(server.file_cache().len(), 1);
+
+        std::fs::write(dir.path().join("app.css"), "body{color:red}").unwrap();
+        let third = server.router().handle(get()).await.unwrap();
+        assert_eq!(third.body.as_deref(), Some(&b"body{color:red}"[..]));
+        assert_ne!(third.headers.get("etag"), first.headers.get("etag"));
+        assert_eq!(server.metrics().file_cache_misses(), 2);
+        assert_eq!(server.file_cache().total_bytes(), 15);
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_sidecars() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.js"), "plain").unwrap();
+        std::fs::write(dir.path().join("app.js.gz"), "gzipped").unwrap();
+        std::fs::write(dir.path().join("app.js.br"), "brotli").unwrap();
+        std::fs::write(dir.path().join("site.css"), "css").unwrap();
+        std::fs::write(dir.path().join("orphan.js.gz"), "orphan").unwrap();
+        std::fs::create_dir(dir.path().join("bundle.js")).unwrap();
+        std::fs::write(dir.path().join("bundle.js.gz"), "bundle").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.precompressed = true;
+        let server = server::Server::new(config);
+
+        let get = |path: &str, accept: &str| {
+            let mut request = Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+            request.headers.insert("accept-encoding", accept.parse().unwrap());
+            request
+        };
+
+        let response = server.router().handle(get("/files/app.js", "gzip, br")).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"brotli"[..]));
+        assert_eq!(response.headers["content-encoding"], "br");
+        assert_eq!(response.headers["content-type"], "application/javascript; charset=utf-8");
+        assert_eq!(response.headers["vary"], "Accept-Encoding");
+
+        let response = server.router().handle(get("/files/app.js", "gzip, br;q=0")).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"gzipped"[..]));
+        assert_eq!(response.headers["content-encoding"], "gzip");
+
+        let response = server.router().handle(get("/files/app.js", "identity")).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"plain"[..]));
+        assert!(response.headers.get("content-encoding").is_none());
+
+        let response = server.router().handle(get("/files/site.css", "gzip, br")).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"css"[..]));
+        assert!(response.headers.get("content-encoding").is_none());
+        assert_eq!(response.headers["vary"], "Accept-Encoding");
+
+        for path in ["/files/orphan.js", "/files/bundle.js"] {
+            let response = server.router().handle(get(path, "gzip, br")).await.unwrap();
+            assert_eq!(response.status, ::http::StatusCode::NOT_FOUND, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_utils_glob_match() {
+        assert!(utils::glob_match("*.css", "app.css"));
+        assert!(!utils::glob_match("*.css", "css/app.css"));
+        assert!(utils::glob_match("assets/**/*.js", "assets/vendor/lib/app.js"));
+        assert!(utils::glob_match("assets/**/*.js", "assets/app.js"));
+        assert!(utils::glob_match("img?.png", "img1.png"));
+        assert!(!utils::glob_match("img?.png", "img10.png"));
+    }
+
+    #[tokio::test]
+    async fn test_file_cache_control_by_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["app.css", "index.html", "notes.txt", "app.js"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.cache_control = [
+            ("*.css", "public, max-age=31536000, immutable"),
+            ("*.html", "no-cache"),
+            ("index.html", "no-store"),
+            ("*.js", "public, max-age=60"),
+        ]
+        .into_iter()
+        .map(|(pattern, value)| (pattern.to_string(), value.to_string()))
+        .collect();
+        config.files.cache_control_default = Some("public, max-age=300".to_string());
+        let server = server::Server::new(config);
+
+        for (path, expected) in [
+            ("/files/app.css", "public, max-age=31536000, immutable"),
+            ("/files/index.html", "no-store"),
+            ("/files/app.js", "public, max-age=60"),
+            ("/files/notes.txt", "public, max-age=300"),
+        ] {
+            let request = Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+            let response = server.router().handle(request).await.unwrap();
+            assert_eq!(response.headers["cache-control"], expected, "{}", path);
+            assert!(response.headers.contains_key("etag"));
+            assert!(response.headers.contains_key("last-modified"));
+        }
+    }
+
+    #[test]
+    fn test_utils_etag() {
+        use std::time::{Duration, UNIX_EPOCH};
+        use utils::etag::{self, EntityTag};
+
+        let strong = EntityTag::parse("\"abc\"").unwrap();
+        let weak = EntityTag::parse(" W/\"abc\" ").unwrap();
+        assert!(!strong.is_weak());
+        assert!(weak.is_weak());
+        assert_eq!(weak.opaque(), "abc");
+        assert_eq!(strong.to_string(), "\"abc\"");
+        assert_eq!(weak.to_string(), "W/\"abc\"");
+        assert_eq!("W/\"\"".parse::<EntityTag>().unwrap(), EntityTag::weak(""));
+        for invalid in ["abc", "\"abc", "abc\"", "w/\"abc\"", "W/abc", "\"a\"b\"", "\"a b\"", "*", ""] {
+            assert_eq!(EntityTag::parse(invalid), None, "{:?}", invalid);
+        }
+
+        assert!(strong.strong_eq(&EntityTag::strong("abc")));
+        assert!(!strong.strong_eq(&weak));
+        assert!(!weak.strong_eq(&weak));
+        assert!(!strong.strong_eq(&EntityTag::strong("abd")));
+        assert!(strong.weak_eq(&weak));
+        assert!(weak.weak_eq(&weak));
+        assert!(!weak.weak_eq(&EntityTag::weak("abd")));
+
+        let tag = etag::from_metadata(10, UNIX_EPOCH + Duration::from_secs(1));
+        assert_eq!(tag.to_string(), "\"a-3b9aca00\"");
+        assert_ne!(tag, etag::from_metadata(11, UNIX_EPOCH + Duration::from_secs(1)));
+        assert_ne!(tag, etag::from_metadata(10, UNIX_EPOCH + Duration::from_secs(2)));
+        assert_eq!(etag::from_bytes(b"hello"), etag::from_bytes(b"hello"));
+        assert_ne!(etag::from_bytes(b"hello"), etag::from_bytes(b"hellp"));
+        assert!(!etag::from_bytes(b"").is_weak());
+        assert_eq!(EntityTag::parse(&etag::from_bytes(b"hello").to_string()), Some(etag::from_bytes(b"hello")));
+
+        assert!(etag::if_none_match_matches("\"abc\"", &strong));
+        assert!(etag::if_none_match_matches("W/\"abc\"", &strong));
+        assert!(etag::if_none_match_matches("\"abc\"", &weak));
+        assert!(etag::if_none_match_matches("\"x\", W/\"abc\"", &weak));
+        assert!(etag::if_none_match_matches("\"x\",W/\"y\",  \"abc\"", &strong));
+        assert!(!etag::if_none_match_matches("\"x\", W/\"y\"", &strong));
+        assert!(etag::if_none_match_matches("*", &weak));
+        assert!(etag::if_none_match_matches(" * ", &strong));
+        assert!(!etag::if_none_match_matches("", &strong));
+        assert!(!etag::if_none_match_matches("abc", &strong));
+        assert!(etag::if_none_match_matches("garbage, \"abc\"", &strong));
+
+        let comma = EntityTag::strong("a,b");
+        assert!(etag::if_none_match_matches("\"x\", \"a,b\"", &comma));
+        assert!(!etag::if_none_match_matches("\"a\", \"b\"", &comma));
+
+        assert!(etag::if_match_matches("\"abc\"", &strong));
+        assert!(etag::if_match_matches("\"x\", \"abc\"", &strong));
+        assert!(!etag::if_match_matches("W/\"abc\"", &strong));
+        assert!(!etag::if_match_matches("\"abc\"", &weak));
+        assert!(etag::if_match_matches("*", &strong));
+
+        assert!(etag::if_range_matches("\"abc\"", &strong));
+        assert!(!etag::if_range_matches("W/\"abc\"", &strong));
+        assert!(!etag::if_range_matches("\"abc\"", &weak));
+        assert!(!etag::if_range_matches("\"abc\", \"x\"", &strong));
+        assert!(!etag::if_range_matches("*", &strong));
+        assert!(!etag::if_range_matches("Sun, 06 Nov 1994 08:49:37 GMT", &strong));
+    }
+
+    #[tokio::test]
+    async fn test_file_get_if_none_match() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.css"), "body {}").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.cache_control_default = Some("no-cache".to_string());
+        let client = testing::TestClient::new(config);
+
+        let response = client.get("/files/app.css").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        let etag = response.header("etag").unwrap().to_string();
+        let weak = format!("W/{}", etag);
+
+        for header in [etag.as_str(), weak.as_str(), "*"] {
+            let response = client.send(testing::TestRequest::get("/files/app.css").header("if-none-match", header)).await;
+            assert_eq!(response.status(), ::http::StatusCode::NOT_MODIFIED, "{}", header);
+            assert!(response.bytes().is_empty());
+            assert_eq!(response.header("etag"), Some(etag.as_str()));
+            assert_eq!(response.header("cache-control"), Some("no-cache"));
+            assert!(response.header("content-length").is_none());
+        }
+
+        let response = client.send(testing::TestRequest::get("/files/app.css").header("if-none-match", "\"stale\"")).await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.text(), "body {}");
+    }
+
+    #[test]
+    fn test_utils_range_parse() {
+        use utils::range::{self, ByteRanges};
+        let single = |range: std::ops::Range<u64>| ByteRanges::Partial(vec![range]);
+
+        assert_eq!(range::parse("bytes=0-3", 10), single(0..4));
+        assert_eq!(range::parse("bytes=6-", 10), single(6..10));
+        assert_eq!(range::parse("bytes=-4", 10), single(6..10));
+        assert_eq!(range::parse("bytes=8-20", 10), single(8..10));
+        assert_eq!(range::parse("bytes=0-1, 4-5", 10), ByteRanges::Partial(vec![0..2, 4..6]));
+        assert_eq!(range::parse("bytes=10-", 10), ByteRanges::Unsatisfiable);
+        assert_eq!(range::parse("bytes=-0", 10), ByteRanges::Unsatisfiable);
+        assert_eq!(range::parse("bytes=5-2", 10), ByteRanges::Full);
+        assert_eq!(range::parse("items=0-3", 10), ByteRanges::Full);
+        assert_eq!(range::parse("bytes=a-b", 10), ByteRanges::Full);
+        assert_eq!(range::content_range(&(6..10), 10), "bytes 6-9/10");
+    }
+
+    #[tokio::test]
+    async fn test_file_get_if_range() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data.txt"), "0123456789").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let client = testing::TestClient::new(config);
+
+        let response = client.get("/files/data.txt").await;
+        assert_eq!(response.header("accept-ranges"), Some("bytes"));
+        let etag = response.header("etag").unwrap().to_string();
+        let last_modified = response.header("last-modified").unwrap().to_string();
+        let ranged = |if_range: &str| testing::TestRequest::get("/files/data.txt").header("range", "bytes=2-5").header("if-range", if_range);
+
+        let response = client.send(testing::TestRequest::get("/files/data.txt").header("range", "bytes=-3")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.text(), "789");
+        assert_eq!(response.header("content-range"), Some("bytes 7-9/10"));
+
+        let response = client.send(ranged(&etag)).await;
+        assert_eq!(response.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.text(), "2345");
+        assert_eq!(response.header("content-range"), Some("bytes 2-5/10"));
+        assert_eq!(response.header("content-length"), Some("4"));
+
+        for stale in ["\"stale\"", &format!("W/{}", etag)] {
+            let response = client.send(ranged(stale)).await;
+            assert_eq!(response.status(), ::http::StatusCode::OK, "{}", stale);
+            assert_eq!(response.text(), "0123456789");
+            assert!(response.header("content-range").is_none());
+        }
+
+        let response = client.send(ranged(&last_modified)).await;
+        assert_eq!(response.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.text(), "2345");
+
+        let response = client.send(ranged("Thu, 01 Jan 2015 00:00:00 GMT")).await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.text(), "0123456789");
+
+        let response = client.send(testing::TestRequest::get("/files/data.txt").header("range", "bytes=10-")).await;
+        assert_eq!(response.status(), ::http::StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.header("content-range"), Some("bytes */10"));
+
+        let response = client.send(ranged(&etag).header("if-none-match", &etag)).await;
+        assert_eq!(response.status(), ::http::StatusCode::NOT_MODIFIED);
+        assert!(response.header("content-range").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_file_get_mmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Vec<u8> = (0..2 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.path().join("large.bin"), &source).unwrap();
+        std::fs::write(dir.path().join("small.txt"), "small").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.allowed_extensions.push("bin".to_string());
+        config.files.use_mmap = true;
+        config.files.mmap_min_size = 1024 * 1024;
+        let client = testing::TestClient::new(config);
+        let ranged = |range: &str| testing::TestRequest::get("/files/large.bin").header("range", range);
+
+        let response = client.get("/files/large.bin").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.bytes(), &source[..]);
+        assert_eq!(client.get("/files/small.txt").await.text(), "small");
+
+        let response = client.send(ranged("bytes=1048570-1048579")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.header("content-range"), Some("bytes 1048570-1048579/2097152"));
+        assert_eq!(response.bytes(), &source[1048570..1048580]);
+
+        let response = client.send(ranged("bytes=0-9, -10")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        let content_type = response.header("content-type").unwrap();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        let body = bytes::Bytes::copy_from_slice(response.bytes());
+        let parts: Vec<_> = multipart::Multipart::new(body, boundary).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(&parts[0].bytes()[..], &source[..10]);
+        assert_eq!(&parts[1].bytes()[..], &source[source.len() - 10..]);
+    }
+
+    #[tokio::test]
+    async fn test_file_get_multipart_byteranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Vec<u8> = (0..2000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.path().join("doc.pdf"), &source).unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.max_ranges = 3;
+        let client = testing::TestClient::new(config);
+        let ranged = |range: &str| testing::TestRequest::get("/files/doc.pdf").header("range", range);
+
+        let response = client.send(ranged("bytes=0-99, 1000-1099, -10")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        assert!(response.header("content-range").is_none());
+        assert_eq!(response.header("content-length"), Some(response.bytes().len().to_string().as_str()));
+        let content_type = response.header("content-type").unwrap();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+
+        let body = bytes::Bytes::copy_from_slice(response.bytes());
+        let parts: Vec<_> = multipart::Multipart::new(body, boundary).unwrap().collect::<Result<_>>().unwrap();
+        let expected = [(0, 100, "bytes 0-99/2000"), (1000, 1100, "bytes 1000-1099/2000"), (1990, 2000, "bytes 1990-1999/2000")];
+        assert_eq!(parts.len(), expected.len());
+        for (part, (start, end, content_range)) in parts.iter().zip(expected) {
+            assert_eq!(part.content_type.as_deref(), Some("application/pdf"));
+            assert_eq!(part.headers.get("content-range").unwrap(), content_range);
+            assert_eq!(&part.bytes()[..], &source[start..end]);
+        }
+
+        let response = client.send(ranged("bytes=5-9")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.header("content-range"), Some("bytes 5-9/2000"));
+        assert_eq!(response.bytes(), &source[5..10]);
+
+        for abusive in ["bytes=0-1,2-3,4-5,6-7", "bytes=0-1999,0-1999"] {
+            let response = client.send(ranged(abusive)).await;
+            assert_eq!(response.status(), ::http::StatusCode::OK, "{}", abusive);
+            assert_eq!(response.bytes(), &source[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_get_uses_mime_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<p>hi</p>").unwrap();
+        std::fs::write(dir.path().join("notes.md"), "# notes").unwrap();
+        std::fs::write(dir.path().join("image"), b"\x89PNG\r\n\x1a\nrest").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.allowed_extensions.clear();
+        config.files.mime_types.insert("md".to_string(), "text/x-markdown".to_string());
+        config.files.sniff_content_type = true;
+        let client = testing::TestClient::new(config.clone());
+
+        for (path, expected) in [
+            ("/files/index.html", "text/html; charset=utf-8"),
+            ("/files/notes.md", "text/x-markdown; charset=utf-8"),
+            ("/files/image", "image/png"),
+        ] {
+            assert_eq!(client.get(path).await.header("content-type"), Some(expected), "{}", path);
+        }
+
+        config.files.sniff_content_type = false;
+        config.files.text_charset.clear();
+        let client = testing::TestClient::new(config.clone());
+        assert_eq!(client.get("/files/image").await.header("content-type"), Some("application/octet-stream"));
+        assert_eq!(client.get("/files/index.html").await.header("content-type"), Some("text/html"));
+
+        config.files.mime_types.insert("bad".to_string(), "not a type".to_string());
+        assert!(config.validate().is_err());
+        config.files.mime_types.clear();
+        config.files.text_charset = "utf-8\r\nx: y".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_download_content_disposition() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("report.txt"), "data").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let server = server::Server::new(config);
+        let get = |path: &str| Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+
+        let response = server.router().handle(get("/files/report.txt")).await.unwrap();
+        assert!(response.headers.get("content-disposition").is_none());
+
+        let response = server.router().handle(get("/files/report.txt?download=1")).await.unwrap();
+        assert_eq!(response.headers["content-disposition"], "attachment; filename=\"report.txt\"");
+
+        let response = server.router().handle(get("/files/report.txt?download=r%C3%A9sum%C3%A9.txt")).await.unwrap();
+        assert_eq!(
+            response.headers["content-disposition"],
+            "attachment; filename=\"r_sum_.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9.txt"
+        );
+
+        let response = server.router().handle(get("/files/report.txt?download=a.txt%0D%0ASet-Cookie:%20x=1")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::BAD_REQUEST);
+
+        let response = Response::ok().with_attachment("evil\r\nx-injected: 1\".txt");
+        assert_eq!(response.headers["content-disposition"], "attachment; filename=\"evilx-injected: 1_.txt\"; filename*=UTF-8''evilx-injected%3A%201%22.txt");
+        assert!(response.headers.get("x-injected").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir_all(docs.join("nested")).unwrap();
+        std::fs::write(docs.join("readme.txt"), "hello").unwrap();
+        std::fs::write(docs.join("nested").join("deep.json"), "{}").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.enable_directory_listing = true;
+        let server = server::Server::new(config);
+
+        let mut request = Request::new(Method::GET, "/files/docs".parse().unwrap(), Version::HTTP_11);
+        request.headers.insert("accept", "application/json".parse().unwrap());
+        let response = server.router().handle(request).await.unwrap();
+        assert_eq!(response.headers["content-type"], "application/json");
+
+        let body: serde_json::Value = serde_json::from_slice(response.body.as_deref().unwrap()).unwrap();
+        let mut entries: Vec<utils::DirectoryEntry> = serde_json::from_value(body.clone()).unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "nested");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].mime_type, None);
+        assert_eq!(entries[1].name, "readme.txt");
+        assert_eq!(entries[1].size, 5);
+        assert_eq!(entries[1].mime_type.as_deref(), Some("text/plain"));
+        let modified = entries[1].modified.as_deref().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(modified).is_ok());
+        for entry in body.as_array().unwrap() {
+            let keys: Vec<_> = entry.as_object().unwrap().keys().map(String::as_str).collect();
+            assert_eq!(keys.len(), 5);
+            for key in ["name", "size", "is_dir", "modified", "mime_type"] {
+                assert!(keys.contains(&key), "{}", key);
+            }
+        }
+
+        let request = Request::new(Method::GET, "/files/docs?format=json".parse().unwrap(), Version::HTTP_11);
+        let response = server.router().handle(request).await.unwrap();
+        assert_eq!(response.headers["content-type"], "application/json");
+
+        let request = Request::new(Method::GET, "/files/docs".parse().unwrap(), Version::HTTP_11);
+        let response = server.router().handle(request).await.unwrap();
+        assert_eq!(response.headers["content-type"], "text/html");
+        assert!(String::from_utf8_lossy(response.body.as_deref().unwrap()).contains("readme.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_directory_archive_download() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir_all(docs.join("sub")).unwrap();
+        std::fs::write(docs.join("a.txt"), "alpha").unwrap();
+        std::fs::write(docs.join("sub/b.json"), "{\"b\":1}").unwrap();
+        std::fs::write(docs.join(".secret.txt"), "hidden").unwrap();
+        std::fs::write(docs.join("tool.exe"), "binary").unwrap();
+        std::fs::write(dir.path().join("top.txt"), "top").unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("outside.txt"), "outside").unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outside.path().join("outside.txt"), docs.join("escape.txt")).unwrap();
+            std::os::unix::fs::symlink(&docs, docs.join("sub/loop")).unwrap();
+        }
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.enable_directory_listing = true;
+        let client = testing::TestClient::new(config.clone());
+        let expected = vec![("docs/a.txt".to_string(), "alpha".to_string()), ("docs/sub/b.json".to_string(), "{\"b\":1}".to_string())];
+
+        let response = client.get("/files/docs/?format=zip").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.header("content-type"), Some("application/zip"));
+        assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+        assert!(response.header("content-disposition").unwrap().contains("docs.zip"));
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(response.bytes().to_vec())).unwrap();
+        let mut files = Vec::new();
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index).unwrap();
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).unwrap();
+            files.push((file.name().to_string(), contents));
+        }
+        assert_eq!(files, expected);
+
+        let response = client.get("/files/docs?format=tar.gz").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert!(response.header("content-disposition").unwrap().contains("docs.tar.gz"));
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(response.bytes()));
+        let files: Vec<(String, String)> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                (entry.path().unwrap().to_string_lossy().into_owned(), contents)
+            })
+            .collect();
+        assert_eq!(files, expected);
+
+        let response = client.get("/files/?format=zip").await;
+        assert!(response.header("content-disposition").unwrap().contains("files.zip"));
+        assert_eq!(zip::ZipArchive::new(std::io::Cursor::new(response.bytes().to_vec())).unwrap().len(), 3);
+
+        config.files.archive.max_entries = 1;
+        let response = testing::TestClient::new(config.clone()).get("/files/docs/?format=zip").await;
+        assert_eq!(response.status(), ::http::StatusCode::PAYLOAD_TOO_LARGE);
+        config.files.archive.enabled = false;
+        let response = testing::TestClient::new(config.clone()).get("/files/docs/?format=zip").await;
+        assert_eq!(response.status(), ::http::StatusCode::FORBIDDEN);
+
+        config.files.archive = config::FileArchiveConfig::default();
+        let (_server, addr) = spawn_server(config).await;
+        let raw = send_raw(addr, b"GET /files/docs/?format=zip HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(raw.starts_with("HTTP/1.1 200 OK\r\n"), "{}", raw);
+        assert!(raw.contains("transfer-encoding: chunked\r\n"));
+        assert!(!raw.contains("content-length"));
+        assert!(raw.ends_with("\r\n0\r\n\r\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_directory_listing_degrades_on_broken_entries() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir_all(&docs).unwrap();
+        std::fs::write(docs.join("ok.txt"), "hello").unwrap();
+        std::os::unix::fs::symlink(docs.join("missing.txt"), docs.join("dangling.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("docs"), docs.join("linked")).unwrap();
+        let odd_name = std::ffi::OsStr::from_bytes(b"odd\xff.txt");
+        let has_odd_name = std::fs::write(docs.join(odd_name), "x").is_ok();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.enable_directory_listing = true;
+        let client = testing::TestClient::new(config.clone());
+
+        let response = client.get("/files/docs/").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        let html = response.text();
+        assert!(html.contains("<td><a href=\"/files/docs/dangling.txt\">dangling.txt</a></td><td>-</td><td>-</td>"), "{}", html);
+        assert!(html.contains("<a href=\"/files/docs/linked/\">linked/</a>"), "{}", html);
+        assert!(html.contains(">ok.txt</a></td><td>5 B</td>"), "{}", html);
+        if has_odd_name {
+            assert!(html.contains("odd\u{fffd}.txt"), "{}", html);
+        }
+
+        let entries: Vec<utils::DirectoryEntry> = client.get("/files/docs/?format=json").await.json().unwrap();
+        let dangling = entries.iter().find(|entry| entry.name == "dangling.txt").unwrap();
+        assert_eq!((dangling.size, dangling.is_dir, dangling.modified.as_deref()), (0, false, None));
+        assert!(entries.iter().any(|entry| entry.name == "linked" && entry.is_dir));
+
+        let names = |entries: Vec<std::fs::DirEntry>| -> Vec<String> {
+            entries.iter().map(|entry| entry.file_name().to_string_lossy().into_owned()).collect()
+        };
+        config.files.hide_non_utf8_names = true;
+        let listed = names(utils::read_directory(&docs, &config.files).unwrap());
+        assert_eq!(listed.len(), 3, "{:?}", listed);
+        assert!(!listed.iter().any(|name| name.starts_with("odd")));
+
+        let missing = dir.path().join("gone");
+        match utils::read_directory(&missing, &config.files) {
+            Err(Error::Internal(message)) => assert!(message.contains("gone"), "{}", message),
+            other => panic!("{:?}", other.map(names)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing_sorting_and_navigation() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir_all(docs.join("nested")).unwrap();
+        std::fs::write(docs.join("small.txt"), "a").unwrap();
+        std::fs::write(docs.join("large.txt"), "a".repeat(100)).unwrap();
+        std::fs::write(docs.join("medium.txt"), "a".repeat(10)).unwrap();
+        std::fs::write(docs.join(".secret"), "hidden").unwrap();
+        std::fs::write(docs.join("nested").join("deep.txt"), "deep").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.enable_directory_listing = true;
+        let server = server::Server::new(config.clone());
+
+        async fn list(server: &server::Server, path: &str) -> Vec<String> {
+            let request = Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+            let response = server.router().handle(request).await.unwrap();
+            let body: Vec<utils::DirectoryEntry> = serde_json::from_slice(response.body.as_deref().unwrap()).unwrap();
+            body.into_iter().map(|entry| entry.name).collect()
+        }
+
+        assert_eq!(
+            list(&server, "/files/docs?format=json&sort=size&order=desc").await,
+            ["nested", "large.txt", "medium.txt", "small.txt"]
+        );
+        assert_eq!(
+            list(&server, "/files/docs?format=json&sort=size").await,
+            ["nested", "small.txt", "medium.txt", "large.txt"]
+        );
+        assert_eq!(
+            list(&server, "/files/docs?format=json").await,
+            ["nested", "large.txt", "medium.txt", "small.txt"]
+        );
+
+        config.files.show_hidden = true;
+        let hidden_server = server::Server::new(config);
+        assert!(list(&hidden_server, "/files/docs?format=json").await.contains(&".secret".to_string()));
+
+        let request = Request::new(Method::GET, "/files/docs/nested".parse().unwrap(), Version::HTTP_11);
+        let response = server.router().handle(request).await.unwrap();
+        let html = String::from_utf8_lossy(response.body.as_deref().unwrap()).into_owned();
+        assert!(html.contains("<a href=\"/files/docs/nested/deep.txt\">deep.txt</a>"));
+        assert!(html.contains("<a href=\"/files/docs/\">../</a>"));
+
+        let request = Request::new(Method::GET, "/files/docs/".parse().unwrap(), Version::HTTP_11);
+        let response = server.router().handle(request).await.unwrap();
+        let html = String::from_utf8_lossy(response.body.as_deref().unwrap()).into_owned();
+        assert!(html.contains("<a href=\"/files/docs/nested/\">nested/</a>"));
+        assert!(!html.contains(".secret"));
+
+        let request = Request::new(Method::GET, "/files/".parse().unwrap(), Version::HTTP_11);
+        let response = server.router().handle(request).await.unwrap();
+        let html = String::from_utf8_lossy(response.body.as_deref().unwrap()).into_owned();
+        assert!(html.contains("<a href=\"/files/docs/\">docs/</a>"));
+        assert!(!html.contains("../"));
+
+        let request = Request::new(Method::GET, "/files/docs/nested/deep.txt".parse().unwrap(), Version::HTTP_11);
+        let response = server.router().handle(request).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"deep"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_unsorted_directory_listing_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir_all(docs.join("nested")).unwrap();
+        for i in 0..500 {
+            std::fs::write(docs.join(format!("file-{:03}.txt", i)), "a").unwrap();
+        }
+        std::fs::write(docs.join(".secret"), "hidden").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.enable_directory_listing = true;
+        let server = server::Server::new(config);
+        let client = testing::TestClient::from_server(&server);
+
+        let request = Request::new(Method::GET, "/files/docs?format=json&sort=none".parse().unwrap(), Version::HTTP_11);
+        let response = server.router().handle(request).await.unwrap();
+        assert!(response.body.is_none() && response.stream.is_some());
+
+        let response = client.get("/files/docs?format=json&sort=none").await;
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        let streamed: Vec<utils::DirectoryEntry> = response.json().unwrap();
+        let sorted: Vec<utils::DirectoryEntry> = client.get("/files/docs?format=json").await.json().unwrap();
+        assert_eq!(streamed.len(), 501);
+        let names = |entries: &[utils::DirectoryEntry]| entries.iter().map(|entry| entry.name.clone()).collect::<std::collections::HashSet<_>>();
+        assert_eq!(names(&streamed), names(&sorted));
+        assert!(streamed.iter().any(|entry| entry.name == "nested" && entry.is_dir));
+
+        let html = client.get("/files/docs/?sort=none").await.text();
+        assert!(html.starts_with("<!DOCTYPE html>") && html.ends_with("</html>"));
+        assert!(html.contains("<a href=\"/files/docs/file-042.txt\">file-042.txt</a>"));
+        assert!(html.contains("<a href=\"/files/docs/nested/\">nested/</a>"));
+        assert!(html.contains("<a href=\"/files/\">../</a>"));
+        assert!(!html.contains(".secret"));
+    }
+
+    // The validator stands in for a slow disk: it holds the upload on its
+    // blocking task until released. The test runs on a single-threaded
+    // runtime, so other requests only finish if file work is off the runtime.
+    #[tokio::test]
+    async fn test_slow_file_work_does_not_block_other_requests() {
+        struct SlowDisk {
+            started: std::sync::Mutex<Option<tokio::sync::oneshot::Sender<()>>>,
+            release: std::sync::Mutex<std::sync::mpsc::Receiver<()>>,
+        }
+        impl upload::UploadValidator for SlowDisk {
+            fn validate(&self, _: &upload::Upload<'_>) -> Result<()> {
+                if let Some(started) = self.started.lock().unwrap().take() {
+                    let _ = started.send(());
+                }
+                let _ = self.release.lock().unwrap().recv_timeout(std::time::Duration::from_secs(5));
+                Ok(())
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("fast.txt"), "fast").unwrap();
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let server = server::Server::new(config);
+        let (started, upload_started) = tokio::sync::oneshot::channel();
+        let (release, held) = std::sync::mpsc::channel();
+        server.files().validators.add(SlowDisk {
+            started: std::sync::Mutex::new(Some(started)),
+            release: std::sync::Mutex::new(held),
+        });
+        let client = std::sync::Arc::new(testing::TestClient::from_server(&server));
+
+        let upload = tokio::spawn({
+            let client = client.clone();
+            async move { client.send(testing::TestRequest::put("/files/slow.txt").body("slow")).await }
+        });
+        upload_started.await.unwrap();
+
+        let begun = std::time::Instant::now();
+        assert_eq!(client.get("/files/fast.txt").await.text(), "fast");
+        assert_eq!(client.get("/").await.status(), ::http::StatusCode::OK);
+        assert!(begun.elapsed() < std::time::Duration::from_secs(2), "{:?}", begun.elapsed());
+        assert!(!upload.is_finished());
+
+        release.send(()).unwrap();
+        assert_eq!(upload.await.unwrap().status(), ::http::StatusCode::CREATED);
+        assert_eq!(std::fs::read_to_string(dir.path().join("slow.txt")).unwrap(), "slow");
+    }
+
+    #[tokio::test]
+    async fn test_spa_fallback_serves_index() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<div id=app></div>").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.spa_fallback = Some("index.html".to_string());
+        let server = server::Server::new(config);
+
+        let request = |method: Method, path: &str, accept: &str| {
+            let mut request = Request::new(method, path.parse().unwrap(), Version::HTTP_11);
+            request.headers.insert("accept", accept.parse().unwrap());
+            request
+        };
+        let html = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+        let response = server.router().handle(request(Method::GET, "/some/client/route", html)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+        assert_eq!(response.headers["content-type"], "text/html; charset=utf-8");
+        assert_eq!(response.body.as_deref(), Some(&b"<div id=app></div>"[..]));
+
+        let response = server.router().handle(request(Method::HEAD, "/app/settings", html)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+        assert!(response.body.is_none());
+
+        let response = server.router().handle(request(Method::GET, "/missing.js", html)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::NOT_FOUND);
+
+        let response = server.router().handle(request(Method::GET, "/api/users", "application/json")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::NOT_FOUND);
+
+        let response = server.router().handle(request(Method::POST, "/some/client/route", html)).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::NOT_FOUND);
+
+        let response = server.router().handle(request(Method::GET, "/", html)).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"Welcome to Rust HTTP Server"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_file_put_with_preconditions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let server = server::Server::new(config);
+
+        let put = |body: &'static [u8], headers: &[(&'static str, &str)]| {
+            let mut request = Request::new(Method::PUT, "/files/data.json".parse().unwrap(), Version::HTTP_11);
+            for (name, value) in headers {
+                request.headers.insert(*name, value.parse().unwrap());
+            }
+            request.body = Some(bytes::Bytes::from_static(body));
+            request
+        };
+
+        let response = server.router().handle(put(b"\x00\x01v1", &[("if-none-match", "*")])).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::CREATED);
+        assert_eq!(std::fs::read(dir.path().join("data.json")).unwrap(), b"\x00\x01v1");
+        let etag = response.headers["etag"].to_str().unwrap().to_string();
+
+        let response = server.router().handle(put(b"v2", &[("if-none-match", "*")])).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::PRECONDITION_FAILED);
+
+        let response = server.router().handle(put(b"v2", &[("if-match", "\"stale\"")])).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::PRECONDITION_FAILED);
+        assert_eq!(std::fs::read(dir.path().join("data.json")).unwrap(), b"\x00\x01v1");
+
+        let response = server.router().handle(put(b"v2", &[("if-match", &format!("\"other\", {}", etag))])).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::NO_CONTENT);
+        assert_ne!(response.headers["etag"], etag.as_str());
+        assert_eq!(std::fs::read(dir.path().join("data.json")).unwrap(), b"v2");
+
+        let response = server.router().handle(put(b"v3", &[])).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::NO_CONTENT);
+        assert_eq!(std::fs::read(dir.path().join("data.json")).unwrap(), b"v3");
+    }
+
+    #[tokio::test]
+    async fn test_file_delete_and_post_preconditions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "v1").unwrap();
+
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let client = testing::TestClient::new(config.clone());
+        let last_modified = client.get("/files/notes.txt").await.header("last-modified").unwrap().to_string();
+
+        let response = client.send(testing::TestRequest::delete("/files/notes.txt").header("if-match", "\"stale\"")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PRECONDITION_FAILED);
+        assert!(path.exists());
+
+        let response = client.send(testing::TestRequest::delete("/files/notes.txt").header("if-unmodified-since", "Thu, 01 Jan 2015 00:00:00 GMT")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PRECONDITION_FAILED);
+        assert!(path.exists());
+
+        let response = client.send(testing::TestRequest::post("/files/notes.txt").header("if-match", "\"stale\"").body("v2")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PRECONDITION_FAILED);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "v1");
+
+        let response = client.send(testing::TestRequest::delete("/files/notes.txt").header("if-unmodified-since", &last_modified)).await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert!(!path.exists());
+
+        let response = client.send(testing::TestRequest::delete("/files/notes.txt").header("if-match", "*")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PRECONDITION_FAILED);
+        let response = client.send(testing::TestRequest::post("/files/notes.txt").header("if-match", "*").body("v2")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PRECONDITION_FAILED);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "v1").unwrap();
+        let etag = client.get("/files/notes.txt").await.header("etag").unwrap().to_string();
+        let response = client.send(testing::TestRequest::delete("/files/notes.txt").header("if-match", &etag)).await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "v1").unwrap();
+        config.files.require_delete_preconditions = true;
+        let client = testing::TestClient::new(config);
+        let response = client.send(testing::TestRequest::delete("/files/notes.txt")).await;
+        assert_eq!(response.status(), ::http::StatusCode::PRECONDITION_REQUIRED);
+        assert!(path.exists());
+        let response = client.send(testing::TestRequest::delete("/files/notes.txt").header("if-match", "*")).await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_file_move_and_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("draft.txt"), "draft").unwrap();
+        std::fs::write(dir.path().join("taken.txt"), "taken").unwrap();
+
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let client = testing::TestClient::new(config.clone());
+        let transfer = |method: &[u8], source: &str, destination: &str| {
+            testing::TestRequest::new(Method::from_bytes(method).unwrap(), source).header("destination", destination)
+        };
+
+        let response = client.send(transfer(b"MOVE", "/files/draft.txt", "/files/archive/2024/final%20draft.txt")).await;
+        assert_eq!(response.status(), ::http::StatusCode::CREATED);
+        assert_eq!(response.header("location"), Some("/files/archive/2024/final%20draft.txt"));
+        assert!(!dir.path().join("draft.txt").exists());
+        assert_eq!(std::fs::read_to_string(dir.path().join("archive/2024/final draft.txt")).unwrap(), "draft");
+
+        let response = client.send(transfer(b"COPY", "/files/taken.txt", "http://localhost/files/copy.txt")).await;
+        assert_eq!(response.status(), ::http::StatusCode::CREATED);
+        assert_eq!(std::fs::read_to_string(dir.path().join("copy.txt")).unwrap(), "taken");
+        assert!(dir.path().join("taken.txt").exists());
+
+        let response = client.send(transfer(b"MOVE", "/files/copy.txt", "/files/taken.txt")).await;
+        assert_eq!(response.status(), ::http::StatusCode::CONFLICT);
+        assert!(dir.path().join("copy.txt").exists());
+        let response = client.send(transfer(b"MOVE", "/files/copy.txt", "/files/taken.txt").header("overwrite", "T")).await;
+        assert_eq!(response.status(), ::http::StatusCode::NO_CONTENT);
+        assert!(!dir.path().join("copy.txt").exists());
+
+        for destination in ["/files/../escape.txt", "/files/%2e%2e/escape.txt", "/other/escape.txt", "/files/tool.exe"] {
+            let response = client.send(transfer(b"MOVE", "/files/taken.txt", destination)).await;
+            assert_eq!(response.status(), ::http::StatusCode::BAD_REQUEST, "{}", destination);
+            assert!(dir.path().join("taken.txt").exists());
+        }
+        assert!(!dir.path().parent().unwrap().join("escape.txt").exists());
+
+        let response = client.send(transfer(b"MOVE", "/files/missing.txt", "/files/new.txt")).await;
+        assert_eq!(response.status(), ::http::StatusCode::NOT_FOUND);
+
+        let (_server, addr) = spawn_server(config).await;
+        let raw = send_raw(addr, b"COPY /files/taken.txt HTTP/1.1\r\nHost: localhost\r\nDestination: /files/raw.txt\r\nConnection: close\r\n\r\n").await;
+        assert!(raw.starts_with("HTTP/1.1 201 Created\r\n"), "{}", raw);
+        assert!(dir.path().join("raw.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_webdav_propfind_and_options() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("docs/sub dir")).unwrap();
+        std::fs::write(dir.path().join("docs/a&b.txt"), "hello").unwrap();
+        std::fs::write(dir.path().join("docs/.hidden.txt"), "x").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.enable_directory_listing = true;
+        let client = testing::TestClient::new(config.clone());
+        let propfind = |uri: &str| testing::TestRequest::new(Method::from_bytes(b"PROPFIND").unwrap(), uri);
+
+        let response = client.send(propfind("/files/docs/").header("depth", "1")).await;
+        assert_eq!(response.status(), ::http::StatusCode::MULTI_STATUS);
+        assert_eq!(response.header("content-type"), Some("application/xml; charset=utf-8"));
+        let xml = response.text();
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n"), "{}", xml);
+        let responses: Vec<&str> = xml.split("<D:response>").skip(1).collect();
+        assert_eq!(responses.len(), 3, "{}", xml);
+        assert!(responses[0].starts_with("<D:href>/files/docs/</D:href>"), "{}", responses[0]);
+        assert!(responses[0].contains("<D:displayname>docs</D:displayname><D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(responses[2].starts_with("<D:href>/files/docs/a%26b.txt</D:href>"), "{}", responses[2]);
+        assert!(responses[2].contains(
+            "<D:displayname>a&amp;b.txt</D:displayname><D:resourcetype/><D:getcontentlength>5</D:getcontentlength><D:getcontenttype>text/plain; charset=utf-8</D:getcontenttype><D:getlastmodified>"
+        ));
+        assert!(responses[1].starts_with("<D:href>/files/docs/sub%20dir/</D:href>"), "{}", responses[1]);
+        assert!(responses[1].contains("<D:collection/>") && !responses[1].contains("getcontentlength"));
+        for response in &responses {
+            assert!(response.contains("<D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"));
+            assert!(response.contains(" GMT</D:getlastmodified>"));
+        }
+        assert!(xml.ends_with("</D:multistatus>\n"));
+
+        assert_eq!(client.get("/files/docs/a%26b.txt").await.text(), "hello");
+        let response = client.send(propfind("/files/docs/a%26b.txt").header("depth", "0")).await;
+        assert_eq!(response.status(), ::http::StatusCode::MULTI_STATUS);
+        assert_eq!(response.text().matches("<D:response>").count(), 1);
+        let response = client.send(propfind("/files").header("depth", "0")).await;
+        assert!(response.text().contains("<D:href>/files/</D:href><D:propstat><D:prop><D:displayname>files</D:displayname>"));
+
+        for depth in [Some("infinity"), None] {
+            let request = match depth {
+                Some(depth) => propfind("/files/docs/").header("depth", depth),
+                None => propfind("/files/docs/"),
+            };
+            assert_eq!(client.send(request).await.status(), ::http::StatusCode::FORBIDDEN, "{:?}", depth);
+        }
+        let response = client.send(propfind("/files/docs/").header("depth", "2")).await;
+        assert_eq!(response.status(), ::http::StatusCode::BAD_REQUEST);
+        let response = client.send(propfind("/files/missing/").header("depth", "1")).await;
+        assert_eq!(response.status(), ::http::StatusCode::NOT_FOUND);
+
+        let response = client.send(testing::TestRequest::options("/files/docs/")).await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.header("dav"), Some("1"));
+        assert!(response.header("allow").unwrap().contains("PROPFIND"));
+
+        config.files.enable_directory_listing = false;
+        let client = testing::TestClient::new(config.clone());
+        let response = client.send(propfind("/files/docs/").header("depth", "1")).await;
+        assert_eq!(response.status(), ::http::StatusCode::FORBIDDEN);
+
+        let (_server, addr) = spawn_server(config).await;
+        let raw = send_raw(addr, b"PROPFIND /files/docs/ HTTP/1.1\r\nHost: localhost\r\nDepth: 0\r\nConnection: close\r\n\r\n").await;
+        assert!(raw.starts_with("HTTP/1.1 207 Multi-Status\r\n"), "{}", raw);
+    }
+
+    #[test]
+    fn test_atomic_write_never_exposes_partial_body() {
+        use std::io::Write;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.txt");
+        let old = vec![b'a'; 64 * 1024];
+        let new = vec![b'b'; 64 * 1024];
+        std::fs::write(&path, &old).unwrap();
+
+        let done = std::sync::Arc::new(AtomicBool::new(false));
+        let reader = {
+            let path = path.clone();
+            let done = done.clone();
+            let (old, new) = (old.clone(), new.clone());
+            std::thread::spawn(move || {
+                let mut reads = 0;
+                while !done.load(Ordering::Acquire) {
+                    let body = std::fs::read(&path).unwrap();
+                    assert!(body == old || body == new, "observed partial body of {} bytes", body.len());
+                    reads += 1;
+                }
+                reads
+            })
+        };
+
+        storage::write_atomic_with(&path, |file| {
+            for chunk in new.chunks(4096) {
+                file.write_all(chunk)?;
+                std::thread::sleep(std::time::Duration::from_millis(2));
+            }
+            Ok(())
+        })
+        .unwrap();
+        done.store(true, Ordering::Release);
+
+        assert!(reader.join().unwrap() > 0);
+        assert_eq!(std::fs::read(&path).unwrap(), new);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let failed = storage::write_atomic_with(&path, |_| Err(std::io::Error::other("disk full")));
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), new);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_path_locks_serialize_writers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let locks = std::sync::Arc::new(storage::PathLocks::new());
+        let active = std::sync::Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let locks = locks.clone();
+                let active = active.clone();
+                std::thread::spawn(move || {
+                    locks.with_lock(std::path::Path::new("/files/same.txt"), || {
+                        assert_eq!(active.fetch_add(1, Ordering::SeqCst), 0);
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload_stores_file_part() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.allowed_extensions = Vec::new();
+        let server = server::Server::new(config);
+
+        let binary: Vec<u8> = (0..=255u8).chain(b"\r\n--not-the-boundary\r\n".iter().copied()).collect();
+        let mut body = b"preamble to ignore\r\n--XyZ\r\n".to_vec();
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"note\"\r\n\r\nhello world\r\n--XyZ\r\n");
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"upload\"; filename=\"C:\\\\tmp\\\\blob.bin\"\r\n");
+        body.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+        body.extend_from_slice(&binary);
+        body.extend_from_slice(b"\r\n--XyZ--\r\nepilogue");
+
+        let mut request = Request::new(Method::POST, "/files".parse().unwrap(), Version::HTTP_11);
+        request.headers.insert("content-type", "multipart/form-data; boundary=\"XyZ\"".parse().unwrap());
+        request.body = Some(body.clone().into());
+
+        let parts: Vec<_> = request.multipart().unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name.as_deref(), Some("note"));
+        assert_eq!(parts[0].text(), Some("hello world"));
+        assert_eq!(parts[1].filename.as_deref(), Some("C:\\tmp\\blob.bin"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("application/octet-stream"));
+
+        let response = server.router().handle(request).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::CREATED);
+        assert_eq!(std::fs::read(dir.path().join("blob.bin")).unwrap(), binary);
+
+        let truncated = &body[..body.len() - b"\r\n--XyZ--\r\nepilogue".len()];
+        let mut request = Request::new(Method::POST, "/files".parse().unwrap(), Version::HTTP_11);
+        request.headers.insert("content-type", "multipart/form-data; boundary=XyZ".parse().unwrap());
+        request.body = Some(bytes::Bytes::copy_from_slice(truncated));
+        let response = server.router().handle(request).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_upload_quota_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("existing.txt"), "0123456789").unwrap();
+
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.max_total_bytes = Some(30);
+        config.files.directory_quotas.insert("logs".to_string(), 5);
+        let server = server::Server::new(config);
+
+        let request = |method: Method, path: &str, body: &'static str| {
+            let mut request = Request::new(method, path.parse().unwrap(), Version::HTTP_11);
+            request.body = Some(bytes::Bytes::from_static(body.as_bytes()));
+            request
+        };
+
+        let response = server.router().handle(request(Method::POST, "/files/a.txt", "0123456789")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::CREATED);
+        assert_eq!(server.files().quota.usage(), 20);
+
+        let response = server.router().handle(request(Method::POST, "/files/b.txt", "0123456789AB")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::INSUFFICIENT_STORAGE);
+        assert!(!dir.path().join("b.txt").exists());
+
+        let response = server.router().handle(request(Method::PUT, "/files/logs/app.txt", "123456")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::INSUFFICIENT_STORAGE);
+
+        let response = server.router().handle(request(Method::DELETE, "/files/a.txt", "")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+        assert_eq!(server.files().quota.usage(), 10);
+
+        let response = server.router().handle(request(Method::POST, "/files/b.txt", "0123456789AB")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::CREATED);
+        assert_eq!(server.files().quota.usage(), 22);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_file_mutations() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit_path = dir.path().join("audit.jsonl");
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().join("files").to_string_lossy().into_owned();
+        config.files.max_file_size = 8;
+        config.audit.enabled = true;
+        config.audit.path = audit_path.to_string_lossy().into_owned();
+        let server = server::Server::new(config);
+
+        let request = |method: Method, path: &str, body: &'static str| {
+            let mut request = Request::new(method, path.parse().unwrap(), Version::HTTP_11);
+            request.remote_addr = Some("203.0.113.9:5000".parse().unwrap());
+            request.body = Some(bytes::Bytes::from_static(body.as_bytes()));
+            request
+        };
+
+        server.router().handle(request(Method::POST, "/files/a.txt", "hello")).await.unwrap();
+        server.router().handle(request(Method::GET, "/files/a.txt", "")).await.unwrap();
+        server.router().handle(request(Method::DELETE, "/files/a.txt", "")).await.unwrap();
+        let oversized = server.router().handle(request(Method::POST, "/files/b.txt", "too large body")).await.unwrap();
+        assert_eq!(oversized.status, ::http::StatusCode::PAYLOAD_TOO_LARGE);
+        server.audit_log().unwrap().flush();
+
+        let entries: Vec<audit::AuditEntry> = std::fs::read_to_string(&audit_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[0].method.as_str(), entries[0].path.as_str()), ("POST", "/files/a.txt"));
+        assert_eq!((entries[0].status, entries[0].bytes), (201, 5));
+        assert_eq!(entries[0].client_ip.as_deref(), Some("203.0.113.9"));
+        assert_eq!(entries[0].result, "success");
+        assert_eq!((entries[1].method.as_str(), entries[1].status), ("DELETE", 200));
+        assert_eq!((entries[2].status, entries[2].result.as_str()), (413, "denied"));
+        assert!(chrono::DateTime::parse_from_rfc3339(&entries[0].timestamp).is_ok());
+    }
+
+    #[test]
+    fn test_redirect_constructors() {
+        let cases = [
+            (Response::redirect("/login"), ::http::StatusCode::FOUND),
+            (Response::see_other("/login"), ::http::StatusCode::SEE_OTHER),
+            (Response::temporary_redirect("/login"), ::http::StatusCode::TEMPORARY_REDIRECT),
+            (Response::permanent_redirect("/login"), ::http::StatusCode::PERMANENT_REDIRECT),
+        ];
+        for (response, status) in cases {
+            assert_eq!(response.status, status);
+            assert_eq!(response.headers["location"], "/login");
+        }
+
+        let response = Response::redirect("/docs/my file ü.txt?q=a b");
+        assert_eq!(response.headers["location"], "/docs/my%20file%20%C3%BC.txt?q=a%20b");
+        let response = Response::redirect("/safe\r\nSet-Cookie: x=1");
+        assert_eq!(response.headers["location"], "/safe%0D%0ASet-Cookie:%20x=1");
+    }
+
+    #[tokio::test]
+    async fn test_redirect_rules_preserve_remainder() {
+        let config = config::Config {
+            redirects: vec![
+                config::RedirectRule { from: "/old/{*rest}".to_string(), to: "/new/{rest}".to_string(), status: 301 },
+                config::RedirectRule { from: "/home".to_string(), to: "/".to_string(), status: 302 },
+            ],
+            ..Default::default()
+        };
+        let server = server::Server::new(config);
+
+        let get = |path: &str| Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+
+        let response = server.router().handle(get("/old/docs/guide.html?lang=en")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers["location"], "/new/docs/guide.html?lang=en");
+
+        let response = server.router().handle(get("/home")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::FOUND);
+        assert_eq!(response.headers["location"], "/");
+
+        let response = server.router().handle(get("/homepage")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::NOT_FOUND);
+
+        let invalid = config::Config {
+            redirects: vec![config::RedirectRule { from: "/a".to_string(), to: "/b".to_string(), status: 200 }],
+            ..Default::default()
+        };
+        assert!(server::Server::try_new(invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("posts")).unwrap();
+        std::fs::write(dir.path().join("posts/hello world.txt"), "hello").unwrap();
+
+        let rule = |pattern: &str, replacement: &str, last: bool| config::RewriteRule {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            last,
+        };
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.rewrites = vec![
+            rule("/blog/(?<slug>[^/]+)", "/files/posts/$slug.txt", false),
+            rule("/a/(.*)", "/b/$1", false),
+            rule("/b/(.*)", "/c/$1", true),
+            rule("/c/(.*)", "/d/$1", false),
+            rule("/ping", "/pong", false),
+            rule("/pong", "/ping", false),
+        ];
+        let client = testing::TestClient::new(config.clone());
+
+        let response = client.get("/blog//hello%20world?ref=feed").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.text(), "hello");
+        assert_eq!(client.metrics().rewrite_hits("/blog/(?<slug>[^/]+)"), 1);
+
+        let rewrites = rewrite::Rewrites::from_config(&config.rewrites, client.metrics().clone()).unwrap();
+        assert_eq!(rewrites.rewrite("/a/x", Some("q=1")).unwrap(), Some(("/c/x".to_string(), Some("q=1".to_string()))));
+        assert_eq!(client.metrics().rewrite_hits("/c/(.*)"), 0);
+        assert_eq!(rewrites.rewrite("/other", None).unwrap(), None);
+        assert!(matches!(rewrites.rewrite("/ping", None), Err(Error::LoopDetected(_))));
+        assert!(client.metrics().rewrite_hits("/ping") >= 10);
+
+        let response = client.get("/ping").await;
+        assert_eq!(response.status(), ::http::StatusCode::LOOP_DETECTED);
+        assert!(client.metrics().render().contains("http_rewrite_hits_total{rule=\"/a/(.*)\"} 1\n"));
+
+        let invalid = config::Config { rewrites: vec![rule("/(", "/x", false)], ..Default::default() };
+        assert!(server::Server::try_new(invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_site_files() {
+        let client = testing::TestClient::new(config::Config::default());
+        assert_eq!(client.get("/robots.txt").await.status(), ::http::StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/favicon.ico").await.status(), ::http::StatusCode::NOT_FOUND);
+        assert!(!config::Config::default().site_files.is_quiet("/robots.txt"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let favicon = dir.path().join("favicon.ico");
+        std::fs::write(&favicon, b"\x00\x00\x01\x00icon").unwrap();
+        let mut config = config::Config::default();
+        config.site_files.robots_txt = Some("User-agent: *\nDisallow: /files/\n".to_string());
+        config.site_files.favicon_path = Some(favicon.to_string_lossy().into_owned());
+        config.site_files.favicon_max_age = 3600;
+        let client = testing::TestClient::new(config.clone());
+
+        let response = client.get("/robots.txt").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.header("content-type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(response.text(), "User-agent: *\nDisallow: /files/\n");
+
+        std::fs::write(&favicon, b"changed").unwrap();
+        let response = client.get("/favicon.ico").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.header("content-type"), Some("image/x-icon"));
+        assert_eq!(response.header("cache-control"), Some("public, max-age=3600"));
+        assert_eq!(response.bytes(), b"\x00\x00\x01\x00icon");
+        let etag = response.header("etag").unwrap().to_string();
+        let response = client.send(testing::TestRequest::get("/favicon.ico").header("if-none-match", &etag)).await;
+        assert_eq!(response.status(), ::http::StatusCode::NOT_MODIFIED);
+        assert!(config.site_files.is_quiet("/favicon.ico"));
+        assert!(!config.site_files.is_quiet("/"));
+
+        config.site_files.robots_path = Some("robots.txt".to_string());
+        assert!(config.validate().is_err());
+        config.site_files.robots_txt = None;
+        config.site_files.robots_path = Some(dir.path().join("missing.txt").to_string_lossy().into_owned());
+        assert!(server::Server::try_new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_mirroring() {
+        let (sink, sink_addr) = spawn_server(demo_config()).await;
+
+        let mut config = demo_config();
+        config.mirror.enabled = true;
+        config.mirror.upstream = format!("http://{}", sink_addr);
+        config.mirror.percentage = 50.0;
+        config.mirror.paths = vec!["/echo".to_string()];
+        config.mirror.max_body_bytes = 16;
+        config.mirror.max_in_flight = 1000;
+        let client = testing::TestClient::new(config.clone());
+
+        for _ in 0..400 {
+            let response = client.get("/echo/shadow").await;
+            assert_eq!(response.status(), ::http::StatusCode::OK);
+            assert_eq!(response.text(), "shadow");
+        }
+        assert_eq!(client.get("/user-agent").await.status(), ::http::StatusCode::BAD_REQUEST);
+        let large = testing::TestRequest::new(Method::POST, "/echo/large").body(vec![b'x'; 32]);
+        config.mirror.percentage = 100.0;
+        let always = testing::TestClient::new(config.clone());
+        assert_eq!(always.send(large).await.text(), "large");
+
+        let mirrored = || sink.metrics().requests_total(&Method::GET, ::http::StatusCode::OK);
+        let mut last = u64::MAX;
+        for _ in 0..200 {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            let (sent, failed, _) = client.metrics().mirror_counts();
+            if mirrored() == last && sent + failed == last {
+                break;
+            }
+            last = mirrored();
+        }
+        let (sent, failed, skipped) = client.metrics().mirror_counts();
+        assert_eq!(failed, 0);
+        assert_eq!(skipped, 0);
+        assert!((120..=280).contains(&sent), "mirrored {} of 400", sent);
+        assert_eq!(sink.metrics().requests_total(&Method::GET, ::http::StatusCode::OK), sent);
+        assert_eq!(sink.metrics().requests_total(&Method::GET, ::http::StatusCode::BAD_REQUEST), 0);
+        assert_eq!(always.metrics().mirror_counts(), (0, 0, 1));
+        assert_eq!(sink.metrics().requests_total(&Method::POST, ::http::StatusCode::OK), 0);
+
+        config.mirror.upstream = "ftp://example.com".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_client() {
+        let (upstream, addr) = spawn_server(demo_config()).await;
+        let http_client = client::HttpClient::new();
+
+        let response = http_client.get(&format!("http://{}/echo/upstream", addr)).send().await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+        assert_eq!(response.body.as_deref(), Some(&b"upstream"[..]));
+        let response = http_client
+            .get(&format!("http://{}/user-agent", addr))
+            .header("user-agent", "aggregator/1.0")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"aggregator/1.0"[..]));
+        let response = http_client.post(&format!("http://{}/echo/posted", addr)).body("ignored").send().await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"posted"[..]));
+        assert_eq!(upstream.metrics().stats().connections_accepted, 1);
+
+        let mut router = router::Router::new();
+        router.layer(middleware::Extension(http_client.clone()));
+        let target = format!("http://{}/echo/via-handler", addr);
+        router.add_route_with(
+            Method::GET,
+            "/aggregate",
+            move |request: Request| {
+                let client = request.extension::<client::HttpClient>().cloned();
+                let target = target.clone();
+                async move { client.unwrap().get(&target).send().await }
+            },
+            router::RouteOptions::default(),
+        );
+        let response = testing::TestClient::from_router(router).get("/aggregate").await;
+        assert_eq!(response.text(), "via-handler");
+        assert_eq!(upstream.metrics().stats().connections_accepted, 1);
+
+        let short = config::HttpClientConfig { max_response_bytes: 4, ..Default::default() };
+        let result = client::HttpClient::from_config(&short).unwrap().get(&format!("http://{}/echo/too-long", addr)).send().await;
+        assert!(matches!(result, Err(Error::Upstream(_))));
+        let result = http_client.get("/relative").send().await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        let _held = tokio::spawn(async move {
+            let _connection = silent.accept().await;
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        });
+        let result = http_client
+            .get(&format!("http://{}/", silent_addr))
+            .timeout(std::time::Duration::from_millis(50))
+            .send()
+            .await;
+        assert!(matches!(result, Err(Error::Timeout(_))));
+
+        let dir = tempfile::tempdir().unwrap();
+        let tls = config::TlsConfig {
+            enabled: true,
+            self_signed: true,
+            self_signed_dir: Some(dir.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let (_secure, secure_addr) = spawn_server(config::Config { tls, ..demo_config() }).await;
+        let url = format!("https://localhost:{}/echo/secure", secure_addr.port());
+        assert!(http_client.get(&url).send().await.is_err());
+        let trusted = config::HttpClientConfig {
+            ca_path: Some(dir.path().join("self-signed.pem").to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let response = client::HttpClient::from_config(&trusted).unwrap().get(&url).send().await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"secure"[..]));
+    }
+
+    #[cfg(feature = "templates")]
+    #[tokio::test]
+    async fn test_templates_escape_and_override() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("page.html"), "<p title=\"{{ title }}\">{{ body }}</p>{{ markup|safe }}").unwrap();
+        std::fs::write(dir.path().join("page.txt"), "{{ body }}").unwrap();
+        let config = config::TemplatesConfig { dir: Some(dir.path().to_string_lossy().into_owned()), reload: false };
+        let templates = templates::Templates::from_config(&config).unwrap();
+
+        let context = serde_json::json!({
+            "title": "\"><script>alert(1)</script>",
+            "body": "<img src=x onerror=alert('x')> & more",
+            "markup": "<em>trusted</em>",
+        });
+        let response = Response::ok().with_template(&templates, "page.html", &context).unwrap();
+        assert_eq!(response.headers["content-type"], "text/html");
+        assert_eq!(
+            response.body.as_deref().unwrap(),
+            b"<p title=\"&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;\">&lt;img src=x onerror=alert(&#39;x&#39;)&gt; &amp; more</p><em>trusted</em>"
+        );
+        assert_eq!(templates.render("page.txt", &context).unwrap(), "<img src=x onerror=alert('x')> & more");
+        let missing = Response::ok().with_template(&templates, "missing.html", &context);
+        assert!(matches!(&missing, Err(Error::Template(_))));
+        assert_eq!(missing.unwrap_err().status_code(), ::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let files = tempfile::tempdir().unwrap();
+        std::fs::write(files.path().join("<b>&'x'.txt"), "x").unwrap();
+        let mut server_config = config::Config::default();
+        server_config.files.root_dir = files.path().to_string_lossy().into_owned();
+        server_config.files.enable_directory_listing = true;
+        let html = testing::TestClient::new(server_config.clone()).get("/files/").await.text();
+        assert!(html.contains("<h1>Directory Listing: </h1>"), "{}", html);
+        assert!(html.contains("<a href=\"/files/%3Cb%3E%26%27x%27.txt\">&lt;b&gt;&amp;&#39;x&#39;.txt</a>"), "{}", html);
+
+        std::fs::write(dir.path().join("directory_listing.html"), "{% for row in rows %}[{{ row.name }}]{% endfor %}").unwrap();
+        server_config.templates = config::TemplatesConfig { reload: true, ..config };
+        let client = testing::TestClient::new(server_config.clone());
+        assert_eq!(client.get("/files/").await.text(), "[&lt;b&gt;&amp;&#39;x&#39;.txt]");
+        std::fs::write(dir.path().join("directory_listing.html"), "{{ rows|length }} entries").unwrap();
+        assert_eq!(client.get("/files/").await.text(), "1 entries");
+
+        std::fs::write(dir.path().join("broken.html"), "{% if %}").unwrap();
+        assert!(matches!(server::Server::try_new(server_config), Err(Error::Config(_))));
+    }
+
+    fn host_request(path: &str, host: Option<&str>) -> Request {
+        let mut request = Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+        if let Some(host) = host {
+            request.headers.insert("host", host.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_request_host_normalization() {
+        assert_eq!(host_request("/", Some("API.Example.com:8080")).host().as_deref(), Some("api.example.com"));
+        assert_eq!(host_request("/", Some("[::1]:4221")).host().as_deref(), Some("[::1]"));
+        assert_eq!(host_request("/", Some("www.example.com.")).host().as_deref(), Some("www.example.com"));
+        assert_eq!(host_request("http://Absolute.test/x", None).host().as_deref(), Some("absolute.test"));
+        assert_eq!(host_request("/", None).host(), None);
+    }
+
+    #[tokio::test]
+    async fn test_virtual_hosts_dispatch() {
+        let mut api = router::Router::new();
+        api.get("/", |_| Ok(Response::ok().with_text("api")));
+        let mut www = router::Router::new();
+        www.get("/", |_| Ok(Response::ok().with_text("www")));
+
+        let mut router = router::Router::new();
+        router.get("/", |_| Ok(Response::ok().with_text("default")));
+        router.host("api.example.com", api).host("WWW.example.com", www);
+
+        let body = |response: Response| String::from_utf8(response.body.unwrap().to_vec()).unwrap();
+        assert_eq!(body(router.handle(host_request("/", Some("api.example.com:443"))).await.unwrap()), "api");
+        assert_eq!(body(router.handle(host_request("/", Some("www.EXAMPLE.com"))).await.unwrap()), "www");
+        assert_eq!(body(router.handle(host_request("/", Some("other.example.com"))).await.unwrap()), "default");
+        assert_eq!(body(router.handle(host_request("/", None)).await.unwrap()), "default");
+
+        router.strict_hosts(true);
+        let response = router.handle(host_request("/", Some("other.example.com"))).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::MISDIRECTED_REQUEST);
+        assert_eq!(body(router.handle(host_request("/", Some("api.example.com"))).await.unwrap()), "api");
+    }
+
+    #[tokio::test]
+    async fn test_virtual_host_root_dir_override() {
+        let default_root = tempfile::tempdir().unwrap();
+        let api_root = tempfile::tempdir().unwrap();
+        std::fs::write(default_root.path().join("who.txt"), "default").unwrap();
+        std::fs::write(api_root.path().join("who.txt"), "api").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.root_dir = default_root.path().to_string_lossy().into_owned();
+        config.virtual_hosts.hosts.insert(
+            "api.example.com".to_string(),
+            config::VirtualHostConfig { root_dir: Some(api_root.path().to_string_lossy().into_owned()) },
+        );
+        let server = server::Server::new(config);
+
+        let response = server.router().handle(host_request("/files/who.txt", Some("api.example.com"))).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"api"[..]));
+        let response = server.router().handle(host_request("/files/who.txt", Some("localhost:4221"))).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"default"[..]));
+    }
+
+    mod smuggling {
+        use super::*;
+
+        fn parse(raw: &[u8], strict: bool) -> Result<Option<Request>> {
+            let mut buffer = bytes::BytesMut::from(raw);
+            let mut state = parser::ParseState::default();
+            parser::parse_request_with(&mut buffer, &mut state, &parser::ParseOptions { strict, ..Default::default() })
+        }
+
+        fn assert_rejected(raw: &[u8]) {
+            for strict in [true, false] {
+                match parse(raw, strict) {
+                    Err(Error::BadRequest(_)) => {}
+                    other => panic!("expected rejection of {:?}, got {:?}", String::from_utf8_lossy(raw), other.map(|r| r.is_some())),
+                }
+            }
+        }
+
+        #[test]
+        fn test_smuggling_cl_te_rejected() {
+            assert_rejected(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 13\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nSMUGGLED");
+        }
+
+        #[test]
+        fn test_smuggling_te_cl_rejected() {
+            assert_rejected(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n8\r\nSMUGGLED\r\n0\r\n\r\n");
+        }
+
+        #[test]
+        fn test_smuggling_te_te_obfuscation_rejected() {
+            for te in [
+                "Transfer-Encoding: xchunked",
+                "Transfer-Encoding: chunked\r\nTransfer-Encoding: x",
+                "Transfer-Encoding: chunked, identity",
+                "Transfer-Encoding: gzip",
+                "Transfer-Encoding: chunked, chunked",
+            ] {
+                let raw = format!("POST / HTTP/1.1\r\nHost: a\r\n{}\r\n\r\n0\r\n\r\n", te);
+                assert_rejected(raw.as_bytes());
+            }
+            assert_rejected(b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+        }
+
+        #[test]
+        fn test_smuggling_invalid_content_length_rejected() {
+            for cl in [
+                "Content-Length: 5\r\nContent-Length: 6",
+                "Content-Length: 5, 6",
+                "Content-Length: +5",
+                "Content-Length: -5",
+                "Content-Length: 0x5",
+                "Content-Length: 5 5",
+                "Content-Length: 99999999999999999999999",
+            ] {
+                let raw = format!("POST / HTTP/1.1\r\nHost: a\r\n{}\r\n\r\nhello", cl);
+                assert_rejected(raw.as_bytes());
+            }
+        }
+
+        #[test]
+        fn test_smuggling_invalid_header_names_rejected() {
+            assert_rejected(b"GET / HTTP/1.1\r\nHost: a\r\nTransfer Encoding: chunked\r\n\r\n");
+            assert_rejected(b"GET / HTTP/1.1\r\nHost: a\r\nX-Foo\x01: bar\r\n\r\n");
+            assert_rejected(b"GET / HTTP/1.1\r\nHost: a\r\nno-colon-here\r\n\r\n");
+        }
+
+        #[test]
+        fn test_smuggling_strict_only_rules() {
+            let cases: [&[u8]; 3] = [
+                b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello",
+                b"POST / HTTP/1.1\r\nContent-Length : 5\r\n\r\nhello",
+                b"GET / HTTP/1.1\r\nX-Folded: a\r\n b\r\n\r\n",
+            ];
+            for raw in cases {
+                assert!(matches!(parse(raw, true), Err(Error::BadRequest(_))));
+                assert!(parse(raw, false).unwrap().is_some());
+            }
+        }
+
+        #[test]
+        fn test_chunked_body_decoded_and_pipelined_bytes_kept() {
+            let raw = b"POST /echo HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: Chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+            let mut buffer = bytes::BytesMut::new();
+            let mut state = parser::ParseState::default();
+            let mut request = None;
+            for chunk in raw.chunks(7) {
+                buffer.extend_from_slice(chunk);
+                if request.is_none() {
+                    request = parser::parse_request(&mut buffer, &mut state).unwrap();
+                }
+            }
+            assert_eq!(request.unwrap().body.unwrap(), "hello world");
+            assert_eq!(&buffer[..], b"GET / HTTP/1.1\r\n\r\n");
+
+            let mut buffer = bytes::BytesMut::from(&raw[..]);
+            let mut state = parser::ParseState::default();
+            state.body_limit = Some(8);
+            assert!(matches!(
+                parser::parse_request(&mut buffer, &mut state),
+                Err(Error::ContentTooLarge { limit: 8, actual: None })
+            ));
+
+            assert!(matches!(
+                parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+5\r\nhello\r\n0\r\n\r\n", true),
+                Err(Error::BadRequest(_))
+            ));
+            assert!(matches!(
+                parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nhello\r\n0\r\n\r\n", true),
+                Err(Error::BadRequest(_))
+            ));
+        }
+
+        #[test]
+        fn test_chunked_body_of_many_small_chunks() {
+            let mut raw = b"POST /echo HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            for i in 0..200_000 {
+                raw.extend_from_slice(format!("1\r\n{}\r\n", i % 10).as_bytes());
+            }
+            raw.extend_from_slice(b"0\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+
+            let mut buffer = bytes::BytesMut::new();
+            let mut state = parser::ParseState::default();
+            let mut request = None;
+            for chunk in raw.chunks(4096) {
+                buffer.extend_from_slice(chunk);
+                if request.is_none() {
+                    request = parser::parse_request(&mut buffer, &mut state).unwrap();
+                }
+            }
+            let body = request.unwrap().body.unwrap();
+            assert_eq!(body.len(), 200_000);
+            assert!(body.iter().enumerate().all(|(i, &b)| b == b'0' + (i % 10) as u8));
+            assert_eq!(&buffer[..], b"GET / HTTP/1.1\r\n\r\n");
+        }
+
+        #[tokio::test]
+        async fn test_smuggling_connection_gets_400_and_close() {
+            let (_server, addr) = spawn_server(config::Config::default()).await;
+            let response = send_raw(
+                addr,
+                b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG",
+            )
+            .await;
+            assert!(response.starts_with("HTTP/1.1 400"));
+            assert!(response.to_ascii_lowercase().contains("connection: close\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_parse_limits_reject_partial_head() {
+        let options = parser::ParseOptions {
+            max_request_line: 64,
+            max_header_line: 32,
+            max_header_bytes: 128,
+            max_headers: 3,
+            ..Default::default()
+        };
+        let parse = |raw: &[u8]| {
+            let mut buffer = bytes::BytesMut::from(raw);
+            let whole = parser::parse_request_with(&mut buffer, &mut parser::ParseState::default(), &options);
+
+            let mut buffer = bytes::BytesMut::new();
+            let mut state = parser::ParseState::default();
+            let mut trickled = Ok(None);
+            for &byte in raw {
+                buffer.extend_from_slice(&[byte]);
+                trickled = parser::parse_request_with(&mut buffer, &mut state, &options);
+                if !matches!(trickled, Ok(None)) {
+                    break;
+                }
+            }
+            assert_eq!(format!("{:?}", whole.as_ref().map(Option::is_some)), format!("{:?}", trickled.map(|r| r.is_some())));
+            whole
+        };
+
+        let long_line = format!("GET /{} HTTP/1.1", "a".repeat(100));
+        assert!(matches!(parse(long_line.as_bytes()), Err(Error::UriTooLong(64))));
+
+        let long_header = format!("GET / HTTP/1.1\r\nX-Big: {}", "b".repeat(40));
+        assert!(matches!(parse(long_header.as_bytes()), Err(Error::HeaderFieldsTooLarge(_))));
+
+        let many = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n";
+        assert!(matches!(parse(many.as_bytes()), Err(Error::HeaderFieldsTooLarge(_))));
+
+        let bulky = format!("GET / HTTP/1.1\r\n{}", "X-Pad: 0123456789abcdefghij\r\n".repeat(5));
+        assert!(matches!(parse(bulky.as_bytes()), Err(Error::HeaderFieldsTooLarge(_))));
+
+        assert!(parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_parse_limits_respond_and_close() {
+        let mut config = config::Config::default();
+        config.security.max_request_line = 256;
+        config.security.max_header_line = 128;
+        config.security.max_header_bytes = 1024;
+        config.security.max_headers = 8;
+        let (_server, addr) = spawn_server(config).await;
+
+        let long_uri = format!("GET /{} HTTP/1.1\r\nHost: a\r\n\r\n", "u".repeat(4096));
+        let long_header = format!("GET / HTTP/1.1\r\nHost: a\r\nX-Big: {}\r\n\r\n", "h".repeat(512));
+        let many_headers = format!("GET / HTTP/1.1\r\nHost: a\r\n{}\r\n", "X-N: 1\r\n".repeat(20));
+        let huge_head = format!("GET / HTTP/1.1\r\nHost: a\r\n{}", "X-Pad: 0123456789012345678901234567890123456789\r\n".repeat(2000));
+
+        for (raw, status) in [
+            (long_uri, "414"),
+            (long_header, "431"),
+            (many_headers, "431"),
+            (huge_head, "431"),
+        ] {
+            let response = send_raw(addr, raw.as_bytes()).await;
+            assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}", response);
+            assert!(response.to_ascii_lowercase().contains("connection: close\r\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_requests_get_error_responses() {
+        let (server, addr) = spawn_server(config::Config::default()).await;
+
+        let cases: [(&[u8], &str); 8] = [
+            (b"garbage\r\n\r\n", "400"),
+            (b"GET /\r\n\r\n", "505"),
+            (b"GET / HTTP/1.1 extra\r\n\r\n", "400"),
+            (b"G(T / HTTP/1.1\r\n\r\n", "400"),
+            (b"GET / HTTP/1.1\r\nX-Bad: \xff\xfe\r\n\r\n", "400"),
+            (b"GET / FTP/1.0\r\n\r\n", "400"),
+            (b"GET / HTTP/3.0\r\n\r\n", "505"),
+            (b"BREW /pot HTTP/1.1\r\n\r\n", "501"),
+        ];
+        for (raw, status) in cases {
+            let response = send_raw(addr, raw).await;
+            assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{:?} -> {}", String::from_utf8_lossy(raw), response);
+            assert!(response.to_ascii_lowercase().contains("connection: close\r\n"));
+            assert!(!response.ends_with("\r\n\r\n"), "expected a text body: {}", response);
+        }
+        assert!(server.metrics().render().contains(&format!("http_parse_errors_total {}\n", cases.len())));
+    }
+
+    async fn read_head(stream: &mut tokio::net::TcpStream) -> String {
+        use tokio::io::AsyncReadExt;
+
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            let read = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut byte)).await;
+            match read.expect("server did not answer before the body was sent").unwrap() {
+                0 => break,
+                _ => head.push(byte[0]),
+            }
+        }
+        String::from_utf8_lossy(&head).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_sends_interim_response() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (_server, addr) = spawn_server(demo_config()).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"POST /echo/upload HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n")
+            .await
+            .unwrap();
+
+        assert_eq!(read_head(&mut stream).await, "HTTP/1.1 100 Continue\r\n\r\n");
+        stream.write_all(b"hello").await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("upload"));
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_rejects_before_body() {
+        use tokio::io::AsyncWriteExt;
+
+        let mut config = config::Config::default();
+        config.security.max_request_size = 1024;
+        let (_server, addr) = spawn_server(config).await;
+
+        let cases = [
+            ("POST /missing HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n", "404"),
+            ("POST /echo/big HTTP/1.1\r\nHost: a\r\nContent-Length: 4096\r\nExpect: 100-continue\r\n\r\n", "413"),
+            ("POST /echo/x HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nExpect: 200-ok\r\n\r\n", "417"),
+        ];
+        for (head, status) in cases {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let response = read_head(&mut stream).await;
+            assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}", response);
+            assert!(response.to_ascii_lowercase().contains("connection: close\r\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunked_body_limit_enforced_while_reading() {
+        use tokio::io::AsyncWriteExt;
+
+        let mut config = config::Config::default();
+        config.security.max_request_size = 1024;
+        let (_server, addr) = spawn_server(config).await;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"POST /echo/x HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n").await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        // The oversized chunk is refused from its size line, before any of its data arrives.
+        stream.write_all(b"800\r\n").await.unwrap();
+        let response = read_head(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        assert!(response.to_ascii_lowercase().contains("connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_request_decompression() {
+        use std::io::Write;
+
+        let compress = |coding: &str, data: &[u8]| -> Vec<u8> {
+            match coding {
+                "gzip" => {
+                    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(data).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => {
+                    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(data).unwrap();
+                    encoder.finish().unwrap()
+                }
+            }
+        };
+        let post = |coding: &str, body: Vec<u8>| {
+            let mut request = Request::new(Method::POST, "/json".parse().unwrap(), Version::HTTP_11);
+            request.headers.insert("content-encoding", coding.parse().unwrap());
+            request.headers.insert("content-length", body.len().into());
+            request.body = Some(body.into());
+            request
+        };
+
+        let mut router = router::Router::new();
+        router.layer(decompression::RequestDecompression::new(1024 * 1024));
+        router.post("/json", |request| {
+            assert!(request.header("content-encoding").is_none());
+            assert_eq!(request.content_length(), request.body.as_ref().map(|body| body.len()));
+            let value: serde_json::Value = request.body_as_json()?;
+            Ok(Response::ok().with_text(value["name"].as_str().unwrap_or_default()))
+        });
+
+        for coding in ["gzip", "deflate"] {
+            let response = router.handle(post(coding, compress(coding, br#"{"name":"ferris"}"#))).await.unwrap();
+            assert_eq!(response.body.as_deref(), Some(&b"ferris"[..]));
+        }
+
+        let bomb = compress("gzip", &vec![0u8; 16 * 1024 * 1024]);
+        assert!(bomb.len() < 64 * 1024);
+        let error = router.handle(post("gzip", bomb)).await.unwrap_err();
+        assert_eq!(error.status_code(), ::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let error = router.handle(post("br", b"{}".to_vec())).await.unwrap_err();
+        assert_eq!(error.status_code(), ::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let error = router.handle(post("gzip", b"not gzip".to_vec())).await.unwrap_err();
+        assert_eq!(error.status_code(), ::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_request_form_decoding() {
+        #[derive(serde::Deserialize)]
+        struct Signup {
+            name: String,
+            age: u32,
+            tags: Vec<String>,
+            note: String,
+        }
+
+        let form_request = |content_type: Option<&str>, body: &'static str| {
+            let mut request = Request::new(Method::POST, "/form".parse().unwrap(), Version::HTTP_11);
+            if let Some(content_type) = content_type {
+                request.headers.insert("content-type", content_type.parse().unwrap());
+            }
+            request.body = Some(bytes::Bytes::from_static(body.as_bytes()));
+            request
+        };
+
+        let request = form_request(
+            Some("application/x-www-form-urlencoded; charset=UTF-8"),
+            "name=J%C3%BCrgen+M%C3%BCller&age=41&tags=rust&tags=%E6%97%A5%E6%9C%AC&note=&flag",
+        );
+        let form = request.form().unwrap();
+        assert_eq!(form["name"], "Jürgen Müller");
+        assert_eq!(form["tags"], "日本");
+        assert_eq!(form["note"], "");
+        assert_eq!(form["flag"], "");
+
+        let signup: Signup = request.form_as().unwrap();
+        assert_eq!(signup.name, "Jürgen Müller");
+        assert_eq!(signup.age, 41);
+        assert_eq!(signup.tags, vec!["rust".to_string(), "日本".to_string()]);
+        assert_eq!(signup.note, "");
+
+        let request = form_request(Some("application/x-www-form-urlencoded"), "name=a&age=old&tags=x&note=");
+        let error = request.form_as::<Signup>().err().unwrap();
+        assert_eq!(error.status_code(), ::http::StatusCode::BAD_REQUEST);
+
+        for content_type in [Some("application/json"), None] {
+            let error = form_request(content_type, "a=b").form().unwrap_err();
+            assert_eq!(error.status_code(), ::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+    }
+
+    #[test]
+    fn test_request_json_strict() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Item {
+            #[allow(dead_code)]
+            count: u32,
+        }
+
+        let json_request = |content_type: &str, body: &str| {
+            let mut request = Request::new(Method::POST, "/items".parse().unwrap(), Version::HTTP_11);
+            request.headers.insert("content-type", content_type.parse().unwrap());
+            request.body = Some(body.to_string().into());
+            request.extensions.insert(http::JsonLimit(64));
+            request
+        };
+        let status = |result: Result<Item>| result.unwrap_err().status_code();
+
+        assert!(json_request("application/json; charset=utf-8", r#"{"count": 3}"#).json::<Item>().is_ok());
+        assert!(json_request("application/merge-patch+json", r#"{"count": 3}"#).json::<Item>().is_ok());
+
+        let request = json_request("text/plain", r#"{"count": 3}"#);
+        assert_eq!(status(request.json()), ::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(request.body_as_json::<Item>().is_ok());
+
+        let oversized = format!(r#"{{"count": 3, "pad": "{}"}}"#, "x".repeat(100));
+        assert_eq!(status(json_request("application/json", &oversized).json()), ::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        match json_request("application/json", "{\n  \"count\": 3,,\n}").json::<Item>() {
+            Err(Error::BadRequest(message)) => {
+                assert!(message.contains("line 2 column 14"), "{}", message);
+                assert!(message.contains("\"count\": 3,,"), "{}", message);
+            }
+            other => panic!("expected 400, got {:?}", other),
+        }
+
+        match json_request("application/json", r#"{"count": "three"}"#).json::<Item>() {
+            Err(Error::BadRequest(message)) => assert!(message.contains("invalid type"), "{}", message),
+            other => panic!("expected 400, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_utils_parse_accept() {
+        let ranges = utils::parse_accept("text/*;q=0.5, application/json, */*;q=0.1, text/html;level=1, bogus, text/plain;q=x, */json");
+        let summary: Vec<(String, f32)> = ranges
+            .iter()
+            .map(|range| (format!("{}/{}", range.media_type, range.subtype), range.quality))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("text/html".to_string(), 1.0),
+                ("application/json".to_string(), 1.0),
+                ("text/*".to_string(), 0.5),
+                ("*/*".to_string(), 0.1),
+            ]
+        );
+        assert_eq!(ranges[0].params, vec![("level".to_string(), "1".to_string())]);
+
+        let ranges = utils::parse_accept("*/*");
+        assert_eq!(utils::accept_quality(&ranges, "image/png"), Some((1.0, 0)));
+
+        let ranges = utils::parse_accept("text/*;q=0.5, text/plain;q=0, */*;q=0.2");
+        assert_eq!(utils::accept_quality(&ranges, "text/plain").map(|(q, _)| q), Some(0.0));
+        assert_eq!(utils::accept_quality(&ranges, "text/css").map(|(q, _)| q), Some(0.5));
+        assert_eq!(utils::accept_quality(&ranges, "image/png").map(|(q, _)| q), Some(0.2));
+        assert_eq!(utils::accept_quality(&utils::parse_accept("text/html"), "image/png"), None);
+    }
+
+    #[test]
+    fn test_request_content_negotiation() {
+        let with_accept = |accept: Option<&str>| {
+            let mut request = Request::new(Method::GET, "/".parse().unwrap(), Version::HTTP_11);
+            if let Some(accept) = accept {
+                request.headers.insert("accept", accept.parse().unwrap());
+            }
+            request
+        };
+        let offers = ["application/json", "text/html"];
+
+        assert_eq!(with_accept(None).preferred(&offers), Some("application/json"));
+        assert!(with_accept(None).accepts("image/png"));
+        assert_eq!(with_accept(Some("*/*")).preferred(&offers), Some("application/json"));
+        assert_eq!(with_accept(Some("text/*;q=0.5, */*;q=0.1")).preferred(&offers), Some("text/html"));
+        assert_eq!(with_accept(Some("*/*, text/html")).preferred(&offers), Some("text/html"));
+        assert_eq!(
+            with_accept(Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")).preferred(&offers),
+            Some("text/html")
+        );
+        assert_eq!(with_accept(Some("image/png")).preferred(&offers), None);
+        assert!(!with_accept(Some("application/json;q=0, */*")).accepts("application/json"));
+        assert!(with_accept(Some("application/json;q=0, */*")).accepts("text/html"));
+    }
+
+    #[tokio::test]
+    async fn test_error_responses_follow_accept() {
+        let client = testing::TestClient::new(config::Config::default());
+        let fetch = |accept: &str| client.send(testing::TestRequest::get("/missing").header("accept", accept));
+
+        let json = fetch("application/json").await;
+        assert_eq!(json.status(), ::http::StatusCode::NOT_FOUND);
+        assert_eq!(json.header("content-type"), Some("application/json"));
+        let body = json.json::<serde_json::Value>().unwrap();
+        assert_eq!(body["error"]["code"], "route_not_found");
+        assert_eq!(body["error"]["message"], "Route not found: /missing");
+        assert!(body["error"]["details"].is_null());
+        assert!(body["error"].as_object().unwrap().contains_key("request_id"));
+
+        let html = fetch("text/html,application/xhtml+xml,*/*;q=0.8").await;
+        assert!(html.header("content-type").unwrap().starts_with("text/html"));
+        assert!(html.text().contains("<h1>404 Not Found</h1>"));
+        assert!(html.text().contains("<p>Route not found: /missing</p>"));
+
+        let text = fetch("*/*").await;
+        assert_eq!(text.header("content-type"), Some("text/plain"));
+        assert_eq!(text.text(), "Route not found: /missing");
+    }
+
+    #[derive(Debug)]
+    enum AccountError {
+        Taken(String),
+        Store,
+    }
+
+    impl std::fmt::Display for AccountError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                AccountError::Taken(name) => write!(f, "{} is already taken", name),
+                AccountError::Store => write!(f, "account store unreachable"),
+            }
+        }
+    }
+
+    impl ResponseError for AccountError {
+        fn status_code(&self) -> ::http::StatusCode {
+            match self {
+                AccountError::Taken(_) => ::http::StatusCode::CONFLICT,
+                AccountError::Store => ::http::StatusCode::INTERNAL_SERVER_ERROR,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_error_from_handler() {
+        let mut router = router::Router::new();
+        let register = |request: Request| async move {
+            match request.params.get("name").map(String::as_str) {
+                Some("admin") => Err(AccountError::Taken("admin".to_string())),
+                Some("down") => Err(AccountError::Store),
+                _ => Ok(Response::new(::http::StatusCode::CREATED)),
+            }
+        };
+        router.add_handler(Method::POST, "/accounts/{name}", register, router::RouteOptions::default());
+        let client = testing::TestClient::from_router(router);
+
+        let taken = client.send(testing::TestRequest::post("/accounts/admin").header("accept", "application/json")).await;
+        assert_eq!(taken.status(), ::http::StatusCode::CONFLICT);
+        assert_eq!(taken.header("content-type"), Some("text/plain"));
+        assert_eq!(taken.text(), "admin is already taken");
+
+        let down = client.send(testing::TestRequest::post("/accounts/down")).await;
+        assert_eq!(down.status(), ::http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(down.text(), "Internal Server Error");
+
+        let created = client.send(testing::TestRequest::post("/accounts/ada")).await;
+        assert_eq!(created.status(), ::http::StatusCode::CREATED);
+
+        let own = Error::BadRequest("nope".to_string()).into_error();
+        assert!(matches!(own, Error::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_content_too_large_error_carries_limits() {
+        let mut router = router::Router::new();
+        let tight = router::RouteOptions { max_body_size: Some(4), ..Default::default() };
+        router.post_with("/tight", |_| async { Ok(Response::ok()) }, tight);
+        let client = testing::TestClient::from_router(router);
+        let upload = |accept: &str| client.send(testing::TestRequest::post("/tight").header("accept", accept).body("123456"));
+
+        let json = upload("application/json").await;
+        assert_eq!(json.status(), ::http::StatusCode::PAYLOAD_TOO_LARGE);
+        let body = json.json::<serde_json::Value>().unwrap();
+        assert_eq!(body["error"]["code"], "content_too_large");
+        assert_eq!(body["error"]["message"], "Content too large: the limit is 4 bytes");
+        assert_eq!(body["error"]["details"], serde_json::json!({ "limit": 4, "actual": 6 }));
+
+        let html = upload("text/html").await;
+        assert!(html.text().contains("<h1>413 Payload Too Large</h1>"));
+        assert!(html.text().contains("<dt>limit</dt><dd>4</dd>"));
+        assert!(html.text().contains("<dt>actual</dt><dd>6</dd>"));
+    }
+
+    #[test]
+    fn test_utils_canonical_path() {
+        assert_eq!(utils::canonical_path("/"), "/");
+        assert_eq!(utils::canonical_path("//echo//hi/"), "/echo/hi");
+        assert_eq!(utils::canonical_path("/a/./b/../c"), "/a/c");
+        assert_eq!(utils::canonical_path("/../../etc"), "/etc");
+        assert_eq!(utils::canonical_path(""), "/");
+    }
+
+    #[tokio::test]
+    async fn test_router_slash_policies() {
+        let echo_router = |policy: config::SlashPolicy| {
+            let mut router = router::Router::new();
+            router.slash_policy(policy);
+            router.get("/echo/{param}", |request| Ok(Response::ok().with_text(&request.params["param"])));
+            router.get("/", |_| Ok(Response::ok().with_text("root")));
+            router
+        };
+        let get = |path: &str| Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+
+        let router = echo_router(config::SlashPolicy::Merge);
+        for path in ["/echo/hi/", "//echo/hi", "/echo//hi", "/echo/./hi", "/x/../echo/hi"] {
+            let response = router.handle(get(path)).await.unwrap();
+            assert_eq!(response.body.as_deref(), Some(&b"hi"[..]), "{}", path);
+        }
+        assert!(router.handle(get("/echo//")).await.is_err());
+
+        let router = echo_router(config::SlashPolicy::Redirect);
+        let response = router.handle(get("//echo/hi/?x=1")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers["location"], "/echo/hi?x=1");
+        let response = router.handle(get("/echo/hi")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::OK);
+        assert!(router.handle(get("/missing/")).await.is_err());
+
+        let router = echo_router(config::SlashPolicy::Strict);
+        assert!(router.handle(get("/echo/hi/")).await.is_err());
+        assert!(router.handle(get("//echo/hi")).await.is_err());
+        assert_eq!(router.handle(get("/echo/hi")).await.unwrap().status, ::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_server_merges_slashes_by_default() {
+        let client = testing::TestClient::new(demo_config());
+        let response = client.get("//echo/./hi/").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        assert_eq!(response.text(), "hi");
+    }
+
+    #[tokio::test]
+    async fn test_router_duplicate_route_policies() {
+        let text = |body: &'static str| move |_: Request| Ok(Response::ok().with_text(body));
+        let get = || Request::new(Method::GET, "/files/a.txt".parse().unwrap(), Version::HTTP_11);
+
+        let mut router = router::Router::new();
+        router.duplicate_routes(config::DuplicateRoutePolicy::Error);
+        router.get("/files/{filename}", text("first"));
+        assert!(matches!(
+            router.try_add_route(Method::GET, "/files/{name}", text("second")),
+            Err(Error::Config(_))
+        ));
+        assert!(router.try_add_route(Method::POST, "/files/{name}", text("post")).is_ok());
+        let handler = |_: Request| async { Ok(Response::ok()) };
+        let options = router::RouteOptions::default;
+        assert!(router.try_add_route_with(Method::POST, "/files/{id}", handler, options()).is_err());
+        assert!(router.try_add_handler(Method::POST, "/files/{id}", |_: Request| async { Ok::<_, Error>(Response::ok()) }, options()).is_err());
+        assert_eq!(router.routes().len(), 2);
+
+        let mut config = demo_config();
+        config.server.duplicate_routes = config::DuplicateRoutePolicy::Error;
+        config.metrics.enabled = true;
+        config.metrics.address = None;
+        config.metrics.path = "/".to_string();
+        assert!(matches!(server::Server::try_new(config), Err(Error::Config(_))));
+
+        let mut router = router::Router::new();
+        router.duplicate_routes(config::DuplicateRoutePolicy::Replace);
+        router.get("/files/{filename}", text("first")).get("/files/{filename}", text("second"));
+        assert_eq!(router.routes().len(), 1);
+        assert_eq!(router.handle(get()).await.unwrap().body.as_deref(), Some(&b"second"[..]));
+
+        let mut router = router::Router::new();
+        router.get("/files/{filename}", text("first")).get("/files/{filename}", text("second"));
+        assert_eq!(router.routes().len(), 2);
+        assert_eq!(router.handle(get()).await.unwrap().body.as_deref(), Some(&b"first"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_router_nest_and_routes_listing() {
+        let mut api = router::Router::new();
+        api.get("/", |_| Ok(Response::ok().with_text("api root")))
+            .get("/users/{id}", |request| Ok(Response::ok().with_text(&request.params["id"])));
+        api.layer(security::SecurityHeaders::from_config(&config::SecurityHeadersConfig::default()));
+
+        let mut router = router::Router::new();
+        router.duplicate_routes(config::DuplicateRoutePolicy::Error);
+        router.get("/", |_| Ok(Response::ok().with_text("root")));
+        router.nest("/api/v1/", api).unwrap();
+
+        let listed: Vec<(String, String, Vec<String>)> = router
+            .routes()
+            .into_iter()
+            .map(|route| (route.method.to_string(), route.pattern, route.param_names))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                ("GET".to_string(), "/".to_string(), vec![]),
+                ("GET".to_string(), "/api/v1".to_string(), vec![]),
+                ("GET".to_string(), "/api/v1/users/{id}".to_string(), vec!["id".to_string()]),
+            ]
+        );
+
+        let request = Request::new(Method::GET, "/api/v1/users/7".parse().unwrap(), Version::HTTP_11);
+        let response = router.handle(request).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"7"[..]));
+        assert!(response.headers.contains_key("x-content-type-options"));
+
+        let request = Request::new(Method::GET, "/".parse().unwrap(), Version::HTTP_11);
+        assert!(!router.handle(request).await.unwrap().headers.contains_key("x-content-type-options"));
+
+        let mut other = router::Router::new();
+        other.get("/api/v1/users/{user}", |_| Ok(Response::ok()));
+        assert!(matches!(router.merge(other), Err(Error::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_routes_mutated_while_serving() {
+        let (server, addr) = spawn_server(config::Config::default()).await;
+        let request = b"GET /late HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert!(send_raw(addr, request).await.starts_with("HTTP/1.1 404"));
+
+        server
+            .router()
+            .insert(Method::GET, "/late", |_| Ok(Response::ok().with_text("registered at runtime")))
+            .unwrap();
+        let response = send_raw(addr, request).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("registered at runtime"));
+
+        assert!(server.router().remove_route(&Method::GET, "/late"));
+        assert!(!server.router().remove_route(&Method::GET, "/late"));
+        assert!(send_raw(addr, request).await.starts_with("HTTP/1.1 404"));
+
+        server.router().replace(vec![router::Route::new(Method::GET, "/late", |_| Ok(Response::ok().with_text("only route")))]);
+        assert_eq!(server.router().routes().len(), 1);
+        assert!(send_raw(addr, request).await.ends_with("only route"));
+        assert!(send_raw(addr, b"GET /echo/x HTTP/1.1\r\nHost: a\r\n\r\n").await.starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn test_route_timeouts() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::{Duration, Instant};
+
+        struct DropFlag(std::sync::Arc<AtomicBool>);
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = std::sync::Arc::new(AtomicBool::new(false));
+        let mut router = router::Router::new();
+        router.request_timeout(Some(Duration::from_millis(50)));
+        router.get_with(
+            "/slow",
+            {
+                let dropped = dropped.clone();
+                move |_| {
+                    let flag = DropFlag(dropped.clone());
+                    async move {
+                        let _flag = flag;
+                        tokio::time::sleep(Duration::from_secs(10)).await;
+                        Ok(Response::ok())
+                    }
+                }
+            },
+            router::RouteOptions::default(),
+        );
+        router.get_with(
+            "/patient",
+            |_| async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(Response::ok().with_text("done"))
+            },
+            router::RouteOptions { timeout: Some(Duration::from_secs(5)), ..Default::default() },
+        );
+        router.get_with(
+            "/impatient",
+            |_| async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(Response::ok())
+            },
+            router::RouteOptions { timeout: Some(Duration::from_millis(20)), ..Default::default() },
+        );
+        let get = |path: &str| Request::new(Method::GET, path.parse().unwrap(), Version::HTTP_11);
+
+        let started = Instant::now();
+        let error = router.handle(get("/slow")).await.unwrap_err();
+        assert_eq!(error.status_code(), ::http::StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(dropped.load(Ordering::SeqCst));
+
+        let response = router.handle(get("/patient")).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some(&b"done"[..]));
+
+        let started = Instant::now();
+        let error = router.handle(get("/impatient")).await.unwrap_err();
+        assert!(matches!(error, Error::Timeout(ref message) if message.contains("/impatient")));
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_route_tree_matches_linear_scan() {
+        let mut router = router::Router::new();
+        for (method, pattern) in [
+            (Method::GET, "/"),
+            (Method::GET, "/user-agent"),
+            (Method::GET, "/users/me"),
+            (Method::GET, "/api/health"),
+            (Method::GET, "/echo/{message}"),
+            (Method::POST, "/echo/{message}"),
+            (Method::GET, "/users/{id}/posts/{post}"),
+            (Method::GET, "/v{version}/items"),
+            (Method::GET, "/assets/{name}-{size}"),
+            (Method::GET, "/files/{*filename}"),
+            (Method::GET, "/api/{*rest}"),
+        ] {
+            router.add_route(method, pattern, |_| Ok(Response::ok()));
+        }
+
+        let paths = [
+            "/", "//", "/user-agent", "/user-agent/", "/echo", "/echo/", "/echo/hi", "/echo/hi/", "/echo//hi",
+            "/files", "/files/", "/files/a/b.txt", "/users/me", "/users/me/posts/3", "/users/1/posts/2",
+            "/users//posts/2", "/v2/items", "/v/items", "/assets/logo-small", "/assets/logo", "/api/health",
+            "/api/health/deep", "/api/", "/missing", "",
+        ];
+        for method in [Method::GET, Method::POST, Method::PUT] {
+            for path in paths {
+                assert_eq!(
+                    router.find_route(&method, path),
+                    router.find_route_linear(&method, path),
+                    "{} {}",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_route_tree_precedence() {
+        let mut router = router::Router::new();
+        router
+            .get("/files/{*path}", |_| Ok(Response::ok()))
+            .get("/files/{name}", |_| Ok(Response::ok()))
+            .get("/files/{name}-{size}", |_| Ok(Response::ok()))
+            .get("/files/index.html", |_| Ok(Response::ok()));
+        let matched = |path: &str| router.find_route(&Method::GET, path).map(|(pattern, _)| pattern);
+
+        assert_eq!(matched("/files/index.html").as_deref(), Some("/files/index.html"));
+        assert_eq!(matched("/files/cat-large").as_deref(), Some("/files/{name}-{size}"));
+        assert_eq!(matched("/files/cat").as_deref(), Some("/files/{name}"));
+        assert_eq!(matched("/files/cat/large").as_deref(), Some("/files/{*path}"));
+        assert_eq!(matched("/files/").as_deref(), Some("/files/{*path}"));
+
+        let (_, params) = router.find_route(&Method::GET, "/files/cat-large").unwrap();
+        assert_eq!(params["name"], "cat");
+        assert_eq!(params["size"], "large");
+    }
+
+    struct TestCa {
+        cert: rcgen::Certificate,
+        key: rcgen::KeyPair,
+        path: String,
+    }
+
+    fn write_pem(dir: &std::path::Path, name: &str, pem: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, pem).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn test_ca(dir: &std::path::Path) -> TestCa {
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.distinguished_name.push(rcgen::DnType::CommonName, "Test CA");
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        let path = write_pem(dir, "ca.pem", &cert.pem());
+        TestCa { cert, key, path }
+    }
+
+    fn issue_cert(ca: &TestCa, dir: &std::path::Path, name: &str, common_name: &str, sans: &[&str]) -> (String, String) {
+        let mut params = rcgen::CertificateParams::new(sans.iter().map(|san| san.to_string()).collect::<Vec<_>>()).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, common_name);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca.cert, &ca.key).unwrap();
+        (
+            write_pem(dir, &format!("{}.pem", name), &cert.pem()),
+            write_pem(dir, &format!("{}.key", name), &key.serialize_pem()),
+        )
+    }
+
+    fn tls_server_config(ca: &TestCa, dir: &std::path::Path) -> config::TlsConfig {
+        let (cert_path, key_path) = issue_cert(ca, dir, "server", "localhost", &["localhost"]);
+        config::TlsConfig {
+            enabled: true,
+            cert_path: Some(cert_path),
+            key_path: Some(key_path),
+            ..Default::default()
+        }
+    }
+
+    fn tls_connector(
+        ca: &TestCa,
+        versions: &[&'static rustls::SupportedProtocolVersion],
+        client_cert: Option<&(String, String)>,
+    ) -> tokio_rustls::TlsConnector {
+        tokio_rustls::TlsConnector::from(std::sync::Arc::new(tls_client_config(ca, versions, client_cert)))
+    }
+
+    fn tls_client_config(
+        ca: &TestCa,
+        versions: &[&'static rustls::SupportedProtocolVersion],
+        client_cert: Option<&(String, String)>,
+    ) -> rustls::ClientConfig {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca.cert.der().clone()).unwrap();
+        let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_root_certificates(roots);
+        match client_cert {
+            Some((cert_path, key_path)) => {
+                let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(std::fs::File::open(cert_path).unwrap()))
+                    .collect::<std::io::Result<Vec<_>>>()
+                    .unwrap();
+                let key = rustls_pemfile::private_key(&mut std::io::BufReader::new(std::fs::File::open(key_path).unwrap()))
+                    .unwrap()
+                    .unwrap();
+                builder.with_client_auth_cert(certs, key).unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        }
+    }
+
+    async fn connect_tls(
+        addr: std::net::SocketAddr,
+        connector: &tokio_rustls::TlsConnector,
+        server_name: &str,
+    ) -> std::io::Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>> {
+        let socket = tokio::net::TcpStream::connect(addr).await?;
+        let name = rustls::pki_types::ServerName::try_from(server_name.to_string()).unwrap();
+        connector.connect(name, socket).await
+    }
+
+    async fn send_tls(
+        addr: std::net::SocketAddr,
+        connector: &tokio_rustls::TlsConnector,
+        request: &[u8],
+    ) -> std::io::Result<String> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = connect_tls(addr, connector, "localhost").await?;
+        stream.write_all(request).await?;
+        stream.shutdown().await?;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        Ok(String::from_utf8_lossy(&response).to_string())
+    }
+
+    #[test]
+    fn test_tls_server_config_permutations() {
+        use rustls::SupportedCipherSuite;
+
+        let dir = tempfile::tempdir().unwrap();
+        let ca = test_ca(dir.path());
+        let base = tls_server_config(&ca, dir.path());
+        let suites = |tls: &config::TlsConfig| {
+            tls::server_config(tls).unwrap().crypto_provider().cipher_suites.clone()
+        };
+
+        let defaults = suites(&base);
+        assert!(defaults.iter().any(|suite| matches!(suite, SupportedCipherSuite::Tls13(_))));
+        assert!(defaults.iter().any(|suite| matches!(suite, SupportedCipherSuite::Tls12(_))));
+        assert!(tls::server_config(&base).unwrap().alpn_protocols.contains(&b"http/1.1".to_vec()));
+
+        let tls13 = config::TlsConfig { min_version: config::TlsVersion::Tls13, ..base.clone() };
+        assert!(suites(&tls13).iter().all(|suite| matches!(suite, SupportedCipherSuite::Tls13(_))));
+
+        let named = config::TlsConfig {
+            cipher_suites: vec!["TLS13_AES_256_GCM_SHA384".into(), "tls_ecdhe_ecdsa_with_aes_128_gcm_sha256".into()],
+            ..base.clone()
+        };
+        let names: Vec<_> = suites(&named).iter().map(|suite| suite.suite()).collect();
+        assert_eq!(
+            names,
+            [rustls::CipherSuite::TLS13_AES_256_GCM_SHA384, rustls::CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256]
+        );
+
+        let conflicting = config::TlsConfig { min_version: config::TlsVersion::Tls13, ..named.clone() };
+        let app_config = config::Config { tls: conflicting, ..Default::default() };
+        assert!(matches!(app_config.validate(), Err(Error::Config(ref message)) if message.contains("TLS 1.2 only")));
+
+        let unknown = config::TlsConfig { cipher_suites: vec!["TLS_NULL_WITH_NULL_NULL".into()], ..base.clone() };
+        assert!(matches!(tls::validate(&unknown), Err(Error::Config(ref message)) if message.contains("Unknown")));
+
+        let missing_ca = config::TlsConfig { client_auth: config::ClientAuth::Required, ..base.clone() };
+        assert!(tls::validate(&missing_ca).is_err());
+        let with_ca = config::TlsConfig { client_ca_path: Some(ca.path.clone()), ..missing_ca };
+        assert!(tls::server_config(&with_ca).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_enforces_min_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = test_ca(dir.path());
+        let tls = config::TlsConfig { min_version: config::TlsVersion::Tls13, ..tls_server_config(&ca, dir.path()) };
+        let (_server, addr) = spawn_server(config::Config { tls, ..demo_config() }).await;
+        let request = b"GET /echo/secure HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let legacy = tls_connector(&ca, &[&rustls::version::TLS12], None);
+        assert!(send_tls(addr, &legacy, request).await.is_err());
+
+        let modern = tls_connector(&ca, &[&rustls::version::TLS13], None);
+        let response = send_tls(addr, &modern, request).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.to_ascii_lowercase().contains("strict-transport-security"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_counts_against_header_deadline() {
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let ca = test_ca(dir.path());
+        let mut config = config::Config { tls: tls_server_config(&ca, dir.path()), ..demo_config() };
+        config.security.header_timeout = 1;
+        let (_server, addr) = spawn_server(config).await;
+
+        let started = std::time::Instant::now();
+        let mut stalled = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut byte = [0u8; 1];
+        let read = tokio::time::timeout(std::time::Duration::from_secs(5), stalled.read(&mut byte)).await;
+        assert!(matches!(read, Ok(Ok(0) | Err(_))), "{:?}", read);
+        assert!(started.elapsed() < std::time::Duration::from_secs(3), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_mtls_client_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = test_ca(dir.path());
+        let tls = config::TlsConfig {
+            client_auth: config::ClientAuth::Required,
+            client_ca_path: Some(ca.path.clone()),
+            client_rules: vec![config::ClientCertRule {
+                prefix: "/whoami".into(),
+                allow: vec!["*.mesh.local".into()],
+            }],
+            ..tls_server_config(&ca, dir.path())
+        };
+        let (server, addr) = spawn_server(config::Config { tls, ..demo_config() }).await;
+        server
+            .router()
+            .insert(Method::GET, "/whoami", |request| {
+                let identity = request.client_identity().expect("identity attached to request");
+                let body = format!("{} {}", identity.common_name.as_deref().unwrap_or("-"), identity.subject_alt_names.join(","));
+                Ok(Response::ok().with_text(&body))
+            })
+            .unwrap();
+        let request = b"GET /whoami HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let versions = [&rustls::version::TLS13];
+
+        let anonymous = tls_connector(&ca, &versions, None);
+        let rejected = send_tls(addr, &anonymous, request).await;
+        assert!(!rejected.is_ok_and(|response| response.starts_with("HTTP")));
+
+        let service = issue_cert(&ca, dir.path(), "service", "svc-a", &["svc-a.mesh.local", "spiffe://mesh/svc-a"]);
+        let response = send_tls(addr, &tls_connector(&ca, &versions, Some(&service)), request).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("svc-a svc-a.mesh.local,spiffe://mesh/svc-a"), "{}", response);
+
+        let outsider = issue_cert(&ca, dir.path(), "outsider", "intruder", &["intruder.example.com"]);
+        let outsider = tls_connector(&ca, &versions, Some(&outsider));
+        let response = send_tls(addr, &outsider, request).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+
+        let echo = send_tls(addr, &outsider, b"GET /echo/hi HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        assert!(echo.starts_with("HTTP/1.1 200"), "{}", echo);
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn test_mtls_client_identity_over_http2() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = test_ca(dir.path());
+        let tls = config::TlsConfig {
+            client_auth: config::ClientAuth::Required,
+            client_ca_path: Some(ca.path.clone()),
+            client_rules: vec![config::ClientCertRule {
+                prefix: "/whoami".into(),
+                allow: vec!["*.mesh.local".into()],
+            }],
+            ..tls_server_config(&ca, dir.path())
+        };
+        let (server, addr) = spawn_server(config::Config { tls, ..demo_config() }).await;
+        server
+            .router()
+            .insert(Method::GET, "/whoami", |request| {
+                let identity = request.client_identity().expect("identity attached to request");
+                let version = request.extensions.get::<tls::TlsInfo>().expect("TLS info attached to request").protocol;
+                Ok(Response::ok().with_text(&format!("{} {:?}", identity.common_name.as_deref().unwrap_or("-"), version)))
+            })
+            .unwrap();
+
+        let whoami = |cert: (String, String)| {
+            let mut client = tls_client_config(&ca, &[&rustls::version::TLS13], Some(&cert));
+            client.alpn_protocols = vec![b"h2".to_vec()];
+            let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client));
+            async move {
+                let stream = connect_tls(addr, &connector, "localhost").await.unwrap();
+                assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+                let (mut client, connection) = h2::client::handshake(stream).await.unwrap();
+                tokio::spawn(connection);
+                let request = ::http::Request::get("https://localhost/whoami").body(()).unwrap();
+                let (response, _) = client.send_request(request, true).unwrap();
+                let response = response.await.unwrap();
+                let status = response.status();
+                let mut body = response.into_body();
+                let mut data = Vec::new();
+                while let Some(chunk) = body.data().await {
+                    data.extend_from_slice(&chunk.unwrap());
+                }
+                (status, String::from_utf8(data).unwrap())
+            }
+        };
+
+        let service = issue_cert(&ca, dir.path(), "service", "svc-a", &["svc-a.mesh.local"]);
+        let (status, body) = whoami(service).await;
+        assert_eq!(status, ::http::StatusCode::OK, "{}", body);
+        assert_eq!(body, "svc-a TLSv1_3");
+
+        let outsider = issue_cert(&ca, dir.path(), "outsider", "intruder", &["intruder.example.com"]);
+        assert_eq!(whoami(outsider).await.0, ::http::StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_tls_sni_certificate_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = test_ca(dir.path());
+        let (cert_path, key_path) = issue_cert(&ca, dir.path(), "default", "default", &["localhost", "fallback.test"]);
+        let (alpha_cert, alpha_key) = issue_cert(&ca, dir.path(), "alpha", "alpha.test", &["alpha.test"]);
+        let (beta_cert, beta_key) = issue_cert(&ca, dir.path(), "beta", "*.beta.test", &["*.beta.test"]);
+        let tls = config::TlsConfig {
+            enabled: true,
+            cert_path: Some(cert_path),
+            key_path: Some(key_path),
+            certificates: vec![
+                config::TlsCertificate { hostnames: vec!["alpha.test".into()], cert_path: alpha_cert, key_path: alpha_key },
+                config::TlsCertificate { hostnames: vec!["*.beta.test".into()], cert_path: beta_cert, key_path: beta_key },
+            ],
+            ..Default::default()
+        };
+
+        let mut overlapping = tls.clone();
+        overlapping.certificates[1].hostnames.push("ALPHA.test.".into());
+        assert!(matches!(tls::validate(&overlapping), Err(Error::Config(ref message)) if message.contains("more than one")));
+
+        let (_server, addr) = spawn_server(config::Config { tls, ..Default::default() }).await;
+        let connector = tls_connector(&ca, &[&rustls::version::TLS13], None);
+        for (server_name, expected) in [("alpha.test", "alpha.test"), ("api.beta.test", "*.beta.test"), ("fallback.test", "default")] {
+            let stream = connect_tls(addr, &connector, server_name).await.unwrap();
+            let chain = stream.get_ref().1.peer_certificates().unwrap();
+            let presented = tls::ClientIdentity::from_chain(chain).unwrap();
+            assert_eq!(presented.common_name.as_deref(), Some(expected), "{}", server_name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_certificate_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = test_ca(dir.path());
+        let (cert_path, key_path) = issue_cert(&ca, dir.path(), "server", "cert-a", &["localhost"]);
+        let tls = config::TlsConfig {
+            enabled: true,
+            cert_path: Some(cert_path.clone()),
+            key_path: Some(key_path),
+            ..Default::default()
+        };
+        let (server, addr) = spawn_server(config::Config { tls, ..Default::default() }).await;
+        let connector = tls_connector(&ca, &[&rustls::version::TLS13], None);
+        let presented = |stream: tokio_rustls::client::TlsStream<tokio::net::TcpStream>| {
+            let chain = stream.get_ref().1.peer_certificates().unwrap();
+            tls::ClientIdentity::from_chain(chain).unwrap().common_name.unwrap()
+        };
+
+        let established = connect_tls(addr, &connector, "localhost").await.unwrap();
+        assert_eq!(presented(connect_tls(addr, &connector, "localhost").await.unwrap()), "cert-a");
+
+        issue_cert(&ca, dir.path(), "server", "cert-b", &["localhost"]);
+        server.reload_tls().unwrap();
+        assert_eq!(presented(connect_tls(addr, &connector, "localhost").await.unwrap()), "cert-b");
+        assert_eq!(presented(established), "cert-a");
+
+        std::fs::write(&cert_path, "not a certificate").unwrap();
+        assert!(server.reload_tls().is_err());
+        assert_eq!(presented(connect_tls(addr, &connector, "localhost").await.unwrap()), "cert-b");
+    }
+
+    #[derive(Debug)]
+    struct AcceptAnyServerCert;
+
+    impl rustls::client::danger::ServerCertVerifier for AcceptAnyServerCert {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &rustls::pki_types::CertificateDer<'_>,
+            _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+            _server_name: &rustls::pki_types::ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: rustls::pki_types::UnixTime,
+        ) -> std::result::Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &rustls::pki_types::CertificateDer<'_>,
+            _dss: &rustls::DigitallySignedStruct,
+        ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &rustls::pki_types::CertificateDer<'_>,
+            _dss: &rustls::DigitallySignedStruct,
+        ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+            rustls::crypto::ring::default_provider()
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_self_signed_dev_mode() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("dev-certs");
+        let tls = config::TlsConfig {
+            enabled: true,
+            self_signed: true,
+            self_signed_dir: Some(cache_dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let (_server, addr) = spawn_server(config::Config { tls, ..demo_config() }).await;
+
+        let client = rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(std::sync::Arc::new(AcceptAnyServerCert))
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client));
+        let mut stream = connect_tls(addr, &connector, "localhost").await.unwrap();
+        let presented = tls::ClientIdentity::from_chain(stream.get_ref().1.peer_certificates().unwrap()).unwrap();
+        assert!(presented.subject_alt_names.contains(&"localhost".to_string()));
+        assert!(presented.subject_alt_names.contains(&"127.0.0.1".to_string()));
+
+        stream.write_all(b"GET /echo/dev HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("dev"), "{}", response);
+
+        let names = ["localhost".to_string()];
+        let cached = tls::SelfSignedCertificate::load_or_generate(Some(&cache_dir), &names).unwrap();
+        assert_eq!(cached.cert.as_ref(), stream.get_ref().1.peer_certificates().unwrap()[0].as_ref());
+        assert_eq!(cached.fingerprint().len(), 32 * 3 - 1);
+    }
+
+    #[tokio::test]
+    async fn test_plain_http_redirects_to_https() {
+        let mut config = config::Config::default();
+        config.server.port = 8443;
+        config.server.redirect_http_port = Some(8080);
+        let server = server::Server::new(config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server.serve_https_redirects(listener));
+
+        let response = send_raw(addr, b"GET /files/report.txt?download=1&v=2 HTTP/1.1\r\nHost: Example.com:8080\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 301"), "{}", response);
+        assert!(response.contains("location: https://example.com:8443/files/report.txt?download=1&v=2\r\n"), "{}", response);
+        assert!(response.contains("strict-transport-security: max-age=31536000"), "{}", response);
+
+        let response = send_raw(addr, b"GET / HTTP/1.0\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.0 400"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_server_binds_multiple_listen_addresses() {
+        let scalar: config::ServerConfig = ::config::Config::builder()
+            .add_source(::config::File::from_str("host = \"::\"\nport = 4221\nworkers = 1\nbacklog = 16", ::config::FileFormat::Toml))
+            .build()
+            .and_then(|source| source.try_deserialize())
+            .unwrap();
+        assert_eq!(scalar.listen_addresses(), vec!["[::]:4221".to_string()]);
+        let single: config::ServerConfig = ::config::Config::builder()
+            .add_source(::config::File::from_str("host = \"127.0.0.1\"\nport = 4221\nworkers = 1\nbacklog = 16\nlisten = \"0.0.0.0:8080\"", ::config::FileFormat::Toml))
+            .build()
+            .and_then(|source| source.try_deserialize())
+            .unwrap();
+        assert_eq!(single.listen_addresses(), vec!["0.0.0.0:8080".to_string()]);
+
+        let config = config::Config {
+            server: config::ServerConfig { listen: vec!["127.0.0.1:0".to_string(), "[::1]:0".to_string()], ..Default::default() },
+            ..demo_config()
+        };
+        let server = std::sync::Arc::new(server::Server::new(config));
+        let listeners = server.bind().await.unwrap();
+        let addrs: Vec<_> = listeners.iter().map(|listener| listener.local_addr().unwrap()).collect();
+        assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6());
+        let running = server.clone();
+        tokio::spawn(async move { running.serve_all(listeners).await });
+
+        for addr in &addrs {
+            let response = send_raw(*addr, b"GET /echo/both HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+            assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("both"), "{}", response);
+        }
+        assert_eq!(server.local_addrs(), addrs);
+
+        let taken = addrs[0].to_string();
+        let config = config::Config {
+            server: config::ServerConfig { listen: vec!["127.0.0.1:0".to_string(), taken.clone()], ..Default::default() },
+            ..demo_config()
+        };
+        let err = server::Server::new(config).bind().await.unwrap_err();
+        assert!(matches!(&err, Error::Bind(address, _) if *address == taken), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_systemd_listen_fds() {
+        use std::os::unix::io::AsRawFd;
+
+        assert_eq!(systemd::listen_fds(None, None, 42).unwrap(), None);
+        assert_eq!(systemd::listen_fds(Some("42"), None, 42).unwrap(), None);
+        assert_eq!(systemd::listen_fds(Some("41"), Some("2"), 42).unwrap(), None);
+        assert_eq!(systemd::listen_fds(Some("42"), Some("0"), 42).unwrap(), None);
+        assert_eq!(systemd::listen_fds(Some("42"), Some("2"), 42).unwrap(), Some(3..5));
+        assert!(systemd::listen_fds(Some("42"), Some("two"), 42).is_err());
+        assert!(systemd::listen_fds(Some("42"), Some("-1"), 42).is_err());
+        assert!(systemd::listen_fds(Some("pid"), Some("1"), 42).is_err());
+
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(!systemd::is_listening_socket(left.as_raw_fd()));
+        let connected = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(connected.local_addr().unwrap()).unwrap();
+        assert!(!systemd::is_listening_socket(stream.as_raw_fd()));
+        assert!(systemd::is_listening_socket(connected.as_raw_fd()));
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints() {
+        let (server, addr) = spawn_server(demo_config()).await;
+        let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin = admin_listener.local_addr().unwrap();
+        tokio::spawn(server.serve_admin(admin_listener));
+
+        send_raw(addr, b"GET /echo/stats HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        send_raw(addr, b"GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+
+        let body = |response: &str| serde_json::from_str::<serde_json::Value>(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+        let response = send_raw(admin, b"GET /stats HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let stats = body(&response);
+        assert_eq!(stats["requests"]["2xx"], 1);
+        assert_eq!(stats["requests"]["4xx"], 1);
+        assert_eq!(stats["requests"]["total"], 2);
+        assert!(stats["uptime_seconds"].is_u64() && stats["active_connections"].as_i64().unwrap() >= 1);
+
+        let response = send_raw(admin, b"GET /routes HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let routes = body(&response);
+        assert!(routes.as_array().unwrap().iter().any(|route| route["method"] == "GET" && route["pattern"] == "/echo/{param}"), "{}", routes);
+
+        assert!(!response.contains("/stats"));
+        let response = send_raw(addr, b"GET /stats HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+        let response = send_raw(admin, b"POST /reload-config HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let response = send_raw(admin, b"POST /shutdown HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 202"), "{}", response);
+        tokio::time::timeout(std::time::Duration::from_secs(1), server.wait_for_shutdown()).await.unwrap();
+
+        let overlapping = config::Config {
+            server: config::ServerConfig { listen: vec!["0.0.0.0:4222".to_string()], ..Default::default() },
+            admin: config::AdminConfig { enabled: true, ..Default::default() },
+            ..Default::default()
+        };
+        assert!(overlapping.validate().is_err());
+        let separate = config::Config { admin: config::AdminConfig { enabled: true, ..Default::default() }, ..Default::default() };
+        assert!(separate.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_stats_connection_and_byte_counters() {
+        let (server, addr) = spawn_server(demo_config()).await;
+        let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin = admin_listener.local_addr().unwrap();
+        tokio::spawn(server.serve_admin(admin_listener));
+
+        let request = b"GET /echo/a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /echo/b HTTP/1.1\r\nHost: localhost\r\n\r\nGET /echo/c HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let response = send_raw(addr, request).await;
+        assert_eq!(response.matches("HTTP/1.1 200").count(), 3, "{}", response);
+
+        let stats = server.metrics().stats();
+        assert_eq!(stats.connections_accepted, 1);
+        assert_eq!(stats.requests_total, 3);
+        assert_eq!(stats.reused_requests, 2);
+        assert!((stats.keep_alive_reuse_ratio - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.bytes_read, request.len() as u64);
+        assert_eq!(stats.bytes_written, response.len() as u64);
+
+        let response = send_raw(admin, b"GET /stats HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let body: serde_json::Value = serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+        assert_eq!(body["connections_accepted"], 2);
+        assert_eq!(body["keep_alive"]["reused_requests"], 2);
+        assert_eq!(body["bytes"]["written"], stats.bytes_written);
+        assert!(body["bytes"]["read"].as_u64().unwrap() > stats.bytes_read);
+        assert_eq!(body["file_cache"]["hit_rate"], 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_config_reload_applies_new_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().join("files").to_string_lossy().into_owned();
+        config.files.max_file_size = 8;
+        std::fs::write(&path, serde_json::to_string(&config).unwrap()).unwrap();
+        let server = server::Server::new(config.clone()).with_config_path(&path);
+
+        let upload = || {
+            let mut request = Request::new(Method::POST, "/files/a.txt".parse().unwrap(), Version::HTTP_11);
+            request.body = Some(bytes::Bytes::from_static(b"0123456789"));
+            server.router().handle(request)
+        };
+        assert_eq!(upload().await.unwrap().status, ::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let mut updated = config.clone();
+        updated.files.max_file_size = 64;
+        updated.server.port = 8080;
+        let changes = config::changed_fields(&config, &updated);
+        assert_eq!(changes.iter().map(|change| change.field.as_str()).collect::<Vec<_>>(), ["files.max_file_size", "server.port"]);
+        assert_eq!(changes[0].to_string(), "files.max_file_size: 8 -> 64");
+        assert!(config::is_reloadable(&changes[0].field) && !config::is_reloadable(&changes[1].field));
+
+        std::fs::write(&path, serde_json::to_string(&updated).unwrap()).unwrap();
+        server.reload_config().unwrap();
+        assert_eq!(server.config().files.max_file_size, 64);
+        assert_eq!(upload().await.unwrap().status, ::http::StatusCode::CREATED);
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(server.reload_config().is_err());
+        updated.server.workers = 0;
+        std::fs::write(&path, serde_json::to_string(&updated).unwrap()).unwrap();
+        assert!(server.reload_config().is_err());
+        assert_eq!(server.config().server.workers, config.server.workers);
+        assert_eq!(server.config().files.max_file_size, 64);
+    }
+
+    #[tokio::test]
+    async fn test_config_reload_moves_quota_to_new_root() {
+        let old_root = tempfile::tempdir().unwrap();
+        let new_root = tempfile::tempdir().unwrap();
+        std::fs::write(old_root.path().join("existing.txt"), "0123456789012345678901234").unwrap();
+
+        let mut config = demo_config();
+        config.files.root_dir = old_root.path().to_string_lossy().into_owned();
+        config.files.max_total_bytes = Some(30);
+        let server = server::Server::new(config.clone());
+
+        let upload = |path: &str, body: &'static str| {
+            let mut request = Request::new(Method::POST, path.parse().unwrap(), Version::HTTP_11);
+            request.body = Some(bytes::Bytes::from_static(body.as_bytes()));
+            server.router().handle(request)
+        };
+        assert_eq!(upload("/files/a.txt", "0123456789").await.unwrap().status, ::http::StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(server.files().quota.usage(), 25);
+
+        let mut updated = config.clone();
+        updated.files.root_dir = new_root.path().to_string_lossy().into_owned();
+        assert!(config::is_reloadable("files.root_dir"));
+        server.apply_config(updated).unwrap();
+        assert_eq!(server.files().quota.usage(), 0);
+
+        assert_eq!(upload("/files/a.txt", "0123456789").await.unwrap().status, ::http::StatusCode::CREATED);
+        assert!(new_root.path().join("a.txt").exists());
+        assert_eq!(server.files().quota.usage(), 10);
+        let response = upload("/files/b.txt", "01234567890123456789AB").await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::INSUFFICIENT_STORAGE);
+    }
+
+    #[tokio::test]
+    async fn test_config_reload_rebuilds_middleware() {
+        let config = config::Config::default();
+        let (server, addr) = spawn_server(config.clone()).await;
+        let get = || {
+            let mut request = Request::new(Method::GET, "/".parse().unwrap(), Version::HTTP_11);
+            request.remote_addr = Some("203.0.113.9:5000".parse().unwrap());
+            server.router().handle(request)
+        };
+        for _ in 0..3 {
+            assert_eq!(get().await.unwrap().status, ::http::StatusCode::OK);
+        }
+
+        let mut updated = config.clone();
+        updated.security.rate_limit_requests = 2;
+        updated.security.headers.frame_options = Some("DENY".to_string());
+        assert!(config::is_reloadable("security.rate_limit_requests") && config::is_reloadable("security.headers.frame_options"));
+        assert!(!config::is_reloadable("security.headersx") && !config::is_reloadable("security.rate_limit_store.backend"));
+        server.apply_config(updated.clone()).unwrap();
+        let response = get().await.unwrap();
+        assert_eq!(response.headers["x-frame-options"], "DENY");
+        assert_eq!(get().await.unwrap().status, ::http::StatusCode::OK);
+        let limited = get().await.unwrap();
+        assert_eq!(limited.status, ::http::StatusCode::TOO_MANY_REQUESTS);
+        assert!(limited.headers.contains_key("retry-after"));
+
+        updated.security.ip_deny = vec!["127.0.0.1".to_string()];
+        server.apply_config(updated.clone()).unwrap();
+        assert_eq!(get().await.unwrap().status, ::http::StatusCode::TOO_MANY_REQUESTS, "counts carry over a reload");
+        let mut denied = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut response = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut denied, &mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 403 Forbidden\r\n"), "{}", String::from_utf8_lossy(&response));
+
+        updated.auth.enabled = true;
+        updated.auth.algorithm = "none".to_string();
+        assert!(server.apply_config(updated).is_err());
+        assert!(server.config().auth.enabled == config.auth.enabled && server.config().security.ip_deny.len() == 1);
+    }
+
+    #[tokio::test]
+    async fn test_serve_directory_mode_is_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("assets/css")).unwrap();
+        std::fs::write(dir.path().join("assets/css/site.css"), "body {}").unwrap();
+        std::fs::write(dir.path().join("notes.md"), "# notes").unwrap();
+
+        let mut config = config::Config::default();
+        config.files.allowed_extensions = vec!["txt".to_string()];
+        let config = config.serve_directory(&dir.path().to_string_lossy());
+        let server = std::sync::Arc::new(server::Server::try_new_read_only(config).unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let running = server.clone();
+        tokio::spawn(async move { running.serve(listener).await });
+
+        let get = |path: &str, method: &str| format!("{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", method, path);
+        let response = send_raw(addr, get("/assets/css/site.css", "GET").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("body {}"), "{}", response);
+        assert!(response.contains("content-type: text/css"), "{}", response);
+        let response = send_raw(addr, get("/notes.md", "GET").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("# notes"), "{}", response);
+        let response = send_raw(addr, get("/assets/css/site.css", "HEAD").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("\r\n\r\n"), "{}", response);
+        let response = send_raw(addr, get("/assets/", "GET").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.contains("css"), "{}", response);
+        let response = send_raw(addr, get("/", "GET").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.contains("notes.md"), "{}", response);
+
+        for method in ["POST", "PUT", "DELETE"] {
+            let response = send_raw(addr, get("/notes.md", method).as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 404"), "{} {}", method, response);
+        }
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.md")).unwrap(), "# notes");
+        let response = send_raw(addr, get("/files/notes.md", "GET").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        assert!(server.router().routes().iter().all(|route| route.method == Method::GET || route.method == Method::HEAD));
+    }
+
+    #[test]
+    fn test_pid_file_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.pid");
+
+        let pid_file = pidfile::PidFile::create(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().trim(), std::process::id().to_string());
+        let err = pidfile::PidFile::create(&path).unwrap_err();
+        assert!(err.to_string().contains(&std::process::id().to_string()), "{}", err);
+        drop(pid_file);
+        assert!(!path.exists());
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let stale_pid = child.id();
+        child.wait().unwrap();
+        assert!(!pidfile::process_alive(stale_pid));
+        std::fs::write(&path, format!("{}\n", stale_pid)).unwrap();
+        let pid_file = pidfile::PidFile::create(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(pid_file.path()).unwrap().trim(), std::process::id().to_string());
+        drop(pid_file);
+
+        std::fs::write(&path, "not a pid").unwrap();
+        let pid_file = pidfile::PidFile::create(&path).unwrap();
+        std::fs::write(&path, "1\n").unwrap();
+        drop(pid_file);
+        assert!(path.exists(), "a PID file taken over by another process is left alone");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode() {
+        let mut config = demo_config();
+        config.maintenance.retry_after = 30;
+        config.maintenance.allow_paths = vec!["/health".to_string(), "/echo/ready".to_string()];
+        let (server, addr) = spawn_server(config).await;
+        let get = |path: &str, accept: &str| format!("GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\nConnection: close\r\n\r\n", path, accept);
+
+        let response = send_raw(addr, get("/echo/up", "*/*").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        server.set_maintenance(true);
+        assert!(server.is_maintenance());
+        let response = send_raw(addr, get("/echo/up", "application/json").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(response.contains("retry-after: 30\r\n"), "{}", response);
+        assert!(response.contains("\"code\":\"service_unavailable\""), "{}", response);
+        let response = send_raw(addr, get("/files/index.html", "text/html").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 503") && response.contains("<h1>Down for maintenance</h1>"), "{}", response);
+        let response = send_raw(addr, get("/echo/ready", "*/*").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("ready"), "{}", response);
+        let response = send_raw(addr, get("/healthy", "*/*").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+
+        server.set_maintenance(false);
+        let response = send_raw(addr, get("/echo/up", "*/*").as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let mut config = demo_config();
+        config.maintenance.enabled = true;
+        config.maintenance.body = Some("<p>Back soon</p>".to_string());
+        config.maintenance.allow_ips = vec!["10.0.0.0/8".to_string()];
+        let server = server::Server::new(config);
+        let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin = admin_listener.local_addr().unwrap();
+        tokio::spawn(server.serve_admin(admin_listener));
+        let request = |ip: &str| {
+            let mut request = Request::new(Method::GET, "/echo/ip".parse().unwrap(), Version::HTTP_11);
+            request.remote_addr = Some(format!("{}:5000", ip).parse().unwrap());
+            request
+        };
+        let response = server.router().handle(request("192.0.2.1")).await.unwrap();
+        assert_eq!(response.status, ::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.body.as_deref(), Some(&b"<p>Back soon</p>"[..]));
+        assert_eq!(response.headers.get("content-type").unwrap(), "text/html; charset=utf-8");
+        assert_eq!(server.router().handle(request("10.1.2.3")).await.unwrap().status, ::http::StatusCode::OK);
+
+        let body = "{\"enabled\": false}";
+        let toggle = format!("POST /maintenance HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        let response = send_raw(admin, toggle.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(!server.is_maintenance());
+        assert_eq!(server.router().handle(request("192.0.2.1")).await.unwrap().status, ::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_buffer_budget_bounds_concurrent_uploads() {
+        let mut config = demo_config();
+        config.performance.max_buffered_bytes = 1024 * 1024;
+        config.performance.buffer_wait_ms = 10_000;
+        let (server, addr) = spawn_server(config).await;
+
+        let body = vec![b'x'; 256 * 1024];
+        let mut request = format!("POST /echo/ok HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        request.extend_from_slice(&body);
+        let request = std::sync::Arc::new(request);
+
+        let clients: Vec<_> = (0..32)
+            .map(|_| {
+                let request = request.clone();
+                tokio::spawn(async move { send_raw(addr, &request).await })
+            })
+            .collect();
+        for client in clients {
+            let response = client.await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        }
+
+        let budget = server.metrics().buffer_budget();
+        assert!(budget.peak() <= 1024 * 1024, "peak {} exceeded the budget", budget.peak());
+        assert!(budget.peak() >= 256 * 1024);
+        assert_eq!(budget.used(), 0);
+        assert!(server.metrics().render().contains("http_buffered_bytes 0"));
+    }
+
+    #[tokio::test]
+    async fn test_buffer_budget_rejects_oversized_request() {
+        let mut config = config::Config::default();
+        config.performance.max_buffered_bytes = 64 * 1024;
+        let (_server, addr) = spawn_server(config).await;
+
+        let response = send_raw(addr, b"POST /echo/big HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1048576\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(response.contains("retry-after: 1"));
+    }
+
+    #[test]
+    fn test_buffer_pool_reuse() {
+        use buffer_pool::{BufferPool, BUFFER_CAPACITY};
+
+        let pool = BufferPool::new(2, 16 * 1024);
+        let mut buffer = pool.get();
+        buffer.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        let address = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!((pool.pooled(), pool.hits(), pool.misses()), (1, 0, 1));
+        let buffer = pool.get();
+        assert_eq!(buffer.as_ptr(), address);
+        assert!(buffer.is_empty() && buffer.capacity() >= BUFFER_CAPACITY);
+        assert_eq!(pool.hits(), 1);
+        drop(buffer);
+
+        // Consumed bytes are reclaimed once nothing else holds them.
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[b'x'; 1000]);
+        drop(buffer.split_to(1000));
+        pool.put(buffer);
+        assert_eq!(pool.pooled(), 1);
+
+        // A buffer still backing a request body cannot be reclaimed for free.
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[b'x'; 1000]);
+        let body = buffer.split_to(1000).freeze();
+        pool.put(buffer);
+        assert_eq!(pool.pooled(), 0);
+        drop(body);
+
+        let mut grown = pool.take();
+        grown.reserve(64 * 1024);
+        pool.put(grown);
+        assert_eq!(pool.pooled(), 0);
+
+        for _ in 0..3 {
+            pool.put(bytes::BytesMut::with_capacity(BUFFER_CAPACITY));
+        }
+        assert_eq!(pool.pooled(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_connections_reuse_pooled_buffers() {
+        let (server, addr) = spawn_server(demo_config()).await;
+        for _ in 0..5 {
+            let response = send_raw(addr, b"GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        }
+        let pool = server.metrics().buffer_pool();
+        assert!(pool.hits() >= 8, "hits {} misses {}", pool.hits(), pool.misses());
+        assert!(pool.misses() <= 2, "hits {} misses {}", pool.hits(), pool.misses());
+        assert!(server.metrics().render().contains(&format!("buffer_pool_hits_total {}\n", pool.hits())));
+    }
+
+    async fn spawn_duplex_connection(config: config::Config) -> tokio::io::DuplexStream {
+        let (client, socket) = tokio::io::duplex(64 * 1024);
+        let server = server::Server::new(config.clone());
+        let router = std::sync::Arc::new(server.router().clone());
+        let filter = std::sync::Arc::new(security::IpFilter::default());
+        let metrics = std::sync::Arc::new(metrics::Metrics::new());
+        let addr: std::net::SocketAddr = "192.0.2.7:40000".parse().unwrap();
+        let deadline = server::Server::header_deadline(&config);
+        tokio::spawn(server::Server::handle_connection(socket, addr, std::sync::Arc::new(config), router, filter, metrics, None, deadline, drain::Drain::new().signal()));
+        client
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection_over_duplex() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = std::sync::Arc::new(server::Server::new(demo_config()));
+        let (mut client, socket) = tokio::io::duplex(64 * 1024);
+        let serving = server.clone();
+        let connection = tokio::spawn(async move { serving.serve_connection(socket, "192.0.2.7:40000".parse().unwrap()).await });
+
+        client
+            .write_all(b"GET /echo/one HTTP/1.1\r\nHost: localhost\r\n\r\nGET /echo/two HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        connection.await.unwrap().unwrap();
+
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2, "{}", response);
+        assert!(response.ends_with("two"), "{}", response);
+        assert_eq!(server.drain().await, drain::DrainReport::default());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slowloris_header_deadline() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut config = config::Config::default();
+        config.security.header_timeout = 10;
+        let mut client = spawn_duplex_connection(config).await;
+
+        let started = tokio::time::Instant::now();
+        let trickle = b"GET /echo/slow HTTP/1.1\r\nHost: localhost\r\nX-Padding: aaaaaaaaaaaaaaaa";
+        for byte in trickle {
+            if client.write_all(&[*byte]).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(response.contains("connection: close"));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= std::time::Duration::from_secs(10) && elapsed < std::time::Duration::from_secs(12), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slowloris_minimum_body_rate() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut config = demo_config();
+        config.security.min_body_rate = 1000;
+        config.security.body_rate_window = 5;
+
+        let mut client = spawn_duplex_connection(config.clone()).await;
+        client.write_all(b"POST /echo/slow HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100000\r\n\r\n").await.unwrap();
+        let started = tokio::time::Instant::now();
+        for _ in 0..60 {
+            if client.write_all(&[b'x'; 100]).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(started.elapsed() < std::time::Duration::from_secs(7), "{:?}", started.elapsed());
+
+        let mut client = spawn_duplex_connection(config).await;
+        client.write_all(b"POST /echo/fast HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4000\r\n\r\n").await.unwrap();
+        for _ in 0..4 {
+            client.write_all(&[b'x'; 1000]).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    async fn read_response(stream: &mut tokio::net::TcpStream) -> String {
+        use tokio::io::AsyncReadExt;
+
+        let head = read_head(stream).await;
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .map_or(0, |length| length.trim().parse().unwrap());
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).await.unwrap();
+        head + &String::from_utf8_lossy(&body)
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_request_limit() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut config = demo_config();
+        config.performance.max_requests_per_connection = 3;
+        let (server, addr) = spawn_server(config).await;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        for i in 1..=3 {
+            stream.write_all(format!("GET /echo/{} HTTP/1.1\r\nHost: localhost\r\n\r\n", i).as_bytes()).await.unwrap();
+            let response = read_response(&mut stream).await;
+            assert!(response.starts_with("HTTP/1.1 200") && response.ends_with(&i.to_string()), "{}", response);
+            if i < 3 {
+                assert!(response.contains(&format!("keep-alive: timeout=5, max={}\r\n", 3 - i)), "{}", response);
+                assert!(!response.contains("connection: close"));
+            } else {
+                assert!(response.contains("connection: close\r\n"), "{}", response);
+                assert!(!response.contains("keep-alive:"));
+            }
+        }
+        let _ = stream.write_all(b"GET /echo/4 HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let mut rest = Vec::new();
+        let _ = stream.read_to_end(&mut rest).await;
+        assert!(rest.is_empty());
+        assert_eq!(server.metrics().requests_by_class()[1], 3);
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_negotiation() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (_server, addr) = spawn_server(config::Config::default()).await;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /echo/old HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.contains("connection: keep-alive\r\n") && response.contains("keep-alive: timeout=5\r\n"), "{}", response);
+        stream.write_all(b"GET /echo/again HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.contains("connection: close\r\n") && response.ends_with("again"), "{}", response);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /echo/plain HTTP/1.0\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.contains("connection: close\r\n") && response.ends_with("plain"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_response_matches_request_version() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (_server, addr) = spawn_server(demo_config()).await;
+
+        let response = send_raw(addr, b"GET /echo/old HTTP/1.0\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+        assert!(response.contains("connection: close\r\n") && !response.contains("transfer-encoding"), "{}", response);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /echo/first HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n") && response.contains("content-length: 5\r\n"), "{}", response);
+        stream.write_all(b"GET /echo/second HTTP/1.0\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n") && response.ends_with("second"), "{}", response);
+
+        let response = send_raw(addr, b"GET /echo/new HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+        for raw in [&b"GET /echo/ancient\r\n\r\n"[..], b"GET / HTTP/2.0\r\n\r\n", b"GET / HTTP/1.2\r\n\r\n"] {
+            let response = send_raw(addr, raw).await;
+            assert!(response.starts_with("HTTP/1.1 505"), "{:?} -> {}", String::from_utf8_lossy(raw), response);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_target_forms() {
+        let (server, addr) = spawn_server(demo_config()).await;
+
+        let response = send_raw(addr, b"GET /echo/origin HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("origin"), "{}", response);
+
+        let response = send_raw(addr, b"GET http://Example.com/echo/absolute?x=1 HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("absolute"), "{}", response);
+        let response = send_raw(addr, b"GET http://example.com/echo/absolute HTTP/1.1\r\nHost: example.com\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("absolute"), "{}", response);
+        let response = send_raw(addr, b"GET http://example.com/echo/absolute HTTP/1.1\r\nHost: other.test\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400") && response.contains("does not match"), "{}", response);
+
+        let response = send_raw(addr, b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let allow = response.lines().find_map(|line| line.strip_prefix("allow: ")).unwrap();
+        for method in ["OPTIONS", "GET", "HEAD", "POST", "PUT", "DELETE"] {
+            assert!(allow.split(", ").any(|allowed| allowed == method), "{}", allow);
+        }
+        let response = send_raw(addr, b"GET * HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+
+        let response = send_raw(addr, b"GET /echo/anonymous HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400") && response.contains("Missing Host header"), "{}", response);
+        assert!(response.contains("connection: close\r\n"), "{}", response);
+        let response = send_raw(addr, b"GET /echo/twice HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        let response = send_raw(addr, b"GET /echo/legacy HTTP/1.0\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.0 200") && response.ends_with("legacy"), "{}", response);
+
+        assert!(server.metrics().render().contains("http_parse_errors_total 4\n"));
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_answered_in_order() {
+        let (_server, addr) = spawn_server(demo_config()).await;
+
+        let response = send_raw(
+            addr,
+            b"POST /echo/first HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET /echo/second HTTP/1.1\r\nHost: a\r\n\r\nGET /echo/third HTTP/1.1\r\nHost: a\r\n\r\n",
+        )
+        .await;
+        let statuses = response.matches("HTTP/1.1 200 OK").count();
+        assert_eq!(statuses, 3, "{}", response);
+        let first = response.find("first").unwrap();
+        let second = response.find("second").unwrap();
+        let third = response.find("third").unwrap();
+        assert!(first < second && second < third, "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_request_split_across_writes() {
+        use tokio::io::AsyncWriteExt;
+
+        let (_server, addr) = spawn_server(demo_config()).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /echo/one HTTP/1.1\r\nHost: a\r\n\r\nGET /echo/two HTTP/1.1\r\nHo").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("one"), "{}", response);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        stream.write_all(b"st: a\r\n\r\n").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("two"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_method_override_and_patch_routing() {
+        let method_router = |override_enabled: bool| {
+            let mut router = router::Router::new();
+            if override_enabled {
+                router.layer(methods::MethodOverride::new());
+            }
+            let reply = |request: Request| Ok(Response::ok().with_text(request.method.as_str()));
+            router.post("/item", reply).put("/item", reply).patch("/item", reply).delete("/item", reply);
+            router
+        };
+        use testing::{TestClient, TestRequest};
+
+        let client = TestClient::from_router(method_router(false));
+        let overridden = |method: &str| TestRequest::post("/item").header("x-http-method-override", method);
+        assert_eq!(client.send(overridden("DELETE")).await.text(), "POST");
+        assert_eq!(client.send(TestRequest::patch("/item")).await.text(), "PATCH");
+
+        let client = TestClient::from_router(method_router(true));
+        assert_eq!(client.send(overridden("put")).await.text(), "PUT");
+        assert_eq!(client.send(TestRequest::post("/item").form("name=x&_method=PATCH")).await.text(), "PATCH");
+        assert_eq!(client.send(TestRequest::post("/item").form("name=x")).await.text(), "POST");
+        assert_eq!(client.send(overridden("TRACE")).await.status(), ::http::StatusCode::BAD_REQUEST);
+
+        let get = TestRequest::get("/item").header("x-http-method-override", "DELETE");
+        assert!(client.send(get).await.status().is_client_error());
+    }
+
+    #[tokio::test]
+    async fn test_trace_policy() {
+        let raw = b"TRACE /echo/x?y=1 HTTP/1.1\r\nHost: localhost\r\nCookie: session=secret\r\nX-Probe: 1\r\n\r\n";
+
+        let (_server, addr) = spawn_server(config::Config::default()).await;
+        let response = send_raw(addr, raw).await;
+        assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+
+        let mut config = config::Config::default();
+        config.server.trace = config::TracePolicy::Echo;
+        let (_server, addr) = spawn_server(config).await;
+        let response = send_raw(addr, raw).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.contains("content-type: message/http"), "{}", response);
+        let (_, echoed) = response.split_once("\r\n\r\n").unwrap();
+        assert!(echoed.starts_with("TRACE /echo/x?y=1 HTTP/1.1\r\n") && echoed.contains("x-probe: 1\r\n"), "{}", echoed);
+        assert!(!echoed.contains("secret"), "{}", echoed);
+    }
+
+    #[tokio::test]
+    async fn test_request_span_fields() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let logging = config::LoggingConfig {
+            format: config::LogFormat::Json,
+            ..Default::default()
+        };
+        let writer = CaptureWriter::default();
+        let _guard = tracing::subscriber::set_default(logging::build_subscriber(&logging, None, writer.clone(), false));
+
+        let mut client = spawn_duplex_connection(demo_config()).await;
+        client
+            .write_all(b"GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: abc-123\r\n\r\nGET /nope HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.contains("HTTP/1.1 404"), "{}", response);
+
+        let output = writer.contents();
+        let completed: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|line| line["fields"]["message"] == "request completed")
+            .collect();
+        assert_eq!(completed.len(), 2, "{}", output);
+
+        let ok = &completed[0]["span"];
+        assert_eq!(ok["name"], "request");
+        assert_eq!(ok["method"], "GET");
+        assert_eq!(ok["path"], "/echo/hi");
+        assert_eq!(ok["remote_addr"], "192.0.2.7:40000");
+        assert_eq!(ok["request_id"], "abc-123");
+        assert_eq!(ok["status"], 200);
+        assert!(ok["latency_ms"].as_f64().is_some_and(|latency| latency >= 0.0), "{}", ok);
+
+        let missing = &completed[1]["span"];
+        assert_eq!(missing["path"], "/nope");
+        assert_eq!(missing["status"], 404);
+        assert!(missing["request_id"].as_str().is_some_and(|id| id.len() == 36), "{}", missing);
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_headers_and_params_redacted_in_logs() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let logging = config::LoggingConfig {
+            level: "trace".to_string(),
+            redact_headers: vec!["X-Session".to_string()],
+            ..Default::default()
+        };
+        let writer = CaptureWriter::default();
+        let _guard = tracing::subscriber::set_default(logging::build_subscriber(&logging, None, writer.clone(), false));
+        let config = config::Config { logging, ..demo_config() };
+
+        let mut client = spawn_duplex_connection(config.clone()).await;
+        client
+            .write_all(b"GET /echo/hi?page=2&token=tok-9f8e HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer s3cr3t-value\r\nX-Session: sess-4471\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let mut client = spawn_duplex_connection(config).await;
+        client.write_all(b"GET /x?api_key=key-5521 HTTP/1.1\r\nHost: localhost\r\nCookie: sid=c00k1e\xff\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 400"));
+
+        let output = writer.contents();
+        assert!(output.contains("request headers") && output.contains("Rejecting malformed request"), "{}", output);
+        assert!(output.contains("page=2&token=[REDACTED]"), "{}", output);
+        assert!(output.contains("authorization: [REDACTED]"), "{}", output);
+        for secret in ["tok-9f8e", "s3cr3t-value", "sess-4471", "key-5521", "c00k1e"] {
+            assert!(!output.contains(secret), "{} leaked: {}", secret, output);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dump_wire_hex_dumps_redacted_traffic() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut logging = config::LoggingConfig {
+            format: config::LogFormat::Json,
+            dump_wire: true,
+            dump_wire_limit: 128,
+            ..Default::default()
+        };
+        logging.targets.insert(logging::WIRE_TARGET.to_string(), "trace".to_string());
+        let writer = CaptureWriter::default();
+        let _guard = tracing::subscriber::set_default(logging::build_subscriber(&logging, None, writer.clone(), false));
+
+        let request = format!(
+            "GET /echo/hi?token=tok-1234 HTTP/1.1\r\nAuthorization: Bearer s3cr3t\r\nHost: localhost\r\nX-Request-Id: wire-1\r\nX-Padding: {}\r\nConnection: close\r\n\r\n",
+            "p".repeat(100)
+        );
+        let mut client = spawn_duplex_connection(config::Config { logging: logging.clone(), ..demo_config() }).await;
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let undump = |dump: &str| -> String {
+            let bytes: Vec<u8> = dump
+                .lines()
+                .skip(1)
+                .filter_map(|line| line.split_once("  |"))
+                .flat_map(|(hex, _)| hex.split_whitespace().skip(1).map(|byte| u8::from_str_radix(byte, 16).unwrap()).collect::<Vec<_>>())
+                .collect();
+            String::from_utf8(bytes).unwrap()
+        };
+        let output = writer.contents();
+        let dumps: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|line| line["target"] == logging::WIRE_TARGET)
+            .collect();
+        assert_eq!(dumps.len(), 2, "{}", output);
+
+        let received = &dumps[0];
+        let message = received["fields"]["message"].as_str().unwrap();
+        assert!(message.starts_with("request bytes:\n00000000  47 45 54 20 2f 65 63 68"), "{}", message);
+        assert!(message.contains("|GET /echo/hi?tok|"), "{}", message);
+        assert!(message.ends_with(&format!("... {} more bytes", request.len() - 128)), "{}", message);
+        assert_eq!(received["fields"]["bytes"], request.len());
+        assert_eq!(received["span"]["request_id"], "wire-1");
+        let connection_id = received["fields"]["connection_id"].as_u64().unwrap();
+        let text = undump(message);
+        assert!(text.starts_with("GET /echo/hi?token=[REDACTED] HTTP/1.1\r\nAuthorization: [REDACTED]\r\nHost: localhost\r\n"), "{}", text);
+        assert!(!output.contains("s3cr3t") && !output.contains("tok-1234"), "{}", output);
+
+        let sent = &dumps[1];
+        assert_eq!(sent["fields"]["connection_id"], connection_id);
+        assert_eq!(sent["span"]["request_id"], "wire-1");
+        let head = undump(sent["fields"]["message"].as_str().unwrap());
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n") && response.starts_with(&head), "{}", head);
+        assert_eq!(sent["fields"]["bytes"], response.find("\r\n\r\n").unwrap() + 4);
+
+        let quiet = CaptureWriter::default();
+        let _guard = tracing::subscriber::set_default(logging::build_subscriber(&config::LoggingConfig::default(), None, quiet.clone(), false));
+        let mut client = spawn_duplex_connection(config::Config { logging, ..demo_config() }).await;
+        client.write_all(request.as_bytes()).await.unwrap();
+        client.read_to_string(&mut String::new()).await.unwrap();
+        assert!(!quiet.contents().contains("request bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_log_and_route_histograms() {
+        let writer = CaptureWriter::default();
+        let _guard = tracing::subscriber::set_default(logging::build_subscriber(&config::LoggingConfig::default(), None, writer.clone(), false));
+
+        let metrics = std::sync::Arc::new(metrics::Metrics::new());
+        let mut router = router::Router::new();
+        router
+            .route_metrics(metrics.clone())
+            .slow_request_threshold(Some(std::time::Duration::from_millis(50)))
+            .get("/fast/{id}", |_| Ok(Response::ok()))
+            .get_with(
+                "/slow/{id}",
+                |_| async {
+                    tokio::time::sleep(std::time::Duration::from_millis(120)).await;
+                    Ok(Response::ok())
+                },
+                router::RouteOptions::default(),
+            );
+
+        for id in 0..2 {
+            let mut request = Request::new(Method::GET, format!("/slow/{}", id).parse().unwrap(), Version::HTTP_11);
+            request.extensions.insert(http::RequestId(format!("req-{}", id)));
+            router.handle(request).await.unwrap();
+        }
+        router.handle(Request::new(Method::GET, "/fast/1".parse().unwrap(), Version::HTTP_11)).await.unwrap();
+
+        let output = writer.contents();
+        let slow: Vec<&str> = output.lines().filter(|line| line.contains("Slow request")).collect();
+        assert_eq!(slow.len(), 2, "{}", output);
+        assert!(slow[0].contains("WARN") && slow[0].contains("/slow/{id}") && slow[0].contains("req-0") && slow[0].contains("200"), "{}", slow[0]);
+        assert!(!output.contains("/fast/"), "{}", output);
+
+        let latencies = metrics.route_latencies();
+        assert_eq!(latencies.iter().map(|route| route.route.as_str()).collect::<Vec<_>>(), ["/fast/{id}", "/slow/{id}"]);
+        let slow = &latencies[1];
+        assert_eq!((slow.method.as_str(), slow.count), ("GET", 2));
+        assert!(slow.p50_ms > 100.0 && slow.p50_ms <= 250.0 && slow.p99_ms >= slow.p50_ms, "{:?}", slow);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("http_route_duration_seconds_count{method=\"GET\",route=\"/slow/{id}\"} 2"), "{}", rendered);
+        assert!(rendered.contains("http_route_duration_seconds_bucket{method=\"GET\",route=\"/fast/{id}\",le=\"+Inf\"} 1"), "{}", rendered);
+        assert!(!rendered.contains("/slow/0"));
+    }
+
+    #[cfg(feature = "otel")]
+    #[derive(Debug, Clone, Default)]
+    struct CaptureSpans(std::sync::Arc<std::sync::Mutex<Vec<opentelemetry_sdk::trace::SpanData>>>);
+
+    #[cfg(feature = "otel")]
+    impl opentelemetry_sdk::trace::SpanExporter for CaptureSpans {
+        async fn export(&self, batch: Vec<opentelemetry_sdk::trace::SpanData>) -> opentelemetry_sdk::error::OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_otel_request_span_attributes() {
+        use opentelemetry::trace::{SpanId, TraceId};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = CaptureSpans::default();
+        let telemetry = otel::Telemetry::with_exporter("test-service", spans.clone());
+        let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(telemetry.tracer()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut client = spawn_duplex_connection(demo_config()).await;
+        client
+            .write_all(
+                b"GET /echo/hi HTTP/1.1\r\nHost: localhost\r\ntraceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n\r\n\
+                  GET /nope HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        telemetry.shutdown().unwrap();
+
+        let spans = spans.0.lock().unwrap();
+        let requests: Vec<_> = spans.iter().filter(|span| span.name == "request").collect();
+        assert_eq!(requests.len(), 2, "{:?}", spans);
+        let attribute = |span: &opentelemetry_sdk::trace::SpanData, key: &str| {
+            span.attributes.iter().find(|attribute| attribute.key.as_str() == key).map(|attribute| attribute.value.to_string())
+        };
+
+        let ok = requests[0];
+        assert_eq!(ok.span_context.trace_id(), TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap());
+        assert_eq!(ok.parent_span_id, SpanId::from_hex("00f067aa0ba902b7").unwrap());
+        assert_eq!(attribute(ok, "http.method").as_deref(), Some("GET"));
+        assert_eq!(attribute(ok, "http.route").as_deref(), Some("/echo/{param}"));
+        assert_eq!(attribute(ok, "http.status_code").as_deref(), Some("200"));
+
+        let missing = requests[1];
+        assert_ne!(missing.span_context.trace_id(), ok.span_context.trace_id());
+        assert_eq!(missing.parent_span_id, SpanId::INVALID);
+        assert_eq!(attribute(missing, "http.route"), None);
+        assert_eq!(attribute(missing, "http.status_code").as_deref(), Some("404"));
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_hits_and_bypasses() {
+        use response_cache::ResponseCache;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+        use testing::{TestClient, TestRequest};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = ResponseCache::new(Duration::from_secs(60), 16, 1024, 4096);
+        let mut router = router::Router::new();
+        router.layer(cache.clone());
+        let counted = |cache_control: Option<&'static str>| {
+            let calls = calls.clone();
+            move |request: Request| {
+                let count = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                let response = Response::ok().with_text(&format!("{} #{}", request.path(), count));
+                Ok(match cache_control {
+                    Some(value) => response.with_header("cache-control", value),
+                    None => response,
+                })
+            }
+        };
+        router
+            .get("/api/report", counted(None))
+            .get("/api/other", counted(None))
+            .get("/api/short", counted(Some("public, max-age=0")))
+            .get("/api/no-store", counted(Some("no-store")))
+            .get("/api/private", counted(Some("private, max-age=60")))
+            .get("/api/missing", |_| Err(Error::FileNotFound("gone".to_string())))
+            .get("/api/cookie", {
+                let calls = calls.clone();
+                move |_| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Ok(Response::ok().with_header("set-cookie", "id=1").with_text("cookie"))
+                }
+            })
+            .get("/api/greeting", {
+                let calls = calls.clone();
+                move |request: Request| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    let language = request.header("accept-language").and_then(|value| value.to_str().ok()).unwrap_or("en");
+                    Ok(Response::ok().with_header("vary", "Accept-Language").with_text(language))
+                }
+            });
+        let client = TestClient::from_router(router);
+        let calls_after = |expected: usize| assert_eq!(calls.load(Ordering::SeqCst), expected);
+
+        let first = client.get("/api/report").await;
+        assert_eq!(first.header("x-cache"), Some("MISS"));
+        assert_eq!(first.header("age"), None);
+        let second = client.get("/api/report").await;
+        assert_eq!(second.header("x-cache"), Some("HIT"));
+        assert_eq!(second.header("age"), Some("0"));
+        assert_eq!(second.text(), first.text());
+        calls_after(1);
+
+        assert_eq!(client.get("/api/report?page=2").await.header("x-cache"), Some("MISS"));
+        assert_eq!(client.get("/api/report?page=2").await.header("x-cache"), Some("HIT"));
+        calls_after(2);
+        let refreshed = client.send(TestRequest::get("/api/report").header("cache-control", "no-cache")).await;
+        assert_eq!(refreshed.header("x-cache"), Some("MISS"));
+        assert_eq!(refreshed.text(), "/api/report #3");
+        assert_eq!(client.get("/api/report").await.text(), "/api/report #3");
+        let authorized = client.send(TestRequest::get("/api/report").header("authorization", "Bearer x")).await;
+        assert_eq!(authorized.header("x-cache"), None);
+        assert_eq!(client.send(TestRequest::head("/api/report")).await.header("x-cache"), None);
+        calls_after(4);
+
+        for path in ["/api/no-store", "/api/private", "/api/short", "/api/cookie"] {
+            client.get(path).await;
+            assert_eq!(client.get(path).await.header("x-cache"), Some("MISS"), "{}", path);
+        }
+        calls_after(12);
+        client.get("/api/missing").await;
+        assert_eq!(client.get("/api/missing").await.status(), ::http::StatusCode::NOT_FOUND);
+        assert_eq!(cache.len(), 2);
+
+        let english = client.send(TestRequest::get("/api/greeting").header("accept-language", "en")).await;
+        let french = client.send(TestRequest::get("/api/greeting").header("accept-language", "fr")).await;
+        assert_eq!((english.header("x-cache"), french.header("x-cache")), (Some("MISS"), Some("MISS")));
+        let french = client.send(TestRequest::get("/api/greeting").header("accept-language", "fr")).await;
+        assert_eq!((french.header("x-cache"), french.text().as_str()), (Some("HIT"), "fr"));
+        let english = client.send(TestRequest::get("/api/greeting").header("accept-language", "en")).await;
+        assert_eq!((english.header("x-cache"), english.text().as_str()), (Some("HIT"), "en"));
+        calls_after(14);
+
+        client.get("/api/other").await;
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.purge("/api/rep"), 0);
+        assert_eq!(cache.purge("/api/report"), 2);
+        assert_eq!(client.get("/api/report").await.header("x-cache"), Some("MISS"));
+        assert_eq!(client.get("/api/other").await.header("x-cache"), Some("HIT"));
+        assert_eq!(cache.purge("/"), 4);
+        assert!(cache.is_empty());
+        assert_eq!(cache.total_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_skips_cookie_requests() {
+        use response_cache::ResponseCache;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+        use testing::{TestClient, TestRequest};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = ResponseCache::new(Duration::from_secs(60), 16, 1024, 4096);
+        let mut router = router::Router::new();
+        router.layer(cache.clone());
+        let counted = |vary: Option<&'static str>| {
+            let calls = calls.clone();
+            move |request: Request| {
+                let count = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                let user = request.header("cookie").and_then(|value| value.to_str().ok()).unwrap_or("anonymous").to_string();
+                let response = Response::ok().with_text(&format!("{} #{}", user, count));
+                Ok(match vary {
+                    Some(value) => response.with_header("vary", value),
+                    None => response,
+                })
+            }
+        };
+        router.get("/api/shared", counted(None)).get("/api/session", counted(Some("Cookie")));
+        let client = TestClient::from_router(router);
+        let with_cookie = |path: &str, cookie: &str| TestRequest::get(path).header("cookie", cookie);
+
+        let personal = client.send(with_cookie("/api/shared", "session=alice")).await;
+        assert_eq!((personal.header("x-cache"), personal.text().as_str()), (Some("MISS"), "session=alice #1"));
+        assert!(cache.is_empty());
+        let anonymous = client.get("/api/shared").await;
+        assert_eq!((anonymous.header("x-cache"), anonymous.text().as_str()), (Some("MISS"), "anonymous #2"));
+        let personal = client.send(with_cookie("/api/shared", "session=alice")).await;
+        assert_eq!((personal.header("x-cache"), personal.text().as_str()), (Some("MISS"), "session=alice #3"));
+        assert_eq!(client.get("/api/shared").await.header("x-cache"), Some("HIT"));
+
+        client.send(with_cookie("/api/session", "session=alice")).await;
+        let alice = client.send(with_cookie("/api/session", "session=alice")).await;
+        assert_eq!((alice.header("x-cache"), alice.text().as_str()), (Some("HIT"), "session=alice #4"));
+        let bob = client.send(with_cookie("/api/session", "session=bob")).await;
+        assert_eq!((bob.header("x-cache"), bob.text().as_str()), (Some("MISS"), "session=bob #5"));
+        assert_eq!(client.get("/api/session").await.text(), "anonymous #6");
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_expiry_and_budget() {
+        use response_cache::ResponseCache;
+        use std::time::Duration;
+
+        let router_with = |cache: &response_cache::ResponseCache| {
+            let mut router = router::Router::new();
+            router.layer(cache.clone());
+            router.get("/item/{id}", |request: Request| {
+                let body = "x".repeat(request.params["id"].parse().unwrap());
+                Ok(Response::ok().with_text(&body))
+            });
+            testing::TestClient::from_router(router)
+        };
+
+        let cache = ResponseCache::new(Duration::from_millis(50), 16, 1024, 4096);
+        let client = router_with(&cache);
+        assert_eq!(client.get("/item/10").await.header("x-cache"), Some("MISS"));
+        assert_eq!(client.get("/item/10").await.header("x-cache"), Some("HIT"));
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert_eq!(client.get("/item/10").await.header("x-cache"), Some("MISS"));
+        assert_eq!(cache.len(), 1);
+
+        let cache = ResponseCache::new(Duration::from_secs(60), 2, 1024, 4096);
+        let client = router_with(&cache);
+        client.get("/item/1").await;
+        client.get("/item/2").await;
+        client.get("/item/1").await;
+        client.get("/item/3").await;
+        assert_eq!(cache.len(), 2);
+        assert_eq!(client.get("/item/1").await.header("x-cache"), Some("HIT"));
+        assert_eq!(client.get("/item/2").await.header("x-cache"), Some("MISS"));
+
+        let cache = ResponseCache::new(Duration::from_secs(60), 16, 1024, 1500);
+        let client = router_with(&cache);
+        client.get("/item/2000").await;
+        assert!(cache.is_empty());
+        client.get("/item/700").await;
+        client.get("/item/800").await;
+        assert_eq!(cache.len(), 1);
+        assert!(cache.total_bytes() <= 1500);
+        assert_eq!(client.get("/item/800").await.header("x-cache"), Some("HIT"));
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_config_and_admin_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "one").unwrap();
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.response_cache.enabled = true;
+        config.response_cache.paths = vec!["/files".to_string()];
+        let server = server::Server::new(config);
+        let client = testing::TestClient::from_server(&server);
+
+        assert_eq!(client.get("/files/a.txt").await.header("x-cache"), Some("MISS"));
+        std::fs::write(dir.path().join("a.txt"), "two").unwrap();
+        let cached = client.get("/files/a.txt").await;
+        assert_eq!((cached.header("x-cache"), cached.text().as_str()), (Some("HIT"), "one"));
+        assert_eq!(client.get("/echo/x").await.header("x-cache"), None);
+
+        let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin = admin_listener.local_addr().unwrap();
+        tokio::spawn(server.serve_admin(admin_listener));
+        let body = "{\"prefix\": \"/files\"}";
+        let purge = format!("POST /cache/purge HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        let response = send_raw(admin, purge.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("{\"purged\":1}"), "{}", response);
+        assert!(server.response_cache().unwrap().is_empty());
+        let fresh = client.get("/files/a.txt").await;
+        assert_eq!((fresh.header("x-cache"), fresh.text().as_str()), (Some("MISS"), "two"));
+
+        assert!(server::Server::new(config::Config::default()).response_cache().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get_for_dynamic_responses() {
+        use std::time::{Duration, UNIX_EPOCH};
+        use testing::{TestClient, TestRequest};
+        use utils::etag::{self, EntityTag};
+
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut router = router::Router::new();
+        router
+            .get("/report", move |_| {
+                let body = serde_json::json!({ "total": 42 });
+                let tag = etag::from_bytes(body.to_string().as_bytes());
+                Ok(Response::ok()
+                    .with_json(&body)?
+                    .with_etag(&tag)
+                    .with_last_modified(modified)
+                    .with_header("cache-control", "no-cache"))
+            })
+            .get("/weak", |_| Ok(Response::ok().with_etag(&EntityTag::weak("v1")).with_text("weak")))
+            .post("/report", |_| Ok(Response::ok().with_etag(&EntityTag::strong("post")).with_text("posted")))
+            .get("/gone", |_| Ok(Response::not_found().with_etag(&EntityTag::strong("gone")).with_text("gone")));
+        let client = TestClient::from_router(router);
+
+        let first = client.get("/report").await;
+        assert_eq!(first.status(), ::http::StatusCode::OK);
+        let tag = first.header("etag").unwrap().to_string();
+        assert_eq!(first.header("last-modified"), Some("Tue, 14 Nov 2023 22:13:20 GMT"));
+
+        let revalidated = client.send(TestRequest::get("/report").header("if-none-match", &tag)).await;
+        assert_eq!(revalidated.status(), ::http::StatusCode::NOT_MODIFIED);
+        assert!(revalidated.bytes().is_empty());
+        assert_eq!(revalidated.header("etag"), Some(tag.as_str()));
+        assert_eq!(revalidated.header("cache-control"), Some("no-cache"));
+        assert_eq!(revalidated.header("content-length"), None);
+        assert_eq!(revalidated.header("content-type"), None);
+
+        let changed = client.send(TestRequest::get("/report").header("if-none-match", "\"old\"")).await;
+        assert_eq!(changed.status(), ::http::StatusCode::OK);
+        assert_eq!(changed.json::<serde_json::Value>().unwrap()["total"], 42);
+
+        let since = |date: &str| TestRequest::get("/report").header("if-modified-since", date);
+        assert_eq!(client.send(since("Tue, 14 Nov 2023 22:13:20 GMT")).await.status(), ::http::StatusCode::NOT_MODIFIED);
+        assert_eq!(client.send(since("Wed, 15 Nov 2023 00:00:00 GMT")).await.status(), ::http::StatusCode::NOT_MODIFIED);
+        assert_eq!(client.send(since("Tue, 14 Nov 2023 22:13:19 GMT")).await.status(), ::http::StatusCode::OK);
+        assert_eq!(client.send(since("not a date")).await.status(), ::http::StatusCode::OK);
+        let both = since("Wed, 15 Nov 2023 00:00:00 GMT").header("if-none-match", "\"old\"");
+        assert_eq!(client.send(both).await.status(), ::http::StatusCode::OK);
+
+        let weak = client.send(TestRequest::get("/weak").header("if-none-match", "\"v1\"")).await;
+        assert_eq!(weak.status(), ::http::StatusCode::NOT_MODIFIED);
+        let post = client.send(TestRequest::post("/report").header("if-none-match", "*")).await;
+        assert_eq!(post.status(), ::http::StatusCode::OK);
+        let gone = client.send(TestRequest::get("/gone").header("if-none-match", "\"gone\"")).await;
+        assert_eq!(gone.status(), ::http::StatusCode::NOT_FOUND);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data.json"), "{\"a\":1}").unwrap();
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let (_server, addr) = spawn_server(config).await;
+        let response = send_raw(addr, b"GET /files/data.json HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let tag = response.lines().find_map(|line| line.strip_prefix("etag: ")).unwrap().to_string();
+        let pipelined = format!(
+            "GET /files/data.json HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {}\r\n\r\nGET /echo/after HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            tag
+        );
+        let response = send_raw(addr, pipelined.as_bytes()).await;
+        let (not_modified, after) = response.split_once("\r\n\r\n").unwrap();
+        assert!(not_modified.starts_with("HTTP/1.1 304 Not Modified"), "{}", response);
+        assert!(!not_modified.contains("content-length"), "{}", response);
+        assert!(after.starts_with("HTTP/1.1 200") && after.ends_with("after"), "{}", response);
+    }
+
+    #[cfg(feature = "embed")]
+    #[tokio::test]
+    async fn test_embedded_assets() {
+        static ASSETS: embed::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/tests/fixtures/embed");
+
+        let mut router = router::Router::new();
+        router.embedded("/assets", &ASSETS);
+        router.embedded_with("/nocache", embed::EmbeddedAssets::new(&ASSETS).cache_control(None));
+        let client = testing::TestClient::from_router(router);
+
+        let css = client.get("/assets/css/app.css").await;
+        assert_eq!(css.status(), ::http::StatusCode::OK);
+        assert_eq!(css.header("content-type"), Some("text/css"));
+        assert_eq!(css.header("cache-control"), Some("public, max-age=31536000, immutable"));
+        assert_eq!(css.bytes(), include_bytes!("../tests/fixtures/embed/css/app.css"));
+        let etag = css.header("etag").unwrap().to_string();
+        assert_eq!(etag, utils::etag::from_bytes(include_bytes!("../tests/fixtures/embed/css/app.css")).to_string());
+
+        let revalidated = client
+            .send(testing::TestRequest::new(Method::GET, "/assets/css/app.css").header("if-none-match", &etag))
+            .await;
+        assert_eq!(revalidated.status(), ::http::StatusCode::NOT_MODIFIED);
+        assert!(revalidated.bytes().is_empty());
+
+        let partial = client.send(testing::TestRequest::new(Method::GET, "/assets/css/app.css").header("range", "bytes=0-3")).await;
+        assert_eq!(partial.status(), ::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(partial.bytes(), b"body");
+
+        let head = client.send(testing::TestRequest::new(Method::HEAD, "/assets/css/app.css")).await;
+        assert_eq!(head.status(), ::http::StatusCode::OK);
+        assert!(head.bytes().is_empty());
+
+        let index = client.get("/assets/").await;
+        assert_eq!(index.header("content-type"), Some("text/html"));
+        assert!(index.text().contains("<title>Embedded</title>"));
+        assert_eq!(client.get("/assets/missing.js").await.status(), ::http::StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/nocache/css/app.css").await.header("cache-control"), None);
+    }
+
+    #[tokio::test]
+    async fn test_builtin_route_toggles() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        let mut config = config::Config::default();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        let not_allowed = |status: ::http::StatusCode| {
+            matches!(status, ::http::StatusCode::NOT_FOUND | ::http::StatusCode::METHOD_NOT_ALLOWED)
+        };
+
+        let client = testing::TestClient::new(config.clone());
+        assert_eq!(client.get("/").await.status(), ::http::StatusCode::OK);
+        assert_eq!(client.get("/echo/hello").await.status(), ::http::StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/user-agent").await.status(), ::http::StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/files/notes.txt").await.text(), "notes");
+        for method in [Method::POST, Method::PUT, Method::DELETE, Method::from_bytes(b"MOVE").unwrap()] {
+            let request = testing::TestRequest::new(method.clone(), "/files/notes.txt").body("changed");
+            let response = client.send(request).await.status();
+            assert!(not_allowed(response), "{} {}", method, response);
+        }
+        assert!(not_allowed(client.send(testing::TestRequest::new(Method::POST, "/files").body("x")).await.status()));
+        let options = client.send(testing::TestRequest::new(Method::OPTIONS, "/files/notes.txt")).await;
+        assert_eq!(options.header("allow"), Some(webdav::READ_ONLY_METHODS));
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "notes");
+
+        let mut routes = config.clone();
+        routes.routes = config::RoutesConfig { enable_echo: true, enable_user_agent: true, enable_files: false, files_read_only: true };
+        let client = testing::TestClient::from_router(router::Router::with_default_routes(&routes));
+        assert_eq!(client.get("/echo/hello").await.text(), "hello");
+        let agent = client.send(testing::TestRequest::new(Method::GET, "/user-agent").header("user-agent", "toggle/1.0")).await;
+        assert_eq!(agent.text(), "toggle/1.0");
+        assert_eq!(client.get("/files/notes.txt").await.status(), ::http::StatusCode::NOT_FOUND);
+
+        routes.routes = config::RoutesConfig { enable_files: true, files_read_only: false, ..Default::default() };
+        let client = testing::TestClient::from_router(router::Router::with_default_routes(&routes));
+        assert_eq!(client.get("/echo/hello").await.status(), ::http::StatusCode::NOT_FOUND);
+        let response = client.send(testing::TestRequest::new(Method::DELETE, "/files/notes.txt")).await;
+        assert!(response.status().is_success(), "{}", response.status());
+        assert!(!dir.path().join("notes.txt").exists());
+
+        assert_eq!(config::RoutesConfig::default().active(), vec!["files (read-only)"]);
+        assert_eq!(config::RoutesConfig { enable_files: false, ..Default::default() }.active(), Vec::<&str>::new());
+    }
+
+    #[tokio::test]
+    async fn test_route_body_and_rate_limits() {
+        use std::time::Duration;
+
+        let mut config = demo_config();
+        config.security.max_request_size = 16;
+        let (server, addr) = spawn_server(config).await;
+        let upload = router::RouteOptions {
+            max_body_size: Some(256),
+            rate_limit: Some((3, Duration::from_secs(60))),
+            ..Default::default()
+        };
+        server.router().replace(vec![
+            router::Route::new_async(
+                Method::POST,
+                "/upload",
+                |request: Request| async move { Ok(Response::ok().with_text(&request.body.map_or(0, |body| body.len()).to_string())) },
+                upload,
+            ),
+            router::Route::new(Method::POST, "/echo/{param}", |request| Ok(Response::ok().with_text(&request.params["param"]))),
+        ]);
+        let post = |path: &str, size: usize| {
+            format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", path, size, "x".repeat(size))
+        };
+
+        let response = send_raw(addr, post("/upload", 100).as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("100"), "{}", response);
+        let response = send_raw(addr, post("/upload", 300).as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        let response = send_raw(addr, post("/echo/small", 100).as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+        for _ in 0..2 {
+            let response = send_raw(addr, post("/upload", 10).as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        }
+        let response = send_raw(addr, post("/upload", 10).as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 429"), "{}", response);
+        let retry_after: u64 = response.split("retry-after: ").nth(1).unwrap().split("\r\n").next().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&retry_after), "{}", response);
+        for _ in 0..5 {
+            let response = send_raw(addr, post("/echo/small", 4).as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("small"), "{}", response);
+        }
+
+        let mut router = router::Router::new();
+        let tight = router::RouteOptions { max_body_size: Some(4), ..Default::default() };
+        router.post_with("/tight", |_| async { Ok(Response::ok()) }, tight);
+        let mut request = Request::new(Method::POST, "/tight".parse().unwrap(), Version::HTTP_11);
+        request.body = Some(bytes::Bytes::from_static(b"12345"));
+        assert!(matches!(router.handle(request.clone()).await, Err(Error::ContentTooLarge { limit: 4, actual: Some(5) })));
+        request.body = Some(bytes::Bytes::from_static(b"1234"));
+        assert!(router.handle(request).await.is_ok());
+
+        let limiter = rate_limit::RateLimiter::memory();
+        let window = Duration::from_millis(50);
+        assert!(limiter.check("a", 1, window).await.is_ok());
+        assert!(matches!(limiter.check("a", 1, window).await, Err(Error::TooManyRequests(1))));
+        assert!(limiter.check("b", 1, window).await.is_ok());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(limiter.check("a", 1, window).await.is_ok());
+    }
+
+    async fn rate_limit_store_contract(store: &dyn rate_limit::RateLimitStore, prefix: &str) {
+        use std::time::Duration;
+
+        let window = Duration::from_millis(300);
+        let key = format!("{}a", prefix);
+        for expected in 1..=3 {
+            let hit = store.hit(&key, window).await.unwrap();
+            assert_eq!(hit.count, expected);
+            assert!(hit.reset_after > Duration::ZERO && hit.reset_after <= window, "{:?}", hit);
+        }
+        assert_eq!(store.hit(&format!("{}b", prefix), window).await.unwrap().count, 1);
+        tokio::time::sleep(window + Duration::from_millis(50)).await;
+        assert_eq!(store.hit(&key, window).await.unwrap().count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_memory_store_contract() {
+        rate_limit_store_contract(&rate_limit::MemoryStore::new(), "").await;
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_memory_store_prunes_by_entry_window() {
+        use rate_limit::RateLimitStore;
+        use std::time::Duration;
+
+        let store = rate_limit::MemoryStore::new();
+        store.hit("long", Duration::from_secs(60)).await.unwrap();
+        for i in 0..10_001 {
+            store.hit(&format!("short-{}", i), Duration::from_millis(50)).await.unwrap();
+        }
+        assert_eq!(store.len(), 10_002);
+
+        // The sweep waits for its interval, then drops only expired windows.
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        store.hit("trigger", Duration::from_millis(50)).await.unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.hit("long", Duration::from_millis(50)).await.unwrap().count, 2);
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore = "needs a Redis server at REDIS_URL (default redis://127.0.0.1:6379)"]
+    async fn test_rate_limit_redis_store_contract() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let store = rate_limit::RedisStore::new(&url, "ratelimit-test:", std::time::Duration::from_secs(1)).unwrap();
+        let prefix = format!("{}:{}:", std::process::id(), fastrand::u64(..));
+        rate_limit_store_contract(&store, &prefix).await;
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_middleware_and_store_failures() {
+        use futures::future::BoxFuture;
+        use std::time::Duration;
+
+        struct Unreachable;
+        impl rate_limit::RateLimitStore for Unreachable {
+            fn hit<'a>(&'a self, _key: &'a str, _window: Duration) -> BoxFuture<'a, Result<rate_limit::Hit>> {
+                Box::pin(async { Err(Error::Upstream("connection refused".to_string())) })
+            }
+        }
+        let window = Duration::from_secs(60);
+        let open = rate_limit::RateLimiter::new(std::sync::Arc::new(Unreachable), true);
+        assert!(open.check("k", 1, window).await.is_ok());
+        let closed = rate_limit::RateLimiter::new(std::sync::Arc::new(Unreachable), false);
+        let error = closed.check("k", 1, window).await.unwrap_err();
+        assert_eq!(error.status_code(), ::http::StatusCode::SERVICE_UNAVAILABLE);
+
+        let mut config = config::Config::default();
+        config.security.rate_limit_requests = 2;
+        let (_server, addr) = spawn_server(config.clone()).await;
+        let get = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        for _ in 0..2 {
+            let response = send_raw(addr, get).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        }
+        let response = send_raw(addr, get).await;
+        assert!(response.starts_with("HTTP/1.1 429") && response.contains("retry-after: "), "{}", response);
+
+        config.security.rate_limit_store.backend = config::RateLimitBackend::Redis;
+        config.security.rate_limit_store.redis_url = "http://cache:6379".to_string();
+        assert!(config.validate().is_err());
+        config.security.rate_limit_store.redis_url = "redis://cache:6379".to_string();
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "redis"));
+        config.security.rate_limit_window = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_csrf_double_submit() {
+        let mut config = config::Config::default();
+        config.security.csrf.enabled = true;
+        config.security.csrf.exempt_paths = vec!["/api".to_string()];
+        let server = server::Server::new(config.clone());
+        let form = |request: Request| {
+            let token = request.extension::<csrf::CsrfToken>().map(csrf::CsrfToken::hidden_input).unwrap_or_default();
+            Ok(Response::ok().with_html(&token))
+        };
+        server.router().replace(vec![
+            router::Route::new(Method::GET, "/form", form),
+            router::Route::new(Method::POST, "/form", |_| Ok(Response::ok().with_text("saved"))),
+            router::Route::new(Method::DELETE, "/form", |_| Ok(Response::ok().with_text("deleted"))),
+            router::Route::new(Method::POST, "/api/hook", |_| Ok(Response::ok().with_text("hooked"))),
+        ]);
+        let client = testing::TestClient::from_server(&server);
+
+        let response = client.get("/form").await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+        let cookie = response.header("set-cookie").unwrap().to_string();
+        assert!(cookie.starts_with("csrf_token=") && cookie.ends_with("; Path=/; SameSite=Lax"), "{}", cookie);
+        let token = cookie["csrf_token=".len()..].split(';').next().unwrap().to_string();
+        assert_eq!(response.text(), format!("<input type=\"hidden\" name=\"csrf_token\" value=\"{}\">", token));
+        let cookie_header = format!("theme=dark; csrf_token={}", token);
+
+        let response = client.send(testing::TestRequest::get("/form").header("cookie", &cookie_header)).await;
+        assert!(response.header("set-cookie").is_none());
+        assert!(response.text().contains(&token));
+
+        let response = client.send(testing::TestRequest::post("/form").header("accept", "application/json")).await;
+        assert_eq!(response.status(), ::http::StatusCode::FORBIDDEN);
+        assert!(response.text().contains("CSRF token missing or invalid"), "{}", response.text());
+        let response = client.send(testing::TestRequest::post("/form").header("accept", "text/html").header("cookie", &cookie_header)).await;
+        assert_eq!(response.status(), ::http::StatusCode::FORBIDDEN);
+        assert_eq!(response.header("content-type"), Some("text/html"));
+        let forged = client
+            .send(testing::TestRequest::post("/form").header("cookie", &cookie_header).header("x-csrf-token", &format!("{}x", token)))
+            .await;
+        assert_eq!(forged.status(), ::http::StatusCode::FORBIDDEN);
+        let response = client
+            .send(testing::TestRequest::post("/form").header("x-csrf-token", &token))
+            .await;
+        assert_eq!(response.status(), ::http::StatusCode::FORBIDDEN);
+
+        let response = client
+            .send(testing::TestRequest::post("/form").header("cookie", &cookie_header).header("x-csrf-token", &token))
+            .await;
+        assert_eq!((response.status(), response.text().as_str()), (::http::StatusCode::OK, "saved"));
+        let response = client
+            .send(testing::TestRequest::post("/form").header("cookie", &cookie_header).form(&format!("name=x&csrf_token={}", token)))
+            .await;
+        assert_eq!((response.status(), response.text().as_str()), (::http::StatusCode::OK, "saved"));
+        let response = client
+            .send(testing::TestRequest::delete("/form").header("cookie", &cookie_header).header("x-csrf-token", &token))
+            .await;
+        assert_eq!(response.status(), ::http::StatusCode::OK);
+
+        let response = client.send(testing::TestRequest::post("/api/hook")).await;
+        assert_eq!((response.status(), response.text().as_str()), (::http::StatusCode::OK, "hooked"));
+        assert!(response.header("set-cookie").is_none());
+
+        config.security.csrf.cookie_name = "bad name".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_signature_validation() {
+        struct RejectMarker;
+        impl upload::UploadValidator for RejectMarker {
+            fn validate(&self, upload: &upload::Upload<'_>) -> Result<()> {
+                match upload.body.windows(5).any(|window| window == b"EICAR") {
+                    true => Err(Error::PermissionDenied(format!("{} failed the scan", upload.path))),
+                    false => Ok(()),
+                }
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = demo_config();
+        config.files.root_dir = dir.path().to_string_lossy().into_owned();
+        config.files.upload_validation.enabled = true;
+        let server = server::Server::new(config.clone());
+        let client = testing::TestClient::from_server(&server);
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        let html = b"<html><script>alert(1)</script></html>".to_vec();
+
+        let response = client.send(testing::TestRequest::put("/files/photo.png").body(html.clone())).await;
+        assert_eq!(response.status(), ::http::StatusCode::UNSUPPORTED_MEDIA_TYPE, "{}", response.text());
+        assert!(!dir.path().join("photo.png").exists());
+        let response = client.send(testing::TestRequest::put("/files/photo.png").body(png.clone())).await;
+        assert_eq!(response.status(), ::http::StatusCode::CREATED);
+        assert_eq!(std::fs::read(dir.path().join("photo.png")).unwrap(), png);
+        let response = client.send(testing::TestRequest::post("/files/posted.png").body(png.clone())).await;
+        assert_eq!(response.status(), ::http::StatusCode::CREATED, "{}", response.text());
+        assert_eq!(std::fs::read(dir.path().join("posted.png")).unwrap(), png);
+        let response = client.send(testing::TestRequest::post("/files/posted.gif").body(html.clone())).await;
+        assert_eq!(response.status(), ::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let declared = testing::TestRequest::put("/files/page.html").header("content-type", "image/png").body(html.clone());
+        assert_eq!(client.send(declared).await.status(), ::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response = client.send(testing::TestRequest::put("/files/page.html").header("content-type", "text/html").body(html.clone())).await;
+        assert_eq!(response.status(), ::http::StatusCode::CREATED);
+
+        let mut body = b"--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"cat.gif\"\r\n\r\n".to_vec();
+        body.extend_from_slice(&html);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+        let upload = testing::TestRequest::post("/files").header("content-type", "multipart/form-data; boundary=XyZ").body(body);
+        assert_eq!(client.send(upload).await.status(), ::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(!dir.path().join("cat.gif").exists());
+
+        server.files().validators.add(RejectMarker);
+        assert_eq!(server.files().validators.len(), 2);
+        let response = client.send(testing::TestRequest::put("/files/notes.txt").body("X5O EICAR test")).await;
+        assert_eq!(response.status(), ::http::StatusCode::FORBIDDEN);
+        assert!(!dir.path().join("notes.txt").exists());
+        let response = client.send(testing::TestRequest::put("/files/notes.txt").body("clean")).await;
+        assert_eq!(response.status(), ::http::StatusCode::CREATED);
+
+        assert_eq!(utils::mime::sniff(b"RIFF\x10\0\0\0WEBPVP8 "), Some("image/webp"));
+        config.files.upload_validation.types.push("text/html".to_string());
+        assert!(config.validate().is_err());
+    }
+}
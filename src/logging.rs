@@ -0,0 +1,163 @@
+use crate::{
+    config::{LogFormat, LogRotation, LoggingConfig},
+    Error, Result,
+};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, EnvFilter};
+
+type FilterReload = Box<dyn Fn(EnvFilter) -> std::result::Result<(), reload::Error> + Send + Sync>;
+
+#[cfg(feature = "otel")]
+type OtelTracer = Option<opentelemetry_sdk::trace::SdkTracer>;
+#[cfg(not(feature = "otel"))]
+type OtelTracer = Option<std::convert::Infallible>;
+
+pub const WIRE_TARGET: &str = "rust_https_server::wire";
+
+static FILTER_RELOAD: OnceLock<(FilterReload, Option<String>)> = OnceLock::new();
+
+pub fn filter_directives(config: &LoggingConfig, level_override: Option<&str>) -> String {
+    let level = level_override.unwrap_or(&config.level);
+    let mut directives = vec![format!("rust_https_server={}", level)];
+
+    let mut targets: Vec<_> = config.targets.iter().collect();
+    targets.sort();
+    directives.extend(targets.into_iter().map(|(target, level)| format!("{}={}", target, level)));
+
+    directives.join(",")
+}
+
+pub fn build_subscriber<W>(
+    config: &LoggingConfig,
+    level_override: Option<&str>,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    build_reloadable_subscriber(config, level_override, writer, ansi, None).0
+}
+
+fn build_reloadable_subscriber<W>(
+    config: &LoggingConfig,
+    level_override: Option<&str>,
+    writer: W,
+    ansi: bool,
+    tracer: OtelTracer,
+) -> (Box<dyn Subscriber + Send + Sync>, FilterReload)
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(filter_directives(config, level_override)))
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match config.format {
+        LogFormat::Text => {
+            let builder = builder.with_filter_reloading();
+            let handle = builder.reload_handle();
+            (with_tracer(builder.finish(), tracer), Box::new(move |filter| handle.reload(filter)))
+        }
+        LogFormat::Json => {
+            let builder = builder.json().with_filter_reloading();
+            let handle = builder.reload_handle();
+            (with_tracer(builder.finish(), tracer), Box::new(move |filter| handle.reload(filter)))
+        }
+    }
+}
+
+fn with_tracer<S>(subscriber: S, tracer: OtelTracer) -> Box<dyn Subscriber + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    #[cfg(feature = "otel")]
+    let subscriber = {
+        use tracing_subscriber::layer::SubscriberExt;
+        subscriber.with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+    };
+    #[cfg(not(feature = "otel"))]
+    let _ = tracer;
+    Box::new(subscriber)
+}
+
+pub fn init(config: &LoggingConfig, level_override: Option<&str>) -> Result<Option<WorkerGuard>> {
+    #[cfg(feature = "otel")]
+    let tracer = crate::otel::tracer();
+    #[cfg(not(feature = "otel"))]
+    let tracer = None;
+
+    let ((subscriber, filter_reload), guard) = match config.output.as_str() {
+        "stderr" => (build_reloadable_subscriber(config, level_override, std::io::stderr, true, tracer), None),
+        "stdout" => (build_reloadable_subscriber(config, level_override, std::io::stdout, true, tracer), None),
+        path => {
+            let appender = file_appender(Path::new(path), config.rotation)?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (build_reloadable_subscriber(config, level_override, writer, false, tracer), Some(guard))
+        }
+    };
+
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| Error::Config(format!("Failed to install logger: {}", e)))?;
+    let _ = FILTER_RELOAD.set((filter_reload, level_override.map(str::to_string)));
+
+    Ok(guard)
+}
+
+pub fn reload(config: &LoggingConfig) -> Result<()> {
+    let Some((filter_reload, level_override)) = FILTER_RELOAD.get() else {
+        return Ok(());
+    };
+    let directives = filter_directives(config, level_override.as_deref());
+    filter_reload(EnvFilter::new(directives)).map_err(|e| Error::Config(format!("Failed to reload log filter: {}", e)))
+}
+
+pub fn hex_dump(bytes: &[u8], omitted: usize) -> String {
+    let mut out = String::with_capacity(bytes.len() * 5);
+    for (index, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(out, "\n{:08x} ", index * 16);
+        for column in 0..16 {
+            match chunk.get(column) {
+                Some(byte) => {
+                    let _ = write!(out, " {:02x}", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        out.push('|');
+    }
+    if omitted > 0 {
+        let _ = write!(out, "\n... {} more bytes", omitted);
+    }
+    out
+}
+
+fn file_appender(path: &Path, rotation: LogRotation) -> Result<RollingFileAppender> {
+    let directory = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::Config(format!("Invalid log file path: {}", path.display())))?;
+
+    let rotation = match rotation {
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+    };
+
+    std::fs::create_dir_all(directory)?;
+    Ok(RollingFileAppender::new(rotation, directory, file_name))
+}
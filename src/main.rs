@@ -1,5 +1,6 @@
-use clap::Parser;
-use rust_https_server::config::Config;
+use clap::{Parser, Subcommand};
+use rust_https_server::config::{self, Config};
+use rust_https_server::logging;
 use rust_https_server::server::Server;
 use std::process;
 use tracing::{error, info};
@@ -8,7 +9,7 @@ use tracing::{error, info};
 #[command(name = "rust-https-server")]
 #[command(about = "An HTTP server implementation in Rust")]
 struct Args {
-    #[arg(short, long, default_value = "127.0.0.1")]
+    #[arg(long, default_value = "127.0.0.1")]
     host: String,
 
     #[arg(short, long, default_value = "4221")]
@@ -17,30 +18,183 @@ struct Args {
     #[arg(short, long)]
     config: Option<String>,
 
-    #[arg(long, default_value = "info")]
-    log_level: String,
+    #[arg(long)]
+    log_level: Option<String>,
+
+    #[arg(long)]
+    dev_tls: bool,
+
+    #[arg(long)]
+    dump_wire: bool,
+
+    #[arg(long, value_name = "DIR")]
+    serve: Option<String>,
+
+    #[arg(long, value_name = "PATH")]
+    pid_file: Option<String>,
+
+    #[cfg(all(unix, feature = "daemon"))]
+    #[arg(long)]
+    daemon: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Routes,
 }
 
-#[tokio::main]
-async fn main() {
+type ReadyHook = Box<dyn FnOnce() + Send>;
+
+fn main() {
     let args = Args::parse();
 
-    tracing_subscriber::fmt()
-        .with_env_filter(format!("rust_https_server={}", args.log_level))
-        .init();
+    let mut config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Some(dir) = &args.serve {
+        if !std::path::Path::new(dir).is_dir() {
+            eprintln!("Not a directory: {}", dir);
+            process::exit(1);
+        }
+        config = config.serve_directory(dir);
+        config.server.host = args.host.clone();
+        config.server.port = args.port;
+        config.server.listen.clear();
+    }
+
+    if args.dev_tls {
+        config.tls.enabled = true;
+        config.tls.self_signed = true;
+    }
+
+    if args.dump_wire {
+        config.logging.dump_wire = true;
+        config.logging.targets.insert(logging::WIRE_TARGET.to_string(), "trace".to_string());
+    }
+
+    let ready_hook = daemonize(&args, &config);
+
+    #[cfg(feature = "otel")]
+    if config.otel.enabled {
+        if let Err(e) = rust_https_server::otel::init(&config.otel) {
+            eprintln!("Failed to initialise OpenTelemetry: {}", e);
+            process::exit(1);
+        }
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start the async runtime: {}", e);
+            process::exit(1);
+        }
+    };
+    runtime.block_on(start(args, config, ready_hook));
+
+    #[cfg(feature = "otel")]
+    if let Err(e) = rust_https_server::otel::shutdown() {
+        eprintln!("{}", e);
+    }
+}
+
+#[cfg(all(unix, feature = "daemon"))]
+fn daemonize(args: &Args, config: &Config) -> Option<ReadyHook> {
+    if !args.daemon {
+        return None;
+    }
+    let log_path = match config.logging.output.as_str() {
+        "stderr" | "stdout" => None,
+        path => Some(std::path::PathBuf::from(path)),
+    };
+    match rust_https_server::daemon::daemonize() {
+        Ok(daemon) => Some(Box::new(move || {
+            if let Err(e) = daemon.ready(log_path.as_deref()) {
+                error!("Failed to detach from the terminal: {}", e);
+            }
+        })),
+        Err(e) => {
+            eprintln!("Failed to daemonize: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "daemon")))]
+fn daemonize(_args: &Args, _config: &Config) -> Option<ReadyHook> {
+    None
+}
+
+async fn start(args: Args, config: Config, ready_hook: Option<ReadyHook>) {
+    let _log_guard = match logging::init(&config.logging, args.log_level.as_deref()) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Failed to initialise logging: {}", e);
+            process::exit(1);
+        }
+    };
 
     info!("Starting Rust HTTP Server");
 
-    let config = match Config::load(args.config.as_deref()) {
-        Ok(config) => config,
+    if let Err(e) = config.validate() {
+        error!("Invalid configuration: {}", e);
+        process::exit(1);
+    }
+
+    if args.serve.is_none() {
+        let active = config.routes.active();
+        match active.is_empty() {
+            true => info!("Built-in routes: none"),
+            false => info!("Built-in routes: {}", active.join(", ")),
+        }
+    }
+
+    let server = match &args.serve {
+        Some(_) => Server::try_new_read_only(config),
+        None => Server::try_new(config),
+    };
+    let server = match server {
+        Ok(server) => match (&args.config, &args.serve) {
+            (Some(path), None) => server.with_config_path(path),
+            _ => server,
+        },
         Err(e) => {
-            error!("Failed to load configuration: {}", e);
+            error!("Failed to initialise server: {}", e);
             process::exit(1);
         }
     };
+    let server = match &args.pid_file {
+        Some(path) => server.with_pid_file(path),
+        None => server,
+    };
+    let server = match ready_hook {
+        Some(hook) => server.on_ready(hook),
+        None => server,
+    };
+
+    if let Some(Command::Routes) = args.command {
+        for route in server.router().routes() {
+            let line = format!("{:<8} {:<32} {}", route.method.as_str(), route.pattern, route.param_names.join(", "));
+            println!("{}", line.trim_end());
+        }
+        return;
+    }
+
+    #[cfg(feature = "otel")]
+    rust_https_server::otel::export_metrics(server.metrics().clone());
+
+    if let Some(dir) = &args.serve {
+        let scheme = if server.config().tls.enabled { "https" } else { "http" };
+        println!("Serving {} at {}://{}/", dir, scheme, config::socket_address(&args.host, args.port));
+    }
 
-    let server = Server::new(config);
-    
     if let Err(e) = server.run().await {
         error!("Server error: {}", e);
         process::exit(1);
@@ -0,0 +1,90 @@
+use crate::{
+    config::MaintenanceConfig,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    utils::Cidr,
+    Result,
+};
+use futures::future::BoxFuture;
+use http::StatusCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const MAINTENANCE_MESSAGE: &str = "The server is down for maintenance. Please try again later.";
+
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+    retry_after: u64,
+    body: Option<String>,
+    content_type: String,
+    allow_paths: Vec<String>,
+    allow_ips: Vec<Cidr>,
+}
+
+impl Maintenance {
+    pub fn from_config(config: &MaintenanceConfig) -> Result<Self> {
+        Ok(Self {
+            enabled: Arc::new(AtomicBool::new(config.enabled)),
+            retry_after: config.retry_after,
+            body: config.body.clone(),
+            content_type: config.content_type.clone(),
+            allow_paths: config.allow_paths.clone(),
+            allow_ips: config.allow_ips.iter().map(|cidr| Cidr::parse(cidr)).collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    fn is_exempt(&self, request: &Request) -> bool {
+        let path = request.path();
+        let path_allowed = self.allow_paths.iter().any(|allowed| {
+            path.strip_prefix(allowed.trim_end_matches('/'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        path_allowed || request.client_ip().is_some_and(|ip| self.allow_ips.iter().any(|cidr| cidr.contains(ip)))
+    }
+
+    pub fn response(&self, request: &Request) -> Response {
+        let status = StatusCode::SERVICE_UNAVAILABLE;
+        let response = match &self.body {
+            Some(body) => Response::new(status).with_content_type(&self.content_type).with_body(body.clone()),
+            None => match request.preferred(&["text/plain", "application/json", "text/html"]) {
+                Some("application/json") => {
+                    let body = serde_json::json!({
+                        "error": {
+                            "code": "service_unavailable",
+                            "message": MAINTENANCE_MESSAGE,
+                            "details": { "retry_after": self.retry_after },
+                            "request_id": request.request_id(),
+                        },
+                    });
+                    Response::new(status).with_json(&body).unwrap_or_else(|_| Response::new(status).with_text(MAINTENANCE_MESSAGE))
+                }
+                Some("text/html") => Response::new(status).with_html(&format!(
+                    "<!DOCTYPE html>\n<html>\n<head><title>Down for maintenance</title></head>\n<body>\n<h1>Down for maintenance</h1>\n<p>{}</p>\n</body>\n</html>",
+                    MAINTENANCE_MESSAGE
+                )),
+                _ => Response::new(status).with_text(MAINTENANCE_MESSAGE),
+            },
+        };
+        response.with_header("retry-after", &self.retry_after.to_string())
+    }
+}
+
+impl Middleware for Maintenance {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            if self.is_enabled() && !self.is_exempt(&request) {
+                return Ok(self.response(&request));
+            }
+            next.run(request).await
+        })
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    config::TracePolicy,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    Error, Result,
+};
+use futures::future::BoxFuture;
+use http::Method;
+use tracing::debug;
+
+const OVERRIDE_HEADER: &str = "x-http-method-override";
+const OVERRIDE_FIELD: &str = "_method";
+const OVERRIDABLE_METHODS: &[Method] = &[Method::PUT, Method::PATCH, Method::DELETE];
+const TRACE_HIDDEN_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+
+#[derive(Debug, Clone, Default)]
+pub struct MethodOverride;
+
+impl MethodOverride {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn apply(&self, request: &mut Request) -> Result<()> {
+        if request.method != Method::POST {
+            return Ok(());
+        }
+
+        let requested = match request.header(OVERRIDE_HEADER) {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .map_err(|_| Error::BadRequest("Invalid X-HTTP-Method-Override header".to_string()))?
+                    .trim()
+                    .to_string(),
+            ),
+            None => request.form().ok().and_then(|mut fields| fields.remove(OVERRIDE_FIELD)),
+        };
+        let Some(requested) = requested else {
+            return Ok(());
+        };
+
+        let method = OVERRIDABLE_METHODS
+            .iter()
+            .find(|method| method.as_str().eq_ignore_ascii_case(&requested))
+            .ok_or_else(|| Error::BadRequest(format!("Method override to {:?} is not allowed", requested)))?;
+        debug!("Overriding POST {} as {}", request.path(), method);
+        request.method = method.clone();
+        Ok(())
+    }
+}
+
+impl Middleware for MethodOverride {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        match self.apply(&mut request) {
+            Ok(()) => next.run(request),
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TraceMethod {
+    policy: TracePolicy,
+}
+
+impl TraceMethod {
+    pub fn new(policy: TracePolicy) -> Self {
+        Self { policy }
+    }
+
+    pub fn echo(request: &Request) -> Response {
+        let target = request.uri.path_and_query().map_or("/", |path| path.as_str());
+        let mut message = format!("{} {} {:?}\r\n", request.method, target, request.version);
+        for (name, value) in &request.headers {
+            if TRACE_HIDDEN_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            message.push_str(name.as_str());
+            message.push_str(": ");
+            message.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            message.push_str("\r\n");
+        }
+        Response::ok().with_content_type("message/http").with_body(message)
+    }
+}
+
+impl Middleware for TraceMethod {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        if request.method != Method::TRACE {
+            return next.run(request);
+        }
+        let result = match self.policy {
+            TracePolicy::Echo => Ok(Self::echo(&request)),
+            TracePolicy::Reject => Err(Error::MethodNotAllowed(request.method.to_string())),
+        };
+        Box::pin(async move { result })
+    }
+}
@@ -0,0 +1,505 @@
+use crate::buffer_pool::BufferPool;
+use crate::budget::MemoryBudget;
+use dashmap::DashMap;
+use http::{Method, StatusCode};
+use serde::Serialize;
+use std::fmt::Write;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const DURATION_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const SIZE_BUCKETS: &[f64] = &[100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0, 10_000_000.0];
+
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+    count: AtomicU64,
+    scale: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64], scale: f64) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+            scale,
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        if let Some(index) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum.fetch_add((value * self.scale) as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = q.clamp(0.0, 1.0) * count as f64;
+        let mut cumulative = 0;
+        let mut lower = 0.0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            let in_bucket = bucket.load(Ordering::Relaxed);
+            if in_bucket > 0 && (cumulative + in_bucket) as f64 >= rank {
+                let fraction = (rank - cumulative as f64) / in_bucket as f64;
+                return Some(lower + (bound - lower) * fraction.max(0.0));
+            }
+            cumulative += in_bucket;
+            lower = *bound;
+        }
+        self.bounds.last().copied()
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        self.render_series(out, name, "");
+    }
+
+    fn render_series(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+        let count = self.count();
+        let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, labels, count);
+        let labels = labels.trim_end_matches(',');
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum.load(Ordering::Relaxed) as f64 / self.scale);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, count);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteLatency {
+    pub method: String,
+    pub route: String,
+    pub count: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    requests: DashMap<(Method, u16), AtomicU64>,
+    request_duration: Histogram,
+    route_duration: DashMap<(Method, String), Histogram>,
+    rewrite_hits: DashMap<String, AtomicU64>,
+    response_size: Histogram,
+    active_connections: AtomicI64,
+    keep_alive_connections: AtomicI64,
+    connections_accepted: AtomicU64,
+    reused_requests: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    parse_errors: AtomicU64,
+    client_disconnects: AtomicU64,
+    file_bytes_served: AtomicU64,
+    file_cache_hits: AtomicU64,
+    file_cache_misses: AtomicU64,
+    mirror_sent: AtomicU64,
+    mirror_failed: AtomicU64,
+    mirror_skipped: AtomicU64,
+    buffered: MemoryBudget,
+    buffers: BufferPool,
+    started: Instant,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            requests: DashMap::new(),
+            request_duration: Histogram::new(DURATION_BUCKETS, 1_000_000.0),
+            route_duration: DashMap::new(),
+            rewrite_hits: DashMap::new(),
+            response_size: Histogram::new(SIZE_BUCKETS, 1.0),
+            active_connections: AtomicI64::new(0),
+            keep_alive_connections: AtomicI64::new(0),
+            connections_accepted: AtomicU64::new(0),
+            reused_requests: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            parse_errors: AtomicU64::new(0),
+            client_disconnects: AtomicU64::new(0),
+            file_bytes_served: AtomicU64::new(0),
+            file_cache_hits: AtomicU64::new(0),
+            file_cache_misses: AtomicU64::new(0),
+            mirror_sent: AtomicU64::new(0),
+            mirror_failed: AtomicU64::new(0),
+            mirror_skipped: AtomicU64::new(0),
+            buffered: MemoryBudget::default(),
+            buffers: BufferPool::default(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn with_buffer_limit(limit: usize) -> Self {
+        Self {
+            buffered: MemoryBudget::new(limit),
+            ..Self::new()
+        }
+    }
+
+    pub fn with_buffer_pool(self, buffers: BufferPool) -> Self {
+        Self { buffers, ..self }
+    }
+
+    pub fn buffer_budget(&self) -> &MemoryBudget {
+        &self.buffered
+    }
+
+    pub fn buffer_pool(&self) -> &BufferPool {
+        &self.buffers
+    }
+
+    pub fn record_request(&self, method: &Method, status: StatusCode, duration: Duration, response_size: usize) {
+        self.requests
+            .entry((method.clone(), status.as_u16()))
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(1, Ordering::Relaxed);
+        self.request_duration.observe(duration.as_secs_f64());
+        self.response_size.observe(response_size as f64);
+    }
+
+    pub fn record_route(&self, method: &Method, route: &str, duration: Duration) {
+        let key = (method.clone(), route.to_string());
+        if let Some(histogram) = self.route_duration.get(&key) {
+            histogram.observe(duration.as_secs_f64());
+            return;
+        }
+        self.route_duration
+            .entry(key)
+            .or_insert_with(|| Histogram::new(DURATION_BUCKETS, 1_000_000.0))
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn route_latencies(&self) -> Vec<RouteLatency> {
+        let to_ms = |histogram: &Histogram, q| histogram.quantile(q).map_or(0.0, |seconds| seconds * 1000.0);
+        let mut latencies: Vec<RouteLatency> = self
+            .route_duration
+            .iter()
+            .map(|entry| RouteLatency {
+                method: entry.key().0.to_string(),
+                route: entry.key().1.clone(),
+                count: entry.value().count(),
+                p50_ms: to_ms(entry.value(), 0.5),
+                p95_ms: to_ms(entry.value(), 0.95),
+                p99_ms: to_ms(entry.value(), 0.99),
+            })
+            .collect();
+        latencies.sort_by(|left, right| (&left.route, &left.method).cmp(&(&right.route, &right.method)));
+        latencies
+    }
+
+    pub fn requests_total(&self, method: &Method, status: StatusCode) -> u64 {
+        self.requests
+            .get(&(method.clone(), status.as_u16()))
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    pub fn request_counts(&self) -> Vec<(Method, u16, u64)> {
+        self.requests
+            .iter()
+            .map(|entry| (entry.key().0.clone(), entry.key().1, entry.value().load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn requests_by_class(&self) -> [u64; 5] {
+        let mut classes = [0; 5];
+        for entry in self.requests.iter() {
+            if let Some(class) = classes.get_mut((entry.key().1 / 100) as usize - 1) {
+                *class += entry.value().load(Ordering::Relaxed);
+            }
+        }
+        classes
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn record_parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_client_disconnect(&self) {
+        self.client_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_file_bytes(&self, bytes: u64) {
+        self.file_bytes_served.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_file_cache(&self, hit: bool) {
+        let counter = if hit { &self.file_cache_hits } else { &self.file_cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn file_cache_hits(&self) -> u64 {
+        self.file_cache_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn file_cache_misses(&self) -> u64 {
+        self.file_cache_misses.load(Ordering::Relaxed)
+    }
+
+    pub fn record_mirror(&self, ok: bool) {
+        let counter = if ok { &self.mirror_sent } else { &self.mirror_failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_mirror_skipped(&self) {
+        self.mirror_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn mirror_counts(&self) -> (u64, u64, u64) {
+        (
+            self.mirror_sent.load(Ordering::Relaxed),
+            self.mirror_failed.load(Ordering::Relaxed),
+            self.mirror_skipped.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn record_rewrite(&self, rule: &str) {
+        if let Some(counter) = self.rewrite_hits.get(rule) {
+            counter.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.rewrite_hits
+            .entry(rule.to_string())
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rewrite_hits(&self, rule: &str) -> u64 {
+        self.rewrite_hits.get(rule).map_or(0, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    pub fn record_reused_request(&self) {
+        self.reused_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bytes_read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_bytes_written(&self, bytes: usize) {
+        self.bytes_written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> ServerStats {
+        let [informational, success, redirection, client_error, server_error] = self.requests_by_class();
+        let total = informational + success + redirection + client_error + server_error;
+        let reused_requests = self.reused_requests.load(Ordering::Relaxed);
+        let (hits, misses) = (self.file_cache_hits(), self.file_cache_misses());
+        let ratio = |part: u64, whole: u64| if whole == 0 { 0.0 } else { part as f64 / whole as f64 };
+        ServerStats {
+            uptime_seconds: self.uptime().as_secs(),
+            connections_accepted: self.connections_accepted.load(Ordering::Relaxed),
+            active_connections: self.active_connections(),
+            requests_by_class: [informational, success, redirection, client_error, server_error],
+            requests_total: total,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            reused_requests,
+            keep_alive_reuse_ratio: ratio(reused_requests, total),
+            file_cache_hits: hits,
+            file_cache_misses: misses,
+            file_cache_hit_rate: ratio(hits, hits + misses),
+        }
+    }
+
+    pub fn connection_opened(&self) -> ConnectionGuard<'_> {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard { gauge: &self.active_connections }
+    }
+
+    pub fn keep_alive_started(&self) -> ConnectionGuard<'_> {
+        self.keep_alive_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard { gauge: &self.keep_alive_connections }
+    }
+
+    pub fn active_connections(&self) -> i64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP http_requests_total Total HTTP requests by method and status.");
+        let _ = writeln!(out, "# TYPE http_requests_total counter");
+        let mut requests: Vec<(String, u16, u64)> = self
+            .requests
+            .iter()
+            .map(|entry| (entry.key().0.to_string(), entry.key().1, entry.value().load(Ordering::Relaxed)))
+            .collect();
+        requests.sort();
+        for (method, status, count) in requests {
+            let _ = writeln!(out, "http_requests_total{{method=\"{}\",status=\"{}\"}} {}", method, status, count);
+        }
+
+        self.request_duration.render(&mut out, "http_request_duration_seconds", "HTTP request latency in seconds.");
+        self.response_size.render(&mut out, "http_response_size_bytes", "HTTP response body size in bytes.");
+
+        let name = "http_route_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Handler latency in seconds by method and route pattern.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut routes: Vec<_> = self.route_duration.iter().collect();
+        routes.sort_by(|left, right| (&left.key().1, left.key().0.as_str()).cmp(&(&right.key().1, right.key().0.as_str())));
+        for entry in routes {
+            let (method, route) = entry.key();
+            let labels = format!("method=\"{}\",route=\"{}\",", method, route.replace('\\', "\\\\").replace('"', "\\\""));
+            entry.value().render_series(&mut out, name, &labels);
+        }
+
+        let _ = writeln!(out, "# HELP http_rewrite_hits_total Requests rewritten by each rewrite rule.");
+        let _ = writeln!(out, "# TYPE http_rewrite_hits_total counter");
+        let mut rewrites: Vec<(String, u64)> = self
+            .rewrite_hits
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().load(Ordering::Relaxed)))
+            .collect();
+        rewrites.sort();
+        for (rule, count) in rewrites {
+            let _ = writeln!(out, "http_rewrite_hits_total{{rule=\"{}\"}} {}", rule.replace('\\', "\\\\").replace('"', "\\\""), count);
+        }
+
+        let gauges = [
+            ("http_active_connections", "Currently open connections.", &self.active_connections),
+            ("http_keep_alive_connections", "Connections idle in keep-alive.", &self.keep_alive_connections),
+        ];
+        for (name, help, gauge) in gauges {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, gauge.load(Ordering::Relaxed));
+        }
+        let _ = writeln!(out, "# HELP http_buffered_bytes Request bytes currently buffered in memory.");
+        let _ = writeln!(out, "# TYPE http_buffered_bytes gauge");
+        let _ = writeln!(out, "http_buffered_bytes {}", self.buffered.used());
+
+        let counters = [
+            ("http_connections_accepted_total", "Connections accepted since startup.", &self.connections_accepted),
+            ("http_reused_requests_total", "Requests served on a kept-alive connection.", &self.reused_requests),
+            ("http_bytes_read_total", "Bytes read from clients.", &self.bytes_read),
+            ("http_bytes_written_total", "Bytes written to clients.", &self.bytes_written),
+            ("http_parse_errors_total", "Requests that could not be parsed.", &self.parse_errors),
+            ("http_client_disconnects_total", "Connections the client closed or reset while a request was in progress.", &self.client_disconnects),
+            ("file_bytes_served_total", "Bytes served by the file handler.", &self.file_bytes_served),
+            ("file_cache_hits_total", "File requests served from the in-memory cache.", &self.file_cache_hits),
+            ("file_cache_misses_total", "File requests that had to read from disk.", &self.file_cache_misses),
+            ("http_mirror_sent_total", "Mirrored requests answered by the shadow upstream.", &self.mirror_sent),
+            ("http_mirror_failed_total", "Mirrored requests that failed or timed out.", &self.mirror_failed),
+            ("http_mirror_skipped_total", "Sampled requests not mirrored because of body size or load.", &self.mirror_skipped),
+        ];
+        for (name, help, counter) in counters.into_iter().chain(self.buffers.counters()) {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerStats {
+    pub uptime_seconds: u64,
+    pub connections_accepted: u64,
+    pub active_connections: i64,
+    pub requests_by_class: [u64; 5],
+    pub requests_total: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub reused_requests: u64,
+    pub keep_alive_reuse_ratio: f64,
+    pub file_cache_hits: u64,
+    pub file_cache_misses: u64,
+    pub file_cache_hit_rate: f64,
+}
+
+pub(crate) struct CountingIo<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> CountingIo<S> {
+    pub(crate) fn new(inner: S, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingIo<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.metrics.record_bytes_read(buf.filled().len() - before);
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingIo<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            self.metrics.record_bytes_written(n);
+        }
+        result
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(n)) = result {
+            self.metrics.record_bytes_written(n);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+pub struct ConnectionGuard<'a> {
+    gauge: &'a AtomicI64,
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.gauge.fetch_sub(1, Ordering::Relaxed);
+    }
+}
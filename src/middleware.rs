@@ -0,0 +1,120 @@
+use crate::{http::{Request, Response}, router::Router, Result};
+use arc_swap::ArcSwap;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>>;
+}
+
+impl Middleware for Arc<dyn Middleware> {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        (**self).handle(request, next)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    router: &'a Router,
+    then: Option<&'a Next<'a>>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a [Arc<dyn Middleware>], router: &'a Router) -> Self {
+        Self { middleware, router, then: None }
+    }
+
+    // Runs `middleware` and then carries on with `then`, for layers that hold
+    // a stack of their own.
+    fn nested(middleware: &'a [Arc<dyn Middleware>], then: &'a Next<'a>) -> Self {
+        Self { middleware, router: then.router, then: Some(then) }
+    }
+
+    pub fn run(self, request: Request) -> BoxFuture<'a, Result<Response>> {
+        match (self.middleware.split_first(), self.then) {
+            (Some((current, rest)), _) => current.handle(request, Next { middleware: rest, ..self }),
+            (None, Some(then)) => then.run(request),
+            (None, None) => Box::pin(self.router.route(request)),
+        }
+    }
+}
+
+// A stack of layers that can be swapped as a whole while the router serves
+// requests. A request runs through the stack it started with. The server
+// keeps the middleware built from its configuration here, so a reload can
+// replace it.
+#[derive(Clone, Default)]
+pub struct Layers {
+    layers: Arc<ArcSwap<Vec<Arc<dyn Middleware>>>>,
+}
+
+impl Layers {
+    pub fn new(layers: Vec<Arc<dyn Middleware>>) -> Self {
+        Self {
+            layers: Arc::new(ArcSwap::from_pointee(layers)),
+        }
+    }
+
+    pub fn replace(&self, layers: Vec<Arc<dyn Middleware>>) {
+        self.layers.store(Arc::new(layers));
+    }
+}
+
+impl Middleware for Layers {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let layers = self.layers.load_full();
+            Next::nested(&layers, &next).run(request).await
+        })
+    }
+}
+
+pub struct Scoped<M> {
+    prefix: String,
+    inner: M,
+}
+
+impl<M> Scoped<M> {
+    pub fn new(prefix: &str, inner: M) -> Self {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            inner,
+        }
+    }
+
+    fn applies_to(&self, path: &str) -> bool {
+        match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+}
+
+impl<M> Middleware for Scoped<M>
+where
+    M: Middleware,
+{
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        if self.applies_to(request.path()) {
+            self.inner.handle(request, next)
+        } else {
+            next.run(request)
+        }
+    }
+}
+
+// Inserts a clone of `T` into every request's extensions, for state that
+// handlers share, such as the server's `HttpClient`.
+#[derive(Debug, Clone)]
+pub struct Extension<T>(pub T);
+
+impl<T> Middleware for Extension<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        request.extensions.insert(self.0.clone());
+        next.run(request)
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    client::{ClientRequest, HttpClient},
+    config::MirrorConfig,
+    http::{Request, Response},
+    metrics::Metrics,
+    middleware::{Middleware, Next},
+    Error, Result,
+};
+use futures::future::BoxFuture;
+use http::header::{self, HeaderName};
+use http::Uri;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+const HOP_BY_HOP: &[HeaderName] = &[
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::TE,
+    header::TRAILER,
+    header::UPGRADE,
+    header::PROXY_AUTHORIZATION,
+    header::CONTENT_LENGTH,
+    header::EXPECT,
+];
+
+// Copies a sample of requests to a shadow upstream. The copy is sent from a
+// spawned task and its response is drained and dropped, so the primary
+// response never waits on the shadow.
+pub struct Mirror {
+    client: HttpClient,
+    upstream: Uri,
+    percentage: f64,
+    paths: Vec<String>,
+    max_body_bytes: usize,
+    max_in_flight: usize,
+    in_flight: Arc<AtomicUsize>,
+    timeout: Duration,
+    metrics: Arc<Metrics>,
+}
+
+impl Mirror {
+    pub fn from_config(config: &MirrorConfig, client: HttpClient, metrics: Arc<Metrics>) -> Result<Self> {
+        let upstream = config.upstream.parse::<Uri>()?;
+        if !matches!(upstream.scheme_str(), Some("http" | "https")) || upstream.authority().is_none() {
+            return Err(Error::Config(format!("mirror.upstream must be an http:// or https:// URL: {:?}", config.upstream)));
+        }
+        Ok(Self {
+            client,
+            upstream,
+            percentage: config.percentage,
+            paths: config.paths.clone(),
+            max_body_bytes: config.max_body_bytes,
+            max_in_flight: config.max_in_flight,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            timeout: Duration::from_millis(config.timeout_ms),
+            metrics,
+        })
+    }
+
+    fn wants(&self, request: &Request) -> bool {
+        let path = request.path();
+        let matches = self.paths.iter().any(|prefix| {
+            path.strip_prefix(prefix.trim_end_matches('/'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        matches && fastrand::f64() * 100.0 < self.percentage
+    }
+
+    fn shadow_request(&self, request: &Request) -> Option<ClientRequest> {
+        let authority = self.upstream.authority()?;
+        let base = self.upstream.path().trim_end_matches('/');
+        let path_and_query = request.uri.path_and_query().map_or("/", |path| path.as_str());
+        let url = format!("{}://{}{}{}", self.upstream.scheme_str()?, authority, base, path_and_query);
+
+        let mut headers = request.headers.clone();
+        for name in HOP_BY_HOP {
+            headers.remove(name);
+        }
+        headers.insert(header::HOST, authority.as_str().parse().ok()?);
+        Some(
+            self.client
+                .request(request.method.clone(), &url)
+                .headers(headers)
+                .body(request.body.clone().unwrap_or_default())
+                .timeout(self.timeout),
+        )
+    }
+
+    fn send(&self, request: &Request) {
+        if request.body.as_ref().is_some_and(|body| body.len() > self.max_body_bytes) {
+            self.metrics.record_mirror_skipped();
+            return;
+        }
+        let Some(slot) = InFlight::acquire(&self.in_flight, self.max_in_flight) else {
+            self.metrics.record_mirror_skipped();
+            return;
+        };
+        let Some(shadow) = self.shadow_request(request) else {
+            self.metrics.record_mirror(false);
+            return;
+        };
+
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let _slot = slot;
+            let result = shadow.send().await;
+            if let Err(e) = &result {
+                debug!("Mirrored request failed: {}", e);
+            }
+            metrics.record_mirror(result.is_ok());
+        });
+    }
+}
+
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn acquire(counter: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        let slot = Self(counter.clone());
+        (counter.fetch_add(1, Ordering::AcqRel) < max).then_some(slot)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Middleware for Mirror {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        if self.wants(&request) {
+            self.send(&request);
+        }
+        next.run(request)
+    }
+}
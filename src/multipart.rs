@@ -0,0 +1,225 @@
+use crate::{Error, Result};
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Part {
+    pub fn bytes(&self) -> &Bytes {
+        &self.body
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+
+    pub fn reader(&self) -> impl std::io::Read {
+        self.body.clone().reader()
+    }
+}
+
+#[derive(Debug)]
+pub struct Multipart {
+    body: Bytes,
+    delimiter: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl Multipart {
+    pub fn new(body: Bytes, boundary: &str) -> Result<Self> {
+        if boundary.is_empty() || boundary.len() > 70 {
+            return Err(Error::BadRequest("Invalid multipart boundary".to_string()));
+        }
+
+        let mut multipart = Self {
+            body,
+            delimiter: format!("--{}", boundary).into_bytes(),
+            position: 0,
+            finished: false,
+        };
+        multipart.skip_preamble()?;
+        Ok(multipart)
+    }
+
+    pub fn from_content_type(body: Bytes, content_type: &str) -> Result<Self> {
+        let (media_type, params) = parse_header_params(content_type);
+        if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+            return Err(Error::UnsupportedEncoding(media_type));
+        }
+        let boundary = params
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+            .map(|(_, value)| value)
+            .ok_or_else(|| Error::BadRequest("Missing multipart boundary".to_string()))?;
+        Self::new(body, &boundary)
+    }
+
+    fn skip_preamble(&mut self) -> Result<()> {
+        let start = if self.body.starts_with(&self.delimiter) {
+            0
+        } else {
+            let mut needle = b"\r\n".to_vec();
+            needle.extend_from_slice(&self.delimiter);
+            find(&self.body, &needle, 0).map(|index| index + 2).ok_or_else(missing_boundary)?
+        };
+        self.position = start + self.delimiter.len();
+        self.after_delimiter()
+    }
+
+    fn after_delimiter(&mut self) -> Result<()> {
+        let rest = &self.body[self.position..];
+        if rest.starts_with(b"--") {
+            self.finished = true;
+            return Ok(());
+        }
+
+        let padding = rest.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        if !rest[padding..].starts_with(b"\r\n") {
+            return Err(Error::BadRequest("Malformed multipart delimiter".to_string()));
+        }
+        self.position += padding + 2;
+        Ok(())
+    }
+
+    fn next_part(&mut self) -> Result<Part> {
+        let head_end = if self.body[self.position..].starts_with(b"\r\n") {
+            (self.position, self.position + 2)
+        } else {
+            find(&self.body, b"\r\n\r\n", self.position)
+                .map(|index| (index, index + 4))
+                .ok_or_else(|| Error::BadRequest("Malformed multipart headers".to_string()))?
+        };
+        let headers = parse_part_headers(&self.body[self.position..head_end.0])?;
+
+        let mut needle = b"\r\n".to_vec();
+        needle.extend_from_slice(&self.delimiter);
+        let body_end = find(&self.body, &needle, head_end.1).ok_or_else(missing_boundary)?;
+        let body = self.body.slice(head_end.1..body_end);
+
+        self.position = body_end + needle.len();
+        self.after_delimiter()?;
+
+        let disposition = headers
+            .get("content-disposition")
+            .and_then(|value| value.to_str().ok())
+            .map(parse_header_params);
+        let param = |key: &str| {
+            disposition
+                .as_ref()
+                .and_then(|(_, params)| params.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)))
+                .map(|(_, value)| value.clone())
+        };
+
+        Ok(Part {
+            name: param("name"),
+            filename: param("filename"),
+            content_type: headers.get("content-type").and_then(|value| value.to_str().ok()).map(str::to_string),
+            headers,
+            body,
+        })
+    }
+}
+
+impl Iterator for Multipart {
+    type Item = Result<Part>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let part = self.next_part();
+        if part.is_err() {
+            self.finished = true;
+        }
+        Some(part)
+    }
+}
+
+pub fn parse_header_params(value: &str) -> (String, Vec<(String, String)>) {
+    let mut segments = split_unquoted(value, ';').into_iter();
+    let primary = segments.next().unwrap_or_default().trim().to_string();
+    let params = segments
+        .filter_map(|segment| {
+            let (name, value) = segment.split_once('=')?;
+            Some((name.trim().to_string(), unquote(value.trim())))
+        })
+        .collect();
+    (primary, params)
+}
+
+fn split_unquoted(value: &str, separator: char) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if c == separator && !in_quotes => {
+                segments.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    segments.push(current);
+    segments
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                unquoted.push(next);
+            }
+        } else {
+            unquoted.push(c);
+        }
+    }
+    unquoted
+}
+
+fn parse_part_headers(head: &[u8]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let text = std::str::from_utf8(head).map_err(|_| Error::BadRequest("Invalid multipart headers".to_string()))?;
+    for line in text.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::BadRequest(format!("Invalid multipart header: {}", line)))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| Error::BadRequest(format!("Invalid multipart header name: {}", name)))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| Error::BadRequest("Invalid multipart header value".to_string()))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| from + index)
+}
+
+fn missing_boundary() -> Error {
+    Error::BadRequest("Missing multipart boundary".to_string())
+}
\ No newline at end of file
@@ -0,0 +1,172 @@
+use crate::{config::OtelConfig, http::Request, metrics::Metrics, Error, Result};
+use http::{HeaderMap, StatusCode};
+use opentelemetry::metrics::MeterProvider as _;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{Status, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider, SpanExporter};
+use opentelemetry_sdk::Resource;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const INSTRUMENTATION_NAME: &str = "rust-https-server";
+
+static TELEMETRY: OnceLock<Telemetry> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: Option<SdkMeterProvider>,
+}
+
+impl Telemetry {
+    pub fn from_config(config: &OtelConfig) -> Result<Self> {
+        let endpoint = config.endpoint.trim_end_matches('/');
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint))
+            .build()
+            .map_err(|e| Error::Config(format!("Failed to build the OTLP span exporter: {}", e)))?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Self::resource(&config.service_name))
+            .build();
+
+        let meter_provider = match config.export_metrics {
+            true => {
+                let exporter = opentelemetry_otlp::MetricExporter::builder()
+                    .with_http()
+                    .with_endpoint(format!("{}/v1/metrics", endpoint))
+                    .build()
+                    .map_err(|e| Error::Config(format!("Failed to build the OTLP metric exporter: {}", e)))?;
+                let reader = PeriodicReader::builder(exporter)
+                    .with_interval(Duration::from_secs(config.metrics_interval))
+                    .build();
+                Some(
+                    SdkMeterProvider::builder()
+                        .with_reader(reader)
+                        .with_resource(Self::resource(&config.service_name))
+                        .build(),
+                )
+            }
+            false => None,
+        };
+
+        Ok(Self { tracer_provider, meter_provider })
+    }
+
+    pub fn with_exporter<E>(service_name: &str, exporter: E) -> Self
+    where
+        E: SpanExporter + 'static,
+    {
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter)
+            .with_resource(Self::resource(service_name))
+            .build();
+        Self { tracer_provider, meter_provider: None }
+    }
+
+    fn resource(service_name: &str) -> Resource {
+        Resource::builder().with_service_name(service_name.to_string()).build()
+    }
+
+    pub fn tracer(&self) -> SdkTracer {
+        self.tracer_provider.tracer(INSTRUMENTATION_NAME)
+    }
+
+    pub fn export_metrics(&self, metrics: Arc<Metrics>) {
+        let Some(meter_provider) = &self.meter_provider else {
+            return;
+        };
+        let meter = meter_provider.meter(INSTRUMENTATION_NAME);
+
+        let requests = metrics.clone();
+        meter
+            .u64_observable_counter("http.server.requests")
+            .with_description("Total HTTP requests by method and status.")
+            .with_callback(move |observer| {
+                for (method, status, count) in requests.request_counts() {
+                    let attributes = [KeyValue::new("http.method", method.to_string()), KeyValue::new("http.status_code", status as i64)];
+                    observer.observe(count, &attributes);
+                }
+            })
+            .build();
+
+        meter
+            .i64_observable_gauge("http.server.active_connections")
+            .with_description("Currently open connections.")
+            .with_callback(move |observer| observer.observe(metrics.active_connections(), &[]))
+            .build();
+    }
+
+    pub fn shutdown(&self) -> Result<()> {
+        self.tracer_provider
+            .shutdown()
+            .map_err(|e| Error::Internal(format!("Failed to flush traces: {}", e)))?;
+        if let Some(meter_provider) = &self.meter_provider {
+            meter_provider
+                .shutdown()
+                .map_err(|e| Error::Internal(format!("Failed to flush metrics: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn init(config: &OtelConfig) -> Result<()> {
+    let telemetry = Telemetry::from_config(config)?;
+    TELEMETRY
+        .set(telemetry)
+        .map_err(|_| Error::Config("OpenTelemetry is already initialised".to_string()))
+}
+
+pub fn tracer() -> Option<SdkTracer> {
+    TELEMETRY.get().map(Telemetry::tracer)
+}
+
+pub fn export_metrics(metrics: Arc<Metrics>) {
+    if let Some(telemetry) = TELEMETRY.get() {
+        telemetry.export_metrics(metrics);
+    }
+}
+
+pub fn shutdown() -> Result<()> {
+    match TELEMETRY.get() {
+        Some(telemetry) => telemetry.shutdown(),
+        None => Ok(()),
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+pub(crate) fn start_request(span: &Span, request: &Request) {
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(&request.headers));
+    let _ = span.set_parent(parent);
+    span.set_attribute("http.method", request.method.to_string());
+    span.set_attribute("url.path", request.path().to_string());
+}
+
+pub(crate) fn record_route(span: &Span, pattern: &str) {
+    span.set_attribute("http.route", pattern.to_string());
+}
+
+pub(crate) fn finish_request(span: &Span, status: StatusCode) {
+    span.set_attribute("http.status_code", status.as_u16() as i64);
+    if status.is_server_error() {
+        span.set_status(Status::error(status.to_string()));
+    }
+}
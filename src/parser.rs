@@ -0,0 +1,450 @@
+use crate::{
+    config::{self, Config},
+    http::Request,
+    redact::Redactor,
+    Error, Result,
+};
+use bytes::BytesMut;
+use http::{HeaderMap, HeaderValue, Method, Uri, Version};
+
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub strict: bool,
+    pub max_request_line: usize,
+    pub max_header_line: usize,
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    pub redactor: Redactor,
+}
+
+impl ParseOptions {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            strict: config.server.strict_parsing,
+            max_request_line: config.security.max_request_line,
+            max_header_line: config.security.max_header_line,
+            max_header_bytes: config.security.max_header_bytes,
+            max_headers: config.security.max_headers,
+            redactor: Redactor::from_config(&config.logging),
+        }
+    }
+
+    // Checks the lines that completed since the last call, plus the length of
+    // the partial line at the end, and returns where the head ends once the
+    // blank line after it has arrived. The search for line ends resumes from
+    // where the previous call stopped, so a trickled head is only read once.
+    fn scan_head(&self, buffer: &[u8], scan: &mut HeadScan) -> Result<Option<usize>> {
+        while let Some(newline) = buffer[scan.offset..].iter().position(|&b| b == b'\n') {
+            let end = scan.offset + newline;
+            let line = &buffer[scan.line_start..end];
+            scan.line_start = end + 1;
+            scan.offset = end + 1;
+            let crlf = line.last() == Some(&b'\r');
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if scan.request_line_end.is_none() {
+                scan.request_line_end = Some(end + 1);
+                self.check_request_line(line)?;
+            } else if line.is_empty() && crlf && scan.crlf {
+                self.check_header_bytes(end + 1, scan)?;
+                return Ok(Some(end + 1));
+            } else {
+                self.check_header_line(line, scan.headers)?;
+                scan.headers += usize::from(!line.is_empty());
+            }
+            scan.crlf = crlf;
+            self.check_header_bytes(scan.line_start, scan)?;
+        }
+        scan.offset = buffer.len();
+
+        let partial = &buffer[scan.line_start..];
+        let partial = partial.strip_suffix(b"\r").unwrap_or(partial);
+        if scan.request_line_end.is_none() {
+            self.check_request_line(partial)?;
+        } else {
+            self.check_header_line(partial, scan.headers)?;
+            self.check_header_bytes(buffer.len(), scan)?;
+        }
+        Ok(None)
+    }
+
+    fn check_request_line(&self, line: &[u8]) -> Result<()> {
+        if line.len() > self.max_request_line {
+            return Err(Error::UriTooLong(self.max_request_line));
+        }
+        Ok(())
+    }
+
+    fn check_header_line(&self, line: &[u8], headers: usize) -> Result<()> {
+        if line.len() > self.max_header_line {
+            return Err(Error::HeaderFieldsTooLarge(format!("header line exceeds {} bytes", self.max_header_line)));
+        }
+        if !line.is_empty() && headers >= self.max_headers {
+            return Err(Error::HeaderFieldsTooLarge(format!("more than {} headers", self.max_headers)));
+        }
+        Ok(())
+    }
+
+    fn check_header_bytes(&self, end: usize, scan: &HeadScan) -> Result<()> {
+        let header_bytes = end.saturating_sub(scan.request_line_end.unwrap_or(end));
+        if header_bytes > self.max_header_bytes {
+            return Err(Error::HeaderFieldsTooLarge(format!("headers exceed {} bytes", self.max_header_bytes)));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            strict: true,
+            max_request_line: config::default_max_request_line(),
+            max_header_line: config::default_max_header_line(),
+            max_header_bytes: config::default_max_header_bytes(),
+            max_headers: config::default_max_headers(),
+            redactor: Redactor::default(),
+        }
+    }
+}
+
+// Carried between calls while a request arrives in pieces. The header scan
+// resumes where the last call stopped, the head is parsed once when it is
+// complete and kept here until the body is in, and a chunked body is decoded
+// as its chunks complete. `body_limit` caps the decoded size of a chunked body;
+// the server sets it once the head is known.
+#[derive(Debug, Clone, Default)]
+pub struct ParseState {
+    pub body_limit: Option<usize>,
+    scan: HeadScan,
+    head: Option<ParsedHead>,
+    chunked: ChunkedProgress,
+}
+
+impl ParseState {
+    // The head of a request whose body is still arriving.
+    pub fn pending_head(&self) -> Option<&Request> {
+        self.head.as_ref().map(|head| &head.request)
+    }
+}
+
+// `offset` is where the search for the next line end resumes and `line_start`
+// where the current line began; `crlf` says whether the last complete line
+// ended in CRLF, since only a CRLF CRLF pair ends the head.
+#[derive(Debug, Clone, Copy, Default)]
+struct HeadScan {
+    offset: usize,
+    line_start: usize,
+    request_line_end: Option<usize>,
+    headers: usize,
+    crlf: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ParsedHead {
+    request: Request,
+    framing: BodyFraming,
+    end: usize,
+}
+
+// The buffer holds the head, then `decoded` bytes of body, then raw chunk data
+// not yet decoded.
+#[derive(Debug, Clone, Copy, Default)]
+struct ChunkedProgress {
+    decoded: usize,
+    last_chunk: bool,
+    trailer_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFraming {
+    None,
+    Length(usize),
+    Chunked,
+}
+
+const SUPPORTED_METHODS: &[Method] = &[
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::OPTIONS,
+    Method::PATCH,
+    Method::TRACE,
+    Method::CONNECT,
+];
+
+pub const WEBDAV_METHODS: &[&str] = &["MOVE", "COPY", "PROPFIND"];
+
+fn is_http_version(version: &str) -> bool {
+    match version.strip_prefix("HTTP/").map(str::as_bytes) {
+        Some([major, b'.', minor]) => major.is_ascii_digit() && minor.is_ascii_digit(),
+        Some([major]) => major.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+pub(crate) fn preview_line(line: &[u8]) -> String {
+    const PREVIEW_LEN: usize = 64;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let mut preview = line[..line.len().min(PREVIEW_LEN)].escape_ascii().to_string();
+    if line.len() > PREVIEW_LEN {
+        preview.push_str("...");
+    }
+    preview
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+pub fn parse_request(buffer: &mut BytesMut, state: &mut ParseState) -> Result<Option<Request>> {
+    parse_request_with(buffer, state, &ParseOptions::default())
+}
+
+pub fn parse_request_with(buffer: &mut BytesMut, state: &mut ParseState, options: &ParseOptions) -> Result<Option<Request>> {
+    let (head_end, framing) = match &state.head {
+        Some(head) => (head.end, head.framing),
+        None => {
+            let Some(head_end) = options.scan_head(buffer, &mut state.scan)? else {
+                return Ok(None);
+            };
+            let (request, framing) = parse_head(&buffer[..head_end], options)?;
+            state.head = Some(ParsedHead { request, framing, end: head_end });
+            (head_end, framing)
+        }
+    };
+
+    let body = match framing {
+        BodyFraming::None => {
+            let _ = buffer.split_to(head_end);
+            None
+        }
+        BodyFraming::Length(length) => {
+            if buffer.len() - head_end < length {
+                return Ok(None);
+            }
+            let _ = buffer.split_to(head_end);
+            Some(buffer.split_to(length).freeze())
+        }
+        BodyFraming::Chunked => {
+            if !decode_chunked(buffer, head_end, state, options)? {
+                return Ok(None);
+            }
+            let _ = buffer.split_to(head_end);
+            Some(buffer.split_to(state.chunked.decoded).freeze())
+        }
+    };
+    let mut request = std::mem::take(state).head.expect("head is parsed before the body").request;
+    request.body = body;
+
+    Ok(Some(request))
+}
+
+fn parse_head(head: &[u8], options: &ParseOptions) -> Result<(Request, BodyFraming)> {
+    let mut lines = head.split(|&b| b == b'\n');
+    
+    let request_line = lines.next().ok_or_else(|| Error::Parse("No request line".to_string()))?;
+    let request_line = std::str::from_utf8(request_line)
+        .map_err(|_| Error::Parse(format!("Invalid UTF-8 in request line: {}", preview_line(&options.redactor.request_line(request_line)))))?;
+    let request_line = request_line.trim_end_matches('\r');
+    
+    let parts: Vec<&str> = request_line.split_whitespace().collect();
+    if let [method, target] = parts[..] {
+        if method.bytes().all(is_token_byte) && target.starts_with('/') {
+            return Err(Error::VersionNotSupported("HTTP/0.9".to_string()));
+        }
+    }
+    if parts.len() != 3 {
+        return Err(Error::Parse(format!("Invalid request line: {}", preview_line(&options.redactor.request_line(request_line.as_bytes())))));
+    }
+    
+    if !parts[0].bytes().all(is_token_byte) {
+        return Err(Error::Parse(format!("Invalid method: {}", preview_line(parts[0].as_bytes()))));
+    }
+    let uri = parts[1]
+        .parse::<Uri>()
+        .map_err(|_| Error::Parse(format!("Invalid request target: {}", preview_line(options.redactor.target(parts[1]).as_bytes()))))?;
+    let version = match parts[2] {
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/1.1" => Version::HTTP_11,
+        version if is_http_version(version) => return Err(Error::VersionNotSupported(version.to_string())),
+        version => return Err(Error::Parse(format!("Invalid HTTP version: {}", preview_line(version.as_bytes())))),
+    };
+    let method = match parts[0].parse::<Method>() {
+        Ok(method) if SUPPORTED_METHODS.contains(&method) || WEBDAV_METHODS.contains(&method.as_str()) => method,
+        _ => return Err(Error::NotImplemented(format!("method {}", parts[0]))),
+    };
+    
+    let mut request = Request::new(method, uri, version);
+    let mut headers = HeaderMap::new();
+    
+    for line in lines {
+        let line = std::str::from_utf8(line)
+            .map_err(|_| Error::Parse(format!("Invalid UTF-8 in header line: {}", preview_line(&options.redactor.header_line(line)))))?;
+        let line = line.trim_end_matches('\r');
+        
+        if line.is_empty() {
+            break;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            if options.strict {
+                return Err(Error::BadRequest("Obsolete header line folding".to_string()));
+            }
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::BadRequest(format!("Malformed header line: {}", line)))?;
+        let name = match name.trim_end_matches([' ', '\t']) {
+            trimmed if trimmed.len() != name.len() && options.strict => {
+                return Err(Error::BadRequest(format!("Whitespace before colon in header: {}", trimmed)));
+            }
+            trimmed => trimmed,
+        };
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(Error::BadRequest(format!("Invalid header name: {:?}", name)));
+        }
+
+        let name = name.to_lowercase();
+        let value = value.trim();
+        
+        if let Ok(header_value) = HeaderValue::from_str(value) {
+            if let Ok(header_name) = http::header::HeaderName::from_lowercase(name.as_bytes()) {
+                headers.append(header_name, header_value);
+            }
+        } else if name == "content-length" || name == "transfer-encoding" {
+            return Err(Error::BadRequest(format!("Invalid {} header", name)));
+        }
+    }
+
+    let framing = body_framing(&headers, version, options)?;
+    request.headers = headers;
+    Ok((request, framing))
+}
+
+fn body_framing(headers: &HeaderMap, version: Version, options: &ParseOptions) -> Result<BodyFraming> {
+    let has_length = headers.contains_key(http::header::CONTENT_LENGTH);
+
+    if headers.contains_key(http::header::TRANSFER_ENCODING) {
+        if has_length {
+            return Err(Error::BadRequest("Both Transfer-Encoding and Content-Length present".to_string()));
+        }
+        if version == Version::HTTP_10 {
+            return Err(Error::BadRequest("Transfer-Encoding is not allowed in HTTP/1.0 requests".to_string()));
+        }
+
+        let mut codings = Vec::new();
+        for value in headers.get_all(http::header::TRANSFER_ENCODING) {
+            let value = value.to_str().map_err(|_| Error::BadRequest("Invalid Transfer-Encoding header".to_string()))?;
+            codings.extend(value.split(',').map(str::trim).filter(|coding| !coding.is_empty()));
+        }
+        return match codings.as_slice() {
+            [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(BodyFraming::Chunked),
+            _ => Err(Error::BadRequest(format!("Unsupported transfer coding: {}", codings.join(", ")))),
+        };
+    }
+
+    if !has_length {
+        return Ok(BodyFraming::None);
+    }
+
+    let mut length = None;
+    for value in headers.get_all(http::header::CONTENT_LENGTH) {
+        let value = value.to_str().map_err(|_| Error::BadRequest("Invalid Content-Length header".to_string()))?;
+        for item in value.split(',').map(str::trim) {
+            if item.is_empty() || !item.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(Error::BadRequest(format!("Invalid Content-Length: {:?}", item)));
+            }
+            let parsed = item
+                .parse::<usize>()
+                .map_err(|_| Error::BadRequest(format!("Content-Length out of range: {}", item)))?;
+            match length {
+                None => length = Some(parsed),
+                Some(existing) if existing != parsed => {
+                    return Err(Error::BadRequest("Conflicting Content-Length values".to_string()));
+                }
+                Some(_) if options.strict => {
+                    return Err(Error::BadRequest("Repeated Content-Length values".to_string()));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    Ok(length.map_or(BodyFraming::None, BodyFraming::Length))
+}
+
+// Decodes whatever complete chunks follow the body decoded so far. Chunk data
+// is copied down to a write cursor while a read cursor steps over the framing,
+// and the undecoded tail is moved down once at the end, so each call costs
+// about the bytes it consumes. Returns true once the last chunk and trailers
+// are in.
+fn decode_chunked(buffer: &mut BytesMut, head_end: usize, state: &mut ParseState, options: &ParseOptions) -> Result<bool> {
+    let progress = &mut state.chunked;
+    let mut write = head_end + progress.decoded;
+    let mut read = write;
+    let complete = loop {
+        let Some(line_end) = buffer[read..].windows(2).position(|window| window == b"\r\n") else {
+            break false;
+        };
+
+        if progress.last_chunk {
+            progress.trailer_bytes += line_end + 2;
+            if progress.trailer_bytes > options.max_header_bytes {
+                return Err(Error::HeaderFieldsTooLarge(format!("trailers exceed {} bytes", options.max_header_bytes)));
+            }
+            read += line_end + 2;
+            if line_end == 0 {
+                break true;
+            }
+            continue;
+        }
+
+        let line = &buffer[read..read + line_end];
+        let size = line.split(|&b| b == b';').next().unwrap_or_default();
+        if size.is_empty() || size.len() > 16 || !size.iter().all(u8::is_ascii_hexdigit) {
+            return Err(Error::BadRequest("Invalid chunk size".to_string()));
+        }
+        let size = std::str::from_utf8(size)
+            .ok()
+            .and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or_else(|| Error::BadRequest("Invalid chunk size".to_string()))?;
+        let total = progress
+            .decoded
+            .checked_add(size)
+            .ok_or_else(|| Error::BadRequest("Invalid chunk size".to_string()))?;
+        if let Some(limit) = state.body_limit.filter(|&limit| total > limit) {
+            return Err(Error::ContentTooLarge { limit, actual: None });
+        }
+
+        if size == 0 {
+            read += line_end + 2;
+            progress.last_chunk = true;
+            continue;
+        }
+
+        let data_start = read + line_end + 2;
+        let chunk_end = data_start
+            .checked_add(size)
+            .filter(|end| end.checked_add(2).is_some())
+            .ok_or_else(|| Error::BadRequest("Invalid chunk size".to_string()))?;
+        if buffer.len() < chunk_end + 2 {
+            break false;
+        }
+        if &buffer[chunk_end..chunk_end + 2] != b"\r\n" {
+            return Err(Error::BadRequest("Missing CRLF after chunk data".to_string()));
+        }
+        buffer.copy_within(data_start..chunk_end, write);
+        write += size;
+        read = chunk_end + 2;
+        progress.decoded = total;
+    };
+
+    if read > write {
+        let end = buffer.len();
+        buffer.copy_within(read..end, write);
+        buffer.truncate(end - (read - write));
+    }
+    Ok(complete)
+}
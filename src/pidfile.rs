@@ -0,0 +1,67 @@
+use crate::{Error, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        match read_pid(&path)? {
+            Some(pid) if process_alive(pid) => {
+                return Err(Error::Config(format!(
+                    "PID file {} belongs to running process {}",
+                    path.display(),
+                    pid
+                )));
+            }
+            Some(pid) => warn!("Replacing stale PID file {} left by process {}", path.display(), pid),
+            None => {}
+        }
+
+        fs::write(&path, format!("{}\n", std::process::id()))?;
+        info!("Wrote PID file {}", path.display());
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if read_pid(&self.path).ok().flatten() == Some(std::process::id()) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.trim().parse().ok().filter(|pid| *pid > 0)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+pub fn process_alive(pid: u32) -> bool {
+    pid == std::process::id()
+}
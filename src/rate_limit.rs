@@ -0,0 +1,273 @@
+use crate::{
+    config::{RateLimitBackend, RateLimitStoreConfig},
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    Error, Result,
+};
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const PRUNE_THRESHOLD: usize = 10_000;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    pub count: u64,
+    pub reset_after: Duration,
+}
+
+// Counts requests per key in fixed windows. `hit` increments the key's
+// counter, starting a window of `window` when the key is new or its last
+// window has expired, and returns the count so far and the time left.
+pub trait RateLimitStore: Send + Sync + 'static {
+    fn hit<'a>(&'a self, key: &'a str, window: Duration) -> BoxFuture<'a, Result<Hit>>;
+}
+
+// Each window keeps its own expiry, since routes with different windows share
+// the store.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    expires: Instant,
+    count: u64,
+}
+
+// Expired windows are swept once the map grows past `PRUNE_THRESHOLD`, at most
+// once per `PRUNE_INTERVAL`, so a large map is not walked on every hit.
+#[derive(Debug)]
+pub struct MemoryStore {
+    windows: DashMap<String, Window>,
+    last_prune: parking_lot::Mutex<Instant>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            windows: DashMap::new(),
+            last_prune: parking_lot::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn hit_now(&self, key: &str, window: Duration) -> Hit {
+        let now = Instant::now();
+        if self.windows.len() > PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+
+        let mut entry = self.windows.entry(key.to_string()).or_insert(Window { expires: now + window, count: 0 });
+        if now >= entry.expires {
+            *entry = Window { expires: now + window, count: 0 };
+        }
+        entry.count += 1;
+        Hit {
+            count: entry.count,
+            reset_after: entry.expires.saturating_duration_since(now),
+        }
+    }
+
+    fn prune(&self, now: Instant) {
+        let Some(mut last_prune) = self.last_prune.try_lock() else {
+            return;
+        };
+        if now.duration_since(*last_prune) < PRUNE_INTERVAL {
+            return;
+        }
+        *last_prune = now;
+        self.windows.retain(|_, entry| entry.expires > now);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.windows.len()
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn hit<'a>(&'a self, key: &'a str, window: Duration) -> BoxFuture<'a, Result<Hit>> {
+        Box::pin(async move { Ok(self.hit_now(key, window)) })
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_store::RedisStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::{Hit, RateLimitStore};
+    use crate::{Error, Result};
+    use futures::future::BoxFuture;
+    use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+    use std::time::Duration;
+    use tokio::sync::OnceCell;
+
+    // INCR and PEXPIRE in one round trip; the expiry is also restored if a
+    // key somehow lost its TTL, so a counter can never stick forever.
+    const HIT_SCRIPT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+local ttl = redis.call('PTTL', KEYS[1])
+if count == 1 or ttl < 0 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[1])
+    ttl = tonumber(ARGV[1])
+end
+return {count, ttl}
+";
+
+    // The connection is opened on first use, so a Redis outage at startup
+    // is handled by the fail-open/fail-closed policy like any later one.
+    pub struct RedisStore {
+        client: redis::Client,
+        connection: OnceCell<ConnectionManager>,
+        script: redis::Script,
+        prefix: String,
+        timeout: Duration,
+    }
+
+    impl RedisStore {
+        pub fn new(url: &str, prefix: &str, timeout: Duration) -> Result<Self> {
+            let client = redis::Client::open(url).map_err(|e| Error::Config(format!("Invalid Redis URL: {}", e)))?;
+            Ok(Self {
+                client,
+                connection: OnceCell::new(),
+                script: redis::Script::new(HIT_SCRIPT),
+                prefix: prefix.to_string(),
+                timeout,
+            })
+        }
+
+        async fn hit_inner(&self, key: &str, window: Duration) -> Result<Hit> {
+            let connection = self
+                .connection
+                .get_or_try_init(|| {
+                    let config = ConnectionManagerConfig::new()
+                        .set_number_of_retries(0)
+                        .set_connection_timeout(self.timeout)
+                        .set_response_timeout(self.timeout);
+                    ConnectionManager::new_with_config(self.client.clone(), config)
+                })
+                .await
+                .map_err(|e| Error::Upstream(format!("Redis connection failed: {}", e)))?;
+            let mut connection = connection.clone();
+            let window_ms = u64::try_from(window.as_millis()).unwrap_or(u64::MAX).max(1);
+            let (count, ttl): (u64, i64) = self
+                .script
+                .key(format!("{}{}", self.prefix, key))
+                .arg(window_ms)
+                .invoke_async(&mut connection)
+                .await
+                .map_err(|e| Error::Upstream(format!("Redis rate limit failed: {}", e)))?;
+            Ok(Hit {
+                count,
+                reset_after: Duration::from_millis(ttl.max(0) as u64),
+            })
+        }
+    }
+
+    impl RateLimitStore for RedisStore {
+        fn hit<'a>(&'a self, key: &'a str, window: Duration) -> BoxFuture<'a, Result<Hit>> {
+            Box::pin(async move {
+                tokio::time::timeout(self.timeout, self.hit_inner(key, window))
+                    .await
+                    .map_err(|_| Error::Timeout(format!("Redis did not answer within {:?}", self.timeout)))?
+            })
+        }
+    }
+}
+
+// Applies limits against a store. When the store itself fails, `fail_open`
+// lets the request through with a warning; otherwise it is refused with 503.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    fail_open: bool,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, fail_open: bool) -> Self {
+        Self { store, fail_open }
+    }
+
+    pub fn memory() -> Self {
+        Self::new(Arc::new(MemoryStore::new()), true)
+    }
+
+    pub fn from_config(config: &RateLimitStoreConfig) -> Result<Self> {
+        let store: Arc<dyn RateLimitStore> = match config.backend {
+            RateLimitBackend::Memory => Arc::new(MemoryStore::new()),
+            #[cfg(feature = "redis")]
+            RateLimitBackend::Redis => Arc::new(RedisStore::new(
+                &config.redis_url,
+                &config.key_prefix,
+                Duration::from_millis(config.timeout_ms),
+            )?),
+            #[cfg(not(feature = "redis"))]
+            RateLimitBackend::Redis => {
+                return Err(Error::Config("The redis rate limit backend requires the `redis` feature".to_string()));
+            }
+        };
+        Ok(Self::new(store, config.fail_open))
+    }
+
+    pub async fn check(&self, key: &str, limit: u64, window: Duration) -> Result<()> {
+        match self.store.hit(key, window).await {
+            Ok(hit) if hit.count > limit => {
+                let secs = hit.reset_after.as_secs() + u64::from(hit.reset_after.subsec_nanos() > 0);
+                Err(Error::TooManyRequests(secs.max(1)))
+            }
+            Ok(_) => Ok(()),
+            Err(e) if self.fail_open => {
+                warn!("Rate limit store failed, allowing request: {}", e);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Rate limit store failed, refusing request: {}", e);
+                Err(Error::ServiceUnavailable(format!("rate limit store unavailable: {}", e)))
+            }
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter").field("fail_open", &self.fail_open).finish()
+    }
+}
+
+// Server-wide limit per client IP. It also hands its limiter to the router
+// as a request extension, so per-route limits count in the same store.
+pub struct RateLimit {
+    limiter: RateLimiter,
+    limit: u64,
+    window: Duration,
+}
+
+impl RateLimit {
+    pub fn new(limiter: RateLimiter, limit: u64, window: Duration) -> Self {
+        Self { limiter, limit, window }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        request.extensions.insert(self.limiter.clone());
+        Box::pin(async move {
+            if let (true, Some(ip)) = (self.limit > 0, request.client_ip()) {
+                self.limiter.check(&format!("ip:{}", ip), self.limit, self.window).await?;
+            }
+            next.run(request).await
+        })
+    }
+}
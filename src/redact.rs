@@ -0,0 +1,124 @@
+use crate::config::LoggingConfig;
+use http::HeaderMap;
+use std::borrow::Cow;
+use std::fmt::Write;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+const DEFAULT_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "x-auth-token",
+];
+
+const DEFAULT_QUERY_PARAMS: &[&str] = &["token", "access_token", "refresh_token", "api_key", "apikey", "password", "secret"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    headers: Vec<String>,
+    query_params: Vec<String>,
+}
+
+impl Redactor {
+    pub fn from_config(config: &LoggingConfig) -> Self {
+        Self {
+            headers: config.redact_headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+            query_params: config.redact_query_params.iter().map(|key| key.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    pub fn is_sensitive_header(&self, name: &str) -> bool {
+        DEFAULT_HEADERS.iter().any(|header| header.eq_ignore_ascii_case(name))
+            || self.headers.iter().any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_sensitive_param(&self, key: &str) -> bool {
+        DEFAULT_QUERY_PARAMS.iter().any(|param| param.eq_ignore_ascii_case(key))
+            || self.query_params.iter().any(|param| param.eq_ignore_ascii_case(key))
+    }
+
+    pub fn headers(&self, headers: &HeaderMap) -> String {
+        let mut out = String::new();
+        for (name, value) in headers {
+            if !out.is_empty() {
+                out.push_str(", ");
+            }
+            let value = match self.is_sensitive_header(name.as_str()) {
+                true => Cow::Borrowed(REDACTED),
+                false => String::from_utf8_lossy(value.as_bytes()),
+            };
+            let _ = write!(out, "{}: {}", name, value);
+        }
+        out
+    }
+
+    pub fn query<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        let sensitive = |pair: &str| self.is_sensitive_param(pair.split_once('=').map_or(pair, |(key, _)| key));
+        if !query.split('&').any(sensitive) {
+            return Cow::Borrowed(query);
+        }
+        let pairs: Vec<Cow<str>> = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if self.is_sensitive_param(key) => Cow::Owned(format!("{}={}", key, REDACTED)),
+                _ => Cow::Borrowed(pair),
+            })
+            .collect();
+        Cow::Owned(pairs.join("&"))
+    }
+
+    pub fn target<'a>(&self, target: &'a str) -> Cow<'a, str> {
+        match target.split_once('?') {
+            Some((path, query)) => match self.query(query) {
+                Cow::Borrowed(_) => Cow::Borrowed(target),
+                Cow::Owned(query) => Cow::Owned(format!("{}?{}", path, query)),
+            },
+            None => Cow::Borrowed(target),
+        }
+    }
+
+    pub fn request_line(&self, line: &[u8]) -> Vec<u8> {
+        let parts: Vec<Cow<[u8]>> = line
+            .split(|&b| b == b' ')
+            .map(|part| match std::str::from_utf8(part).map(|part| self.target(part)) {
+                Ok(Cow::Owned(target)) => Cow::Owned(target.into_bytes()),
+                _ => Cow::Borrowed(part),
+            })
+            .collect();
+        parts.join(&b' ')
+    }
+
+    pub fn message(&self, raw: &[u8]) -> Vec<u8> {
+        let head_end = raw.windows(4).position(|window| window == b"\r\n\r\n").map_or(raw.len(), |position| position + 4);
+        let (head, body) = raw.split_at(head_end);
+        let mut out = Vec::with_capacity(raw.len());
+        for (index, line) in head.split(|&b| b == b'\n').enumerate() {
+            if index > 0 {
+                out.push(b'\n');
+            }
+            let (line, cr) = match line.strip_suffix(b"\r") {
+                Some(line) => (line, &b"\r"[..]),
+                None => (line, &b""[..]),
+            };
+            match index {
+                0 => out.extend_from_slice(&self.request_line(line)),
+                _ => out.extend_from_slice(&self.header_line(line)),
+            }
+            out.extend_from_slice(cr);
+        }
+        out.extend_from_slice(body);
+        out
+    }
+
+    pub fn header_line(&self, line: &[u8]) -> Vec<u8> {
+        match line.iter().position(|&b| b == b':') {
+            Some(colon) if self.is_sensitive_header(String::from_utf8_lossy(&line[..colon]).trim()) => {
+                [&line[..=colon], b" ", REDACTED.as_bytes()].concat()
+            }
+            _ => line.to_vec(),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    config::{Config, RedirectRule},
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    router::Router,
+    security, Error, Result,
+};
+use futures::future::BoxFuture;
+use http::uri::Authority;
+use http::{HeaderValue, StatusCode};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    source: String,
+    capture: Option<String>,
+    target: String,
+    status: StatusCode,
+}
+
+impl Rule {
+    fn parse(rule: &RedirectRule) -> Result<Self> {
+        if !rule.from.starts_with('/') {
+            return Err(Error::Config(format!("Redirect source must start with '/': {}", rule.from)));
+        }
+        let status = StatusCode::from_u16(rule.status)
+            .ok()
+            .filter(StatusCode::is_redirection)
+            .ok_or_else(|| Error::Config(format!("Invalid redirect status: {}", rule.status)))?;
+
+        let (source, capture) = match rule.from.rsplit_once("/{*") {
+            Some((prefix, name)) => {
+                let name = name
+                    .strip_suffix('}')
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| Error::Config(format!("Invalid redirect source: {}", rule.from)))?;
+                (format!("{}/", prefix), Some(name.to_string()))
+            }
+            None => (rule.from.clone(), None),
+        };
+
+        Ok(Self {
+            source,
+            capture,
+            target: rule.to.clone(),
+            status,
+        })
+    }
+
+    fn location(&self, path: &str) -> Option<String> {
+        match &self.capture {
+            None => (path == self.source).then(|| self.target.clone()),
+            Some(name) => {
+                let rest = path
+                    .strip_prefix(self.source.as_str())
+                    .or_else(|| (path == self.source.trim_end_matches('/')).then_some(""))?;
+                Some(self.target.replace(&format!("{{{}}}", name), rest))
+            }
+        }
+    }
+}
+
+pub struct Redirects {
+    rules: Vec<Rule>,
+}
+
+impl Redirects {
+    pub fn from_config(rules: &[RedirectRule]) -> Result<Self> {
+        Ok(Self {
+            rules: rules.iter().map(Rule::parse).collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn resolve(&self, request: &Request) -> Option<Response> {
+        let path = request.path();
+        self.rules.iter().find_map(|rule| {
+            let mut location = rule.location(path)?;
+            if let Some(query) = request.query().filter(|_| !location.contains('?')) {
+                location.push('?');
+                location.push_str(query);
+            }
+            Some(Response::redirect_with(rule.status, &location))
+        })
+    }
+}
+
+impl Middleware for Redirects {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        match self.resolve(&request) {
+            Some(response) => Box::pin(async move { Ok(response) }),
+            None => next.run(request),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpsRedirect {
+    port: u16,
+    hsts: Option<HeaderValue>,
+}
+
+impl HttpsRedirect {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            port: config.server.port,
+            hsts: security::hsts_header(&config.security.headers),
+        }
+    }
+
+    pub fn location(&self, request: &Request) -> Option<String> {
+        let host = request.host()?;
+        let authority = match self.port {
+            443 => host,
+            port => format!("{}:{}", host, port),
+        };
+        authority.parse::<Authority>().ok()?;
+        let path_and_query = request.uri.path_and_query().map_or("/", |path| path.as_str());
+        Some(format!("https://{}{}", authority, path_and_query))
+    }
+
+    pub fn respond(&self, request: &Request) -> Response {
+        let Some(location) = self.location(request) else {
+            return Response::new(StatusCode::BAD_REQUEST).with_text("Missing or invalid Host header");
+        };
+        let mut response = Response::redirect_with(StatusCode::MOVED_PERMANENTLY, &location);
+        if let Some(hsts) = &self.hsts {
+            response.headers.insert("strict-transport-security", hsts.clone());
+        }
+        response
+    }
+
+    pub fn into_router(self) -> Router {
+        let mut router = Router::new();
+        router.fallback(move |request| Ok(self.respond(&request)));
+        router
+    }
+}
\ No newline at end of file
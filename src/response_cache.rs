@@ -0,0 +1,265 @@
+use crate::{
+    config::ResponseCacheConfig,
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    Result,
+};
+use futures::future::BoxFuture;
+use http::{
+    header::{HeaderName, COOKIE},
+    HeaderMap, HeaderValue, Method, StatusCode,
+};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    host: String,
+    target: String,
+}
+
+impl CacheKey {
+    fn new(request: &Request) -> Self {
+        Self {
+            host: request.host().unwrap_or_default(),
+            target: request.uri.path_and_query().map_or_else(|| request.path().to_string(), |target| target.to_string()),
+        }
+    }
+
+    fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    response: Response,
+    size: usize,
+    stored_at: Instant,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+impl Entry {
+    // A request with cookies only reuses a response that was keyed on them.
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary.iter().all(|(name, value)| headers.get(name) == value.as_ref())
+            && (!headers.contains_key(COOKIE) || varies_on_cookie(&self.vary))
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, Vec<Entry>>,
+    count: usize,
+    total_bytes: usize,
+    clock: u64,
+}
+
+impl CacheState {
+    fn remove_where(&mut self, mut remove: impl FnMut(&CacheKey, &Entry) -> bool) -> usize {
+        let mut removed = 0;
+        let mut freed = 0;
+        self.entries.retain(|key, variants| {
+            variants.retain(|entry| {
+                let gone = remove(key, entry);
+                if gone {
+                    removed += 1;
+                    freed += entry.size;
+                }
+                !gone
+            });
+            !variants.is_empty()
+        });
+        self.count -= removed;
+        self.total_bytes -= freed;
+        removed
+    }
+
+    fn evict_lru(&mut self) {
+        let oldest = self.entries.values().flatten().map(|entry| entry.last_used).min();
+        if let Some(oldest) = oldest {
+            self.remove_where(|_, entry| entry.last_used == oldest);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    max_entry_bytes: usize,
+    max_total_bytes: usize,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, max_entries: usize, max_entry_bytes: usize, max_total_bytes: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            max_entry_bytes,
+            max_total_bytes,
+            state: Arc::new(Mutex::new(CacheState::default())),
+        }
+    }
+
+    pub fn from_config(config: &ResponseCacheConfig) -> Self {
+        Self::new(
+            Duration::from_secs(config.ttl_secs),
+            config.max_entries,
+            config.max_entry_bytes,
+            config.max_total_bytes,
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.state.lock().total_bytes
+    }
+
+    pub fn purge(&self, path_prefix: &str) -> usize {
+        let prefix = path_prefix.trim_end_matches('/');
+        self.state.lock().remove_where(|key, _| {
+            key.path()
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    fn lookup(&self, key: &CacheKey, headers: &HeaderMap) -> Option<Response> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        state.remove_where(|_, entry| entry.expires_at <= now);
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(key)?.iter_mut().find(|entry| entry.matches(headers))?;
+        entry.last_used = clock;
+        let age = now.duration_since(entry.stored_at).as_secs();
+        Some(entry.response.clone().with_header("age", &age.to_string()).with_header("x-cache", "HIT"))
+    }
+
+    fn store(&self, key: CacheKey, headers: &HeaderMap, response: &Response) {
+        let Some(ttl) = self.ttl_for(response) else {
+            return;
+        };
+        let Some(vary) = vary_headers(response, headers) else {
+            return;
+        };
+        if headers.contains_key(COOKIE) && !varies_on_cookie(&vary) {
+            return;
+        }
+        let header_bytes: usize = response.headers.iter().map(|(name, value)| name.as_str().len() + value.len()).sum();
+        let size = response.body.as_ref().map_or(0, |body| body.len()) + header_bytes;
+        if size > self.max_entry_bytes || size > self.max_total_bytes || self.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        state.remove_where(|existing, entry| *existing == key && entry.vary == vary);
+        while state.count > 0 && (state.count >= self.max_entries || state.total_bytes + size > self.max_total_bytes) {
+            state.evict_lru();
+        }
+        state.clock += 1;
+        let entry = Entry {
+            vary,
+            response: response.clone(),
+            size,
+            stored_at: now,
+            expires_at: now + ttl,
+            last_used: state.clock,
+        };
+        state.entries.entry(key).or_default().push(entry);
+        state.count += 1;
+        state.total_bytes += size;
+    }
+
+    fn ttl_for(&self, response: &Response) -> Option<Duration> {
+        if response.status != StatusCode::OK || response.stream.is_some() || response.headers.contains_key("set-cookie") {
+            return None;
+        }
+        let directives = cache_control(&response.headers);
+        if directives.iter().any(|(name, _)| matches!(name.as_str(), "no-store" | "private" | "no-cache")) {
+            return None;
+        }
+        let lifetime = ["s-maxage", "max-age"].iter().find_map(|directive| {
+            directives
+                .iter()
+                .find(|(name, _)| name == directive)
+                .and_then(|(_, value)| value.as_deref()?.parse::<u64>().ok())
+        });
+        let ttl = lifetime.map_or(self.ttl, |seconds| self.ttl.min(Duration::from_secs(seconds)));
+        (!ttl.is_zero()).then_some(ttl)
+    }
+}
+
+impl Middleware for ResponseCache {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            if request.method != Method::GET || request.headers.contains_key("authorization") {
+                return next.run(request).await;
+            }
+            let key = CacheKey::new(&request);
+            let directives = cache_control(&request.headers);
+            let has = |directive: &str| directives.iter().any(|(name, _)| name == directive);
+            if !has("no-cache") && !has("no-store") {
+                if let Some(response) = self.lookup(&key, &request.headers) {
+                    return Ok(response);
+                }
+            }
+
+            let no_store = has("no-store");
+            let headers = request.headers.clone();
+            let response = next.run(request).await?;
+            if !no_store {
+                self.store(key, &headers, &response);
+            }
+            Ok(response.with_header("x-cache", "MISS"))
+        })
+    }
+}
+
+fn cache_control(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers
+        .get_all("cache-control")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), Some(value.trim().trim_matches('"').to_string())),
+            None => (directive.to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+fn varies_on_cookie(vary: &[(HeaderName, Option<HeaderValue>)]) -> bool {
+    vary.iter().any(|(name, _)| name == COOKIE)
+}
+
+fn vary_headers(response: &Response, request_headers: &HeaderMap) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    let mut vary = Vec::new();
+    for value in response.headers.get_all("vary") {
+        for name in value.to_str().ok()?.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if name == "*" {
+                return None;
+            }
+            let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+            let value = request_headers.get(&name).cloned();
+            vary.push((name, value));
+        }
+    }
+    Some(vary)
+}
@@ -0,0 +1,141 @@
+use crate::{
+    config::RewriteRule,
+    http::{Request, Response},
+    metrics::Metrics,
+    middleware::{Middleware, Next},
+    utils, Error, Result,
+};
+use futures::future::BoxFuture;
+use http::uri::{Parts, PathAndQuery, Uri};
+use regex::Regex;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+const MAX_PASSES: usize = 10;
+
+#[derive(Debug)]
+struct Rule {
+    pattern: String,
+    regex: Regex,
+    replacement: String,
+    last: bool,
+}
+
+impl Rule {
+    fn parse(rule: &RewriteRule) -> Result<Self> {
+        let regex = Regex::new(&format!("^(?:{})$", rule.pattern))
+            .map_err(|e| Error::Config(format!("Invalid rewrite pattern {}: {}", rule.pattern, e)))?;
+        if !rule.replacement.starts_with('/') {
+            return Err(Error::Config(format!("Rewrite replacement must start with '/': {}", rule.replacement)));
+        }
+        Ok(Self {
+            pattern: rule.pattern.clone(),
+            regex,
+            replacement: rule.replacement.clone(),
+            last: rule.last,
+        })
+    }
+
+    fn apply(&self, target: &str) -> Option<String> {
+        let captures = self.regex.captures(target)?;
+        let mut rewritten = String::new();
+        captures.expand(&self.replacement, &mut rewritten);
+        Some(rewritten)
+    }
+}
+
+pub struct Rewrites {
+    rules: Vec<Rule>,
+    metrics: Arc<Metrics>,
+}
+
+impl Rewrites {
+    pub fn from_config(rules: &[RewriteRule], metrics: Arc<Metrics>) -> Result<Self> {
+        Ok(Self {
+            rules: rules.iter().map(Rule::parse).collect::<Result<_>>()?,
+            metrics,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Rules run in order against the decoded, normalized path, each one seeing
+    // the output of the previous. Passes repeat until a pass changes nothing or
+    // a `last` rule matches; running out of passes is treated as a loop.
+    pub fn rewrite(&self, path: &str, query: Option<&str>) -> Result<Option<(String, Option<String>)>> {
+        let decoded = urlencoding::decode(path).map_err(|_| Error::BadRequest("Path is not valid UTF-8".to_string()))?;
+        let mut path = normalize(&decoded);
+        let mut query = query.map(str::to_string);
+        let mut rewritten = false;
+
+        for _ in 0..MAX_PASSES {
+            let mut changed = false;
+            for rule in &self.rules {
+                let Some(target) = rule.apply(&path) else {
+                    continue;
+                };
+                self.metrics.record_rewrite(&rule.pattern);
+                let (target, target_query) = match target.split_once('?') {
+                    Some((target, target_query)) => (target, Some(target_query.to_string())),
+                    None => (target.as_str(), query.clone()),
+                };
+                let target = normalize(target);
+                debug!(rule = %rule.pattern, from = %path, to = %target, "Rewrote request path");
+                changed |= target != path || target_query != query;
+                rewritten = true;
+                path = target;
+                query = target_query;
+                if rule.last {
+                    return Ok(Some((path, query)));
+                }
+            }
+            if !changed {
+                return Ok(rewritten.then_some((path, query)));
+            }
+        }
+
+        warn!(path = %path, "Rewrite rules did not settle after {} passes", MAX_PASSES);
+        Err(Error::LoopDetected(format!("Rewrite rules did not settle after {} passes", MAX_PASSES)))
+    }
+}
+
+impl Middleware for Rewrites {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        let rewritten = self.rewrite(request.path(), request.query()).and_then(|rewritten| match rewritten {
+            Some((path, query)) => rewrite_uri(&request.uri, &path, query.as_deref()).map(Some),
+            None => Ok(None),
+        });
+        match rewritten {
+            Ok(uri) => {
+                if let Some(uri) = uri {
+                    request.uri = uri;
+                }
+                next.run(request)
+            }
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+}
+
+fn normalize(path: &str) -> String {
+    let canonical = utils::canonical_path(path);
+    match path.ends_with('/') && canonical != "/" {
+        true => format!("{}/", canonical),
+        false => canonical,
+    }
+}
+
+fn rewrite_uri(uri: &Uri, path: &str, query: Option<&str>) -> Result<Uri> {
+    let mut encoded = path.split('/').map(|segment| urlencoding::encode(segment).into_owned()).collect::<Vec<_>>().join("/");
+    if let Some(query) = query {
+        encoded.push('?');
+        encoded.push_str(query);
+    }
+    let mut parts = Parts::from(uri.clone());
+    parts.path_and_query = Some(
+        PathAndQuery::try_from(encoded.as_str()).map_err(|_| Error::BadRequest(format!("Invalid rewritten path: {}", path)))?,
+    );
+    Uri::from_parts(parts).map_err(|e| Error::Internal(format!("Invalid rewritten URI: {}", e)))
+}
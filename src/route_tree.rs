@@ -0,0 +1,181 @@
+use crate::router::Route;
+use http::Method;
+use regex::Regex;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Static(String),
+    Param,
+    Constrained(String),
+    CatchAll,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    constrained: Vec<(String, Regex, Node)>,
+    param: Option<Box<Node>>,
+    catch_all: Vec<usize>,
+    endpoints: Vec<usize>,
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[Segment], index: usize) {
+        let Some((segment, rest)) = segments.split_first() else {
+            self.endpoints.push(index);
+            return;
+        };
+
+        let child = match segment {
+            Segment::Static(text) => self.statics.entry(text.clone()).or_default(),
+            Segment::Param => self.param.get_or_insert_with(Default::default),
+            Segment::Constrained(source) => {
+                let position = match self.constrained.iter().position(|(existing, _, _)| existing == source) {
+                    Some(position) => position,
+                    None => {
+                        let regex = Regex::new(source).expect("segment pattern is escaped");
+                        self.constrained.push((source.clone(), regex, Node::default()));
+                        self.constrained.len() - 1
+                    }
+                };
+                &mut self.constrained[position].2
+            }
+            Segment::CatchAll => {
+                self.catch_all.push(index);
+                return;
+            }
+        };
+        child.insert(rest, index);
+    }
+
+    fn find(&self, routes: &[Route], method: &Method, rest: Option<&str>, captures: &mut Vec<String>) -> Option<usize> {
+        let Some(rest) = rest else {
+            return self.endpoints.iter().copied().find(|&index| routes[index].method == *method);
+        };
+        let (segment, remainder) = match rest.split_once('/') {
+            Some((segment, remainder)) => (segment, Some(remainder)),
+            None => (rest, None),
+        };
+
+        if let Some(found) = self.statics.get(segment).and_then(|child| child.find(routes, method, remainder, captures)) {
+            return Some(found);
+        }
+
+        for (_, regex, child) in &self.constrained {
+            let Some(matched) = regex.captures(segment) else {
+                continue;
+            };
+            let depth = captures.len();
+            captures.extend(matched.iter().skip(1).map(|group| group.map_or("", |group| group.as_str()).to_string()));
+            if let Some(found) = child.find(routes, method, remainder, captures) {
+                return Some(found);
+            }
+            captures.truncate(depth);
+        }
+
+        if let Some(child) = self.param.as_deref().filter(|_| !segment.is_empty()) {
+            captures.push(segment.to_string());
+            if let Some(found) = child.find(routes, method, remainder, captures) {
+                return Some(found);
+            }
+            captures.pop();
+        }
+
+        let found = self.catch_all.iter().copied().find(|&index| routes[index].method == *method)?;
+        captures.push(rest.to_string());
+        Some(found)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RouteTree {
+    root: Node,
+    unstructured: Vec<(usize, Regex)>,
+}
+
+impl RouteTree {
+    pub(crate) fn build(routes: &[Route]) -> Self {
+        let mut tree = Self::default();
+        for (index, route) in routes.iter().enumerate() {
+            match parse_pattern(&route.pattern) {
+                Some(segments) => tree.root.insert(&segments, index),
+                None => {
+                    if let Ok(regex) = Regex::new(&route.regex) {
+                        tree.unstructured.push((index, regex));
+                    }
+                }
+            }
+        }
+        tree
+    }
+
+    pub(crate) fn find(&self, routes: &[Route], method: &Method, path: &str) -> Option<(usize, HashMap<String, String>)> {
+        let mut captures = Vec::new();
+        if let Some(index) = path
+            .strip_prefix('/')
+            .and_then(|rest| self.root.find(routes, method, Some(rest), &mut captures))
+        {
+            let params = routes[index].param_names.iter().cloned().zip(captures).collect();
+            return Some((index, params));
+        }
+
+        self.unstructured.iter().find_map(|(index, regex)| {
+            let route = &routes[*index];
+            if route.method != *method {
+                return None;
+            }
+            let matched = regex.captures(path)?;
+            let params = route
+                .param_names
+                .iter()
+                .zip(matched.iter().skip(1))
+                .filter_map(|(name, group)| group.map(|group| (name.clone(), group.as_str().to_string())))
+                .collect();
+            Some((*index, params))
+        })
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Option<Vec<Segment>> {
+    let body = pattern.strip_prefix('/')?;
+    let parts: Vec<&str> = body.split('/').collect();
+    let mut segments = Vec::with_capacity(parts.len());
+
+    for (position, part) in parts.iter().enumerate() {
+        let inner = part.strip_prefix('{').and_then(|part| part.strip_suffix('}'));
+        let segment = match inner {
+            Some(name) if !name.contains(['{', '}']) => match name.strip_prefix('*') {
+                Some(_) if position + 1 == parts.len() => Segment::CatchAll,
+                Some(_) => return None,
+                None => Segment::Param,
+            },
+            _ if part.contains(['{', '}']) => Segment::Constrained(segment_regex(part)?),
+            _ => Segment::Static(part.to_string()),
+        };
+        segments.push(segment);
+    }
+    Some(segments)
+}
+
+fn segment_regex(part: &str) -> Option<String> {
+    let mut source = String::from("^");
+    let mut rest = part;
+    while let Some(start) = rest.find('{') {
+        let (literal, after) = rest.split_at(start);
+        let end = after.find('}')?;
+        let name = &after[1..end];
+        if literal.contains('}') || name.contains('{') || name.starts_with('*') {
+            return None;
+        }
+        source.push_str(&regex::escape(literal));
+        source.push_str("([^/]+)");
+        rest = &after[end + 1..];
+    }
+    if rest.contains('}') {
+        return None;
+    }
+    source.push_str(&regex::escape(rest));
+    source.push('$');
+    Some(source)
+}
\ No newline at end of file
@@ -1,10 +1,64 @@
-use crate::{http::{Request, Response}, Error, Result};
-use http::Method;
-use regex::Regex;
+use crate::{
+    config::{Config, DuplicateRoutePolicy, SharedConfig, SlashPolicy},
+    http::{BodyLimit, Request, Response},
+    metrics::Metrics,
+    middleware::{Middleware, Next, Scoped},
+    rate_limit::RateLimiter,
+    route_tree::RouteTree,
+    server::Server,
+    storage::FileStore,
+    Error, ResponseError, Result,
+};
+use arc_swap::ArcSwap;
+use futures::future::BoxFuture;
+use http::{uri::PathAndQuery, Method, StatusCode, Uri};
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{warn, Span};
 
-pub type Handler = Arc<dyn Fn(Request) -> Result<Response> + Send + Sync>;
+pub type Handler = Arc<dyn Fn(Request) -> BoxFuture<'static, Result<Response>> + Send + Sync>;
+
+fn sync_handler<F>(handler: F) -> Handler
+where
+    F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+{
+    Arc::new(move |request| {
+        let result = handler(request);
+        Box::pin(async move { result })
+    })
+}
+
+fn async_handler<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response>> + Send + 'static,
+{
+    Arc::new(move |request| Box::pin(handler(request)))
+}
+
+fn fallible_handler<F, Fut, E>(handler: F) -> Handler
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = std::result::Result<Response, E>> + Send + 'static,
+    E: ResponseError,
+{
+    Arc::new(move |request| {
+        let response = handler(request);
+        Box::pin(async move { response.await.map_err(ResponseError::into_error) })
+    })
+}
+
+// Per-route limits override the server-wide ones: `max_body_size` replaces
+// the request's `BodyLimit` (`security.max_request_size`) in either
+// direction, and `rate_limit` allows `count` requests per client IP per window.
+#[derive(Debug, Clone, Default)]
+pub struct RouteOptions {
+    pub timeout: Option<Duration>,
+    pub max_body_size: Option<usize>,
+    pub rate_limit: Option<(u32, Duration)>,
+}
 
 #[derive(Clone)]
 pub struct Route {
@@ -13,6 +67,55 @@ pub struct Route {
     pub regex: String,
     pub param_names: Vec<String>,
     pub handler: Handler,
+    pub options: RouteOptions,
+}
+
+impl Route {
+    pub fn new<F>(method: Method, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+    {
+        Self::with_handler(method, pattern, sync_handler(handler), RouteOptions::default())
+    }
+
+    pub fn new_async<F, Fut>(method: Method, pattern: &str, handler: F, options: RouteOptions) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response>> + Send + 'static,
+    {
+        Self::with_handler(method, pattern, async_handler(handler), options)
+    }
+
+    pub fn new_fallible<F, Fut, E>(method: Method, pattern: &str, handler: F, options: RouteOptions) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<Response, E>> + Send + 'static,
+        E: ResponseError,
+    {
+        Self::with_handler(method, pattern, fallible_handler(handler), options)
+    }
+
+    fn with_handler(method: Method, pattern: &str, handler: Handler, options: RouteOptions) -> Self {
+        let (regex, param_names) = Router::compile_pattern(pattern);
+        Self {
+            method,
+            pattern: pattern.to_string(),
+            regex,
+            param_names,
+            handler,
+            options,
+        }
+    }
+
+    fn check_body_size(&self, request: &Request) -> Result<()> {
+        let limit = self.options.max_body_size.or_else(|| request.extension::<BodyLimit>().map(|limit| limit.0));
+        if let Some(limit) = limit {
+            if let Some(actual) = request.body.as_ref().map(|body| body.len()).filter(|&len| len > limit) {
+                return Err(Error::ContentTooLarge { limit, actual: Some(actual) });
+            }
+        }
+        Ok(())
+    }
 }
 
 impl std::fmt::Debug for Route {
@@ -23,18 +126,106 @@ impl std::fmt::Debug for Route {
             .field("regex", &self.regex)
             .field("param_names", &self.param_names)
             .field("handler", &"<function>")
+            .field("options", &self.options)
             .finish()
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Router {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    pub method: Method,
+    pub pattern: String,
+    pub param_names: Vec<String>,
+}
+
+struct RouteTable {
     routes: Vec<Route>,
+    tree: RouteTree,
+}
+
+impl RouteTable {
+    fn new(routes: Vec<Route>) -> Self {
+        let tree = RouteTree::build(&routes);
+        Self { routes, tree }
+    }
+
+    fn find(&self, method: &Method, path: &str) -> Option<(&Route, HashMap<String, String>)> {
+        self.tree
+            .find(&self.routes, method, path)
+            .map(|(index, params)| (&self.routes[index], params))
+    }
+}
+
+#[derive(Clone)]
+pub struct Router {
+    routes: Arc<ArcSwap<RouteTable>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    fallback: Option<Handler>,
+    hosts: HashMap<String, Arc<Router>>,
+    strict_hosts: bool,
+    slash_policy: SlashPolicy,
+    duplicate_routes: DuplicateRoutePolicy,
+    request_timeout: Option<Duration>,
+    metrics: Option<Arc<Metrics>>,
+    slow_request_threshold: Option<Duration>,
+    rate_limiter: RateLimiter,
+}
+
+impl std::fmt::Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router")
+            .field("routes", &self.routes.load().routes)
+            .field("middleware", &self.middleware.len())
+            .field("fallback", &self.fallback.is_some())
+            .field("hosts", &self.hosts.keys().collect::<Vec<_>>())
+            .field("strict_hosts", &self.strict_hosts)
+            .field("slash_policy", &self.slash_policy)
+            .field("duplicate_routes", &self.duplicate_routes)
+            .field("request_timeout", &self.request_timeout)
+            .field("slow_request_threshold", &self.slow_request_threshold)
+            .finish()
+    }
 }
 
 impl Router {
     pub fn new() -> Self {
-        Self { routes: Vec::new() }
+        Self {
+            routes: Arc::new(ArcSwap::from_pointee(RouteTable::new(Vec::new()))),
+            middleware: Vec::new(),
+            fallback: None,
+            hosts: HashMap::new(),
+            strict_hosts: false,
+            slash_policy: SlashPolicy::Strict,
+            duplicate_routes: DuplicateRoutePolicy::Warn,
+            request_timeout: None,
+            metrics: None,
+            slow_request_threshold: None,
+            rate_limiter: RateLimiter::memory(),
+        }
+    }
+
+    pub fn with_default_routes(config: &Config) -> Self {
+        let mut router = Self::new();
+        let shared: SharedConfig = Arc::new(ArcSwap::from_pointee(config.clone()));
+        let files = Arc::new(FileStore::from_config(&config.files));
+        Server::setup_routes(&mut router, &shared, &Arc::new(Metrics::new()), &files)
+            .expect("the default routes are distinct and duplicates only warn");
+        router
+    }
+
+    pub fn layer<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Middleware,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn layer_at<M>(&mut self, prefix: &str, middleware: M) -> &mut Self
+    where
+        M: Middleware,
+    {
+        self.layer(Scoped::new(prefix, middleware))
     }
 
     pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Self
@@ -69,6 +260,22 @@ impl Router {
         self
     }
 
+    pub fn patch<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+    {
+        self.add_route(Method::PATCH, pattern, handler);
+        self
+    }
+
+    pub fn trace<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+    {
+        self.add_route(Method::TRACE, pattern, handler);
+        self
+    }
+
     pub fn options<F>(&mut self, pattern: &str, handler: F) -> &mut Self
     where
         F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
@@ -77,33 +284,412 @@ impl Router {
         self
     }
 
+    pub fn get_with<F, Fut>(&mut self, pattern: &str, handler: F, options: RouteOptions) -> &mut Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response>> + Send + 'static,
+    {
+        self.add_route_with(Method::GET, pattern, handler, options)
+    }
+
+    pub fn post_with<F, Fut>(&mut self, pattern: &str, handler: F, options: RouteOptions) -> &mut Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response>> + Send + 'static,
+    {
+        self.add_route_with(Method::POST, pattern, handler, options)
+    }
+
+    // The builders without `try_` panic when the route cannot be added, which
+    // only happens for a duplicate under `DuplicateRoutePolicy::Error`. Use the
+    // `try_` forms for routes that come from outside the program.
+    pub fn add_route_with<F, Fut>(&mut self, method: Method, pattern: &str, handler: F, options: RouteOptions) -> &mut Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response>> + Send + 'static,
+    {
+        match self.try_add_route_with(method, pattern, handler, options) {
+            Ok(router) => router,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_add_route_with<F, Fut>(&mut self, method: Method, pattern: &str, handler: F, options: RouteOptions) -> Result<&mut Self>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response>> + Send + 'static,
+    {
+        self.insert_route(Route::new_async(method, pattern, handler, options))?;
+        Ok(self)
+    }
+
+    // Like `add_route_with`, for handlers that fail with their own error type.
+    pub fn add_handler<F, Fut, E>(&mut self, method: Method, pattern: &str, handler: F, options: RouteOptions) -> &mut Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<Response, E>> + Send + 'static,
+        E: ResponseError,
+    {
+        match self.try_add_handler(method, pattern, handler, options) {
+            Ok(router) => router,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_add_handler<F, Fut, E>(&mut self, method: Method, pattern: &str, handler: F, options: RouteOptions) -> Result<&mut Self>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<Response, E>> + Send + 'static,
+        E: ResponseError,
+    {
+        self.insert_route(Route::new_fallible(method, pattern, handler, options))?;
+        Ok(self)
+    }
+
     pub fn add_route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
     where
         F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
     {
-        let (regex_pattern, param_names) = Self::compile_pattern(pattern);
-        let route = Route {
-            method,
-            pattern: pattern.to_string(),
-            regex: regex_pattern,
-            param_names,
-            handler: Arc::new(handler),
-        };
-        self.routes.push(route);
+        match self.try_add_route(method, pattern, handler) {
+            Ok(router) => router,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_add_route<F>(&mut self, method: Method, pattern: &str, handler: F) -> Result<&mut Self>
+    where
+        F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+    {
+        self.insert_route(Route::new(method, pattern, handler))?;
+        Ok(self)
+    }
+
+    pub fn insert<F>(&self, method: Method, pattern: &str, handler: F) -> Result<()>
+    where
+        F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+    {
+        self.insert_route(Route::new(method, pattern, handler))
+    }
+
+    pub fn remove_route(&self, method: &Method, pattern: &str) -> bool {
+        let (regex, _) = Self::compile_pattern(pattern);
+        let mut removed = false;
+        self.routes.rcu(|table| {
+            let mut routes = table.routes.clone();
+            routes.retain(|route| !(route.method == *method && route.regex == regex));
+            removed = routes.len() != table.routes.len();
+            RouteTable::new(routes)
+        });
+        removed
+    }
+
+    pub fn replace(&self, routes: Vec<Route>) {
+        self.routes.store(Arc::new(RouteTable::new(routes)));
+    }
+
+    fn insert_route(&self, route: Route) -> Result<()> {
+        let mut outcome = Ok(None);
+        self.routes.rcu(|table| {
+            let mut routes = table.routes.clone();
+            outcome = Self::apply_insert(&mut routes, route.clone(), self.duplicate_routes);
+            RouteTable::new(routes)
+        });
+        if let Some(shadowed_by) = outcome? {
+            warn!("Route {} {} is shadowed by earlier route {}", route.method, route.pattern, shadowed_by);
+        }
+        Ok(())
+    }
+
+    fn apply_insert(routes: &mut Vec<Route>, route: Route, policy: DuplicateRoutePolicy) -> Result<Option<String>> {
+        let existing = routes
+            .iter()
+            .position(|existing| existing.method == route.method && existing.regex == route.regex);
+        match (existing, policy) {
+            (None, _) => routes.push(route),
+            (Some(index), DuplicateRoutePolicy::Error) => {
+                return Err(Error::Config(format!(
+                    "duplicate route {} {} conflicts with {}",
+                    route.method, route.pattern, routes[index].pattern
+                )));
+            }
+            (Some(index), DuplicateRoutePolicy::Warn) => {
+                let shadowed_by = routes[index].pattern.clone();
+                routes.push(route);
+                return Ok(Some(shadowed_by));
+            }
+            (Some(index), DuplicateRoutePolicy::Replace) => routes[index] = route,
+        }
+        Ok(None)
+    }
+
+    pub fn duplicate_routes(&mut self, policy: DuplicateRoutePolicy) -> &mut Self {
+        self.duplicate_routes = policy;
+        self
+    }
+
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes
+            .load()
+            .routes
+            .iter()
+            .map(|route| RouteInfo {
+                method: route.method.clone(),
+                pattern: route.pattern.clone(),
+                param_names: route.param_names.clone(),
+            })
+            .collect()
+    }
+
+    pub fn allowed_methods(&self) -> Vec<Method> {
+        let mut methods = vec![Method::OPTIONS];
+        let hosted = self.hosts.values().flat_map(|router| router.routes());
+        for route in self.routes().into_iter().chain(hosted) {
+            if route.method == Method::GET && !methods.contains(&Method::HEAD) {
+                methods.push(Method::HEAD);
+            }
+            if !methods.contains(&route.method) {
+                methods.push(route.method);
+            }
+        }
+        methods
+    }
+
+    pub fn nest(&mut self, prefix: &str, router: Router) -> Result<&mut Self> {
+        let prefix = prefix.trim_end_matches('/');
+        for route in router.routes.load().routes.iter() {
+            let pattern = match route.pattern.as_str() {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                pattern => format!("{}{}", prefix, pattern),
+            };
+            self.insert_route(Route::with_handler(
+                route.method.clone(),
+                &pattern,
+                route.handler.clone(),
+                route.options.clone(),
+            ))?;
+        }
+        for middleware in router.middleware {
+            self.layer_at(prefix, middleware);
+        }
+        Ok(self)
+    }
+
+    pub fn merge(&mut self, router: Router) -> Result<&mut Self> {
+        for route in router.routes.load().routes.iter() {
+            self.insert_route(route.clone())?;
+        }
+        self.middleware.extend(router.middleware);
+        if self.fallback.is_none() {
+            self.fallback = router.fallback;
+        }
+        Ok(self)
+    }
+
+    #[cfg(feature = "embed")]
+    pub fn embedded(&mut self, prefix: &str, dir: &'static crate::embed::Dir<'static>) -> &mut Self {
+        self.embedded_with(prefix, crate::embed::EmbeddedAssets::new(dir))
+    }
+
+    #[cfg(feature = "embed")]
+    pub fn embedded_with(&mut self, prefix: &str, assets: crate::embed::EmbeddedAssets) -> &mut Self {
+        let assets = Arc::new(assets);
+        let pattern = format!("{}/{{*path}}", prefix.trim_end_matches('/'));
+        for method in [Method::GET, Method::HEAD] {
+            let assets = assets.clone();
+            self.add_route(method, &pattern, move |request| assets.respond(&request));
+        }
+        self
+    }
+
+    pub fn fallback<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+    {
+        self.fallback = Some(sync_handler(handler));
+        self
+    }
+
+    pub fn request_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    pub fn route_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn slow_request_threshold(&mut self, threshold: Option<Duration>) -> &mut Self {
+        self.slow_request_threshold = threshold;
+        self
+    }
+
+    pub fn host(&mut self, host: &str, router: Router) -> &mut Self {
+        self.hosts.insert(host.trim_end_matches('.').to_ascii_lowercase(), Arc::new(router));
         self
     }
 
-    pub fn handle(&self, request: Request) -> Result<Response> {
-        for route in &self.routes {
-            if route.method == request.method {
-                if let Some(params) = self.match_route(route, request.path()) {
-                    let mut request_with_params = request;
-                    request_with_params.params = params;
-                    return (route.handler)(request_with_params);
+    pub fn strict_hosts(&mut self, strict: bool) -> &mut Self {
+        self.strict_hosts = strict;
+        self
+    }
+
+    pub fn slash_policy(&mut self, policy: SlashPolicy) -> &mut Self {
+        self.slash_policy = policy;
+        self
+    }
+
+    pub async fn handle(&self, mut request: Request) -> Result<Response> {
+        if self.slash_policy != SlashPolicy::Strict {
+            if let Some(normalized) = self.normalize(&request)? {
+                if self.slash_policy == SlashPolicy::Merge {
+                    request = normalized;
+                } else if !self.recognizes(&request) {
+                    let location = normalized.uri.path_and_query().map_or("/", PathAndQuery::as_str);
+                    let status = match request.method {
+                        Method::GET | Method::HEAD => StatusCode::MOVED_PERMANENTLY,
+                        _ => StatusCode::PERMANENT_REDIRECT,
+                    };
+                    return Ok(Response::redirect_with(status, location));
                 }
             }
         }
-        Err(Error::RouteNotFound(request.path().to_string()))
+        Next::new(&self.middleware, self).run(request).await
+    }
+
+    fn normalize(&self, request: &Request) -> Result<Option<Request>> {
+        let canonical = crate::utils::canonical_path(request.path());
+        let mut candidates = vec![canonical.clone()];
+        if canonical != "/" {
+            candidates.push(format!("{}/", canonical));
+        }
+
+        let mut fallback = None;
+        for candidate in candidates {
+            if candidate == request.path() {
+                return Ok(None);
+            }
+            let mut normalized = request.clone();
+            normalized.uri = Self::with_path(&request.uri, &candidate)?;
+            if self.recognizes(&normalized) {
+                return Ok(Some(normalized));
+            }
+            fallback.get_or_insert(normalized);
+        }
+
+        Ok(fallback.filter(|_| self.slash_policy == SlashPolicy::Merge))
+    }
+
+    fn with_path(uri: &Uri, path: &str) -> Result<Uri> {
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.to_string(),
+        };
+        let invalid = || Error::BadRequest(format!("Invalid request path: {}", path));
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse().map_err(|_| invalid())?);
+        Uri::from_parts(parts).map_err(|_| invalid())
+    }
+
+    pub(crate) async fn route(&self, request: Request) -> Result<Response> {
+        if self.hosts.is_empty() {
+            return self.dispatch(request).await;
+        }
+
+        let host = request.host();
+        match host.as_ref().and_then(|host| self.hosts.get(host)) {
+            Some(router) => router.handle(request).await,
+            None if self.strict_hosts => {
+                Ok(Response::new(StatusCode::MISDIRECTED_REQUEST).with_text("Misdirected Request"))
+            }
+            None => self.dispatch(request).await,
+        }
+    }
+
+    pub fn recognizes(&self, request: &Request) -> bool {
+        if !self.hosts.is_empty() {
+            match request.host().and_then(|host| self.hosts.get(&host)) {
+                Some(router) => return router.recognizes(request),
+                None if self.strict_hosts => return false,
+                None => {}
+            }
+        }
+
+        self.fallback.is_some() || self.routes.load().find(&request.method, request.path()).is_some()
+    }
+
+    pub fn route_options(&self, request: &Request) -> Option<RouteOptions> {
+        if !self.hosts.is_empty() {
+            match request.host().and_then(|host| self.hosts.get(&host)) {
+                Some(router) => return router.route_options(request),
+                None if self.strict_hosts => return None,
+                None => {}
+            }
+        }
+        self.routes.load().find(&request.method, request.path()).map(|(route, _)| route.options.clone())
+    }
+
+    pub(crate) async fn dispatch(&self, request: Request) -> Result<Response> {
+        let matched = self.routes.load().find(&request.method, request.path()).map(|(route, params)| {
+            let timeout = route.options.timeout.or(self.request_timeout);
+            let body_size = route.check_body_size(&request);
+            (route.handler.clone(), timeout, route.pattern.clone(), params, body_size, route.options.rate_limit)
+        });
+        if let Some((handler, timeout, pattern, params, body_size, rate_limit)) = matched {
+            let span = Span::current();
+            span.record("route", pattern.as_str());
+            #[cfg(feature = "otel")]
+            crate::otel::record_route(&span, &pattern);
+            body_size?;
+            if let Some((count, window)) = rate_limit {
+                let limiter = request.extension::<RateLimiter>().unwrap_or(&self.rate_limiter);
+                let client = request.client_ip().map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+                limiter.check(&format!("route:{} {}:{}", request.method, pattern, client), count.into(), window).await?;
+            }
+            let method = request.method.clone();
+            let request_id = request.request_id().map(str::to_string);
+            let mut request_with_params = request;
+            request_with_params.params = params;
+            let started = Instant::now();
+            let response = handler(request_with_params);
+            let result = match timeout {
+                Some(limit) => match tokio::time::timeout(limit, response).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        warn!("Handler for {} {} timed out after {:?}", method, pattern, limit);
+                        Err(Error::Timeout(format!("{} {} exceeded {:?}", method, pattern, limit)))
+                    }
+                },
+                None => response.await,
+            };
+            self.observe_route(&method, &pattern, started.elapsed(), &result, request_id.as_deref());
+            return result;
+        }
+        match &self.fallback {
+            Some(fallback) => fallback(request).await,
+            None => Err(Error::RouteNotFound(request.path().to_string())),
+        }
+    }
+
+    fn observe_route(&self, method: &Method, pattern: &str, elapsed: Duration, result: &Result<Response>, request_id: Option<&str>) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_route(method, pattern, elapsed);
+        }
+        if self.slow_request_threshold.is_some_and(|threshold| elapsed > threshold) {
+            let status = match result {
+                Ok(response) => response.status,
+                Err(e) => e.status_code(),
+            };
+            warn!(
+                route = pattern,
+                latency_ms = elapsed.as_millis() as u64,
+                status = status.as_u16(),
+                request_id = request_id.unwrap_or("-"),
+                "Slow request: {} {}",
+                method,
+                pattern
+            );
+        }
     }
 
     fn compile_pattern(pattern: &str) -> (String, Vec<String>) {
@@ -120,8 +706,16 @@ impl Router {
                 }
                 '}' => {
                     if in_param {
-                        param_names.push(param_name.clone());
-                        regex_pattern.push_str("([^/]+)");
+                        match param_name.strip_prefix('*') {
+                            Some(name) => {
+                                param_names.push(name.to_string());
+                                regex_pattern.push_str("(.*)");
+                            }
+                            None => {
+                                param_names.push(param_name.clone());
+                                regex_pattern.push_str("([^/]+)");
+                            }
+                        }
                         in_param = false;
                     }
                 }
@@ -138,8 +732,27 @@ impl Router {
         (format!("^{}$", regex_pattern), param_names)
     }
 
-    fn match_route(&self, route: &Route, path: &str) -> Option<HashMap<String, String>> {
-        if let Ok(regex) = Regex::new(&route.regex) {
+    #[cfg(test)]
+    pub(crate) fn find_route(&self, method: &Method, path: &str) -> Option<(String, HashMap<String, String>)> {
+        self.routes
+            .load()
+            .find(method, path)
+            .map(|(route, params)| (route.pattern.clone(), params))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn find_route_linear(&self, method: &Method, path: &str) -> Option<(String, HashMap<String, String>)> {
+        self.routes.load().routes.iter().find_map(|route| {
+            if route.method != *method {
+                return None;
+            }
+            Self::match_route(route, path).map(|params| (route.pattern.clone(), params))
+        })
+    }
+
+    #[cfg(test)]
+    fn match_route(route: &Route, path: &str) -> Option<HashMap<String, String>> {
+        if let Ok(regex) = regex::Regex::new(&route.regex) {
             if let Some(captures) = regex.captures(path) {
                 let mut params = HashMap::new();
                 for (i, param_name) in route.param_names.iter().enumerate() {
@@ -0,0 +1,262 @@
+use crate::{
+    config::{ProxyHeader, SecurityHeadersConfig},
+    http::{Request, Response},
+    middleware::{Middleware, Next},
+    server::Server,
+    tls::ClientIdentity,
+    utils::{self, Cidr},
+    Result,
+};
+use futures::future::BoxFuture;
+use http::header::HeaderName;
+use http::{HeaderValue, StatusCode};
+use std::net::IpAddr;
+
+pub fn hsts_header(config: &SecurityHeadersConfig) -> Option<HeaderValue> {
+    config.hsts.then(|| {
+        let mut value = format!("max-age={}", config.hsts_max_age);
+        if config.hsts_include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("max-age=31536000"))
+    })
+}
+
+pub struct SecurityHeaders {
+    hsts: Option<HeaderValue>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeaders {
+    pub fn from_config(config: &SecurityHeadersConfig) -> Self {
+        let hsts = hsts_header(config);
+
+        let mut headers = Vec::new();
+
+        if config.content_type_options {
+            headers.push((
+                HeaderName::from_static("x-content-type-options"),
+                HeaderValue::from_static("nosniff"),
+            ));
+        }
+
+        if let Some(frame_options) = &config.frame_options {
+            if let Ok(value) = HeaderValue::from_str(frame_options) {
+                headers.push((HeaderName::from_static("x-frame-options"), value));
+            }
+        }
+
+        if let Some(referrer_policy) = &config.referrer_policy {
+            if let Ok(value) = HeaderValue::from_str(referrer_policy) {
+                headers.push((HeaderName::from_static("referrer-policy"), value));
+            }
+        }
+
+        if let Some(csp) = &config.content_security_policy {
+            let csp = match (&config.frame_options, csp.contains("frame-ancestors")) {
+                (Some(frame_options), false) => {
+                    let ancestors = if frame_options.eq_ignore_ascii_case("DENY") {
+                        "'none'"
+                    } else {
+                        "'self'"
+                    };
+                    format!("{}; frame-ancestors {}", csp.trim_end_matches(';'), ancestors)
+                }
+                _ => csp.clone(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&csp) {
+                headers.push((HeaderName::from_static("content-security-policy"), value));
+            }
+        }
+
+        Self { hsts, headers }
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let secure = request.is_secure();
+            let error_response = Server::error_responder(&request);
+            let mut response = next.run(request).await.unwrap_or_else(error_response);
+
+            for (name, value) in &self.headers {
+                if !response.headers.contains_key(name) {
+                    response.headers.insert(name.clone(), value.clone());
+                }
+            }
+
+            if let (true, Some(hsts)) = (secure, &self.hsts) {
+                if !response.headers.contains_key("strict-transport-security") {
+                    response.headers.insert("strict-transport-security", hsts.clone());
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+pub struct TrustedProxies {
+    proxies: Vec<Cidr>,
+    header: ProxyHeader,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<Cidr>) -> Self {
+        Self {
+            proxies,
+            header: ProxyHeader::default(),
+        }
+    }
+
+    pub fn with_header(mut self, header: ProxyHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn from_config(proxies: &[String]) -> Result<Self> {
+        let proxies = proxies.iter().map(|p| Cidr::parse(p)).collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(proxies))
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    pub fn resolve(&self, request: &Request) -> Option<IpAddr> {
+        let peer = utils::canonical_ip(request.remote_addr?.ip());
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+
+        let hops = match self.header {
+            ProxyHeader::XForwardedFor => Self::x_forwarded_for_hops(request),
+            ProxyHeader::Forwarded => Self::forwarded_hops(request),
+        };
+        let Some(hops) = hops else {
+            return Some(peer);
+        };
+
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = utils::canonical_ip(*ip);
+                    if !self.is_trusted(client) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        Some(client)
+    }
+
+    fn forwarded_hops(request: &Request) -> Option<Vec<Option<IpAddr>>> {
+        let mut hops = Vec::new();
+        for value in request.headers.get_all("forwarded") {
+            let value = value.to_str().ok()?;
+            for element in value.split(',') {
+                let node = element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim().eq_ignore_ascii_case("for").then_some(value)
+                });
+                if let Some(node) = node {
+                    hops.push(utils::parse_forwarded_node(node));
+                }
+            }
+        }
+        (!hops.is_empty()).then_some(hops)
+    }
+
+    fn x_forwarded_for_hops(request: &Request) -> Option<Vec<Option<IpAddr>>> {
+        let mut hops = Vec::new();
+        for value in request.headers.get_all("x-forwarded-for") {
+            let value = value.to_str().ok()?;
+            hops.extend(value.split(',').filter(|s| !s.trim().is_empty()).map(utils::parse_forwarded_node));
+        }
+        (!hops.is_empty()).then_some(hops)
+    }
+}
+
+impl Middleware for TrustedProxies {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            request.client_ip = self.resolve(&request);
+            next.run(request).await
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self { allow, deny }
+    }
+
+    pub fn from_config(allow: &[String], deny: &[String]) -> Result<Self> {
+        let allow = allow.iter().map(|c| Cidr::parse(c)).collect::<Result<Vec<_>>>()?;
+        let deny = deny.iter().map(|c| Cidr::parse(c)).collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(allow, deny))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+impl Middleware for IpFilter {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            match request.client_ip() {
+                Some(ip) if !self.is_allowed(ip) => Ok(Response::new(StatusCode::FORBIDDEN).with_text("Forbidden")),
+                _ => next.run(request).await,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertAuth {
+    patterns: Vec<String>,
+}
+
+impl ClientCertAuth {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self {
+            patterns: patterns.into_iter().map(|pattern| pattern.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    pub fn is_authorized(&self, identity: &ClientIdentity) -> bool {
+        identity.names().any(|name| {
+            let name = name.to_ascii_lowercase();
+            self.patterns.iter().any(|pattern| utils::glob_match(pattern, &name))
+        })
+    }
+}
+
+impl Middleware for ClientCertAuth {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            match request.client_identity() {
+                Some(identity) if self.is_authorized(identity) => next.run(request).await,
+                Some(_) => Ok(Response::new(StatusCode::FORBIDDEN).with_text("Client certificate not authorized")),
+                None => Ok(Response::new(StatusCode::FORBIDDEN).with_text("Client certificate required")),
+            }
+        })
+    }
+}
\ No newline at end of file
@@ -1,280 +1,2244 @@
 use crate::{
-    config::Config,
+    archive::{self, ArchiveFormat},
+    audit::{AuditLayer, AuditLog},
+    auth::JwtAuth,
+    budget::Reservation,
+    buffer_pool::{BufferPool, BUFFER_CAPACITY},
+    client::HttpClient,
+    config::{self, Config, FileConfig, SharedConfig, TcpConfig},
+    csrf::Csrf,
+    decompression::RequestDecompression,
+    drain::{Drain, DrainReport, DrainSignal},
     error::{Error, Result},
-    http::{Request, Response},
-    router::Router,
-    utils,
+    file_cache::{CachedFile, FileCache},
+    http::{BodyLimit, BodyStream, ClientGone, ConditionalGet, JsonLimit, Request, RequestId, Response},
+    logging,
+    maintenance::Maintenance,
+    methods::{MethodOverride, TraceMethod},
+    metrics::{CountingIo, Metrics},
+    middleware::{Extension, Layers, Middleware, Scoped},
+    mirror::Mirror,
+    parser::{self, ParseOptions},
+    pidfile::PidFile,
+    rate_limit::{RateLimit, RateLimiter},
+    redact::Redactor,
+    redirect::{HttpsRedirect, Redirects},
+    response_cache::ResponseCache,
+    rewrite::Rewrites,
+    router::{RouteOptions, Router},
+    security::{ClientCertAuth, IpFilter, SecurityHeaders, TrustedProxies},
+    site_files::SiteFiles,
+    storage::FileStore,
+    tls::{ReloadableAcceptor, TlsInfo},
+    upload::Upload,
+    utils::{self, etag, range::ByteRanges},
+    webdav,
 };
-use bytes::Bytes;
-use http::{HeaderMap, HeaderValue, Method, Uri, Version};
+use arc_swap::ArcSwap;
+use bytes::{Bytes, BytesMut};
+use http::{HeaderValue, Method, StatusCode, Version};
+use parking_lot::Mutex;
+use socket2::{SockRef, TcpKeepalive};
+use std::future::Future;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info};
+use tokio::sync::{watch, Notify};
+use tracing::{debug, error, field, info, info_span, trace, warn, Instrument, Level, Span};
+
+const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+const LINGER_MAX_BYTES: usize = 1024 * 1024;
+
+const ERROR_MEDIA_TYPES: &[&str] = &["text/plain", "application/json", "text/html"];
+
+const PRECOMPRESSED_ENCODINGS: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+type FileHandler = fn(&Request, &Config, &FileStore, &Metrics) -> Result<Response>;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+// What the middleware built from the configuration needs besides the
+// configuration itself, kept so a reload can rebuild it. The rate limiter
+// stays the same, so its counts survive a reload.
+#[derive(Clone)]
+struct MiddlewareState {
+    layers: Layers,
+    metrics: Arc<Metrics>,
+    http_client: HttpClient,
+    maintenance: Maintenance,
+    rate_limiter: RateLimiter,
+    audit_log: Option<Arc<AuditLog>>,
+    response_cache: Option<ResponseCache>,
+}
+
+// A virtual host's root override, with the configuration and file store built
+// from it, so a reload can update both.
+#[derive(Clone)]
+struct VirtualHost {
+    root_dir: Option<String>,
+    config: SharedConfig,
+    files: Arc<FileStore>,
+}
+
+#[derive(Clone)]
+struct ConfigReloader {
+    path: Option<PathBuf>,
+    config: SharedConfig,
+    files: Arc<FileStore>,
+    virtual_hosts: Vec<VirtualHost>,
+    tls: Option<Arc<ReloadableAcceptor>>,
+    ip_filter: Arc<ArcSwap<IpFilter>>,
+    middleware: MiddlewareState,
+}
+
+impl ConfigReloader {
+    fn reload(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let config = Config::try_load(Some(&path.to_string_lossy()))?;
+            config.validate()?;
+            self.apply(config)?;
+        }
+        if let Some(tls) = &self.tls {
+            tls.reload()?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, config: Config) -> Result<()> {
+        let current = self.config.load_full();
+        let changes = config::changed_fields(&current, &config);
+        if changes.is_empty() {
+            info!("Configuration reloaded, nothing changed");
+            return Ok(());
+        }
+        let ip_filter = IpFilter::from_config(&config.security.ip_allow, &config.security.ip_deny)?;
+        let layers = Server::middleware(&config, &self.middleware)?;
+        for change in &changes {
+            match config::is_reloadable(&change.field) {
+                true => info!("Configuration changed: {}", change),
+                false => warn!("Configuration changed: {} (takes effect after a restart)", change),
+            }
+        }
+
+        for host in &self.virtual_hosts {
+            let host_config = Self::virtual_host_config(&config, host.root_dir.as_deref());
+            host.files.quota.set_root(&host_config.files.root_dir);
+            host.config.store(Arc::new(host_config));
+        }
+        self.files.quota.set_root(&config.files.root_dir);
+        if let Err(e) = logging::reload(&config.logging) {
+            warn!("Failed to apply the new log filter: {}", e);
+        }
+        self.ip_filter.store(Arc::new(ip_filter));
+        self.middleware.layers.replace(layers);
+        self.config.store(Arc::new(config));
+        Ok(())
+    }
+
+    fn virtual_host_config(config: &Config, root_dir: Option<&str>) -> Config {
+        let mut host_config = config.clone();
+        if let Some(root_dir) = root_dir {
+            host_config.files.root_dir = root_dir.to_string();
+        }
+        host_config
+    }
+}
 
 pub struct Server {
-    config: Config,
-    router: Router,
+    config: SharedConfig,
+    router: Arc<Router>,
+    ip_filter: Arc<ArcSwap<IpFilter>>,
+    metrics: Arc<Metrics>,
+    files: Arc<FileStore>,
+    audit_log: Option<Arc<AuditLog>>,
+    tls: Option<Arc<ReloadableAcceptor>>,
+    local_addrs: Mutex<Vec<SocketAddr>>,
+    shutdown: Arc<Notify>,
+    drain: Arc<Drain>,
+    reloader: Arc<ConfigReloader>,
+    maintenance: Maintenance,
+    response_cache: Option<ResponseCache>,
+    http_client: HttpClient,
+    pid_file: Option<PathBuf>,
+    ready_hooks: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
 }
 
-impl Server {
-    pub fn new(config: Config) -> Self {
-        let mut server = Self {
-            config,
-            router: Router::new(),
-        };
-        server.setup_routes();
-        server
+impl Server {
+    pub fn new(config: Config) -> Self {
+        Self::try_new(config).expect("invalid server configuration")
+    }
+
+    pub fn try_new(config: Config) -> Result<Self> {
+        Self::build(config, false)
+    }
+
+    pub fn try_new_read_only(config: Config) -> Result<Self> {
+        Self::build(config, true)
+    }
+
+    fn build(config: Config, read_only: bool) -> Result<Self> {
+        let config = Arc::new(config);
+        let shared: SharedConfig = Arc::new(ArcSwap::new(config.clone()));
+        let ip_filter = IpFilter::from_config(&config.security.ip_allow, &config.security.ip_deny)?;
+        let buffers = BufferPool::new(config.performance.buffer_pool_size, config.performance.max_pooled_buffer_size);
+        let metrics = Arc::new(Metrics::with_buffer_limit(config.performance.max_buffered_bytes).with_buffer_pool(buffers));
+        let files = Arc::new(FileStore::from_config(&config.files));
+        let audit_log = AuditLog::from_config(&config.audit)?.map(Arc::new);
+        let tls = match config.tls.enabled {
+            true => Some(Arc::new(ReloadableAcceptor::from_config(&config)?)),
+            false => None,
+        };
+
+        let mut router = Router::new();
+        router
+            .slash_policy(config.server.slash_policy)
+            .duplicate_routes(config.server.duplicate_routes)
+            .request_timeout(Self::request_timeout(&config))
+            .route_metrics(metrics.clone())
+            .slow_request_threshold(Self::slow_request_threshold(&config));
+        let maintenance = Maintenance::from_config(&config.maintenance)?;
+        let response_cache = config.response_cache.enabled.then(|| ResponseCache::from_config(&config.response_cache));
+        let http_client = HttpClient::from_config(&config.client)?;
+        let middleware = MiddlewareState {
+            layers: Layers::default(),
+            metrics: metrics.clone(),
+            http_client: http_client.clone(),
+            maintenance: maintenance.clone(),
+            rate_limiter: RateLimiter::from_config(&config.security.rate_limit_store)?,
+            audit_log: audit_log.clone(),
+            response_cache: response_cache.clone(),
+        };
+        middleware.layers.replace(Self::middleware(&config, &middleware)?);
+        router.layer(middleware.layers.clone());
+        #[cfg(feature = "templates")]
+        router.layer(Extension(crate::templates::Templates::from_config(&config.templates)?));
+        let site_files = SiteFiles::from_config(&config.site_files)?;
+        site_files.register(&mut router)?;
+        let virtual_hosts = match read_only {
+            true => {
+                Self::setup_directory_routes(&mut router, &shared, &metrics, &files)?;
+                Vec::new()
+            }
+            false => {
+                Self::setup_routes(&mut router, &shared, &metrics, &files)?;
+                Self::setup_virtual_hosts(&mut router, &config, &metrics, &site_files)?
+            }
+        };
+
+        let ip_filter = Arc::new(ArcSwap::from_pointee(ip_filter));
+        let reloader = Arc::new(ConfigReloader {
+            path: None,
+            config: shared.clone(),
+            files: files.clone(),
+            virtual_hosts,
+            tls: tls.clone(),
+            ip_filter: ip_filter.clone(),
+            middleware,
+        });
+
+        Ok(Self {
+            config: shared,
+            router: Arc::new(router),
+            ip_filter,
+            metrics,
+            files,
+            audit_log,
+            tls,
+            local_addrs: Mutex::new(Vec::new()),
+            shutdown: Arc::new(Notify::new()),
+            drain: Arc::new(Drain::new()),
+            reloader,
+            maintenance,
+            response_cache,
+            http_client,
+            pid_file: None,
+            ready_hooks: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn with_pid_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pid_file = Some(path.into());
+        self
+    }
+
+    pub fn on_ready<F>(self, hook: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.ready_hooks.lock().push(Box::new(hook));
+        self
+    }
+
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        let reloader = ConfigReloader {
+            path: Some(path.into()),
+            ..(*self.reloader).clone()
+        };
+        self.reloader = Arc::new(reloader);
+        self
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    pub fn reload_config(&self) -> Result<()> {
+        self.reloader.reload()
+    }
+
+    pub fn apply_config(&self, config: Config) -> Result<()> {
+        config.validate()?;
+        self.reloader.apply(config)
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        let config = self.config();
+        let listeners = self.bind().await?;
+        for listener in &listeners {
+            info!("Server listening on {}{}", listener.local_addr()?, if self.tls.is_some() { " (TLS)" } else { "" });
+        }
+        let _pid_file = self.pid_file.as_ref().map(PidFile::create).transpose()?;
+
+        if let Some(tls) = &self.tls {
+            Self::watch_certificates(tls.clone(), config.tls.reload_interval);
+        }
+        if self.reloader.path.is_some() || self.tls.is_some() {
+            Self::watch_hangup(self.reloader.clone())?;
+        }
+
+        if let Some(port) = config.server.redirect_http_port {
+            let redirect_addr = config::socket_address(&config.server.host, port);
+            let redirect_listener = TcpListener::bind(&redirect_addr).await?;
+            info!("Redirecting plain HTTP on {} to HTTPS", redirect_addr);
+            tokio::spawn(self.serve_https_redirects(redirect_listener));
+        }
+
+        if config.admin.enabled {
+            let admin_addr = config::socket_address(&config.admin.host, config.admin.port);
+            let admin_listener = TcpListener::bind(&admin_addr).await.map_err(|err| Error::Bind(admin_addr.clone(), err))?;
+            info!("Admin listening on {}", admin_addr);
+            tokio::spawn(self.serve_admin(admin_listener));
+        }
+
+        if let (true, Some(metrics_addr)) = (config.metrics.enabled, &config.metrics.address) {
+            let metrics_listener = TcpListener::bind(metrics_addr).await?;
+            info!("Metrics listening on {}", metrics_addr);
+
+            let mut router = Router::new();
+            let metrics = self.metrics.clone();
+            router.get(&config.metrics.path, move |_| Ok(Self::metrics_response(&metrics)));
+
+            tokio::spawn(Self::accept_loop(
+                metrics_listener,
+                self.config.clone(),
+                Arc::new(router),
+                self.ip_filter.clone(),
+                self.metrics.clone(),
+                None,
+                self.drain.clone(),
+            ));
+        }
+
+        for hook in std::mem::take(&mut *self.ready_hooks.lock()) {
+            hook();
+        }
+
+        tokio::select! {
+            result = self.serve_all(listeners) => return result,
+            _ = Self::shutdown_signal() => info!("Shutting down"),
+            _ = self.wait_for_shutdown() => info!("Shutting down on admin request"),
+        }
+        #[cfg(unix)]
+        crate::systemd::notify_stopping();
+        let open = self.drain.open_connections();
+        let report = self.drain().await;
+        info!("Closed {} open connections: {} drained, {} aborted", open, report.drained, report.aborted);
+        Ok(())
+    }
+
+    async fn shutdown_signal() {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            match signal(SignalKind::terminate()) {
+                Ok(mut terminate) => {
+                    tokio::select! {
+                        _ = tokio::signal::ctrl_c() => {}
+                        _ = terminate.recv() => {}
+                    }
+                }
+                Err(_) => {
+                    let _ = tokio::signal::ctrl_c().await;
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+    }
+
+    pub async fn bind(&self) -> Result<Vec<TcpListener>> {
+        #[cfg(unix)]
+        if let Some(listeners) = crate::systemd::inherited_listeners()? {
+            return Ok(listeners);
+        }
+
+        let mut listeners = Vec::new();
+        for address in self.config().server.listen_addresses() {
+            let listener = TcpListener::bind(&address).await.map_err(|err| Error::Bind(address, err))?;
+            listeners.push(listener);
+        }
+        Ok(listeners)
+    }
+
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.local_addrs.lock().clone()
+    }
+
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        self.serve_all(vec![listener]).await
+    }
+
+    pub async fn serve_all(&self, listeners: Vec<TcpListener>) -> Result<()> {
+        let mut loops = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            self.local_addrs.lock().push(listener.local_addr()?);
+            loops.push(Self::accept_loop(
+                listener,
+                self.config.clone(),
+                self.router.clone(),
+                self.ip_filter.clone(),
+                self.metrics.clone(),
+                self.tls.clone(),
+                self.drain.clone(),
+            ));
+        }
+        #[cfg(unix)]
+        crate::systemd::notify_ready();
+        futures::future::try_join_all(loops).await?;
+        Ok(())
+    }
+
+    pub fn serve_https_redirects(&self, listener: TcpListener) -> impl Future<Output = Result<()>> + Send + 'static {
+        Self::accept_loop(
+            listener,
+            self.config.clone(),
+            Arc::new(HttpsRedirect::from_config(&self.config()).into_router()),
+            self.ip_filter.clone(),
+            self.metrics.clone(),
+            None,
+            self.drain.clone(),
+        )
+    }
+
+    pub fn serve_admin(&self, listener: TcpListener) -> impl Future<Output = Result<()>> + Send + 'static {
+        Self::accept_loop(
+            listener,
+            self.config.clone(),
+            Arc::new(self.admin_router()),
+            self.ip_filter.clone(),
+            self.metrics.clone(),
+            None,
+            self.drain.clone(),
+        )
+    }
+
+    fn admin_router(&self) -> Router {
+        let mut router = Router::new();
+
+        let metrics = self.metrics.clone();
+        router.get("/stats", move |_| {
+            let stats = metrics.stats();
+            let [informational, success, redirection, client_error, server_error] = stats.requests_by_class;
+            Response::ok().with_json(&serde_json::json!({
+                "uptime_seconds": stats.uptime_seconds,
+                "connections_accepted": stats.connections_accepted,
+                "active_connections": stats.active_connections,
+                "requests": {
+                    "total": stats.requests_total,
+                    "1xx": informational,
+                    "2xx": success,
+                    "3xx": redirection,
+                    "4xx": client_error,
+                    "5xx": server_error,
+                },
+                "bytes": { "read": stats.bytes_read, "written": stats.bytes_written },
+                "keep_alive": { "reused_requests": stats.reused_requests, "reuse_ratio": stats.keep_alive_reuse_ratio },
+                "file_cache": { "hits": stats.file_cache_hits, "misses": stats.file_cache_misses, "hit_rate": stats.file_cache_hit_rate },
+                "routes": metrics.route_latencies(),
+            }))
+        });
+
+        let shutdown = self.shutdown.clone();
+        router.post("/shutdown", move |_| {
+            info!("Shutdown requested through the admin listener");
+            shutdown.notify_one();
+            Response::new(StatusCode::ACCEPTED).with_json(&serde_json::json!({ "status": "shutting down" }))
+        });
+
+        let reloader = self.reloader.clone();
+        router.post("/reload-config", move |_| {
+            reloader.reload()?;
+            Response::ok().with_json(&serde_json::json!({ "status": "reloaded" }))
+        });
+
+        let maintenance = self.maintenance.clone();
+        router.get("/maintenance", move |_| Response::ok().with_json(&serde_json::json!({ "enabled": maintenance.is_enabled() })));
+
+        let maintenance = self.maintenance.clone();
+        router.post("/maintenance", move |request| {
+            let state: serde_json::Value = request.json()?;
+            let enabled = state["enabled"]
+                .as_bool()
+                .ok_or_else(|| Error::BadRequest("Expected a JSON body like {\"enabled\": true}".to_string()))?;
+            info!("Maintenance mode {} through the admin listener", if enabled { "enabled" } else { "disabled" });
+            maintenance.set_enabled(enabled);
+            Response::ok().with_json(&serde_json::json!({ "enabled": enabled }))
+        });
+
+        if let Some(response_cache) = self.response_cache.clone() {
+            router.post("/cache/purge", move |request| {
+                let body: serde_json::Value = request.json()?;
+                let prefix = body["prefix"]
+                    .as_str()
+                    .ok_or_else(|| Error::BadRequest("Expected a JSON body like {\"prefix\": \"/api\"}".to_string()))?;
+                let purged = response_cache.purge(prefix);
+                info!("Purged {} cached responses under {} through the admin listener", purged, prefix);
+                Response::ok().with_json(&serde_json::json!({ "purged": purged }))
+            });
+        }
+
+        let routes = self.router.clone();
+        router.get("/routes", move |_| {
+            let routes: Vec<_> = routes
+                .routes()
+                .into_iter()
+                .map(|route| serde_json::json!({ "method": route.method.as_str(), "pattern": route.pattern, "params": route.param_names }))
+                .collect();
+            Response::ok().with_json(&routes)
+        });
+
+        router
+    }
+
+    pub fn response_cache(&self) -> Option<&ResponseCache> {
+        self.response_cache.as_ref()
+    }
+
+    pub fn set_maintenance(&self, enabled: bool) {
+        info!("Maintenance mode {}", if enabled { "enabled" } else { "disabled" });
+        self.maintenance.set_enabled(enabled);
+    }
+
+    pub fn is_maintenance(&self) -> bool {
+        self.maintenance.is_enabled()
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+
+    // Stops keep-alive connections from taking new requests and waits up to
+    // `server.shutdown_grace_seconds` for them to close before aborting the
+    // rest. Accept loops keep running; `run` drops them first.
+    pub async fn drain(&self) -> DrainReport {
+        let grace = Duration::from_secs(self.config().server.shutdown_grace_seconds);
+        self.drain.drain(grace).await
+    }
+
+    pub async fn wait_for_shutdown(&self) {
+        self.shutdown.notified().await
+    }
+
+    pub fn reload_tls(&self) -> Result<()> {
+        match &self.tls {
+            Some(tls) => tls.reload(),
+            None => Err(Error::Config("TLS is not enabled".to_string())),
+        }
+    }
+
+    fn watch_certificates(tls: Arc<ReloadableAcceptor>, interval_secs: u64) {
+        if interval_secs > 0 {
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = tls.reload_if_changed() {
+                        error!("Failed to reload TLS certificates, keeping the current ones: {}", e);
+                    }
+                }
+            });
+        }
+    }
+
+    fn watch_hangup(reloader: Arc<ConfigReloader>) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangup = signal(SignalKind::hangup())?;
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    info!("SIGHUP received, reloading configuration and TLS certificates");
+                    if let Err(e) = reloader.reload() {
+                        error!("Reload failed, keeping the current configuration: {}", e);
+                    }
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        let _ = reloader;
+
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn http_client(&self) -> &HttpClient {
+        &self.http_client
+    }
+
+    pub fn file_cache(&self) -> &FileCache {
+        &self.files.cache
+    }
+
+    pub fn files(&self) -> &Arc<FileStore> {
+        &self.files
+    }
+
+    pub fn audit_log(&self) -> Option<&Arc<AuditLog>> {
+        self.audit_log.as_ref()
+    }
+
+    // Serves one connection over any byte stream, such as a stream accepted
+    // by another listener or an in-memory pipe in tests and benchmarks. The
+    // connection is tracked for draining like an accepted socket.
+    pub async fn serve_connection<S>(&self, stream: S, addr: SocketAddr) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let _guard = self.drain.track();
+        let config = self.config.load_full();
+        let header_deadline = Self::header_deadline(&config);
+        Self::handle_connection(stream, addr, config, self.router.clone(), self.ip_filter.load_full(), self.metrics.clone(), None, header_deadline, self.drain.signal())
+            .await
+    }
+
+    async fn accept_loop(
+        listener: TcpListener,
+        config: SharedConfig,
+        router: Arc<Router>,
+        ip_filter: Arc<ArcSwap<IpFilter>>,
+        metrics: Arc<Metrics>,
+        tls: Option<Arc<ReloadableAcceptor>>,
+        drain: Arc<Drain>,
+    ) -> Result<()> {
+        loop {
+            match listener.accept().await {
+                Ok((socket, addr)) => {
+                    let config = config.load_full();
+                    // The head deadline runs from accept, so a client that stalls
+                    // the TLS handshake uses up the same allowance.
+                    let header_deadline = Self::header_deadline(&config);
+                    Self::configure_socket(&socket, &config.performance.tcp);
+                    let router = router.clone();
+                    let ip_filter = ip_filter.load_full();
+                    let metrics = metrics.clone();
+                    let disconnects = metrics.clone();
+                    let tls = tls.clone();
+                    let mut guard = drain.track();
+                    let shutdown = drain.signal();
+                    let mut abort = drain.signal();
+                    let span = info_span!("connection", peer = %addr, tls.version = field::Empty, tls.cipher = field::Empty);
+                    
+                    tokio::spawn(
+                        async move {
+                            let connection = async move {
+                                match tls {
+                                    Some(tls) => match Self::until(header_deadline, tls.acceptor().accept(socket)).await {
+                                        None => {
+                                            debug!("TLS handshake with {} timed out", addr);
+                                            Ok(())
+                                        }
+                                        Some(Ok(stream)) => {
+                                            let info = TlsInfo::from_connection(stream.get_ref().1);
+                                            if let Some(info) = &info {
+                                                Span::current()
+                                                    .record("tls.version", field::debug(info.protocol))
+                                                    .record("tls.cipher", field::debug(info.cipher_suite));
+                                            }
+                                            Self::handle_connection(stream, addr, config, router, ip_filter, metrics, info, header_deadline, shutdown).await
+                                        }
+                                        Some(Err(e)) => {
+                                            debug!("TLS handshake with {} failed: {}", addr, e);
+                                            Ok(())
+                                        }
+                                    },
+                                    None => Self::handle_connection(socket, addr, config, router, ip_filter, metrics, None, header_deadline, shutdown).await,
+                                }
+                            };
+                            let result = tokio::select! {
+                                result = connection => result,
+                                _ = abort.aborting() => {
+                                    debug!("Aborting connection from {} after the shutdown grace period", addr);
+                                    guard.abort();
+                                    Ok(())
+                                }
+                            };
+                            drop(guard);
+                            match result {
+                                Err(e) if Self::is_disconnect(&e) => {
+                                    disconnects.record_client_disconnect();
+                                    debug!("Client {} went away: {}", addr, e);
+                                }
+                                Err(e) => error!("Connection error: {}", e),
+                                Ok(()) => {}
+                            }
+                        }
+                        .instrument(span),
+                    );
+                }
+                Err(e) => {
+                    error!("Accept error: {}", e);
+                }
+            }
+        }
+    }
+
+    // Failures are logged and the connection is served with whatever options
+    // did apply.
+    pub(crate) fn configure_socket(socket: &TcpStream, tcp: &TcpConfig) {
+        let socket = SockRef::from(socket);
+        if let Err(e) = socket.set_tcp_nodelay(tcp.nodelay) {
+            warn!("Failed to set TCP_NODELAY: {}", e);
+        }
+        if tcp.keepalive_secs > 0 {
+            let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(tcp.keepalive_secs));
+            if let Err(e) = socket.set_tcp_keepalive(&keepalive) {
+                warn!("Failed to enable TCP keepalive: {}", e);
+            }
+        }
+        if let Some(size) = tcp.recv_buffer_size {
+            if let Err(e) = socket.set_recv_buffer_size(size) {
+                warn!("Failed to set the receive buffer size to {}: {}", size, e);
+            }
+        }
+        if let Some(size) = tcp.send_buffer_size {
+            if let Err(e) = socket.set_send_buffer_size(size) {
+                warn!("Failed to set the send buffer size to {}: {}", size, e);
+            }
+        }
+    }
+
+    fn is_disconnect(error: &Error) -> bool {
+        matches!(
+            error,
+            Error::Io(e) if matches!(e.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted)
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn handle_connection<S>(
+        socket: S,
+        addr: SocketAddr,
+        config: Arc<Config>,
+        router: Arc<Router>,
+        ip_filter: Arc<IpFilter>,
+        metrics: Arc<Metrics>,
+        tls_info: Option<TlsInfo>,
+        mut header_deadline: Option<tokio::time::Instant>,
+        mut shutdown: DrainSignal,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut stream = CountingIo::new(socket, metrics.clone());
+        let _connection = metrics.connection_opened();
+
+        if !ip_filter.is_allowed(addr.ip()) {
+            debug!("Rejecting connection from denied address {}", addr);
+            if !config.security.drop_denied_connections {
+                let response = Response::new(StatusCode::FORBIDDEN).with_text("Forbidden");
+                Self::send_response(&mut stream, response).await?;
+            }
+            return Ok(());
+        }
+
+        let buffer_wait = Duration::from_millis(config.performance.buffer_wait_ms);
+        let pool = metrics.buffer_pool();
+        let mut buffer = pool.get();
+        let mut buffered = metrics.buffer_budget().reservation();
+        if !buffered.grow_to(buffer.capacity(), buffer_wait).await {
+            return Self::reject_over_budget(&mut stream, addr).await;
+        }
+
+        let mut parse_state = parser::ParseState::default();
+        let parse_options = ParseOptions::from_config(&config);
+        let wire_limit = config.logging.dump_wire_limit;
+        let wire_connection = (config.logging.dump_wire && tracing::enabled!(target: logging::WIRE_TARGET, Level::TRACE))
+            .then(|| NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed));
+        let mut expectation_checked = false;
+        let mut body_progress = None;
+        let keep_alive_timeout = Duration::from_secs(config.performance.keep_alive_timeout);
+        let mut served = 0;
+        let mut pipelined = false;
+        
+        loop {
+            if !std::mem::take(&mut pipelined) {
+                if buffer.capacity() - buffer.len() < 1024 && !Self::reserve_buffer(&mut buffer, 4096, &mut buffered, buffer_wait).await {
+                    return Self::reject_over_budget(&mut stream, addr).await;
+                }
+                let idle = served > 0 && buffer.is_empty();
+                let deadline = match body_progress {
+                    Some((started, received)) => Some(Self::body_deadline(started, received, &config)),
+                    None => header_deadline,
+                };
+                if idle && shutdown.is_draining() {
+                    break;
+                }
+                let read = stream.read_buf(&mut *buffer);
+                let n = if idle {
+                    let _idle = metrics.keep_alive_started();
+                    tokio::select! {
+                        read = tokio::time::timeout(keep_alive_timeout, read) => match read {
+                            Ok(n) => n?,
+                            Err(_) => {
+                                debug!("Closing idle keep-alive connection from {}", addr);
+                                break;
+                            }
+                        },
+                        _ = shutdown.draining() => {
+                            debug!("Closing idle keep-alive connection from {} for shutdown", addr);
+                            break;
+                        }
+                    }
+                } else {
+                    match deadline {
+                        Some(deadline) => match tokio::time::timeout_at(deadline, read).await {
+                            Ok(n) => n?,
+                            Err(_) => return Self::reject_slow_client(&mut stream, addr, body_progress.is_some()).await,
+                        },
+                        None => read.await?,
+                    }
+                };
+                if n == 0 {
+                    break;
+                }
+                if idle {
+                    header_deadline = Self::header_deadline(&config);
+                }
+                if let Some((_, received)) = &mut body_progress {
+                    *received += n;
+                }
+            }
+
+            #[cfg(feature = "http2")]
+            if crate::http2::PREFACE.starts_with(&buffer[..buffer.len().min(crate::http2::PREFACE.len())]) {
+                if buffer.len() < crate::http2::PREFACE.len() {
+                    continue;
+                }
+                let io = crate::http2::Rewind::new(std::mem::take(&mut *buffer).freeze(), stream);
+                return crate::http2::serve_connection(io, addr, tls_info, config, router, metrics.clone()).await;
+            }
+            
+            let received = wire_connection.map(|_| (buffer.len(), buffer[..buffer.len().min(wire_limit)].to_vec()));
+            let mut request = match parser::parse_request_with(&mut buffer, &mut parse_state, &parse_options) {
+                Ok(Some(request)) => request,
+                Ok(None) => {
+                    if !expectation_checked {
+                        if let Some(head) = parse_state.pending_head() {
+                            expectation_checked = true;
+                            let body_limit = Self::body_limit(head, &config, &router);
+                            if let Some(response) = Self::check_expectation(head, &config, &router) {
+                                Self::send_response(&mut stream, response.with_header("connection", "close")).await?;
+                                Self::linger_close(&mut stream).await;
+                                return Ok(());
+                            }
+                            let remaining = head.content_length().unwrap_or(0);
+                            let expects_continue = Self::expects_continue(head);
+                            parse_state.body_limit = Some(body_limit);
+                            if !Self::reserve_buffer(&mut buffer, remaining, &mut buffered, buffer_wait).await {
+                                return Self::reject_over_budget(&mut stream, addr).await;
+                            }
+                            if expects_continue {
+                                stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+                                stream.flush().await?;
+                            }
+                            if config.security.min_body_rate > 0 {
+                                body_progress = Some((tokio::time::Instant::now(), 0));
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    metrics.record_parse_error();
+                    let first_line = buffer[..].split(|&b| b == b'\n').next().unwrap_or_default();
+                    let span = info_span!("request", method = field::Empty, path = field::Empty, query = field::Empty, remote_addr = %addr, request_id = field::Empty, status = field::Empty, latency_ms = field::Empty);
+                    span.in_scope(|| debug!("Rejecting malformed request from {}: {} [{}]", addr, e, parser::preview_line(&parse_options.redactor.request_line(first_line))));
+                    let response = Self::parse_error_response(e);
+                    span.record("status", response.status.as_u16());
+                    Self::send_response(&mut stream, response).instrument(span).await?;
+                    Self::linger_close(&mut stream).await;
+                    return Ok(());
+                }
+            };
+
+            let started = Instant::now();
+            let span = Self::request_span(&mut request, addr, &parse_options.redactor);
+            if let (Some(connection_id), Some((buffered_before, mut raw))) = (wire_connection, received) {
+                let bytes = buffered_before - buffer.len();
+                raw.truncate(bytes);
+                let dump = logging::hex_dump(&parse_options.redactor.message(&raw), bytes - raw.len());
+                span.in_scope(|| trace!(target: logging::WIRE_TARGET, connection_id, bytes, "request bytes:{}", dump));
+            }
+            if let Err(e) = Self::reconcile_target(&mut request) {
+                metrics.record_parse_error();
+                span.in_scope(|| debug!("Rejecting request from {}: {}", addr, e));
+                let response = Self::parse_error_response(e).with_version(request.version);
+                span.record("status", response.status.as_u16());
+                Self::send_response(&mut stream, response).instrument(span).await?;
+                Self::linger_close(&mut stream).await;
+                return Ok(());
+            }
+            request.remote_addr = Some(addr);
+            if let Some(info) = &tls_info {
+                if let Some(identity) = &info.client_identity {
+                    request.extensions.insert(identity.clone());
+                }
+                request.extensions.insert(info.clone());
+            }
+            served += 1;
+            if served > 1 {
+                metrics.record_reused_request();
+            }
+            let version = request.version;
+            let max_requests = config.performance.max_requests_per_connection;
+            let last = !Self::wants_keep_alive(&request)
+                || keep_alive_timeout.is_zero()
+                || (max_requests > 0 && served >= max_requests);
+            let quiet = config.site_files.is_quiet(request.path());
+            let (gone, client_gone) = watch::channel(false);
+            request.extensions.insert(ClientGone(client_gone));
+            let respond = Self::respond(request, &config, &router, &metrics).instrument(span.clone());
+            tokio::pin!(respond);
+            // End of stream may only be a half-close after the request, so by
+            // default the handler is left to finish and any requests already
+            // read are answered before the connection closes; only a failed
+            // read cancels it. Without `allow_half_close`, end of stream
+            // cancels the request too.
+            let allow_half_close = config.performance.allow_half_close;
+            let spill_limit = config.security.max_request_size;
+            let mut read_closed = false;
+            let mut overflowed = false;
+            let response = loop {
+                tokio::select! {
+                    biased;
+                    response = &mut respond => break response,
+                    read = Self::read_while_responding(&mut stream, &mut buffer, &mut buffered, spill_limit, &mut overflowed), if !read_closed => match read {
+                        Ok(0) if allow_half_close => read_closed = true,
+                        Ok(0) | Err(_) => {
+                            gone.send_replace(true);
+                            metrics.record_client_disconnect();
+                            span.in_scope(|| debug!("Client {} went away, cancelling the request", addr));
+                            return Ok(());
+                        }
+                        Ok(_) => {}
+                    },
+                }
+            };
+            let last = last || overflowed || shutdown.is_draining();
+            let (response, close) = Self::connection_headers(response, version, last, &config, served);
+            let status = response.status;
+            span.in_scope(|| trace!(headers = %parse_options.redactor.headers(&response.headers), "response headers"));
+            if let Some(connection_id) = wire_connection {
+                let mut head = Vec::new();
+                response.write_head(&mut head);
+                let head = parse_options.redactor.message(&head);
+                let shown = head.len().min(wire_limit);
+                let dump = logging::hex_dump(&head[..shown], head.len() - shown);
+                span.in_scope(|| trace!(target: logging::WIRE_TARGET, connection_id, bytes = head.len(), "response head:{}", dump));
+            }
+            let mut head = pool.get();
+            Self::send_response_with(&mut stream, response, &mut head).instrument(span.clone()).await?;
+            drop(head);
+            Self::finish_request_span(&span, status, started, quiet);
+            if close {
+                break;
+            }
+
+            expectation_checked = false;
+            body_progress = None;
+            pipelined = !buffer.is_empty();
+            if pipelined {
+                header_deadline = Self::header_deadline(&config);
+            }
+            Self::reclaim_buffer(&mut buffer, &mut buffered, pool);
+        }
+        
+        Ok(())
+    }
+
+    pub(crate) fn request_span(request: &mut Request, addr: SocketAddr, redactor: &Redactor) -> Span {
+        let request_id = request
+            .header("x-request-id")
+            .and_then(|value| value.to_str().ok())
+            .map_or_else(|| uuid::Uuid::new_v4().to_string(), str::to_string);
+        request.extensions.insert(RequestId(request_id.clone()));
+        let span = info_span!(
+            "request",
+            method = %request.method,
+            path = request.path(),
+            query = field::Empty,
+            remote_addr = %addr,
+            request_id = %request_id,
+            route = field::Empty,
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        if let Some(query) = request.uri.query() {
+            span.record("query", redactor.query(query).as_ref());
+        }
+        // The trace parent must be set before the span is first entered.
+        #[cfg(feature = "otel")]
+        crate::otel::start_request(&span, request);
+        span.in_scope(|| trace!(headers = %redactor.headers(&request.headers), "request headers"));
+        span
+    }
+
+    pub(crate) fn finish_request_span(span: &Span, status: StatusCode, started: Instant, quiet: bool) {
+        #[cfg(feature = "otel")]
+        crate::otel::finish_request(span, status);
+        let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+        span.record("status", status.as_u16()).record("latency_ms", latency_ms);
+        match quiet {
+            true => span.in_scope(|| debug!("request completed")),
+            false => span.in_scope(|| info!("request completed")),
+        }
+    }
+
+    fn reconcile_target(request: &mut Request) -> Result<()> {
+        if request.uri == "*" {
+            if request.method != Method::OPTIONS {
+                return Err(Error::BadRequest(format!("Asterisk-form target is not allowed for {}", request.method)));
+            }
+        } else if let Some(authority) = request.uri.authority().cloned() {
+            match request.headers.get("host") {
+                Some(host) if !host.as_bytes().eq_ignore_ascii_case(authority.as_str().as_bytes()) => {
+                    return Err(Error::BadRequest(format!("Host header does not match the request target authority {}", authority)));
+                }
+                Some(_) => {}
+                None => {
+                    let host = HeaderValue::from_str(authority.as_str()).map_err(|_| Error::BadRequest("Invalid request target authority".to_string()))?;
+                    request.headers.insert("host", host);
+                }
+            }
+            let path_and_query = request.uri.path_and_query().map_or("/", |path| path.as_str());
+            request.uri = path_and_query.parse().map_err(|_| Error::BadRequest(format!("Invalid request target: {}", path_and_query)))?;
+        }
+
+        if request.version == Version::HTTP_11 {
+            match request.headers.get_all("host").iter().count() {
+                0 => return Err(Error::BadRequest("Missing Host header".to_string())),
+                1 => {}
+                _ => return Err(Error::BadRequest("Multiple Host headers".to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn header_deadline(config: &Config) -> Option<tokio::time::Instant> {
+        match config.security.header_timeout {
+            0 => None,
+            secs => Some(tokio::time::Instant::now() + Duration::from_secs(secs)),
+        }
+    }
+
+    async fn until<F: Future>(deadline: Option<tokio::time::Instant>, future: F) -> Option<F::Output> {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+            None => Some(future.await),
+        }
+    }
+
+    fn wants_keep_alive(request: &Request) -> bool {
+        let has_token = |token: &str| {
+            request
+                .headers
+                .get_all("connection")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        };
+        match request.version {
+            Version::HTTP_11 => !has_token("close"),
+            Version::HTTP_10 => has_token("keep-alive"),
+            _ => false,
+        }
+    }
+
+    fn connection_headers(mut response: Response, version: Version, last: bool, config: &Config, served: usize) -> (Response, bool) {
+        response.version = version;
+        response.headers.entry("date").or_insert_with(utils::http_date::current_header);
+        if version == Version::HTTP_10 && response.headers.remove("transfer-encoding").is_some() {
+            if response.stream.is_some() {
+                response.headers.insert("connection", HeaderValue::from_static("close"));
+                return (response, true);
+            }
+            let length = response.body.as_ref().map_or(0, |body| body.len());
+            response.headers.insert("content-length", HeaderValue::from(length));
+        }
+
+        let close = last
+            || response
+                .headers
+                .get("connection")
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.split(',').any(|token| token.trim().eq_ignore_ascii_case("close")));
+        if close {
+            if !response.headers.contains_key("connection") {
+                response.headers.insert("connection", HeaderValue::from_static("close"));
+            }
+            return (response, true);
+        }
+
+        let status = response.status.as_u16();
+        let has_body = !(100..200).contains(&status) && status != 204 && status != 304;
+        if has_body && !response.headers.contains_key("content-length") && !response.headers.contains_key("transfer-encoding") {
+            let length = response.body.as_ref().map_or(0, |body| body.len());
+            response.headers.insert("content-length", HeaderValue::from(length));
+        }
+        if version == Version::HTTP_10 {
+            response.headers.insert("connection", HeaderValue::from_static("keep-alive"));
+        }
+        let mut keep_alive = format!("timeout={}", config.performance.keep_alive_timeout);
+        if config.performance.max_requests_per_connection > 0 {
+            keep_alive.push_str(&format!(", max={}", config.performance.max_requests_per_connection - served));
+        }
+        if let Ok(value) = HeaderValue::from_str(&keep_alive) {
+            response.headers.insert("keep-alive", value);
+        }
+        (response, false)
+    }
+
+    fn reclaim_buffer(buffer: &mut BytesMut, buffered: &mut Reservation<'_>, pool: &BufferPool) {
+        if buffer.capacity() > BUFFER_CAPACITY && buffer.len() <= BUFFER_CAPACITY {
+            let mut fresh = BytesMut::with_capacity(BUFFER_CAPACITY);
+            fresh.extend_from_slice(buffer);
+            pool.put(std::mem::replace(buffer, fresh));
+        }
+        buffered.shrink_to(buffer.capacity());
+    }
+
+    fn expects_continue(request: &Request) -> bool {
+        request.version == Version::HTTP_11
+            && request
+                .header("expect")
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("100-continue"))
+    }
+
+    // The route's `max_body_size`, or `security.max_request_size` for routes
+    // without one.
+    pub(crate) fn body_limit(request: &Request, config: &Config, router: &Router) -> usize {
+        router
+            .route_options(request)
+            .and_then(|options| options.max_body_size)
+            .unwrap_or(config.security.max_request_size)
+    }
+
+    fn check_expectation(request: &Request, config: &Config, router: &Router) -> Option<Response> {
+        let max_body_size = Self::body_limit(request, config, router);
+        if let Some(length) = request.content_length().filter(|&length| length > max_body_size) {
+            return Some(Self::error_response_for(request, Error::ContentTooLarge { limit: max_body_size, actual: Some(length) }));
+        }
+        if request.version != Version::HTTP_11 || request.header("expect").is_none() {
+            return None;
+        }
+        if !Self::expects_continue(request) {
+            return Some(Response::new(StatusCode::EXPECTATION_FAILED).with_text("Expectation Failed"));
+        }
+        if !router.recognizes(request) {
+            return Some(Self::error_response_for(request, Error::RouteNotFound(request.path().to_string())));
+        }
+        None
+    }
+
+    pub async fn handle_request(&self, request: Request) -> Response {
+        Self::serve_request(request, &self.config(), &self.router, &self.metrics).await
+    }
+
+    pub(crate) async fn serve_request(mut request: Request, config: &Config, router: &Router, metrics: &Metrics) -> Response {
+        let addr = request.remote_addr.unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 0)));
+        let started = Instant::now();
+        let span = Self::request_span(&mut request, addr, &Redactor::from_config(&config.logging));
+        let quiet = config.site_files.is_quiet(request.path());
+        let response = match Self::reconcile_target(&mut request) {
+            Ok(()) => {
+                request.remote_addr = Some(addr);
+                Self::respond(request, config, router, metrics).instrument(span.clone()).await
+            }
+            Err(e) => Self::parse_error_response(e).with_version(request.version),
+        };
+        Self::finish_request_span(&span, response.status, started, quiet);
+        response
+    }
+
+    pub(crate) async fn respond(request: Request, config: &Config, router: &Router, metrics: &Metrics) -> Response {
+        let method = request.method.clone();
+        let started = Instant::now();
+        let response = Self::process_request(request, config, router).await;
+        let body_len = response.body.as_ref().map(|b| b.len()).unwrap_or(0);
+        metrics.record_request(&method, response.status, started.elapsed(), body_len);
+        response
+    }
+
+    async fn process_request(mut request: Request, config: &Config, router: &Router) -> Response {
+        request.extensions.insert(JsonLimit(config.security.max_json_size));
+        request.extensions.insert(BodyLimit(config.security.max_request_size));
+        let conditional = ConditionalGet::from_request(&request);
+        let error_response = Self::error_responder(&request);
+        if request.uri == "*" {
+            let methods = router.allowed_methods();
+            let allow: Vec<&str> = methods.iter().map(Method::as_str).collect();
+            return Response::ok().with_header("allow", &allow.join(", ")).with_content_length(0);
+        }
+        match router.handle(request).await {
+            Ok(response) => conditional.apply(response),
+            Err(e) => error_response(e),
+        }
+    }
+
+    // Keeps what an error response needs from the request, for callers that
+    // hand the request on before they know whether it failed.
+    pub(crate) fn error_responder(request: &Request) -> impl FnOnce(Error) -> Response {
+        let media_type = request.preferred(ERROR_MEDIA_TYPES).unwrap_or("text/plain");
+        let request_id = request.request_id().map(str::to_string);
+        move |error| Self::error_response_as(error, media_type, request_id.as_deref())
+    }
+
+    pub(crate) fn error_response_for(request: &Request, error: Error) -> Response {
+        let media_type = request.preferred(ERROR_MEDIA_TYPES).unwrap_or("text/plain");
+        Self::error_response_as(error, media_type, request.request_id())
+    }
+
+    fn error_response_as(error: Error, media_type: &str, request_id: Option<&str>) -> Response {
+        if let Error::Custom(error) = error {
+            if error.status_code().is_server_error() {
+                error!("Request failed: {}", error);
+            }
+            return error.error_response();
+        }
+        if let Error::TooManyRequests(retry_after) = error {
+            let response = Self::error_body(error, media_type, request_id);
+            return response.with_header("retry-after", &retry_after.to_string());
+        }
+        Self::error_body(error, media_type, request_id)
+    }
+
+    // JSON clients get `{"error": {"code", "message", "details", "request_id"}}`
+    // and browsers an HTML page with the same fields. Server errors are logged
+    // and only their reason phrase is sent.
+    fn error_body(error: Error, media_type: &str, request_id: Option<&str>) -> Response {
+        let status = error.status_code();
+        let reason = status.canonical_reason().unwrap_or("Error");
+        let code = error.code();
+        let details = error.details();
+        let message = match status.is_server_error() {
+            true => {
+                error!("Request failed: {}", error);
+                reason.to_string()
+            }
+            false => error.to_string(),
+        };
+
+        match media_type {
+            "application/json" => {
+                let body = serde_json::json!({
+                    "error": { "code": code, "message": message, "details": details, "request_id": request_id },
+                });
+                Response::new(status).with_json(&body).unwrap_or_else(|_| Response::new(status).with_text(&message))
+            }
+            "text/html" => {
+                let mut extra = String::new();
+                if let Some(serde_json::Value::Object(details)) = &details {
+                    let rows: String = details
+                        .iter()
+                        .map(|(name, value)| format!("<dt>{}</dt><dd>{}</dd>", utils::escape_html(name), utils::escape_html(&value.to_string())))
+                        .collect();
+                    extra.push_str(&format!("<dl>{}</dl>\n", rows));
+                }
+                if let Some(request_id) = request_id {
+                    extra.push_str(&format!("<p><small>Request ID: {}</small></p>\n", utils::escape_html(request_id)));
+                }
+                Response::new(status).with_html(&format!(
+                    "<!DOCTYPE html>\n<html>\n<head><title>{code} {reason}</title></head>\n<body>\n<h1>{code} {reason}</h1>\n<p>{message}</p>\n{extra}</body>\n</html>",
+                    code = status.as_u16(),
+                    reason = reason,
+                    message = utils::escape_html(&message),
+                    extra = extra
+                ))
+            }
+            _ => Response::new(status).with_text(&message),
+        }
+    }
+
+    fn parse_error_response(error: Error) -> Response {
+        let status = match error.status_code() {
+            status if status.is_client_error() => status,
+            StatusCode::NOT_IMPLEMENTED | StatusCode::HTTP_VERSION_NOT_SUPPORTED => error.status_code(),
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Response::new(status)
+            .with_text(&error.to_string())
+            .with_header("connection", "close")
+    }
+
+    fn metrics_response(metrics: &Metrics) -> Response {
+        Response::ok()
+            .with_content_type("text/plain; version=0.0.4")
+            .with_body(metrics.render())
+    }
+
+    async fn send_response<S: AsyncWrite + Unpin>(stream: &mut S, response: Response) -> Result<()> {
+        let mut head = BytesMut::with_capacity(128 + response.headers.len() * 32);
+        Self::send_response_with(stream, response, &mut head).await
+    }
+
+    async fn send_response_with<S: AsyncWrite + Unpin>(stream: &mut S, mut response: Response, head: &mut BytesMut) -> Result<()> {
+        response.headers.entry("date").or_insert_with(utils::http_date::current_header);
+        response.write_with(stream, head).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    async fn reserve_buffer(buffer: &mut BytesMut, additional: usize, buffered: &mut Reservation<'_>, wait: Duration) -> bool {
+        if buffer.capacity() - buffer.len() >= additional {
+            return true;
+        }
+        let needed = (buffer.capacity() * 2).max(buffer.len() + additional);
+        if !buffered.grow_to(needed, wait).await {
+            return false;
+        }
+        buffer.reserve(additional);
+        buffered.account(buffer.capacity());
+        true
+    }
+
+    // Reads from the client while a handler runs, so a disconnect is noticed
+    // even when the buffer is full. Pipelined bytes grow the buffer within the
+    // buffer budget up to `limit`. Past that they are discarded and `overflowed`
+    // is set, so the connection closes after the current response.
+    async fn read_while_responding<S: AsyncRead + Unpin>(
+        stream: &mut S,
+        buffer: &mut BytesMut,
+        buffered: &mut Reservation<'_>,
+        limit: usize,
+        overflowed: &mut bool,
+    ) -> std::io::Result<usize> {
+        if buffer.len() == buffer.capacity() {
+            let grown = buffer.capacity() * 2;
+            if *overflowed || buffer.len() >= limit || !buffered.try_grow_to(grown) {
+                let mut discard = [0; 1024];
+                let n = stream.read(&mut discard).await?;
+                *overflowed |= n > 0;
+                return Ok(n);
+            }
+            buffer.reserve(grown - buffer.len());
+            buffered.account(buffer.capacity());
+        }
+        stream.read_buf(buffer).await
+    }
+
+    fn body_deadline(started: tokio::time::Instant, received: usize, config: &Config) -> tokio::time::Instant {
+        let window = Duration::from_secs(config.security.body_rate_window);
+        let earned = Duration::from_secs_f64(received as f64 / config.security.min_body_rate as f64);
+        started + window.max(earned)
     }
 
-    pub async fn run(&self) -> Result<()> {
-        let addr = format!("{}:{}", self.config.server.host, self.config.server.port);
-        let listener = TcpListener::bind(&addr).await?;
-        
-        info!("Server listening on {}", addr);
-        
-        loop {
-            match listener.accept().await {
-                Ok((socket, addr)) => {
-                    let config = self.config.clone();
-                    let router = self.router.clone();
-                    
-                    tokio::spawn(async move {
-                        if let Err(e) = Self::handle_connection(socket, addr, config, router).await {
-                            error!("Connection error: {}", e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    error!("Accept error: {}", e);
+    async fn reject_slow_client<S: AsyncWrite + Unpin>(stream: &mut S, addr: SocketAddr, in_body: bool) -> Result<()> {
+        let phase = if in_body { "body below the minimum transfer rate" } else { "headers not received in time" };
+        debug!("Closing slow connection from {}: {}", addr, phase);
+        let response = Response::new(StatusCode::REQUEST_TIMEOUT)
+            .with_header("connection", "close")
+            .with_text("Request Timeout");
+        Self::send_response(stream, response).await?;
+        let _ = stream.shutdown().await;
+        Ok(())
+    }
+
+    async fn reject_over_budget<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, addr: SocketAddr) -> Result<()> {
+        debug!("Rejecting request from {}: buffer budget exhausted", addr);
+        let response = Response::new(StatusCode::SERVICE_UNAVAILABLE)
+            .with_header("retry-after", "1")
+            .with_header("connection", "close")
+            .with_text("Service Unavailable");
+        Self::send_response(stream, response).await?;
+        Self::linger_close(stream).await;
+        Ok(())
+    }
+
+    async fn linger_close<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) {
+        if stream.shutdown().await.is_err() {
+            return;
+        }
+        let drain = async {
+            let mut scratch = [0u8; 4096];
+            let mut drained = 0;
+            while drained < LINGER_MAX_BYTES {
+                match stream.read(&mut scratch).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => drained += n,
                 }
             }
+        };
+        let _ = tokio::time::timeout(LINGER_TIMEOUT, drain).await;
+    }
+
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    fn request_timeout(config: &Config) -> Option<Duration> {
+        match config.performance.request_timeout {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
         }
     }
 
-    async fn handle_connection(
-        socket: TcpStream,
-        _addr: SocketAddr,
-        config: Config,
-        router: Router,
-    ) -> Result<()> {
-        let mut stream = socket;
-        let mut buffer = Vec::new();
-        let mut temp_buffer = [0; 4096];
-        
-        loop {
-            let n = stream.read(&mut temp_buffer).await?;
-            if n == 0 {
-                break;
-            }
-            buffer.extend_from_slice(&temp_buffer[..n]);
-            
-            if let Some(request) = Self::parse_request(&buffer)? {
-                let response = Self::process_request(request, &config, &router).await?;
-                Self::send_response(&mut stream, response).await?;
-                break;
-            }
+    fn slow_request_threshold(config: &Config) -> Option<Duration> {
+        match config.performance.slow_request_threshold_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
         }
-        
-        Ok(())
     }
 
-    fn parse_request(buffer: &[u8]) -> Result<Option<Request>> {
-        let mut lines = buffer.split(|&b| b == b'\n');
-        
-        let request_line = lines.next().ok_or_else(|| Error::Parse("No request line".to_string()))?;
-        let request_line = std::str::from_utf8(request_line).map_err(|_| Error::Parse("Invalid UTF-8".to_string()))?;
-        let request_line = request_line.trim_end_matches('\r');
-        
-        let parts: Vec<&str> = request_line.split_whitespace().collect();
-        if parts.len() != 3 {
-            return Err(Error::Parse("Invalid request line".to_string()));
+    // The layers built from the configuration, in order. They are rebuilt
+    // whenever the configuration is reloaded.
+    fn middleware(config: &Config, state: &MiddlewareState) -> Result<Vec<Arc<dyn Middleware>>> {
+        let mut layers: Vec<Arc<dyn Middleware>> = vec![
+            // Outermost, so error responses from the layers below get the headers too.
+            Arc::new(SecurityHeaders::from_config(&config.security.headers)),
+            Arc::new(Extension(state.http_client.clone())),
+            Arc::new(TrustedProxies::from_config(&config.security.trusted_proxies)?.with_header(config.security.proxy_header)),
+            Arc::new(state.maintenance.clone()),
+            Arc::new(RateLimit::new(
+                state.rate_limiter.clone(),
+                config.security.rate_limit_requests as u64,
+                Duration::from_secs(config.security.rate_limit_window),
+            )),
+        ];
+        if config.server.method_override {
+            layers.push(Arc::new(MethodOverride::new()));
         }
-        
-        let method = parts[0].parse::<Method>()?;
-        let uri = parts[1].parse::<Uri>()?;
-        let version = match parts[2] {
-            "HTTP/1.0" => Version::HTTP_10,
-            "HTTP/1.1" => Version::HTTP_11,
-            _ => return Err(Error::Parse("Unsupported HTTP version".to_string())),
-        };
-        
-        let mut request = Request::new(method, uri, version);
-        let mut headers = HeaderMap::new();
-        let mut content_length = None;
-        
-        for line in lines {
-            let line = std::str::from_utf8(line).map_err(|_| Error::Parse("Invalid UTF-8 in headers".to_string()))?;
-            let line = line.trim_end_matches('\r');
-            
-            if line.is_empty() {
-                break;
-            }
-            
-            if let Some((name, value)) = line.split_once(':') {
-                let name = name.trim().to_lowercase();
-                let value = value.trim();
-                
-                if let Ok(header_value) = HeaderValue::from_str(value) {
-                    if let Ok(header_name) = http::header::HeaderName::from_lowercase(name.as_bytes()) {
-                        headers.insert(header_name, header_value);
-                    }
-                    
-                    if name == "content-length" {
-                        content_length = value.parse::<usize>().ok();
-                    }
-                }
+        layers.push(Arc::new(TraceMethod::new(config.server.trace)));
+        if config.security.csrf.enabled {
+            layers.push(Arc::new(Csrf::from_config(&config.security.csrf)?));
+        }
+        if config.mirror.enabled {
+            layers.push(Arc::new(Mirror::from_config(&config.mirror, state.http_client.clone(), state.metrics.clone())?));
+        }
+
+        let redirects = Redirects::from_config(&config.redirects)?;
+        if !redirects.is_empty() {
+            layers.push(Arc::new(redirects));
+        }
+        let rewrites = Rewrites::from_config(&config.rewrites, state.metrics.clone())?;
+        if !rewrites.is_empty() {
+            layers.push(Arc::new(rewrites));
+        }
+
+        if config.tls.enabled {
+            for rule in &config.tls.client_rules {
+                layers.push(Arc::new(Scoped::new(&rule.prefix, ClientCertAuth::new(rule.allow.clone()))));
             }
         }
-        
-        request.headers = headers;
-        
-        if let Some(length) = content_length {
-            let body_start = buffer.windows(4).position(|window| window == b"\r\n\r\n");
-            if let Some(start) = body_start {
-                let body_data = &buffer[start + 4..];
-                if body_data.len() >= length {
-                    request.body = Some(Bytes::copy_from_slice(&body_data[..length]));
-                }
+        if config.auth.enabled {
+            layers.push(Arc::new(JwtAuth::from_config(&config.auth)?));
+        }
+        if config.performance.decompress_requests {
+            layers.push(Arc::new(RequestDecompression::new(config.security.max_request_size)));
+        }
+        if let Some(audit_log) = &state.audit_log {
+            layers.push(Arc::new(Scoped::new("/files", AuditLayer::new(audit_log.clone()))));
+        }
+        if let Some(response_cache) = &state.response_cache {
+            for prefix in &config.response_cache.paths {
+                layers.push(Arc::new(Scoped::new(prefix, response_cache.clone())));
             }
         }
-        
-        Ok(Some(request))
+        Ok(layers)
     }
 
-    async fn process_request(request: Request, _config: &Config, router: &Router) -> Result<Response> {
-        router.handle(request)
+    fn setup_virtual_hosts(
+        router: &mut Router,
+        config: &Arc<Config>,
+        metrics: &Arc<Metrics>,
+        site_files: &SiteFiles,
+    ) -> Result<Vec<VirtualHost>> {
+        router.strict_hosts(config.virtual_hosts.strict);
+
+        let mut virtual_hosts = Vec::new();
+        for (host, vhost) in &config.virtual_hosts.hosts {
+            let host_config = ConfigReloader::virtual_host_config(config, vhost.root_dir.as_deref());
+            let files = Arc::new(FileStore::from_config(&host_config.files));
+            let host_config: SharedConfig = Arc::new(ArcSwap::from_pointee(host_config));
+            virtual_hosts.push(VirtualHost {
+                root_dir: vhost.root_dir.clone(),
+                config: host_config.clone(),
+                files: files.clone(),
+            });
+
+            let mut host_router = Router::new();
+            host_router
+                .slash_policy(config.server.slash_policy)
+                .duplicate_routes(config.server.duplicate_routes)
+                .request_timeout(Self::request_timeout(config))
+                .route_metrics(metrics.clone())
+                .slow_request_threshold(Self::slow_request_threshold(config));
+            site_files.register(&mut host_router)?;
+            Self::setup_routes(&mut host_router, &host_config, metrics, &files)?;
+            router.host(host, host_router);
+        }
+        Ok(virtual_hosts)
     }
 
-    async fn send_response(stream: &mut TcpStream, response: Response) -> Result<()> {
-        let response_bytes = response.to_bytes();
-        stream.write_all(&response_bytes).await?;
-        stream.flush().await?;
+    fn setup_directory_routes(router: &mut Router, shared: &SharedConfig, metrics: &Arc<Metrics>, files: &Arc<FileStore>) -> Result<()> {
+        for method in [Method::GET, Method::HEAD] {
+            let config = shared.clone();
+            let metrics = metrics.clone();
+            let files = files.clone();
+            let handler = move |request: Request| {
+                let (config, files, metrics) = (config.load_full(), files.clone(), metrics.clone());
+                Self::blocking(move || {
+                    let mut response = Self::handle_file_get(&request, &config, &files, &metrics)?;
+                    if request.method == Method::HEAD {
+                        response.body = None;
+                        response.stream = None;
+                    }
+                    Ok(response)
+                })
+            };
+            router.try_add_route_with(method, "/{*filename}", handler, RouteOptions::default())?;
+        }
         Ok(())
     }
 
-    fn setup_routes(&mut self) {
-        let config = self.config.clone();
+    // Runs a handler's filesystem work on the blocking pool, so a slow disk
+    // holds up only the requests waiting on it and not the rest of the worker.
+    async fn blocking<F>(work: F) -> Result<Response>
+    where
+        F: FnOnce() -> Result<Response> + Send + 'static,
+    {
+        match tokio::task::spawn_blocking(work).await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(Error::Internal(format!("File task failed: {}", e))),
+        }
+    }
+
+    pub(crate) fn setup_routes(router: &mut Router, shared: &SharedConfig, metrics: &Arc<Metrics>, files: &Arc<FileStore>) -> Result<()> {
+        let config = shared.load();
+        if config.metrics.enabled && config.metrics.address.is_none() {
+            let metrics = metrics.clone();
+            router.try_add_route(Method::GET, &config.metrics.path, move |_| Ok(Self::metrics_response(&metrics)))?;
+        }
         
-        self.router
-            .get("/", move |_| {
-                Ok(Response::ok().with_text("Welcome to Rust HTTP Server"))
-            })
-            .get("/user-agent", move |request| {
+        router.try_add_route(Method::GET, "/", move |_| {
+            Ok(Response::ok().with_text("Welcome to Rust HTTP Server"))
+        })?;
+        if config.routes.enable_user_agent {
+            router.try_add_route(Method::GET, "/user-agent", move |request| {
                 if let Some(user_agent) = request.user_agent() {
                     Ok(Response::ok().with_text(user_agent))
                 } else {
                     Ok(Response::bad_request().with_text("User-Agent header not found"))
                 }
-            })
-            .get("/echo/{param}", move |request| {
-                let empty = String::new();
-                let param = request.params.get("param").unwrap_or(&empty);
-                Ok(Response::ok().with_text(param))
-            })
-            .post("/echo/{param}", move |request| {
-                let empty = String::new();
-                let param = request.params.get("param").unwrap_or(&empty);
-                Ok(Response::ok().with_text(param))
-            })
-            .get("/files/{filename}", {
-                let config = config.clone();
-                move |request| {
-                    let empty = String::new();
-                    let filename = request.params.get("filename").unwrap_or(&empty);
-                    Self::handle_file_get(filename, &config)
-                }
-            })
-            .post("/files/{filename}", {
-                let config = config.clone();
-                move |request| {
-                    let empty = String::new();
-                    let filename = request.params.get("filename").unwrap_or(&empty);
-                    let body = request.body_as_string().unwrap_or_default();
-                    Self::handle_file_post(filename, &body, &config)
-                }
-            })
-            .delete("/files/{filename}", {
-                let config = config.clone();
-                move |request| {
+            })?;
+        }
+        if config.routes.enable_echo {
+            for method in [Method::GET, Method::POST] {
+                router.try_add_route(method, "/echo/{param}", move |request| {
                     let empty = String::new();
-                    let filename = request.params.get("filename").unwrap_or(&empty);
-                    Self::handle_file_delete(filename, &config)
+                    let param = request.params.get("param").unwrap_or(&empty);
+                    Ok(Response::ok().with_text(param))
+                })?;
+            }
+        }
+        if config.routes.enable_files {
+            Self::setup_file_routes(router, shared, metrics, files, config.routes.files_read_only)?;
+        }
+
+        #[cfg(feature = "demo")]
+        router.try_add_route(Method::POST, "/form", |request| {
+            let fields = request.form()?;
+            Response::ok().with_json(&fields)
+        })?;
+
+        if let Some(index) = &config.files.spa_fallback {
+            let config = shared.clone();
+            let metrics = metrics.clone();
+            let files = files.clone();
+            let index = index.clone();
+            router.fallback(move |request| Self::handle_spa_fallback(&request, &index, &config.load(), &files, &metrics));
+        }
+        Ok(())
+    }
+
+    fn setup_file_routes(router: &mut Router, shared: &SharedConfig, metrics: &Arc<Metrics>, files: &Arc<FileStore>, read_only: bool) -> Result<()> {
+        // Routes that touch the disk take the config snapshot and stores into
+        // a blocking task; see `blocking`.
+        let file_route = |handler: FileHandler| {
+            let (config, files, metrics) = (shared.clone(), files.clone(), metrics.clone());
+            move |request: Request| {
+                let (config, files, metrics) = (config.load_full(), files.clone(), metrics.clone());
+                Self::blocking(move || handler(&request, &config, &files, &metrics))
+            }
+        };
+        router.try_add_route_with(
+            Method::GET,
+            "/files/{*filename}",
+            file_route(|request, config, files, metrics| {
+                let response = Self::handle_file_get(request, config, files, metrics)?;
+                if response.status == StatusCode::OK {
+                    metrics.record_file_bytes(response.body.as_ref().map(|b| b.len() as u64).unwrap_or(0));
                 }
-            });
+                Ok(response)
+            }),
+            RouteOptions::default(),
+        )?;
+        let allowed = match read_only {
+            true => webdav::READ_ONLY_METHODS,
+            false => webdav::ALLOWED_METHODS,
+        };
+        for pattern in ["/files", "/files/{*filename}"] {
+            let propfind = Method::from_bytes(b"PROPFIND").expect("WebDAV method names are valid tokens");
+            let handler = file_route(|request, config, files, _| Self::handle_propfind(request, config, files));
+            router.try_add_route_with(propfind, pattern, handler, RouteOptions::default())?;
+            router.try_add_route(Method::OPTIONS, pattern, move |_| {
+                Ok(Response::ok()
+                    .with_header("dav", "1")
+                    .with_header("allow", allowed)
+                    .with_content_length(0))
+            })?;
+        }
+        if read_only {
+            return Ok(());
+        }
+
+        router
+            .try_add_route_with(Method::POST, "/files/{*filename}", file_route(|request, config, files, _| Self::handle_file_post(request, config, files)), RouteOptions::default())?
+            .try_add_route_with(Method::POST, "/files", file_route(|request, config, files, _| Self::handle_file_upload(request, config, files)), RouteOptions::default())?
+            .try_add_route_with(
+                Method::PUT,
+                "/files/{*filename}",
+                file_route(|request, config, files, _| Self::handle_file_put(request, config, files)),
+                RouteOptions::default(),
+            )?
+            .try_add_route_with(
+                Method::DELETE,
+                "/files/{*filename}",
+                file_route(|request, config, files, _| Self::handle_file_delete(request, config, files)),
+                RouteOptions::default(),
+            )?;
+        let transfers: [(&str, FileHandler); 2] = [
+            ("MOVE", |request, config, files, _| Self::handle_file_transfer(request, config, files, true)),
+            ("COPY", |request, config, files, _| Self::handle_file_transfer(request, config, files, false)),
+        ];
+        for (method, handler) in transfers {
+            let method = Method::from_bytes(method.as_bytes()).expect("WebDAV method names are valid tokens");
+            router.try_add_route_with(method, "/files/{*filename}", file_route(handler), RouteOptions::default())?;
+        }
+        Ok(())
     }
 
-    fn handle_file_get(filename: &str, config: &Config) -> Result<Response> {
-        let sanitized_path = utils::sanitize_path(filename)?;
-        utils::validate_file_extension(&sanitized_path, &config.files.allowed_extensions)?;
+    fn handle_file_get(request: &Request, config: &Config, files: &FileStore, metrics: &Metrics) -> Result<Response> {
+        let sanitized_path = Self::file_path_param(request)?;
+        let file_path = Path::new(&config.files.root_dir).join(&sanitized_path);
+
+        if let Err(e) = utils::validate_file_extension(&sanitized_path, &config.files.allowed_extensions) {
+            if config.files.enable_directory_listing && file_path.is_dir() {
+                return Self::handle_directory_listing(request, &file_path, &sanitized_path, config);
+            }
+            if sanitized_path.is_empty() {
+                return Ok(Response::not_found().with_text("File not found"));
+            }
+            return Err(e);
+        }
         
-        let file_path = std::path::Path::new(&config.files.root_dir).join(&sanitized_path);
+        let attachment = Self::download_name(request, &sanitized_path)?;
+
+        // A sidecar is only an alternative form of a file that exists, never a
+        // stand-in for a missing file or a directory.
+        if config.files.precompressed && file_path.is_file() {
+            for (encoding, suffix) in PRECOMPRESSED_ENCODINGS {
+                if !request.accepts_encoding(encoding) {
+                    continue;
+                }
+                let mut sidecar = file_path.clone().into_os_string();
+                sidecar.push(suffix);
+                if let Some(file) = Self::load_file(Path::new(&sidecar), &sanitized_path, files, metrics)? {
+                    let response = Self::file_response(&file, &sanitized_path, config).with_compression(encoding);
+                    let response = Self::with_download(response, attachment.as_deref());
+                    return Ok(Self::with_range(request, response, &file, config.files.max_ranges));
+                }
+            }
+        }
+
+        if let Some(file) = Self::load_file(&file_path, &sanitized_path, files, metrics)? {
+            let response = Self::file_response(&file, &sanitized_path, config);
+            let response = Self::with_download(response, attachment.as_deref());
+            return Ok(Self::with_range(request, response, &file, config.files.max_ranges));
+        }
         
         if !file_path.exists() {
             return Ok(Response::not_found().with_text("File not found"));
         }
         
-        if !file_path.is_file() {
-            if file_path.is_dir() && config.files.enable_directory_listing {
-                return Self::handle_directory_listing(&file_path, &sanitized_path);
+        if file_path.is_dir() && config.files.enable_directory_listing {
+            return Self::handle_directory_listing(request, &file_path, &sanitized_path, config);
+        }
+        Ok(Response::not_found().with_text("Not a file"))
+    }
+
+    fn handle_spa_fallback(
+        request: &Request,
+        index: &str,
+        config: &Config,
+        files: &FileStore,
+        metrics: &Metrics,
+    ) -> Result<Response> {
+        let not_found = || Err(Error::RouteNotFound(request.path().to_string()));
+
+        if request.method != Method::GET && request.method != Method::HEAD {
+            return not_found();
+        }
+        let last_segment = request.path().rsplit('/').next().unwrap_or("");
+        if last_segment.contains('.') || !Self::accepts_html(request) {
+            return not_found();
+        }
+
+        let index = utils::sanitize_path(index)?;
+        let path = Path::new(&config.files.root_dir).join(&index);
+        let Some(file) = Self::load_file(&path, &index, files, metrics)? else {
+            return not_found();
+        };
+
+        let mut response = Self::file_response(&file, &index, config);
+        if request.method == Method::HEAD {
+            response.body = None;
+        }
+        Ok(response)
+    }
+
+    fn accepts_html(request: &Request) -> bool {
+        let Some(accept) = request.header("accept").and_then(|accept| accept.to_str().ok()) else {
+            return false;
+        };
+        utils::parse_accept(accept)
+            .iter()
+            .any(|range| range.specificity() >= 2 && range.matches("text/html") && range.quality > 0.0)
+    }
+
+    fn file_path_param(request: &Request) -> Result<String> {
+        let filename = request.params.get("filename").map(String::as_str).unwrap_or("");
+        let decoded = urlencoding::decode(filename).map_err(|_| Error::InvalidPath("Path is not valid UTF-8".to_string()))?;
+        utils::sanitize_path(&decoded)
+    }
+
+    fn download_name(request: &Request, path: &str) -> Result<Option<String>> {
+        match request.query_params().first("download") {
+            None | Some("0") | Some("false") => Ok(None),
+            Some("") | Some("1") | Some("true") => Ok(Some(path.rsplit('/').next().unwrap_or(path).to_string())),
+            Some(name) if name.chars().any(|c| c.is_control() || c == '/' || c == '\\') => {
+                Err(Error::BadRequest(format!("Invalid download name: {:?}", name)))
             }
-            return Ok(Response::not_found().with_text("Not a file"));
+            Some(name) => Ok(Some(name.to_string())),
+        }
+    }
+
+    fn with_download(response: Response, attachment: Option<&str>) -> Response {
+        match attachment {
+            Some(name) => response.with_attachment(name),
+            None => response,
+        }
+    }
+
+    fn load_file(path: &Path, name: &str, files: &FileStore, metrics: &Metrics) -> Result<Option<Arc<CachedFile>>> {
+        let file_cache = &files.cache;
+        if let Some(cached) = file_cache.get(path) {
+            metrics.record_file_cache(true);
+            return Ok(Some(cached));
+        }
+
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return Ok(None),
+        };
+        let content = match files.mmap_min_size {
+            Some(min_size) if metadata.len() >= min_size => Self::map_file(path)?,
+            _ => Bytes::from(std::fs::read(path)?),
+        };
+        if file_cache.is_enabled() {
+            metrics.record_file_cache(false);
+        }
+
+        let mime_type = files.mime.content_type(name, &content);
+        let file = CachedFile::new(content, mime_type, metadata.modified()?);
+        Ok(Some(file_cache.insert(path.to_path_buf(), file)))
+    }
+
+    // Falls back to reading the file when it cannot be mapped, e.g. on
+    // filesystems without mmap support.
+    fn map_file(path: &Path) -> Result<Bytes> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+        // SAFETY: the mapping is read-only and its length is fixed at the size
+        // seen at open. Uploads replace files atomically by rename, so the
+        // mapped inode is not modified by this server; a file truncated in
+        // place by another process would fault on access (SIGBUS), which is
+        // why mapping is opt-in.
+        match unsafe { memmap2::MmapOptions::new().len(len as usize).map(&file) } {
+            Ok(mmap) => Ok(Bytes::from_owner(mmap)),
+            Err(e) => {
+                debug!("Could not map {}, reading it instead: {}", path.display(), e);
+                Ok(Bytes::from(std::fs::read(path)?))
+            }
+        }
+    }
+
+    fn file_response(file: &CachedFile, path: &str, config: &Config) -> Response {
+        let mut response = Response::ok()
+            .with_content_type(&file.mime_type)
+            .with_etag(&file.etag)
+            .with_last_modified(file.modified)
+            .with_header("accept-ranges", "bytes")
+            .with_body(file.body.clone());
+        if config.files.precompressed {
+            response = response.with_header("vary", "Accept-Encoding");
+        }
+        let cache_control = utils::match_cache_control(&config.files.cache_control, path)
+            .or(config.files.cache_control_default.as_deref());
+        if let Some(cache_control) = cache_control {
+            response = response.with_header("cache-control", cache_control);
+        }
+        response
+    }
+
+    pub(crate) fn with_range(request: &Request, response: Response, file: &CachedFile, max_ranges: usize) -> Response {
+        let Some(range) = request.headers.get("range").and_then(|value| value.to_str().ok()) else {
+            return response;
+        };
+        if request.method != Method::GET {
+            return response;
+        }
+        if let Some(if_range) = request.headers.get("if-range") {
+            let matches = if_range
+                .to_str()
+                .is_ok_and(|if_range| utils::range::if_range_matches(if_range, &file.etag, file.modified));
+            if !matches {
+                return response;
+            }
+        }
+
+        let len = file.body.len() as u64;
+        match utils::range::parse(range, len) {
+            ByteRanges::Partial(ranges) if ranges.len() == 1 => {
+                let range = &ranges[0];
+                let mut response = response.with_body(file.body.slice(range.start as usize..range.end as usize));
+                response.status = StatusCode::PARTIAL_CONTENT;
+                response.with_header("content-range", &utils::range::content_range(range, len))
+            }
+            ByteRanges::Partial(ranges) => {
+                let requested: u64 = ranges.iter().map(|range| range.end - range.start).sum();
+                if ranges.len() > max_ranges || requested > len {
+                    return response;
+                }
+                let boundary = utils::range::boundary();
+                let body = utils::range::multipart_body(&file.body, &ranges, &file.mime_type, &boundary);
+                let mut response = response
+                    .with_content_type(&format!("multipart/byteranges; boundary={}", boundary))
+                    .with_body(body);
+                response.status = StatusCode::PARTIAL_CONTENT;
+                response
+            }
+            ByteRanges::Unsatisfiable => Response::new(StatusCode::RANGE_NOT_SATISFIABLE)
+                .with_header("content-range", &utils::range::unsatisfied_range(len)),
+            ByteRanges::Full => response,
         }
-        
-        let content = std::fs::read(&file_path)?;
-        let mime_type = utils::get_mime_type(&sanitized_path);
-        
-        Ok(Response::ok()
-            .with_content_type(mime_type)
-            .with_body(content))
     }
 
-    fn handle_file_post(filename: &str, content: &str, config: &Config) -> Result<Response> {
-        let sanitized_path = utils::sanitize_path(filename)?;
+    fn handle_file_post(request: &Request, config: &Config, files: &FileStore) -> Result<Response> {
+        let sanitized_path = Self::file_path_param(request)?;
         utils::validate_file_extension(&sanitized_path, &config.files.allowed_extensions)?;
         
+        let content = request.body.as_deref().unwrap_or_default();
         if content.len() > config.files.max_file_size {
-            return Err(Error::ContentTooLarge(content.len()));
+            return Err(Error::ContentTooLarge { limit: config.files.max_file_size, actual: Some(content.len()) });
         }
         
-        let file_path = std::path::Path::new(&config.files.root_dir).join(&sanitized_path);
+        files.validators.validate(&Upload {
+            path: &sanitized_path,
+            content_type: request.content_type(),
+            body: content,
+        })?;
+
+        let file_path = Path::new(&config.files.root_dir).join(&sanitized_path);
         
-        std::fs::create_dir_all(file_path.parent().unwrap_or_else(|| std::path::Path::new("")))?;
-        std::fs::write(&file_path, content)?;
+        files.locks.with_lock(&file_path, || {
+            Self::check_write_preconditions(request, &file_path)?;
+            files.write_locked(&sanitized_path, &file_path, content)
+        })?;
         
         Ok(Response::created().with_text("File created successfully"))
     }
 
-    fn handle_file_delete(filename: &str, config: &Config) -> Result<Response> {
-        let sanitized_path = utils::sanitize_path(filename)?;
+    fn handle_file_upload(request: &Request, config: &Config, files: &FileStore) -> Result<Response> {
+        let mut stored = Vec::new();
+        for part in request.multipart()? {
+            let part = part?;
+            let Some(filename) = part.filename.as_deref() else {
+                continue;
+            };
+            let basename = filename.rsplit(['/', '\\']).next().unwrap_or("");
+            if basename.is_empty() {
+                continue;
+            }
+
+            let sanitized_path = utils::sanitize_path(basename)?;
+            utils::validate_file_extension(&sanitized_path, &config.files.allowed_extensions)?;
+            if part.body.len() > config.files.max_file_size {
+                return Err(Error::ContentTooLarge { limit: config.files.max_file_size, actual: Some(part.body.len()) });
+            }
+
+            files.validators.validate(&Upload {
+                path: &sanitized_path,
+                content_type: part.content_type.as_deref(),
+                body: &part.body,
+            })?;
+
+            let file_path = Path::new(&config.files.root_dir).join(&sanitized_path);
+            files.write(&sanitized_path, &file_path, &part.body)?;
+            stored.push(sanitized_path);
+        }
+
+        if stored.is_empty() {
+            return Err(Error::BadRequest("No file parts in upload".to_string()));
+        }
+        Response::created().with_json(&serde_json::json!({ "files": stored }))
+    }
+
+    fn handle_file_put(request: &Request, config: &Config, files: &FileStore) -> Result<Response> {
+        let sanitized_path = Self::file_path_param(request)?;
         utils::validate_file_extension(&sanitized_path, &config.files.allowed_extensions)?;
-        
-        let file_path = std::path::Path::new(&config.files.root_dir).join(&sanitized_path);
-        
-        if !file_path.exists() {
-            return Ok(Response::not_found().with_text("File not found"));
+
+        let content = request.body.clone().unwrap_or_default();
+        if content.len() > config.files.max_file_size {
+            return Err(Error::ContentTooLarge { limit: config.files.max_file_size, actual: Some(content.len()) });
         }
-        
-        if !file_path.is_file() {
-            return Ok(Response::bad_request().with_text("Not a file"));
+        files.validators.validate(&Upload {
+            path: &sanitized_path,
+            content_type: request.content_type(),
+            body: &content,
+        })?;
+
+        let file_path = Path::new(&config.files.root_dir).join(&sanitized_path);
+        files.locks.with_lock(&file_path, || Self::put_file(request, &sanitized_path, &file_path, &content, files))
+    }
+
+    fn put_file(request: &Request, relative_path: &str, file_path: &Path, content: &[u8], files: &FileStore) -> Result<Response> {
+        let current_etag = Self::check_write_preconditions(request, file_path)?;
+        if let Some(if_none_match) = request.header("if-none-match").and_then(|value| value.to_str().ok()) {
+            if current_etag.as_ref().is_some_and(|current| etag::if_none_match_matches(if_none_match, current)) {
+                return Err(Error::PreconditionFailed("If-None-Match matched the current file".to_string()));
+            }
         }
+
+        files.write_locked(relative_path, file_path, content)?;
+
+        let metadata = std::fs::metadata(file_path)?;
+        let etag = etag::from_metadata(metadata.len(), metadata.modified()?);
+        let response = match current_etag {
+            Some(_) => Response::new(StatusCode::NO_CONTENT),
+            None => Response::created().with_text("File created successfully"),
+        };
+        Ok(response.with_etag(&etag))
+    }
+
+    fn check_write_preconditions(request: &Request, file_path: &Path) -> Result<Option<etag::EntityTag>> {
+        let current = match std::fs::metadata(file_path) {
+            Ok(metadata) if metadata.is_file() => Some((etag::from_metadata(metadata.len(), metadata.modified()?), metadata.modified()?)),
+            Ok(_) => return Err(Error::BadRequest("Not a file".to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let header = |name| request.header(name).and_then(|value| value.to_str().ok());
+        if let Some(if_match) = header("if-match") {
+            if !current.as_ref().is_some_and(|(current, _)| etag::if_match_matches(if_match, current)) {
+                return Err(Error::PreconditionFailed("If-Match did not match the current file".to_string()));
+            }
+        } else if let Some(since) = header("if-unmodified-since").and_then(utils::http_date::parse) {
+            let modified = current.as_ref().map(|(_, modified)| utils::http_date::truncate(*modified));
+            if modified.is_some_and(|modified| modified > since) {
+                return Err(Error::PreconditionFailed("File was modified after If-Unmodified-Since".to_string()));
+            }
+        }
+        Ok(current.map(|(current, _)| current))
+    }
+
+    fn handle_file_delete(request: &Request, config: &Config, files: &FileStore) -> Result<Response> {
+        let sanitized_path = Self::file_path_param(request)?;
+        utils::validate_file_extension(&sanitized_path, &config.files.allowed_extensions)?;
         
-        std::fs::remove_file(&file_path)?;
-        
-        Ok(Response::ok().with_text("File deleted successfully"))
+        let file_path = Path::new(&config.files.root_dir).join(&sanitized_path);
+
+        if config.files.require_delete_preconditions && !request.headers.contains_key("if-match") && !request.headers.contains_key("if-unmodified-since") {
+            return Err(Error::PreconditionRequired("DELETE requires If-Match or If-Unmodified-Since".to_string()));
+        }
+
+        files.locks.with_lock(&file_path, || {
+            if Self::check_write_preconditions(request, &file_path)?.is_none() {
+                return Ok(Response::not_found().with_text("File not found"));
+            }
+            files.remove_locked(&sanitized_path, &file_path)?;
+            Ok(Response::ok().with_text("File deleted successfully"))
+        })
+    }
+
+    fn handle_file_transfer(request: &Request, config: &Config, files: &FileStore, remove_source: bool) -> Result<Response> {
+        let source = Self::file_path_param(request)?;
+        utils::validate_file_extension(&source, &config.files.allowed_extensions)?;
+        let destination = Self::transfer_destination(request)?;
+        utils::validate_file_extension(&destination, &config.files.allowed_extensions)?;
+        if source == destination {
+            return Err(Error::BadRequest("Source and destination are the same file".to_string()));
+        }
+        let overwrite = match request.header("overwrite").map(|value| value.as_bytes()) {
+            None | Some(b"F") => false,
+            Some(b"T") => true,
+            Some(_) => return Err(Error::BadRequest("Overwrite must be T or F".to_string())),
+        };
+
+        let root = Path::new(&config.files.root_dir);
+        let (source_path, destination_path) = (root.join(&source), root.join(&destination));
+        files.locks.with_locks(&source_path, &destination_path, || {
+            if Self::check_write_preconditions(request, &source_path)?.is_none() {
+                return Ok(Response::not_found().with_text("File not found"));
+            }
+            let replaced = destination_path.exists();
+            if replaced && (!overwrite || !destination_path.is_file()) {
+                return Err(Error::Conflict(format!("Destination already exists: {}", destination)));
+            }
+
+            match remove_source {
+                true => files.rename_locked(&source, &source_path, &destination, &destination_path)?,
+                false => files.copy_locked(&source_path, &destination, &destination_path)?,
+            }
+            let location: Vec<_> = destination.split('/').map(urlencoding::encode).collect();
+            let response = match replaced {
+                true => Response::new(StatusCode::NO_CONTENT),
+                false => Response::created(),
+            };
+            Ok(response.with_header("location", &format!("/files/{}", location.join("/"))))
+        })
+    }
+
+    fn handle_propfind(request: &Request, config: &Config, files: &FileStore) -> Result<Response> {
+        let path = Self::file_path_param(request)?;
+        let depth = webdav::Depth::from_header(request.header("depth").and_then(|value| value.to_str().ok()))?;
+        let target = Path::new(&config.files.root_dir).join(&path);
+        let metadata = match std::fs::metadata(&target) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Response::not_found().with_text("File not found")),
+            Err(e) => return Err(e.into()),
+        };
+
+        let display_name = path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("files");
+        let mut entries = vec![Self::prop_entry(&path, display_name, &metadata, files)];
+        if metadata.is_dir() && depth == webdav::Depth::One {
+            if !config.files.enable_directory_listing {
+                return Err(Error::PermissionDenied("Directory listing is disabled".to_string()));
+            }
+            let mut children = utils::read_directory(&target, &config.files)?;
+            utils::sort_directory_entries(&mut children, utils::ListingSort::default(), false);
+            for child in children {
+                let name = child.file_name().to_string_lossy().into_owned();
+                let Some(metadata) = utils::entry_metadata(&child) else {
+                    continue;
+                };
+                let child_path = format!("{}/{}", path, name);
+                entries.push(Self::prop_entry(&child_path, &name, &metadata, files));
+            }
+        }
+
+        let mut response = Response::ok()
+            .with_content_type("application/xml; charset=utf-8")
+            .with_body(webdav::multistatus(&entries));
+        response.status = StatusCode::MULTI_STATUS;
+        Ok(response)
+    }
+
+    fn prop_entry(path: &str, name: &str, metadata: &std::fs::Metadata, files: &FileStore) -> webdav::PropEntry {
+        let is_dir = metadata.is_dir();
+        webdav::PropEntry {
+            href: webdav::href(path, is_dir),
+            display_name: name.to_string(),
+            is_dir,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            content_type: (!is_dir).then(|| files.mime.lookup(path).unwrap_or(utils::mime::OCTET_STREAM).to_string()),
+        }
     }
 
-    fn handle_directory_listing(dir_path: &std::path::Path, path: &str) -> Result<Response> {
-        let entries: Vec<std::fs::DirEntry> = std::fs::read_dir(dir_path)?
-            .filter_map(|entry| entry.ok())
-            .collect();
+    fn transfer_destination(request: &Request) -> Result<String> {
+        let header = request
+            .header("destination")
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| Error::BadRequest("Missing Destination header".to_string()))?;
+        let uri = header.parse::<::http::Uri>().map_err(|_| Error::BadRequest(format!("Invalid Destination: {}", header)))?;
+        let path = urlencoding::decode(uri.path()).map_err(|_| Error::BadRequest(format!("Invalid Destination: {}", header)))?;
+        let relative = path
+            .strip_prefix("/files/")
+            .ok_or_else(|| Error::BadRequest("Destination must be under /files/".to_string()))?;
+        let sanitized = utils::sanitize_path(relative)?;
+        if sanitized.is_empty() {
+            return Err(Error::BadRequest("Destination must name a file".to_string()));
+        }
+        Ok(sanitized)
+    }
+
+    fn handle_directory_listing(request: &Request, dir_path: &Path, path: &str, config: &Config) -> Result<Response> {
+        let params = request.query_params();
+        let sort = params.first("sort").map(str::parse).transpose()?.unwrap_or_default();
+        let descending = match params.first("order") {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => return Err(Error::BadRequest(format!("Unknown sort order: {}", other))),
+        };
+
+        if let Some(format) = params.first("format").and_then(ArchiveFormat::from_query) {
+            return Self::directory_archive(dir_path, path, format, config);
+        }
+
+        let json = Self::wants_json_listing(request);
+        let base_href = format!("{}/", request.path().trim_end_matches('/'));
+        let parent = base_href[..base_href.len() - 1].rfind('/').map(|i| &base_href[..=i]);
+        let parent = parent.filter(|_| !path.trim_matches('/').is_empty());
+
+        #[cfg(feature = "templates")]
+        let templated = !json && request.extension::<crate::templates::Templates>().is_some();
+        #[cfg(not(feature = "templates"))]
+        let templated = false;
+        if sort == utils::ListingSort::Unsorted && !templated {
+            let head = (!json).then(|| utils::directory_listing_head(path, parent));
+            return Self::stream_directory_listing(dir_path, head, base_href, config);
+        }
+
+        let mut entries = utils::read_directory(dir_path, &config.files)?;
+        utils::sort_directory_entries(&mut entries, sort, descending);
+
+        if json {
+            return Response::ok().with_json(&utils::directory_entries(&entries));
+        }
         
-        let html = utils::generate_directory_listing(path, &entries);
+        #[cfg(feature = "templates")]
+        if let Some(templates) = request.extension::<crate::templates::Templates>() {
+            let listing = crate::templates::DirectoryListing { path, parent, rows: utils::listing_rows(&base_href, &entries) };
+            return Response::ok().with_template(templates, crate::templates::DIRECTORY_LISTING, listing);
+        }
+        let html = utils::generate_directory_listing(path, &base_href, &entries, parent);
         
         Ok(Response::ok()
             .with_content_type("text/html")
             .with_body(html))
     }
-}
 
- 
\ No newline at end of file
+    // Writes the listing while the directory is read, so a directory with
+    // millions of entries is never held in memory. Without an HTML `head` the
+    // listing is a JSON array.
+    fn stream_directory_listing(dir_path: &Path, head: Option<String>, base_href: String, config: &Config) -> Result<Response> {
+        let entries = std::fs::read_dir(dir_path)
+            .map_err(|e| Error::Internal(format!("Cannot list directory {}: {}", dir_path.display(), e)))?;
+        let content_type = if head.is_some() { "text/html" } else { "application/json" };
+        let files = config.files.clone();
+        let directory = dir_path.display().to_string();
+
+        let (sender, stream) = BodyStream::channel(4);
+        tokio::task::spawn_blocking(move || {
+            let mut writer = archive::ChannelWriter::new(sender.clone());
+            let result = Self::write_directory_listing(&mut writer, entries, head, &base_href, &files)
+                .and_then(|_| std::io::Write::flush(&mut writer));
+            if let Err(e) = result {
+                if e.kind() != ErrorKind::BrokenPipe {
+                    error!(directory = %directory, "Directory listing aborted: {}", e);
+                }
+                let _ = sender.blocking_send(Err(e));
+            }
+        });
+        Ok(Response::ok().with_content_type(content_type).with_stream(stream))
+    }
+
+    fn write_directory_listing(
+        writer: &mut impl std::io::Write,
+        entries: std::fs::ReadDir,
+        head: Option<String>,
+        base_href: &str,
+        files: &FileConfig,
+    ) -> std::io::Result<()> {
+        writer.write_all(head.as_deref().unwrap_or("[").as_bytes())?;
+        let mut first = true;
+        for entry in entries {
+            let entry = entry?;
+            if !utils::is_listed(&entry.file_name(), files) {
+                continue;
+            }
+            if head.is_some() {
+                writer.write_all(utils::directory_listing_row(&utils::listing_row(base_href, &entry)).as_bytes())?;
+            } else {
+                if !first {
+                    writer.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut *writer, &utils::directory_entry(&entry))?;
+            }
+            first = false;
+        }
+        let tail = if head.is_some() { utils::DIRECTORY_LISTING_TAIL } else { "]" };
+        writer.write_all(tail.as_bytes())
+    }
+
+    fn directory_archive(dir_path: &Path, path: &str, format: ArchiveFormat, config: &Config) -> Result<Response> {
+        if !config.files.archive.enabled {
+            return Err(Error::PermissionDenied("Archive downloads are disabled".to_string()));
+        }
+        let entries = archive::collect(dir_path, Path::new(&config.files.root_dir), &config.files)?;
+        let base = match path.trim_matches('/').rsplit('/').next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => "files".to_string(),
+        };
+        let filename = format!("{}.{}", base, format.extension());
+        Ok(Response::ok()
+            .with_content_type(format.content_type())
+            .with_attachment(&filename)
+            .with_stream(archive::stream(format, base, entries)))
+    }
+
+    fn wants_json_listing(request: &Request) -> bool {
+        if let Some(format) = request.query_params().first("format") {
+            return format == "json";
+        }
+        request.preferred(&["text/html", "application/json"]) == Some("application/json")
+    }
+}
\ No newline at end of file
@@ -0,0 +1,73 @@
+use crate::{
+    config::SiteFilesConfig,
+    http::Response,
+    router::Router,
+    utils::{
+        self,
+        etag::{self, EntityTag},
+    },
+    Result,
+};
+use bytes::Bytes;
+use http::Method;
+use std::fs;
+
+#[derive(Debug, Clone)]
+struct Favicon {
+    body: Bytes,
+    content_type: &'static str,
+    etag: EntityTag,
+    cache_control: String,
+}
+
+// robots.txt and favicon.ico are read once at startup, so a missing file fails
+// the server before it starts listening instead of on the first crawler hit.
+#[derive(Debug, Clone, Default)]
+pub struct SiteFiles {
+    robots: Option<Bytes>,
+    favicon: Option<Favicon>,
+}
+
+impl SiteFiles {
+    pub fn from_config(config: &SiteFilesConfig) -> Result<Self> {
+        let robots = match (&config.robots_txt, &config.robots_path) {
+            (Some(body), _) => Some(Bytes::from(body.clone())),
+            (None, Some(path)) => Some(Bytes::from(fs::read(path)?)),
+            (None, None) => None,
+        };
+        let favicon = match &config.favicon_path {
+            Some(path) => {
+                let body = Bytes::from(fs::read(path)?);
+                Some(Favicon {
+                    content_type: match utils::get_mime_type(path) {
+                        "application/octet-stream" => "image/x-icon",
+                        mime => mime,
+                    },
+                    etag: etag::from_bytes(&body),
+                    cache_control: format!("public, max-age={}", config.favicon_max_age),
+                    body,
+                })
+            }
+            None => None,
+        };
+        Ok(Self { robots, favicon })
+    }
+
+    pub fn register(&self, router: &mut Router) -> Result<()> {
+        if let Some(robots) = self.robots.clone() {
+            router.try_add_route(Method::GET, "/robots.txt", move |_| {
+                Ok(Response::ok().with_content_type("text/plain; charset=utf-8").with_body(robots.clone()))
+            })?;
+        }
+        if let Some(favicon) = self.favicon.clone() {
+            router.try_add_route(Method::GET, "/favicon.ico", move |_| {
+                Ok(Response::ok()
+                    .with_content_type(favicon.content_type)
+                    .with_etag(&favicon.etag)
+                    .with_header("cache-control", &favicon.cache_control)
+                    .with_body(favicon.body.clone()))
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use crate::{config::FileConfig, file_cache::FileCache, upload::UploadValidators, utils::mime::MimeRegistry, Error, Result};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(content))
+}
+
+pub fn copy_atomic(from: &Path, to: &Path) -> std::io::Result<()> {
+    let mut source = File::open(from)?;
+    write_atomic_with(to, |file| std::io::copy(&mut source, file).map(drop))
+}
+
+pub fn write_atomic_with<F>(path: &Path, write: F) -> std::io::Result<()>
+where
+    F: FnOnce(&mut File) -> std::io::Result<()>,
+{
+    let directory = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let temp_path = directory.join(format!(
+        ".{}.tmp-{}-{}",
+        file_name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create_new(&temp_path).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[derive(Debug, Default)]
+pub struct PathLocks {
+    locks: DashMap<PathBuf, Arc<Mutex<()>>>,
+}
+
+impl PathLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_lock<R>(&self, path: &Path, f: impl FnOnce() -> R) -> R {
+        let lock = self.locks.entry(path.to_path_buf()).or_default().clone();
+        let result = {
+            let _guard = lock.lock();
+            f()
+        };
+        drop(lock);
+        self.locks.remove_if(path, |_, lock| Arc::strong_count(lock) == 1);
+        result
+    }
+
+    // Locks are always taken in path order so two transfers between the same
+    // pair of files cannot deadlock.
+    pub fn with_locks<R>(&self, first: &Path, second: &Path, f: impl FnOnce() -> R) -> R {
+        match first.cmp(second) {
+            std::cmp::Ordering::Equal => self.with_lock(first, f),
+            std::cmp::Ordering::Less => self.with_lock(first, || self.with_lock(second, f)),
+            std::cmp::Ordering::Greater => self.with_lock(second, || self.with_lock(first, f)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.locks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locks.is_empty()
+    }
+}
+
+// The root lives with the usage it was measured from, so re-rooting the quota
+// and dropping the stale totals happen together.
+#[derive(Debug, Default)]
+struct Usage {
+    root: PathBuf,
+    total: u64,
+    directories: HashMap<String, u64>,
+    computed_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct Quota {
+    max_total_bytes: Option<u64>,
+    directories: HashMap<String, u64>,
+    refresh: Duration,
+    usage: Mutex<Usage>,
+}
+
+impl Quota {
+    pub fn new(root: impl Into<PathBuf>, max_total_bytes: Option<u64>, directories: HashMap<String, u64>, refresh: Duration) -> Self {
+        let directories = directories
+            .into_iter()
+            .map(|(dir, limit)| (dir.trim_matches('/').to_string(), limit))
+            .collect();
+        Self {
+            max_total_bytes,
+            directories,
+            refresh,
+            usage: Mutex::new(Usage { root: root.into(), ..Usage::default() }),
+        }
+    }
+
+    pub fn from_config(config: &FileConfig) -> Self {
+        Self::new(
+            &config.root_dir,
+            config.max_total_bytes,
+            config.directory_quotas.clone(),
+            Duration::from_secs(config.quota_refresh_secs),
+        )
+    }
+
+    // Points the quota at a new root after a reload changes `files.root_dir`;
+    // usage is measured again from the new tree on the next check.
+    pub fn set_root(&self, root: impl Into<PathBuf>) {
+        let root = root.into();
+        let mut usage = self.usage.lock();
+        if usage.root != root {
+            *usage = Usage { root, ..Usage::default() };
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_total_bytes.is_some() || !self.directories.is_empty()
+    }
+
+    pub fn usage(&self) -> u64 {
+        let mut usage = self.usage.lock();
+        self.refresh_if_stale(&mut usage);
+        usage.total
+    }
+
+    pub fn reserve(&self, path: &str, new_size: u64, old_size: u64) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let mut usage = self.usage.lock();
+        self.refresh_if_stale(&mut usage);
+
+        let growth = new_size.saturating_sub(old_size);
+        if growth > 0 {
+            if let Some(limit) = self.max_total_bytes {
+                if usage.total + growth > limit {
+                    return Err(Error::InsufficientStorage(format!("upload would exceed the {} byte quota", limit)));
+                }
+            }
+            for (dir, limit) in self.matching_directories(path) {
+                if usage.directories.get(dir).copied().unwrap_or(0) + growth > *limit {
+                    return Err(Error::InsufficientStorage(format!("upload would exceed the {} byte quota for {}", limit, dir)));
+                }
+            }
+        }
+
+        self.apply(&mut usage, path, new_size as i64 - old_size as i64);
+        Ok(())
+    }
+
+    pub fn unreserve(&self, path: &str, new_size: u64, old_size: u64) {
+        if self.is_enabled() {
+            let mut usage = self.usage.lock();
+            self.apply(&mut usage, path, old_size as i64 - new_size as i64);
+        }
+    }
+
+    pub fn release(&self, path: &str, size: u64) {
+        self.unreserve(path, size, 0);
+    }
+
+    fn apply(&self, usage: &mut Usage, path: &str, delta: i64) {
+        usage.total = usage.total.saturating_add_signed(delta);
+        for (dir, _) in self.matching_directories(path) {
+            let entry = usage.directories.entry(dir.clone()).or_default();
+            *entry = entry.saturating_add_signed(delta);
+        }
+    }
+
+    fn matching_directories<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a String, &'a u64)> + 'a {
+        self.directories
+            .iter()
+            .filter(move |(dir, _)| dir.is_empty() || path.strip_prefix(dir.as_str()).is_some_and(|rest| rest.starts_with('/')))
+    }
+
+    fn refresh_if_stale(&self, usage: &mut Usage) {
+        if usage.computed_at.is_some_and(|at| at.elapsed() < self.refresh) {
+            return;
+        }
+
+        let mut files = Vec::new();
+        collect_file_sizes(&usage.root, "", &mut files);
+
+        usage.total = files.iter().map(|(_, size)| size).sum();
+        usage.directories = self
+            .directories
+            .keys()
+            .map(|dir| {
+                let used = files
+                    .iter()
+                    .filter(|(path, _)| dir.is_empty() || path.strip_prefix(dir.as_str()).is_some_and(|rest| rest.starts_with('/')))
+                    .map(|(_, size)| size)
+                    .sum();
+                (dir.clone(), used)
+            })
+            .collect();
+        usage.computed_at = Some(Instant::now());
+    }
+}
+
+fn collect_file_sizes(dir: &Path, prefix: &str, files: &mut Vec<(String, u64)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if metadata.is_dir() {
+            collect_file_sizes(&entry.path(), &format!("{}/", name), files);
+        } else if metadata.is_file() {
+            files.push((name, metadata.len()));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileStore {
+    pub cache: FileCache,
+    pub locks: PathLocks,
+    pub quota: Quota,
+    pub mime: MimeRegistry,
+    pub validators: UploadValidators,
+    // Files at least this large are memory-mapped instead of read; None when
+    // `files.use_mmap` is off.
+    pub mmap_min_size: Option<u64>,
+}
+
+impl FileStore {
+    pub fn from_config(config: &FileConfig) -> Self {
+        Self {
+            cache: FileCache::new(config.cache.clone()),
+            locks: PathLocks::new(),
+            quota: Quota::from_config(config),
+            mime: MimeRegistry::from_config(config),
+            validators: UploadValidators::from_config(config),
+            mmap_min_size: config.use_mmap.then_some(config.mmap_min_size),
+        }
+    }
+
+    pub fn write(&self, relative_path: &str, path: &Path, content: &[u8]) -> Result<()> {
+        self.locks.with_lock(path, || self.write_locked(relative_path, path, content))
+    }
+
+    pub fn write_locked(&self, relative_path: &str, path: &Path, content: &[u8]) -> Result<()> {
+        let old_size = std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+        let new_size = content.len() as u64;
+        self.quota.reserve(relative_path, new_size, old_size)?;
+
+        let result = Self::create_parent(path).and_then(|_| write_atomic(path, content));
+        if let Err(e) = result {
+            self.quota.unreserve(relative_path, new_size, old_size);
+            return Err(e.into());
+        }
+        self.cache.invalidate(path);
+        Ok(())
+    }
+
+    pub fn copy_locked(&self, from: &Path, to_relative: &str, to: &Path) -> Result<()> {
+        let size = std::fs::metadata(from)?.len();
+        let old_size = std::fs::metadata(to).map(|metadata| metadata.len()).unwrap_or(0);
+        self.quota.reserve(to_relative, size, old_size)?;
+
+        if let Err(e) = Self::create_parent(to).and_then(|_| copy_atomic(from, to)) {
+            self.quota.unreserve(to_relative, size, old_size);
+            return Err(e.into());
+        }
+        self.cache.invalidate(to);
+        Ok(())
+    }
+
+    pub fn rename_locked(&self, from_relative: &str, from: &Path, to_relative: &str, to: &Path) -> Result<()> {
+        let size = std::fs::metadata(from)?.len();
+        let old_size = std::fs::metadata(to).map(|metadata| metadata.len()).unwrap_or(0);
+        self.quota.reserve(to_relative, size, old_size)?;
+
+        let result = Self::create_parent(to).and_then(|_| match std::fs::rename(from, to) {
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_atomic(from, to).and_then(|_| std::fs::remove_file(from)),
+            result => result,
+        });
+        if let Err(e) = result {
+            self.quota.unreserve(to_relative, size, old_size);
+            return Err(e.into());
+        }
+        self.quota.release(from_relative, size);
+        self.cache.invalidate(from);
+        self.cache.invalidate(to);
+        Ok(())
+    }
+
+    fn create_parent(path: &Path) -> std::io::Result<()> {
+        path.parent().map_or(Ok(()), std::fs::create_dir_all)
+    }
+
+    pub fn remove(&self, relative_path: &str, path: &Path) -> Result<()> {
+        self.locks.with_lock(path, || self.remove_locked(relative_path, path))
+    }
+
+    pub fn remove_locked(&self, relative_path: &str, path: &Path) -> Result<()> {
+        let size = std::fs::metadata(path)?.len();
+        std::fs::remove_file(path)?;
+        self.quota.release(relative_path, size);
+        self.cache.invalidate(path);
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,85 @@
+use crate::{Error, Result};
+use sd_notify::NotifyState;
+use std::ops::Range;
+use std::os::unix::io::{FromRawFd, RawFd};
+use tokio::net::TcpListener;
+use tracing::{debug, info};
+
+const LISTEN_FDS_START: RawFd = 3;
+
+pub fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Result<Option<Range<RawFd>>> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(None);
+    };
+
+    let listen_pid: u32 = listen_pid
+        .trim()
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid LISTEN_PID: {}", listen_pid)))?;
+    if listen_pid != pid {
+        return Ok(None);
+    }
+
+    let count: RawFd = listen_fds
+        .trim()
+        .parse()
+        .ok()
+        .filter(|count| *count >= 0)
+        .ok_or_else(|| Error::Config(format!("Invalid LISTEN_FDS: {}", listen_fds)))?;
+    match count {
+        0 => Ok(None),
+        count => Ok(Some(LISTEN_FDS_START..LISTEN_FDS_START + count)),
+    }
+}
+
+pub fn is_listening_socket(fd: RawFd) -> bool {
+    socket_option(fd, libc::SO_TYPE) == Some(libc::SOCK_STREAM) && socket_option(fd, libc::SO_ACCEPTCONN) == Some(1)
+}
+
+fn socket_option(fd: RawFd, option: libc::c_int) -> Option<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, option, &mut value as *mut _ as *mut libc::c_void, &mut len) };
+    (result == 0).then_some(value)
+}
+
+pub fn inherited_listeners() -> Result<Option<Vec<TcpListener>>> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    let Some(fds) = self::listen_fds(listen_pid.as_deref(), listen_fds.as_deref(), std::process::id())? else {
+        return Ok(None);
+    };
+
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let mut listeners = Vec::with_capacity(fds.len());
+    for fd in fds {
+        if !is_listening_socket(fd) {
+            return Err(Error::Config(format!("Inherited file descriptor {} is not a listening socket", fd)));
+        }
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        let addr = listener
+            .local_addr()
+            .map_err(|_| Error::Config(format!("Inherited file descriptor {} is not a TCP socket", fd)))?;
+        listener.set_nonblocking(true)?;
+        info!("Using socket {} passed by systemd", addr);
+        listeners.push(TcpListener::from_std(listener)?);
+    }
+    Ok(Some(listeners))
+}
+
+pub fn notify_ready() {
+    notify(NotifyState::Ready);
+}
+
+pub fn notify_stopping() {
+    notify(NotifyState::Stopping);
+}
+
+fn notify(state: NotifyState) {
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{config::TemplatesConfig, utils, Error, Result};
+use minijinja::{AutoEscape, Environment, Output, State, Value};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::error;
+
+pub const DIRECTORY_LISTING: &str = "directory_listing.html";
+
+const DIRECTORY_LISTING_SOURCE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<title>Directory Listing</title>
+<style>
+body { font-family: Arial, sans-serif; margin: 20px; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border: 1px solid #ddd; padding: 8px; text-align: left; }
+th { background-color: #f2f2f2; }
+a { text-decoration: none; color: #0066cc; }
+a:hover { text-decoration: underline; }
+</style>
+</head>
+<body>
+<h1>Directory Listing: {{ path }}</h1>
+<table>
+<tr><th>Name</th><th>Size</th><th>Modified</th></tr>
+{% if parent %}<tr><td><a href="{{ parent }}">../</a></td><td>-</td><td>-</td></tr>
+{% endif %}{% for row in rows %}<tr><td><a href="{{ row.href }}">{{ row.name }}{% if row.is_dir %}/{% endif %}</a></td><td>{{ row.size }}</td><td>{{ row.modified }}</td></tr>
+{% endfor %}</table>
+</body>
+</html>"#;
+
+const BUILTIN: &[(&str, &str)] = &[(DIRECTORY_LISTING, DIRECTORY_LISTING_SOURCE)];
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryListing<'a> {
+    pub path: &'a str,
+    pub parent: Option<&'a str>,
+    pub rows: Vec<utils::ListingRow>,
+}
+
+// Templates are looked up in `templates.dir` first, then among the built-ins,
+// so a file with a built-in's name overrides it. Every template in the
+// directory is compiled at startup so syntax errors stop the server early;
+// with `reload` each render starts from a fresh environment instead.
+#[derive(Debug, Clone)]
+pub struct Templates {
+    env: Arc<Environment<'static>>,
+    dir: Option<PathBuf>,
+    reload: bool,
+}
+
+impl Templates {
+    pub fn from_config(config: &TemplatesConfig) -> Result<Self> {
+        let dir = config.dir.as_ref().map(PathBuf::from);
+        let env = environment(dir.as_deref());
+        if let Some(dir) = &dir {
+            for name in template_names(dir, dir)? {
+                env.get_template(&name)
+                    .map_err(|e| Error::Config(format!("Invalid template {}: {:#}", name, e)))?;
+            }
+        }
+        Ok(Self {
+            env: Arc::new(env),
+            dir,
+            reload: config.reload,
+        })
+    }
+
+    pub fn render<S: Serialize>(&self, name: &str, context: S) -> Result<String> {
+        let fresh;
+        let env = match self.reload {
+            true => {
+                fresh = environment(self.dir.as_deref());
+                &fresh
+            }
+            false => self.env.as_ref(),
+        };
+        env.get_template(name).and_then(|template| template.render(context)).map_err(|e| {
+            error!(template = name, "Template rendering failed: {:#}", e);
+            Error::Template(format!("{}: {}", name, e))
+        })
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            env: Arc::new(environment(None)),
+            dir: None,
+            reload: false,
+        }
+    }
+}
+
+fn environment(dir: Option<&Path>) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_formatter(format_value);
+    let files = dir.map(|dir| minijinja::path_loader(dir.to_path_buf()));
+    env.set_loader(move |name| {
+        if let Some(source) = files.as_ref().map(|files| files(name)).transpose()?.flatten() {
+            return Ok(Some(source));
+        }
+        Ok(BUILTIN.iter().find(|(builtin, _)| *builtin == name).map(|(_, source)| source.to_string()))
+    });
+    env
+}
+
+// Escapes with the crate's own `escape_html` rather than minijinja's, which
+// also escapes '/' and would turn every href into entity soup.
+fn format_value(out: &mut Output, state: &State, value: &Value) -> std::result::Result<(), minijinja::Error> {
+    if state.auto_escape() != AutoEscape::Html || value.is_safe() || value.is_undefined() || value.is_none() {
+        return minijinja::escape_formatter(out, state, value);
+    }
+    out.write_str(&utils::escape_html(&value.to_string()))
+        .map_err(|_| minijinja::Error::new(minijinja::ErrorKind::WriteFailure, "cannot write template output"))
+}
+
+fn template_names(root: &Path, dir: &Path) -> Result<Vec<String>> {
+    let read = fs::read_dir(dir).map_err(|e| Error::Config(format!("Cannot read templates from {}: {}", dir.display(), e)))?;
+    let mut names = Vec::new();
+    for entry in read {
+        let path = entry?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            names.extend(template_names(root, &path)?);
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            names.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(names)
+}
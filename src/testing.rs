@@ -0,0 +1,176 @@
+use crate::{
+    config::Config,
+    http::{Request, Response},
+    metrics::Metrics,
+    router::Router,
+    server::Server,
+    Result,
+};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, Version};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+pub struct TestClient {
+    config: Arc<Config>,
+    router: Arc<Router>,
+    metrics: Arc<Metrics>,
+}
+
+impl TestClient {
+    pub fn new(config: Config) -> Self {
+        Self::from_server(&Server::new(config))
+    }
+
+    pub fn from_server(server: &Server) -> Self {
+        Self {
+            config: server.config(),
+            router: Arc::new(server.router().clone()),
+            metrics: server.metrics().clone(),
+        }
+    }
+
+    pub fn from_router(router: Router) -> Self {
+        Self {
+            config: Arc::new(Config::default()),
+            router: Arc::new(router),
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub async fn send(&self, request: TestRequest) -> TestResponse {
+        let mut response = Server::serve_request(request.0, &self.config, &self.router, &self.metrics).await;
+        if let Some(stream) = response.stream.take() {
+            let body = stream.collect().await.unwrap_or_else(|e| panic!("response stream failed: {}", e));
+            response.body = Some(body);
+        }
+        TestResponse(response)
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.send(TestRequest::get(uri)).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestRequest(Request);
+
+impl TestRequest {
+    pub fn new(method: Method, uri: &str) -> Self {
+        let uri = uri.parse::<Uri>().unwrap_or_else(|e| panic!("invalid test request uri {:?}: {}", uri, e));
+        let mut request = Request::new(method, uri, Version::HTTP_11);
+        request.headers.insert("host", HeaderValue::from_static("localhost"));
+        request.remote_addr = Some(SocketAddr::from(([127, 0, 0, 1], 40000)));
+        Self(request)
+    }
+
+    pub fn get(uri: &str) -> Self {
+        Self::new(Method::GET, uri)
+    }
+
+    pub fn head(uri: &str) -> Self {
+        Self::new(Method::HEAD, uri)
+    }
+
+    pub fn post(uri: &str) -> Self {
+        Self::new(Method::POST, uri)
+    }
+
+    pub fn put(uri: &str) -> Self {
+        Self::new(Method::PUT, uri)
+    }
+
+    pub fn patch(uri: &str) -> Self {
+        Self::new(Method::PATCH, uri)
+    }
+
+    pub fn delete(uri: &str) -> Self {
+        Self::new(Method::DELETE, uri)
+    }
+
+    pub fn options(uri: &str) -> Self {
+        Self::new(Method::OPTIONS, uri)
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.0.version = version;
+        self
+    }
+
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        let value = HeaderValue::from_str(value).unwrap_or_else(|e| panic!("invalid value for {}: {}", name, e));
+        self.0.headers.append(name, value);
+        self
+    }
+
+    pub fn remove_header(mut self, name: &'static str) -> Self {
+        self.0.headers.remove(name);
+        self
+    }
+
+    pub fn remote_addr(mut self, addr: &str) -> Self {
+        self.0.remote_addr = Some(addr.parse().unwrap_or_else(|e| panic!("invalid remote address {:?}: {}", addr, e)));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        let body = body.into();
+        self.0.headers.insert("content-length", HeaderValue::from(body.len()));
+        self.0.body = Some(body);
+        self
+    }
+
+    pub fn json<T: Serialize>(self, data: &T) -> Self {
+        let body = serde_json::to_vec(data).expect("test request body should serialize");
+        self.header("content-type", "application/json").body(body)
+    }
+
+    pub fn form(self, body: &str) -> Self {
+        self.header("content-type", "application/x-www-form-urlencoded").body(body.to_string())
+    }
+
+    pub fn into_request(self) -> Request {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResponse(Response);
+
+impl TestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.0.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.0.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.0.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.0.body.as_deref().unwrap_or_default()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(self.bytes()).into_owned()
+    }
+
+    pub fn json<T>(&self) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        Ok(serde_json::from_slice(self.bytes())?)
+    }
+
+    pub fn into_response(self) -> Response {
+        self.0
+    }
+}
@@ -0,0 +1,426 @@
+use crate::{
+    config::{ClientAuth, Config, TlsConfig, TlsVersion},
+    Error, Result,
+};
+use arc_swap::ArcSwap;
+use parking_lot::Mutex;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, ServerConnection, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::version::{TLS12, TLS13};
+use rustls::{CipherSuite, ProtocolVersion, RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    pub protocol: ProtocolVersion,
+    pub cipher_suite: CipherSuite,
+    pub client_identity: Option<ClientIdentity>,
+}
+
+impl TlsInfo {
+    pub fn from_connection(connection: &ServerConnection) -> Option<Self> {
+        Some(Self {
+            protocol: connection.protocol_version()?,
+            cipher_suite: connection.negotiated_cipher_suite()?.suite(),
+            client_identity: connection.peer_certificates().and_then(ClientIdentity::from_chain),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub common_name: Option<String>,
+    pub subject_alt_names: Vec<String>,
+    pub chain: Vec<CertificateDer<'static>>,
+}
+
+impl ClientIdentity {
+    pub fn from_chain(chain: &[CertificateDer<'_>]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(chain.first()?).ok()?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|name| name.as_str().ok())
+            .map(str::to_string);
+        let subject_alt_names = match cert.subject_alternative_name() {
+            Ok(Some(extension)) => extension.value.general_names.iter().filter_map(general_name).collect(),
+            _ => Vec::new(),
+        };
+
+        Some(Self {
+            common_name,
+            subject_alt_names,
+            chain: chain.iter().map(|cert| cert.clone().into_owned()).collect(),
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.common_name.iter().chain(&self.subject_alt_names).map(String::as_str)
+    }
+}
+
+fn general_name(name: &GeneralName<'_>) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) | GeneralName::URI(name) | GeneralName::RFC822Name(name) => Some(name.to_string()),
+        GeneralName::IPAddress(bytes) => match bytes.len() {
+            4 => Some(IpAddr::from(<[u8; 4]>::try_from(*bytes).ok()?).to_string()),
+            16 => Some(IpAddr::from(<[u8; 16]>::try_from(*bytes).ok()?).to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn validate(config: &TlsConfig) -> Result<()> {
+    if !config.self_signed && (config.cert_path.is_none() || config.key_path.is_none()) {
+        return Err(Error::Config("TLS requires both cert_path and key_path".to_string()));
+    }
+    if config.client_auth != ClientAuth::None && config.client_ca_path.is_none() {
+        return Err(Error::Config("client_auth requires a client_ca_path to verify certificates".to_string()));
+    }
+    if config.client_auth == ClientAuth::None && !config.client_rules.is_empty() {
+        return Err(Error::Config("client_rules require client_auth to be optional or required".to_string()));
+    }
+
+    let mut claimed = HashSet::new();
+    for certificate in &config.certificates {
+        if certificate.hostnames.is_empty() {
+            return Err(Error::Config(format!("TLS certificate {} has no hostnames", certificate.cert_path)));
+        }
+        for hostname in &certificate.hostnames {
+            let normalized = normalize_hostname(hostname);
+            let wildcard_body = normalized.strip_prefix("*.").unwrap_or(&normalized);
+            if wildcard_body.is_empty() || wildcard_body.contains('*') {
+                return Err(Error::Config(format!("Invalid TLS certificate hostname: {}", hostname)));
+            }
+            if !claimed.insert(normalized) {
+                return Err(Error::Config(format!("Hostname {} is claimed by more than one TLS certificate", hostname)));
+            }
+        }
+    }
+    cipher_suites(config).map(|_| ())
+}
+
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+    Ok(TlsAcceptor::from(server_config(config)?))
+}
+
+pub struct SelfSignedCertificate {
+    pub cert: CertificateDer<'static>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl SelfSignedCertificate {
+    const CERT_FILE: &'static str = "self-signed.pem";
+    const KEY_FILE: &'static str = "self-signed.key";
+
+    pub fn generate(hostnames: &[String]) -> Result<Self> {
+        Ok(Self::from_generated(&Self::rcgen(hostnames)?))
+    }
+
+    pub fn load_or_generate(cache_dir: Option<&Path>, hostnames: &[String]) -> Result<Self> {
+        let Some(dir) = cache_dir else {
+            return Self::generate(hostnames);
+        };
+        let (cert_path, key_path) = (dir.join(Self::CERT_FILE), dir.join(Self::KEY_FILE));
+        if cert_path.exists() && key_path.exists() {
+            let mut certs = load_certs(&cert_path.to_string_lossy())?;
+            return Ok(Self {
+                cert: certs.remove(0),
+                key: load_private_key(&key_path.to_string_lossy())?,
+            });
+        }
+
+        let generated = Self::rcgen(hostnames)?;
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&cert_path, generated.cert.pem())?;
+        std::fs::write(&key_path, generated.key_pair.serialize_pem())?;
+        Ok(Self::from_generated(&generated))
+    }
+
+    fn rcgen(hostnames: &[String]) -> Result<rcgen::CertifiedKey> {
+        rcgen::generate_simple_self_signed(hostnames.to_vec())
+            .map_err(|e| Error::Config(format!("Cannot generate self-signed certificate: {}", e)))
+    }
+
+    fn from_generated(generated: &rcgen::CertifiedKey) -> Self {
+        Self {
+            cert: generated.cert.der().clone(),
+            key: PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der()).into(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        Sha256::digest(&self.cert)
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    fn certified_key(&self, provider: &CryptoProvider) -> Result<CertifiedKey> {
+        Ok(CertifiedKey::from_der(vec![self.cert.clone()], self.key.clone_key(), provider)?)
+    }
+}
+
+fn self_signed_hostnames(host: &str) -> Vec<String> {
+    let mut hostnames = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    let unspecified = host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified());
+    if !host.is_empty() && !unspecified && !hostnames.iter().any(|name| name == host) {
+        hostnames.push(host.to_string());
+    }
+    hostnames
+}
+
+pub struct ReloadableAcceptor {
+    config: TlsConfig,
+    self_signed: Option<SelfSignedCertificate>,
+    current: ArcSwap<ServerConfig>,
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl ReloadableAcceptor {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let tls = config.tls.clone();
+        let self_signed = match tls.self_signed {
+            true => {
+                let hostnames = self_signed_hostnames(&config.server.host);
+                let certificate = SelfSignedCertificate::load_or_generate(tls.self_signed_dir.as_deref().map(Path::new), &hostnames)?;
+                info!(
+                    "Using self-signed TLS certificate for {} (SHA-256 fingerprint {})",
+                    hostnames.join(", "),
+                    certificate.fingerprint()
+                );
+                Some(certificate)
+            }
+            false => None,
+        };
+        let current = ArcSwap::new(build_server_config(&tls, self_signed.as_ref())?);
+        let modified = Mutex::new(modification_times(&tls));
+        Ok(Self {
+            config: tls,
+            self_signed,
+            current,
+            modified,
+        })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.load_full())
+    }
+
+    pub fn reload(&self) -> Result<()> {
+        let modified = modification_times(&self.config);
+        let server_config = build_server_config(&self.config, self.self_signed.as_ref())?;
+        self.current.store(server_config);
+        *self.modified.lock() = modified;
+        info!("Reloaded TLS certificates");
+        Ok(())
+    }
+
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        if *self.modified.lock() == modification_times(&self.config) {
+            return Ok(false);
+        }
+        self.reload().map(|_| true)
+    }
+}
+
+fn modification_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    let certificates = config
+        .certificates
+        .iter()
+        .flat_map(|certificate| [Some(certificate.cert_path.as_str()), Some(certificate.key_path.as_str())]);
+    [config.cert_path.as_deref(), config.key_path.as_deref(), config.client_ca_path.as_deref()]
+        .into_iter()
+        .chain(certificates)
+        .map(|path| path.and_then(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()))
+        .collect()
+}
+
+pub fn server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let self_signed = match config.self_signed {
+        true => Some(SelfSignedCertificate::generate(&self_signed_hostnames(""))?),
+        false => None,
+    };
+    build_server_config(config, self_signed.as_ref())
+}
+
+fn build_server_config(config: &TlsConfig, self_signed: Option<&SelfSignedCertificate>) -> Result<Arc<ServerConfig>> {
+    validate(config)?;
+    let provider = Arc::new(CryptoProvider {
+        cipher_suites: cipher_suites(config)?,
+        ..ring::default_provider()
+    });
+
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_protocol_versions(protocol_versions(config.min_version))?;
+    let builder = match config.client_ca_path.as_deref().filter(|_| config.client_auth != ClientAuth::None) {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path)? {
+                roots.add(cert)?;
+            }
+            let mut verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone());
+            if config.client_auth == ClientAuth::Optional {
+                verifier = verifier.allow_unauthenticated();
+            }
+            let verifier = verifier
+                .build()
+                .map_err(|e| Error::Config(format!("Invalid client CA bundle {}: {}", path, e)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let resolver = SniResolver::from_config(config, self_signed, &provider)?;
+    let mut server_config = builder.with_cert_resolver(Arc::new(resolver));
+    server_config.alpn_protocols = alpn_protocols();
+    Ok(Arc::new(server_config))
+}
+
+#[derive(Debug)]
+struct SniResolver {
+    exact: HashMap<String, Arc<CertifiedKey>>,
+    wildcard: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl SniResolver {
+    fn from_config(config: &TlsConfig, self_signed: Option<&SelfSignedCertificate>, provider: &CryptoProvider) -> Result<Self> {
+        let default = match self_signed {
+            Some(certificate) => certificate.certified_key(provider)?,
+            None => certified_key(
+                config.cert_path.as_deref().unwrap_or_default(),
+                config.key_path.as_deref().unwrap_or_default(),
+                provider,
+            )?,
+        };
+        let mut resolver = Self {
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+            default: Arc::new(default),
+        };
+
+        for certificate in &config.certificates {
+            let key = Arc::new(certified_key(&certificate.cert_path, &certificate.key_path, provider)?);
+            for hostname in &certificate.hostnames {
+                let hostname = normalize_hostname(hostname);
+                match hostname.strip_prefix("*.") {
+                    Some(parent) => resolver.wildcard.insert(parent.to_string(), key.clone()),
+                    None => resolver.exact.insert(hostname, key.clone()),
+                };
+            }
+        }
+        Ok(resolver)
+    }
+
+    fn select(&self, server_name: Option<&str>) -> (Arc<CertifiedKey>, String) {
+        if let Some(name) = server_name.map(normalize_hostname) {
+            if let Some(key) = self.exact.get(&name) {
+                return (key.clone(), name);
+            }
+            if let Some((_, parent)) = name.split_once('.') {
+                if let Some(key) = self.wildcard.get(parent) {
+                    return (key.clone(), format!("*.{}", parent));
+                }
+            }
+        }
+        (self.default.clone(), "default".to_string())
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello.server_name();
+        let (key, selected) = self.select(server_name);
+        debug!("Selected TLS certificate {} for SNI {}", selected, server_name.unwrap_or("<none>"));
+        Some(key)
+    }
+}
+
+fn normalize_hostname(hostname: &str) -> String {
+    hostname.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn certified_key(cert_path: &str, key_path: &str, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    Ok(CertifiedKey::from_der(load_certs(cert_path)?, load_private_key(key_path)?, provider)?)
+}
+
+fn alpn_protocols() -> Vec<Vec<u8>> {
+    let mut protocols = Vec::new();
+    if cfg!(feature = "http2") {
+        protocols.push(b"h2".to_vec());
+    }
+    protocols.push(b"http/1.1".to_vec());
+    protocols
+}
+
+static TLS12_AND_LATER: &[&SupportedProtocolVersion] = &[&TLS13, &TLS12];
+static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&TLS13];
+
+fn protocol_versions(min_version: TlsVersion) -> &'static [&'static SupportedProtocolVersion] {
+    match min_version {
+        TlsVersion::Tls12 => TLS12_AND_LATER,
+        TlsVersion::Tls13 => TLS13_ONLY,
+    }
+}
+
+fn permits(min_version: TlsVersion, suite: &SupportedCipherSuite) -> bool {
+    min_version == TlsVersion::Tls12 || matches!(suite, SupportedCipherSuite::Tls13(_))
+}
+
+fn cipher_suites(config: &TlsConfig) -> Result<Vec<SupportedCipherSuite>> {
+    let available = ring::default_provider().cipher_suites;
+    if config.cipher_suites.is_empty() {
+        return Ok(available.into_iter().filter(|suite| permits(config.min_version, suite)).collect());
+    }
+
+    config
+        .cipher_suites
+        .iter()
+        .map(|name| {
+            let suite = available
+                .iter()
+                .find(|suite| suite.suite().as_str().is_some_and(|known| known.eq_ignore_ascii_case(name)))
+                .ok_or_else(|| Error::Config(format!("Unknown TLS cipher suite: {}", name)))?;
+            if !permits(config.min_version, suite) {
+                return Err(Error::Config(format!(
+                    "Cipher suite {} is TLS 1.2 only and cannot be used with min_version = \"1.3\"",
+                    name
+                )));
+            }
+            Ok(*suite)
+        })
+        .collect()
+}
+
+pub(crate) fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).map_err(|e| Error::Config(format!("Cannot read certificates from {}: {}", path, e)))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| Error::Config(format!("Invalid PEM in {}: {}", path, e)))?;
+    if certs.is_empty() {
+        return Err(Error::Config(format!("No certificates found in {}", path)));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).map_err(|e| Error::Config(format!("Cannot read private key from {}: {}", path, e)))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| Error::Config(format!("Invalid PEM in {}: {}", path, e)))?
+        .ok_or_else(|| Error::Config(format!("No private key found in {}", path)))
+}
\ No newline at end of file
@@ -0,0 +1,94 @@
+use crate::{
+    config::FileConfig,
+    utils::mime::{self, MimeRegistry},
+    Error, Result,
+};
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Upload<'a> {
+    pub path: &'a str,
+    pub content_type: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+// Runs on the complete upload body before anything is written, so a veto
+// leaves no trace on disk. The returned error becomes the response.
+pub trait UploadValidator: Send + Sync + 'static {
+    fn validate(&self, upload: &Upload<'_>) -> Result<()>;
+}
+
+// Rejects uploads whose extension or declared Content-Type names one of
+// `types` when the body does not start with that type's signature, so an HTML
+// page cannot be stored as `photo.png`.
+#[derive(Debug, Clone)]
+pub struct SignatureCheck {
+    types: Vec<String>,
+    mime: MimeRegistry,
+}
+
+impl SignatureCheck {
+    pub fn new(types: &[String], mime: MimeRegistry) -> Self {
+        Self {
+            types: types.iter().map(|mime| mime.trim().to_ascii_lowercase()).collect(),
+            mime,
+        }
+    }
+}
+
+impl UploadValidator for SignatureCheck {
+    fn validate(&self, upload: &Upload<'_>) -> Result<()> {
+        let by_extension = self.mime.lookup(upload.path).map(mime::essence);
+        let declared = upload.content_type.map(|declared| mime::essence(declared).to_ascii_lowercase());
+        let detected = mime::sniff(upload.body);
+        for expected in by_extension.into_iter().chain(declared.as_deref()) {
+            if self.types.iter().any(|checked| checked == expected) && detected != Some(expected) {
+                return Err(Error::UnsupportedMediaType(format!("{} is not a valid {} file", upload.path, expected)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct UploadValidators {
+    validators: RwLock<Vec<Arc<dyn UploadValidator>>>,
+}
+
+impl UploadValidators {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: &FileConfig) -> Self {
+        let validators = Self::new();
+        if config.upload_validation.enabled {
+            validators.add(SignatureCheck::new(&config.upload_validation.types, MimeRegistry::from_config(config)));
+        }
+        validators
+    }
+
+    pub fn add(&self, validator: impl UploadValidator) {
+        self.validators.write().push(Arc::new(validator));
+    }
+
+    pub fn validate(&self, upload: &Upload<'_>) -> Result<()> {
+        let validators = self.validators.read().clone();
+        validators.iter().try_for_each(|validator| validator.validate(upload))
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.read().is_empty()
+    }
+}
+
+impl std::fmt::Debug for UploadValidators {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UploadValidators").field("len", &self.len()).finish()
+    }
+}
@@ -1,52 +1,67 @@
+pub mod etag;
+pub mod http_date;
+pub mod mime;
+pub mod range;
+
+use crate::config::FileConfig;
 use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::path::Path;
+use std::str::FromStr;
 
 pub fn get_mime_type(path: &str) -> &'static str {
-    let extension = Path::new(path)
+    Path::new(path)
         .extension()
         .and_then(|ext| ext.to_str())
-        .unwrap_or("");
-
-    match extension.to_lowercase().as_str() {
-        "html" | "htm" => "text/html",
-        "css" => "text/css",
-        "js" => "application/javascript",
-        "json" => "application/json",
-        "xml" => "application/xml",
-        "txt" => "text/plain",
-        "pdf" => "application/pdf",
-        "jpg" | "jpeg" => "image/jpeg",
-        "png" => "image/png",
-        "gif" => "image/gif",
-        "svg" => "image/svg+xml",
-        "ico" => "image/x-icon",
-        "woff" => "font/woff",
-        "woff2" => "font/woff2",
-        "ttf" => "font/ttf",
-        "eot" => "application/vnd.ms-fontobject",
-        "mp4" => "video/mp4",
-        "webm" => "video/webm",
-        "mp3" => "audio/mpeg",
-        "wav" => "audio/wav",
-        "zip" => "application/zip",
-        "tar" => "application/x-tar",
-        "gz" => "application/gzip",
-        _ => "application/octet-stream",
-    }
+        .and_then(mime::builtin_type)
+        .unwrap_or(mime::OCTET_STREAM)
 }
 
+pub const MAX_PATH_DEPTH: usize = 32;
+pub const MAX_PATH_COMPONENT: usize = 255;
+
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
 pub fn sanitize_path(path: &str) -> Result<String, Error> {
-    let path = path.trim_start_matches('/');
-    
-    if path.contains("..") {
-        return Err(Error::InvalidPath("Path traversal not allowed".to_string()));
-    }
-    
     if path.contains('\\') {
         return Err(Error::InvalidPath("Invalid path separator".to_string()));
     }
-    
-    Ok(path.to_string())
+
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(Error::InvalidPath("Path traversal not allowed".to_string())),
+            _ if component.chars().any(char::is_control) => {
+                return Err(Error::InvalidPath("Control character in path".to_string()));
+            }
+            _ if component.len() > MAX_PATH_COMPONENT => {
+                return Err(Error::InvalidPath(format!("Path component longer than {} bytes", MAX_PATH_COMPONENT)));
+            }
+            _ if cfg!(windows) && is_windows_unsafe_component(component) => {
+                return Err(Error::InvalidPath(format!("Reserved file name: {}", component)));
+            }
+            _ => components.push(component),
+        }
+    }
+
+    if components.len() > MAX_PATH_DEPTH {
+        return Err(Error::InvalidPath(format!("Path deeper than {} components", MAX_PATH_DEPTH)));
+    }
+    Ok(components.join("/"))
+}
+
+pub fn is_windows_unsafe_component(component: &str) -> bool {
+    if component.contains([':', '<', '>', '"', '|', '?', '*']) || component.ends_with(['.', ' ']) {
+        return true;
+    }
+    let stem = component.split('.').next().unwrap_or_default().trim_end();
+    WINDOWS_RESERVED_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem))
 }
 
 pub fn validate_file_extension(filename: &str, allowed_extensions: &[String]) -> Result<(), Error> {
@@ -80,7 +95,145 @@ pub fn format_file_size(bytes: u64) -> String {
     }
 }
 
-pub fn generate_directory_listing(path: &str, entries: &[std::fs::DirEntry]) -> String {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListingSort {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    // Directory order, which lets a listing be streamed as it is read.
+    Unsorted,
+}
+
+impl FromStr for ListingSort {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(ListingSort::Name),
+            "size" => Ok(ListingSort::Size),
+            "modified" => Ok(ListingSort::Modified),
+            "none" => Ok(ListingSort::Unsorted),
+            other => Err(Error::BadRequest(format!("Unknown sort key: {}", other))),
+        }
+    }
+}
+
+pub fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.as_encoded_bytes().first() == Some(&b'.')
+}
+
+pub fn read_directory(dir: &Path, config: &FileConfig) -> Result<Vec<std::fs::DirEntry>, Error> {
+    let context = |e: std::io::Error| Error::Internal(format!("Cannot list directory {}: {}", dir.display(), e));
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(context)? {
+        let entry = entry.map_err(context)?;
+        if is_listed(&entry.file_name(), config) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+pub fn is_listed(name: &std::ffi::OsStr, config: &FileConfig) -> bool {
+    (config.show_hidden || !is_hidden(name)) && (!config.hide_non_utf8_names || name.to_str().is_some())
+}
+
+// Follows symlinks, so a dangling link or an entry that vanished since
+// read_dir yields None rather than an error.
+pub fn entry_metadata(entry: &std::fs::DirEntry) -> Option<std::fs::Metadata> {
+    std::fs::metadata(entry.path()).ok()
+}
+
+pub fn sort_directory_entries(entries: &mut Vec<std::fs::DirEntry>, sort: ListingSort, descending: bool) {
+    if sort == ListingSort::Unsorted {
+        return;
+    }
+    let mut keyed: Vec<_> = entries
+        .drain(..)
+        .map(|entry| {
+            let metadata = entry_metadata(&entry);
+            let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+            let size = metadata.as_ref().filter(|m| m.is_file()).map(|m| m.len()).unwrap_or(0);
+            let modified = metadata.and_then(|m| m.modified().ok()).unwrap_or(std::time::UNIX_EPOCH);
+            (is_dir, size, modified, entry.file_name(), entry)
+        })
+        .collect();
+
+    keyed.sort_by(|a, b| {
+        let order = match sort {
+            ListingSort::Name => a.3.cmp(&b.3),
+            ListingSort::Size => a.1.cmp(&b.1).then_with(|| a.3.cmp(&b.3)),
+            ListingSort::Modified => a.2.cmp(&b.2).then_with(|| a.3.cmp(&b.3)),
+            ListingSort::Unsorted => std::cmp::Ordering::Equal,
+        };
+        let order = if descending { order.reverse() } else { order };
+        b.0.cmp(&a.0).then(order)
+    });
+
+    entries.extend(keyed.into_iter().map(|(_, _, _, _, entry)| entry));
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListingRow {
+    pub name: String,
+    pub href: String,
+    pub is_dir: bool,
+    pub size: String,
+    pub modified: String,
+}
+
+pub fn listing_rows(base_href: &str, entries: &[std::fs::DirEntry]) -> Vec<ListingRow> {
+    entries.iter().map(|entry| listing_row(base_href, entry)).collect()
+}
+
+pub fn listing_row(base_href: &str, entry: &std::fs::DirEntry) -> ListingRow {
+    let name = entry.file_name().to_string_lossy().to_string();
+    let metadata = entry_metadata(entry);
+    let size = match &metadata {
+        Some(metadata) if metadata.is_file() => format_file_size(metadata.len()),
+        _ => "-".to_string(),
+    };
+    let modified = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.modified().ok())
+        .map(|t| {
+            let datetime: chrono::DateTime<chrono::Utc> = t.into();
+            datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+        })
+        .unwrap_or_else(|| "-".to_string());
+    let is_dir = metadata.is_some_and(|metadata| metadata.is_dir());
+    let href = format!("{}{}{}", base_href, urlencoding::encode(&name), if is_dir { "/" } else { "" });
+    ListingRow { name, href, is_dir, size, modified }
+}
+
+pub fn generate_directory_listing(path: &str, base_href: &str, entries: &[std::fs::DirEntry], parent: Option<&str>) -> String {
+    let mut html = directory_listing_head(path, parent);
+    for entry in entries {
+        html.push_str(&directory_listing_row(&listing_row(base_href, entry)));
+    }
+    html.push_str(DIRECTORY_LISTING_TAIL);
+    html
+}
+
+pub const DIRECTORY_LISTING_TAIL: &str = "</table>\n</body>\n</html>";
+
+pub fn directory_listing_head(path: &str, parent: Option<&str>) -> String {
     let mut html = String::new();
     html.push_str("<!DOCTYPE html>\n<html>\n<head>\n");
     html.push_str("<title>Directory Listing</title>\n");
@@ -92,54 +245,120 @@ pub fn generate_directory_listing(path: &str, entries: &[std::fs::DirEntry]) ->
     html.push_str("a { text-decoration: none; color: #0066cc; }\n");
     html.push_str("a:hover { text-decoration: underline; }\n");
     html.push_str("</style>\n</head>\n<body>\n");
-    html.push_str(&format!("<h1>Directory Listing: {}</h1>\n", path));
+    html.push_str(&format!("<h1>Directory Listing: {}</h1>\n", escape_html(path)));
     html.push_str("<table>\n");
     html.push_str("<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n");
-    
-    for entry in entries {
-        let name = entry.file_name().to_string_lossy().to_string();
-        let metadata = entry.metadata().unwrap_or_else(|_| std::fs::metadata(".").unwrap_or_else(|_| panic!("Cannot get metadata")));
-        let size = if metadata.is_file() {
-            format_file_size(metadata.len())
-        } else {
-            "-".to_string()
-        };
-        let modified = metadata
-            .modified()
-            .map(|t| {
-                let datetime: chrono::DateTime<chrono::Utc> = t.into();
-                datetime.format("%Y-%m-%d %H:%M:%S").to_string()
-            })
-            .unwrap_or_else(|_| "-".to_string());
-        
-        let link = if metadata.is_dir() {
-            format!("<a href=\"{}/\">{}/</a>", name, name)
-        } else {
-            format!("<a href=\"{}\">{}</a>", name, name)
-        };
-        
-        html.push_str(&format!(
-            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
-            link, size, modified
-        ));
+
+    if let Some(parent) = parent {
+        html.push_str(&format!("<tr><td><a href=\"{}\">../</a></td><td>-</td><td>-</td></tr>\n", parent));
     }
-    
-    html.push_str("</table>\n</body>\n</html>");
     html
 }
 
-pub fn parse_query_string(query: &str) -> std::collections::HashMap<String, String> {
-    let mut params = std::collections::HashMap::new();
-    
-    for pair in query.split('&') {
-        if let Some((key, value)) = pair.split_once('=') {
-            let key = urlencoding::decode(key).unwrap_or_else(|_| key.to_string().into()).to_string();
-            let value = urlencoding::decode(value).unwrap_or_else(|_| value.to_string().into()).to_string();
-            params.insert(key, value);
-        }
+pub fn directory_listing_row(row: &ListingRow) -> String {
+    let suffix = if row.is_dir { "/" } else { "" };
+    format!(
+        "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+        row.href, escape_html(&row.name), suffix, row.size, row.modified
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub modified: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+pub fn directory_entries(entries: &[std::fs::DirEntry]) -> Vec<DirectoryEntry> {
+    entries.iter().map(directory_entry).collect()
+}
+
+pub fn directory_entry(entry: &std::fs::DirEntry) -> DirectoryEntry {
+    let metadata = entry_metadata(entry);
+    let name = entry.file_name().to_string_lossy().to_string();
+    let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+    let modified = metadata.as_ref().and_then(|m| m.modified().ok()).map(|t| {
+        let datetime: chrono::DateTime<chrono::Utc> = t.into();
+        datetime.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    });
+    let mime_type = (!is_dir).then(|| get_mime_type(&name).to_string());
+
+    DirectoryEntry {
+        size: metadata.as_ref().filter(|m| m.is_file()).map_or(0, |m| m.len()),
+        is_dir,
+        name,
+        modified,
+        mime_type,
     }
-    
-    params
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryOptions {
+    pub semicolon_separator: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    pub fn parse(query: &str) -> Self {
+        Self::parse_with(query, QueryOptions::default())
+    }
+
+    pub fn parse_with(query: &str, options: QueryOptions) -> Self {
+        let query = query.strip_prefix('?').unwrap_or(query);
+        let pairs = query
+            .split(|c| c == '&' || (options.semicolon_separator && c == ';'))
+            .filter_map(|pair| url::form_urlencoded::parse(pair.as_bytes()).next())
+            .map(|(key, value)| (key.strip_suffix("[]").unwrap_or(&key).to_string(), value.into_owned()))
+            .collect();
+        Self { pairs }
+    }
+
+    pub fn first(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs.iter().filter(move |(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(name, _)| name == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn to_map(&self) -> HashMap<String, String> {
+        self.pairs.iter().cloned().collect()
+    }
+
+    pub fn deserialize<T>(&self) -> Result<T, Error>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let encoded = serde_html_form::to_string(&self.pairs).map_err(|e| Error::BadRequest(format!("Invalid query string: {}", e)))?;
+        serde_html_form::from_str(&encoded).map_err(|e| Error::BadRequest(format!("Invalid query string: {}", e)))
+    }
+}
+
+pub fn parse_query_string(query: &str) -> HashMap<String, String> {
+    QueryParams::parse(query).to_map()
 }
 
 pub fn is_safe_path(path: &str) -> bool {
@@ -148,4 +367,211 @@ pub fn is_safe_path(path: &str) -> bool {
 
 pub fn normalize_path(path: &str) -> String {
     path.trim_start_matches('/').to_string()
-} 
\ No newline at end of file
+}
+
+pub fn canonical_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let value = value.trim();
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| Error::Config(format!("Invalid IP address in '{}'", value)))?;
+        let addr = canonical_ip(addr);
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| Error::Config(format!("Invalid prefix length in '{}'", value)))?,
+            None => max_prefix,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical_ip(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+pub fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']').and_then(|(ip, _)| ip.parse().ok());
+    }
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    node.rsplit_once(':').and_then(|(ip, _)| ip.parse::<std::net::Ipv4Addr>().ok()).map(IpAddr::V4)
+}
+
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+                let rest = rest[1..].strip_prefix(b"/").unwrap_or(&rest[1..]);
+                (0..=path.len()).any(|i| matches(rest, &path[i..]))
+            }
+            Some((b'*', rest)) => {
+                let limit = path.iter().position(|&b| b == b'/').unwrap_or(path.len());
+                (0..=limit).any(|i| matches(rest, &path[i..]))
+            }
+            Some((b'?', rest)) => path.first().is_some_and(|&b| b != b'/') && matches(rest, &path[1..]),
+            Some((&c, rest)) => path.first() == Some(&c) && matches(rest, &path[1..]),
+        }
+    }
+    matches(pattern.as_bytes(), path.as_bytes())
+}
+
+pub fn match_cache_control<'a>(rules: &'a HashMap<String, String>, path: &str) -> Option<&'a str> {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    rules
+        .iter()
+        .filter(|(pattern, _)| {
+            let subject = if pattern.contains('/') { path } else { file_name };
+            glob_match(pattern, subject)
+        })
+        .max_by_key(|(pattern, _)| {
+            let literals = pattern.chars().filter(|c| !matches!(c, '*' | '?')).count();
+            (pattern.contains('/'), literals, std::cmp::Reverse(pattern.as_str()))
+        })
+        .map(|(_, value)| value.as_str())
+}
+
+pub fn encode_location(location: &str) -> String {
+    let mut encoded = String::with_capacity(location.len());
+    for byte in location.bytes() {
+        match byte {
+            b'!'..=b'~' if !b"\"<>\\^`{|}".contains(&byte) => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    pub media_type: String,
+    pub subtype: String,
+    pub params: Vec<(String, String)>,
+    pub quality: f32,
+}
+
+impl MediaRange {
+    pub fn specificity(&self) -> u8 {
+        match (self.media_type.as_str(), self.subtype.as_str()) {
+            ("*", _) => 0,
+            (_, "*") => 1,
+            _ if self.params.is_empty() => 2,
+            _ => 3,
+        }
+    }
+
+    pub fn matches(&self, mime: &str) -> bool {
+        let essence = mime.split(';').next().unwrap_or_default().trim();
+        let Some((media_type, subtype)) = essence.split_once('/') else {
+            return false;
+        };
+        (self.media_type == "*" || self.media_type.eq_ignore_ascii_case(media_type))
+            && (self.subtype == "*" || self.subtype.eq_ignore_ascii_case(subtype))
+    }
+}
+
+pub fn parse_accept(header: &str) -> Vec<MediaRange> {
+    let mut ranges: Vec<MediaRange> = header.split(',').filter_map(parse_media_range).collect();
+    ranges.sort_by(|a, b| {
+        b.quality
+            .partial_cmp(&a.quality)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| b.specificity().cmp(&a.specificity()))
+    });
+    ranges
+}
+
+fn parse_media_range(segment: &str) -> Option<MediaRange> {
+    let mut parts = segment.split(';');
+    let (media_type, subtype) = parts.next()?.trim().split_once('/')?;
+    let is_token = |value: &str| !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if !is_token(media_type) || !is_token(subtype) || (media_type == "*" && subtype != "*") {
+        return None;
+    }
+
+    let mut quality = 1.0;
+    let mut params = Vec::new();
+    for param in parts {
+        let (name, value) = param.trim().split_once('=')?;
+        let (name, value) = (name.trim(), value.trim().trim_matches('"'));
+        if name.eq_ignore_ascii_case("q") {
+            quality = value.parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))?;
+        } else {
+            params.push((name.to_ascii_lowercase(), value.to_string()));
+        }
+    }
+
+    Some(MediaRange {
+        media_type: media_type.to_ascii_lowercase(),
+        subtype: subtype.to_ascii_lowercase(),
+        params,
+        quality,
+    })
+}
+
+pub fn accept_quality(ranges: &[MediaRange], mime: &str) -> Option<(f32, u8)> {
+    ranges
+        .iter()
+        .filter(|range| range.matches(mime))
+        .max_by_key(|range| range.specificity())
+        .map(|range| (range.quality, range.specificity()))
+}
\ No newline at end of file
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    weak: bool,
+    opaque: String,
+}
+
+impl EntityTag {
+    pub fn strong(opaque: impl Into<String>) -> Self {
+        Self { weak: false, opaque: opaque.into() }
+    }
+
+    pub fn weak(opaque: impl Into<String>) -> Self {
+        Self { weak: true, opaque: opaque.into() }
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    pub fn opaque(&self) -> &str {
+        &self.opaque
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, value),
+        };
+        let opaque = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        if !opaque.bytes().all(is_etag_byte) {
+            return None;
+        }
+        Some(Self { weak, opaque: opaque.to_string() })
+    }
+
+    pub fn strong_eq(&self, other: &Self) -> bool {
+        !self.weak && !other.weak && self.opaque == other.opaque
+    }
+
+    pub fn weak_eq(&self, other: &Self) -> bool {
+        self.opaque == other.opaque
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.opaque)
+    }
+}
+
+impl FromStr for EntityTag {
+    type Err = crate::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse(value).ok_or_else(|| crate::Error::BadRequest(format!("Invalid entity tag: {:?}", value)))
+    }
+}
+
+pub fn from_metadata(len: u64, modified: SystemTime) -> EntityTag {
+    let nanos = modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    EntityTag::strong(format!("{:x}-{:x}", len, nanos))
+}
+
+pub fn from_bytes(bytes: &[u8]) -> EntityTag {
+    EntityTag::strong(format!("{:x}-{:016x}", bytes.len(), fnv1a(bytes)))
+}
+
+pub fn if_none_match_matches(header: &str, etag: &EntityTag) -> bool {
+    list_matches(header, etag, EntityTag::weak_eq)
+}
+
+pub fn if_match_matches(header: &str, etag: &EntityTag) -> bool {
+    list_matches(header, etag, EntityTag::strong_eq)
+}
+
+pub fn if_range_matches(header: &str, etag: &EntityTag) -> bool {
+    EntityTag::parse(header).is_some_and(|tag| tag.strong_eq(etag))
+}
+
+fn list_matches(header: &str, etag: &EntityTag, eq: fn(&EntityTag, &EntityTag) -> bool) -> bool {
+    if header.trim() == "*" {
+        return true;
+    }
+    list_items(header).filter_map(EntityTag::parse).any(|candidate| eq(&candidate, etag))
+}
+
+// Commas are legal inside an opaque tag, so items end at a comma only outside quotes.
+fn list_items(header: &str) -> impl Iterator<Item = &str> {
+    let mut rest = header;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches([',', ' ', '\t']);
+        if rest.is_empty() {
+            return None;
+        }
+        let end = match rest.find([',', '"']) {
+            Some(open) if rest.as_bytes()[open] == b'"' => rest[open + 1..].find('"').map_or(rest.len(), |close| open + close + 2),
+            Some(comma) => comma,
+            None => rest.len(),
+        };
+        let (item, tail) = rest.split_at(end);
+        rest = tail;
+        Some(item)
+    })
+}
+
+// RFC 7232 etagc: %x21 / %x23-7E / obs-text
+fn is_etag_byte(b: u8) -> bool {
+    b == 0x21 || (0x23..=0x7e).contains(&b) || b >= 0x80
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3))
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use http::HeaderValue;
+use std::cell::RefCell;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const IMF_FIXDATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+thread_local! {
+    static CURRENT: RefCell<Option<(u64, HeaderValue)>> = const { RefCell::new(None) };
+}
+
+pub fn format(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).format(IMF_FIXDATE).to_string()
+}
+
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let datetime = parse_imf_fixdate(value).or_else(|| parse_rfc850(value)).or_else(|| parse_asctime(value))?;
+    Some(datetime.and_utc().into())
+}
+
+pub fn truncate(time: SystemTime) -> SystemTime {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+pub fn current_header() -> HeaderValue {
+    let now = SystemTime::now();
+    let second = now.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    CURRENT.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        match &*cached {
+            Some((at, value)) if *at == second => value.clone(),
+            _ => {
+                let value = HeaderValue::from_str(&format(now)).expect("formatted date is a valid header value");
+                *cached = Some((second, value.clone()));
+                value
+            }
+        }
+    })
+}
+
+fn parse_imf_fixdate(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(strip_gmt(value)?, "%a, %d %b %Y %H:%M:%S").ok()
+}
+
+fn parse_rfc850(value: &str) -> Option<NaiveDateTime> {
+    let value = strip_gmt(value)?;
+    let (date, time) = value.rsplit_once(' ')?;
+    let (day, year) = date.rsplit_once('-')?;
+    if year.len() != 2 || !year.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year = full_year(year.parse().ok()?);
+    NaiveDateTime::parse_from_str(&format!("{}-{} {}", day, year, time), "%A, %d-%b-%Y %H:%M:%S").ok()
+}
+
+fn parse_asctime(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, "%a %b %e %H:%M:%S %Y").ok()
+}
+
+fn strip_gmt(value: &str) -> Option<&str> {
+    let split = value.len().checked_sub(3)?;
+    match value.get(split..) {
+        Some(zone) if zone.eq_ignore_ascii_case("gmt") => Some(value[..split].trim_end()),
+        _ => None,
+    }
+}
+
+// RFC 7231 section 7.1.1.1: a two-digit year more than 50 years in the future
+// means the most recent past year with the same last two digits.
+fn full_year(two_digits: i32) -> i32 {
+    let current = Utc::now().year();
+    let year = current - current.rem_euclid(100) + two_digits;
+    if year > current + 50 {
+        year - 100
+    } else {
+        year
+    }
+}
@@ -0,0 +1,152 @@
+use crate::config::FileConfig;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+const BUILTIN_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "application/javascript"),
+    ("mjs", "application/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("xhtml", "application/xhtml+xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("wav", "audio/wav"),
+    ("zip", "application/zip"),
+    ("tar", "application/x-tar"),
+    ("gz", "application/gzip"),
+    ("zst", "application/zstd"),
+];
+
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+];
+
+const WEBP: &str = "image/webp";
+
+#[derive(Debug, Clone)]
+pub struct MimeRegistry {
+    types: HashMap<String, String>,
+    sniff: bool,
+}
+
+impl MimeRegistry {
+    pub fn new(overrides: &HashMap<String, String>, charset: &str, sniff: bool) -> Self {
+        let builtin = BUILTIN_TYPES.iter().map(|&(extension, mime)| (extension.to_string(), mime.to_string()));
+        let overrides = overrides
+            .iter()
+            .map(|(extension, mime)| (extension.trim_start_matches('.').to_ascii_lowercase(), mime.trim().to_string()));
+        let types = builtin
+            .chain(overrides)
+            .map(|(extension, mime)| (extension, with_charset(mime, charset)))
+            .collect();
+        Self { types, sniff }
+    }
+
+    pub fn from_config(config: &FileConfig) -> Self {
+        Self::new(&config.mime_types, &config.text_charset, config.sniff_content_type)
+    }
+
+    pub fn lookup(&self, path: &str) -> Option<&str> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        match self.types.get(extension) {
+            Some(mime) => Some(mime),
+            None => self.types.get(&extension.to_ascii_lowercase()).map(String::as_str),
+        }
+    }
+
+    pub fn content_type(&self, path: &str, body: &[u8]) -> &str {
+        self.lookup(path)
+            .or_else(|| self.sniff.then(|| sniff(body)).flatten())
+            .unwrap_or(OCTET_STREAM)
+    }
+}
+
+impl Default for MimeRegistry {
+    fn default() -> Self {
+        Self::new(&HashMap::new(), "utf-8", false)
+    }
+}
+
+pub fn builtin_type(extension: &str) -> Option<&'static str> {
+    BUILTIN_TYPES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|&(_, mime)| mime)
+}
+
+pub fn sniff(body: &[u8]) -> Option<&'static str> {
+    if body.len() >= 12 && body.starts_with(b"RIFF") && &body[8..12] == b"WEBP" {
+        return Some(WEBP);
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| body.starts_with(signature))
+        .map(|&(_, mime)| mime)
+}
+
+pub fn has_signature(mime: &str) -> bool {
+    mime == WEBP || SIGNATURES.iter().any(|&(_, known)| known == mime)
+}
+
+pub fn essence(mime: &str) -> &str {
+    mime.split(';').next().unwrap_or_default().trim()
+}
+
+pub fn is_textual(mime: &str) -> bool {
+    let essence = essence(mime);
+    essence.starts_with("text/")
+        || matches!(
+            essence,
+            "application/javascript" | "application/xml" | "application/xhtml+xml" | "application/rss+xml" | "application/atom+xml" | "image/svg+xml"
+        )
+}
+
+fn with_charset(mime: String, charset: &str) -> String {
+    if charset.is_empty() || mime.contains(';') || !is_textual(&mime) {
+        return mime;
+    }
+    format!("{}; charset={}", mime, charset)
+}
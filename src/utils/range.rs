@@ -0,0 +1,93 @@
+use super::etag::{self, EntityTag};
+use super::http_date;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::ops::Range;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRanges {
+    Full,
+    Partial(Vec<Range<u64>>),
+    Unsatisfiable,
+}
+
+pub fn parse(header: &str, len: u64) -> ByteRanges {
+    let Some((unit, specs)) = header.trim().split_once('=') else {
+        return ByteRanges::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return ByteRanges::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        let Some((first, last)) = spec.split_once('-') else {
+            return ByteRanges::Full;
+        };
+        let range = match (first.trim(), last.trim()) {
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(suffix) if suffix > 0 && len > 0 => Some(len.saturating_sub(suffix)..len),
+                Ok(_) => None,
+                Err(_) => return ByteRanges::Full,
+            },
+            (first, last) => {
+                let Ok(first) = first.parse::<u64>() else {
+                    return ByteRanges::Full;
+                };
+                let last = match last {
+                    "" => len.saturating_sub(1),
+                    last => match last.parse::<u64>() {
+                        Ok(last) if last >= first => last.min(len.saturating_sub(1)),
+                        _ => return ByteRanges::Full,
+                    },
+                };
+                (first < len).then(|| first..last + 1)
+            }
+        };
+        ranges.extend(range);
+    }
+
+    match ranges.is_empty() {
+        true if specs.split(',').all(|spec| spec.trim().is_empty()) => ByteRanges::Full,
+        true => ByteRanges::Unsatisfiable,
+        false => ByteRanges::Partial(ranges),
+    }
+}
+
+pub fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+pub fn unsatisfied_range(len: u64) -> String {
+    format!("bytes */{}", len)
+}
+
+pub fn boundary() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+pub fn multipart_body(body: &Bytes, ranges: &[Range<u64>], content_type: &str, boundary: &str) -> Bytes {
+    let len = body.len() as u64;
+    let mut out = BytesMut::new();
+    for (index, range) in ranges.iter().enumerate() {
+        if index > 0 {
+            out.put_slice(b"\r\n");
+        }
+        out.put_slice(format!("--{}\r\n", boundary).as_bytes());
+        if !content_type.is_empty() {
+            out.put_slice(format!("content-type: {}\r\n", content_type).as_bytes());
+        }
+        out.put_slice(format!("content-range: {}\r\n\r\n", content_range(range, len)).as_bytes());
+        out.put_slice(&body[range.start as usize..range.end as usize]);
+    }
+    out.put_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    out.freeze()
+}
+
+pub fn if_range_matches(header: &str, etag: &EntityTag, modified: SystemTime) -> bool {
+    let header = header.trim();
+    if header.starts_with('"') || header.starts_with("W/") {
+        return etag::if_range_matches(header, etag);
+    }
+    http_date::parse(header).is_some_and(|date| date == http_date::truncate(modified))
+}
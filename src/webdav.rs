@@ -0,0 +1,75 @@
+use crate::utils::{self, http_date};
+use crate::{Error, Result};
+use std::fmt::Write;
+use std::time::SystemTime;
+
+pub const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, POST, PUT, DELETE, MOVE, COPY, PROPFIND";
+pub const READ_ONLY_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    Zero,
+    One,
+}
+
+impl Depth {
+    // RFC 4918 section 9.1: a missing Depth header means infinity, which
+    // this server refuses rather than walking the whole tree.
+    pub fn from_header(value: Option<&str>) -> Result<Self> {
+        match value.map(str::trim) {
+            Some("0") => Ok(Self::Zero),
+            Some("1") => Ok(Self::One),
+            None => Err(Error::PermissionDenied("PROPFIND with Depth: infinity is not supported".to_string())),
+            Some(value) if value.eq_ignore_ascii_case("infinity") => {
+                Err(Error::PermissionDenied("PROPFIND with Depth: infinity is not supported".to_string()))
+            }
+            Some(value) => Err(Error::BadRequest(format!("Invalid Depth header: {}", value))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PropEntry {
+    pub href: String,
+    pub display_name: String,
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub content_type: Option<String>,
+}
+
+pub fn multistatus(entries: &[PropEntry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for entry in entries {
+        let _ = write!(xml, "<D:response><D:href>{}</D:href><D:propstat><D:prop>", utils::escape_html(&entry.href));
+        let _ = write!(xml, "<D:displayname>{}</D:displayname>", utils::escape_html(&entry.display_name));
+        match entry.is_dir {
+            true => xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+            false => {
+                xml.push_str("<D:resourcetype/>");
+                let _ = write!(xml, "<D:getcontentlength>{}</D:getcontentlength>", entry.len);
+            }
+        }
+        if let Some(content_type) = &entry.content_type {
+            let _ = write!(xml, "<D:getcontenttype>{}</D:getcontenttype>", utils::escape_html(content_type));
+        }
+        if let Some(modified) = entry.modified {
+            let _ = write!(xml, "<D:getlastmodified>{}</D:getlastmodified>", http_date::format(modified));
+        }
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+pub fn href(path: &str, is_dir: bool) -> String {
+    let mut href = String::from("/files");
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        href.push('/');
+        href.push_str(&urlencoding::encode(segment));
+    }
+    if is_dir {
+        href.push('/');
+    }
+    href
+}
@@ -0,0 +1,119 @@
+use rust_https_server::config::{Config, RoutesConfig};
+use rust_https_server::server::Server;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+pub struct TestServer {
+    pub server: Arc<Server>,
+    pub addr: SocketAddr,
+    root: TempDir,
+    task: JoinHandle<rust_https_server::Result<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    pub async fn start_with(configure: impl FnOnce(&mut Config)) -> Self {
+        let root = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.server.host = "127.0.0.1".to_string();
+        config.server.port = 0;
+        config.files.root_dir = root.path().display().to_string();
+        config.routes = RoutesConfig { enable_echo: true, enable_user_agent: true, enable_files: true, files_read_only: false };
+        configure(&mut config);
+
+        let server = Arc::new(Server::try_new(config).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let running = server.clone();
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                result = running.serve(listener) => result,
+                _ = running.wait_for_shutdown() => Ok(()),
+            }
+        });
+        Self { server, addr, root, task }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn root(&self) -> &Path {
+        self.root.path()
+    }
+
+    pub async fn get(&self, path: &str) -> TestResponse {
+        self.request("GET", path, &[], b"").await
+    }
+
+    pub async fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> TestResponse {
+        let mut raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n", method, path);
+        for (name, value) in headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !body.is_empty() || matches!(method, "POST" | "PUT") {
+            raw.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        raw.push_str("\r\n");
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(body);
+        TestResponse::parse(&self.send_raw(&raw).await)
+    }
+
+    pub async fn send_raw(&self, raw: &[u8]) -> Vec<u8> {
+        let mut stream = TcpStream::connect(self.addr).await.unwrap();
+        stream.write_all(raw).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        response
+    }
+
+    pub async fn shutdown(self) {
+        self.server.shutdown();
+        tokio::time::timeout(std::time::Duration::from_secs(5), self.task)
+            .await
+            .expect("server did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn parse(raw: &[u8]) -> Self {
+        let head_end = raw
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap_or_else(|| panic!("incomplete response: {:?}", String::from_utf8_lossy(raw)));
+        let head = std::str::from_utf8(&raw[..head_end]).unwrap();
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        Self { status, headers, body: raw[head_end + 4..].to_vec() }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
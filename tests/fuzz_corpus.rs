@@ -0,0 +1,91 @@
+use bytes::BytesMut;
+use rust_https_server::parser::{parse_request_with, ParseOptions, ParseState};
+use rust_https_server::utils::parse_query_string;
+use std::path::Path;
+
+fn corpus(target: &str) -> Vec<(String, Vec<u8>)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus").join(target);
+    let mut entries: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", dir.display(), e))
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            (path.file_name().unwrap().to_string_lossy().into_owned(), std::fs::read(&path).unwrap())
+        })
+        .collect();
+    entries.sort();
+    assert!(!entries.is_empty(), "empty corpus in {}", dir.display());
+    entries
+}
+
+fn drain(data: &[u8], options: &ParseOptions) -> usize {
+    let mut buffer = BytesMut::from(data);
+    let mut state = ParseState::default();
+    let mut parsed = 0;
+    loop {
+        let before = buffer.len();
+        match parse_request_with(&mut buffer, &mut state, options) {
+            Ok(Some(_)) => {
+                assert!(buffer.len() < before, "a parsed request must consume input");
+                parsed += 1;
+            }
+            _ => return parsed,
+        }
+    }
+}
+
+fn drip(data: &[u8], chunk: usize, options: &ParseOptions) -> usize {
+    let mut buffer = BytesMut::new();
+    let mut state = ParseState::default();
+    let mut parsed = 0;
+    for bytes in data.chunks(chunk) {
+        buffer.extend_from_slice(bytes);
+        loop {
+            match parse_request_with(&mut buffer, &mut state, options) {
+                Ok(Some(_)) => parsed += 1,
+                Ok(None) => break,
+                Err(_) => return parsed,
+            }
+        }
+    }
+    parsed
+}
+
+#[test]
+fn parse_request_corpus_replays_without_panicking() {
+    for (name, data) in corpus("parse_request") {
+        for strict in [true, false] {
+            let options = ParseOptions { strict, ..Default::default() };
+            let whole = drain(&data, &options);
+            for chunk in [1, 2, 7, 64] {
+                assert_eq!(drip(&data, chunk, &options), whole, "{} fed in {}-byte chunks", name, chunk);
+            }
+            for end in 0..data.len() {
+                drain(&data[..end], &options);
+                drain(&data[end..], &options);
+            }
+        }
+    }
+}
+
+#[test]
+fn parse_request_corpus_expectations() {
+    let options = ParseOptions::default();
+    let data: std::collections::HashMap<_, _> = corpus("parse_request").into_iter().collect();
+    assert_eq!(drain(&data["pipelined"], &options), 3);
+    assert_eq!(drain(&data["post_chunked"], &options), 1);
+    assert_eq!(drain(&data["huge_content_length"], &options), 0);
+    assert_eq!(drain(&data["huge_chunk_size"], &options), 0);
+    assert!(parse_request_with(&mut BytesMut::from(&data["huge_chunk_size"][..]), &mut ParseState::default(), &options).is_err());
+}
+
+#[test]
+fn parse_query_string_corpus_replays_without_panicking() {
+    for (name, data) in corpus("parse_query_string") {
+        let query = String::from_utf8(data).unwrap();
+        let params = parse_query_string(&query);
+        assert!(params.len() <= query.split('&').count(), "{}", name);
+        for end in (0..=query.len()).filter(|&end| query.is_char_boundary(end)) {
+            parse_query_string(&query[..end]);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use common::{TestResponse, TestServer};
+
+#[tokio::test]
+async fn serves_basic_routes() {
+    let server = TestServer::start().await;
+
+    let response = server.get("/").await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "Welcome to Rust HTTP Server");
+    assert_eq!(response.header("content-length"), Some("27"));
+
+    let response = server.get("/echo/hello").await;
+    assert_eq!((response.status, response.text().as_str()), (200, "hello"));
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+
+    let response = server.request("GET", "/user-agent", &[("User-Agent", "integration/1.0")], b"").await;
+    assert_eq!((response.status, response.text().as_str()), (200, "integration/1.0"));
+    let response = server.get("/user-agent").await;
+    assert_eq!(response.status, 400);
+
+    let base_url = server.base_url();
+    let authority = base_url.strip_prefix("http://").unwrap();
+    let raw = format!("GET {}/echo/absolute HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", base_url, authority);
+    let response = TestResponse::parse(&server.send_raw(raw.as_bytes()).await);
+    assert_eq!((response.status, response.text().as_str()), (200, "absolute"));
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn file_crud_round_trip() {
+    let server = TestServer::start().await;
+
+    let response = server.request("POST", "/files/notes.txt", &[], b"first draft").await;
+    assert_eq!(response.status, 201);
+    assert_eq!(std::fs::read(server.root().join("notes.txt")).unwrap(), b"first draft");
+
+    let response = server.get("/files/notes.txt").await;
+    assert_eq!((response.status, response.text().as_str()), (200, "first draft"));
+
+    let response = server.request("PUT", "/files/notes.txt", &[], b"second draft").await;
+    assert_eq!(response.status, 204);
+    assert_eq!(server.get("/files/notes.txt").await.text(), "second draft");
+
+    let response = server.request("DELETE", "/files/notes.txt", &[], b"").await;
+    assert_eq!(response.status, 200);
+    assert!(!server.root().join("notes.txt").exists());
+    assert_eq!(server.get("/files/notes.txt").await.status, 404);
+    assert_eq!(server.request("DELETE", "/files/notes.txt", &[], b"").await.status, 404);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn unknown_routes_are_not_found() {
+    let server = TestServer::start().await;
+
+    let response = server.get("/does/not/exist").await;
+    assert_eq!(response.status, 404);
+    assert_eq!(response.text(), "Route not found: /does/not/exist");
+
+    let response = server.request("GET", "/nowhere", &[("Accept", "application/json")], b"").await;
+    assert_eq!(response.status, 404);
+    assert_eq!(response.header("content-type"), Some("application/json"));
+
+    assert_eq!(server.get("/files/missing.txt").await.status, 404);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn oversized_uploads_are_rejected() {
+    let server = TestServer::start_with(|config| {
+        config.security.max_request_size = 1024;
+        config.files.max_file_size = 16;
+    })
+    .await;
+
+    let head = b"POST /files/big.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4096\r\n\r\n";
+    let response = TestResponse::parse(&server.send_raw(head).await);
+    assert_eq!(response.status, 413);
+    assert_eq!(response.header("connection"), Some("close"));
+
+    let response = server.request("POST", "/files/medium.txt", &[], &[b'x'; 64]).await;
+    assert_eq!(response.status, 413);
+    assert!(!server.root().join("big.txt").exists() && !server.root().join("medium.txt").exists());
+
+    assert_eq!(server.request("POST", "/files/small.txt", &[], b"fits").await.status, 201);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn handles_concurrent_requests() {
+    let server = std::sync::Arc::new(TestServer::start().await);
+
+    let requests = (0..32).map(|i| {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let response = server.get(&format!("/echo/client-{}", i)).await;
+            (response.status, response.text(), i)
+        })
+    });
+    for result in futures::future::join_all(requests).await {
+        let (status, body, i) = result.unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, format!("client-{}", i));
+    }
+    assert_eq!(server.server.metrics().requests_by_class()[1], 32);
+
+    let server = std::sync::Arc::into_inner(server).unwrap();
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutdown_stops_accepting_connections() {
+    let server = TestServer::start().await;
+    let addr = server.addr;
+    assert_eq!(server.get("/").await.status, 200);
+
+    server.shutdown().await;
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+